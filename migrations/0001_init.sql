@@ -0,0 +1,161 @@
+-- Initial schema. Statements keep IF NOT EXISTS so databases created before
+-- migrations were introduced adopt this version without errors.
+
+CREATE TABLE IF NOT EXISTS blocks (
+    number INTEGER PRIMARY KEY,
+    hash   TEXT,
+    ts     INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS erc20_transfers (
+    tx_hash      TEXT NOT NULL,
+    log_index    INTEGER NOT NULL,
+    block_number INTEGER NOT NULL,
+    contract     TEXT NOT NULL,
+    "from"       TEXT NOT NULL,
+    "to"         TEXT NOT NULL,
+    amount_wei   TEXT NOT NULL,
+    PRIMARY KEY (tx_hash, log_index)
+);
+
+-- Per-address lookups (`/transfers?address=`, exchange scans) and block-range queries
+CREATE INDEX IF NOT EXISTS idx_transfers_from ON erc20_transfers("from");
+CREATE INDEX IF NOT EXISTS idx_transfers_to ON erc20_transfers("to");
+CREATE INDEX IF NOT EXISTS idx_transfers_block ON erc20_transfers(block_number);
+
+-- Optional forensic copy of each transfer's raw log (STORE_RAW_LOGS)
+CREATE TABLE IF NOT EXISTS erc20_transfer_raw (
+    tx_hash   TEXT NOT NULL,
+    log_index INTEGER NOT NULL,
+    topics    TEXT NOT NULL,
+    data_hex  TEXT NOT NULL,
+    PRIMARY KEY (tx_hash, log_index)
+);
+
+-- Live logs waiting for CONFIRMATIONS; keyed by block hash so a log re-mined in
+-- another block gets its own row and the orphaned one can be told apart
+CREATE TABLE IF NOT EXISTS pending_transfers (
+    block_hash   TEXT NOT NULL,
+    log_index    INTEGER NOT NULL,
+    block_number INTEGER NOT NULL,
+    log_json     TEXT NOT NULL,
+    PRIMARY KEY (block_hash, log_index)
+);
+
+-- `source`: `seed` (env/startup/admin API) or `feed` (EXCHANGE_LIST_URL)
+CREATE TABLE IF NOT EXISTS exchange_addresses (
+    address  TEXT PRIMARY KEY,
+    exchange TEXT NOT NULL,
+    source   TEXT NOT NULL DEFAULT 'seed'
+);
+
+CREATE TABLE IF NOT EXISTS exchange_aliases (
+    label     TEXT PRIMARY KEY,
+    canonical TEXT NOT NULL
+);
+
+-- Optional per-block exchange flow (NETFLOW_BY_BLOCK), only blocks that had some
+CREATE TABLE IF NOT EXISTS netflow_by_block (
+    contract     TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    in_wei       TEXT NOT NULL DEFAULT '0',
+    out_wei      TEXT NOT NULL DEFAULT '0',
+    PRIMARY KEY (contract, block_number)
+);
+
+-- netflow_state counters split by exchange label, per token
+CREATE TABLE IF NOT EXISTS netflow_by_exchange (
+    contract           TEXT NOT NULL,
+    exchange           TEXT NOT NULL,
+    cumulative_in_wei  TEXT NOT NULL DEFAULT '0',
+    cumulative_out_wei TEXT NOT NULL DEFAULT '0',
+    last_block         INTEGER,
+    excluded_in_wei    TEXT NOT NULL DEFAULT '0',
+    excluded_out_wei   TEXT NOT NULL DEFAULT '0',
+    minted_wei         TEXT NOT NULL DEFAULT '0',
+    burned_wei         TEXT NOT NULL DEFAULT '0',
+    seq                INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (contract, exchange)
+);
+
+-- Exchange labels seen on at least one recorded transfer (netflow coverage)
+CREATE TABLE IF NOT EXISTS exchange_coverage (
+    exchange    TEXT PRIMARY KEY,
+    first_block INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS reserve_snapshots (
+    id                 INTEGER PRIMARY KEY AUTOINCREMENT,
+    ts                 INTEGER NOT NULL,
+    block_number       INTEGER NOT NULL,
+    addresses          INTEGER NOT NULL,
+    reserve_wei        TEXT NOT NULL,
+    cumulative_net_wei TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS jobs (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind        TEXT NOT NULL,
+    status      TEXT NOT NULL,
+    progress    REAL NOT NULL DEFAULT 0,
+    started_at  INTEGER NOT NULL,
+    finished_at INTEGER,
+    result      TEXT
+);
+
+-- On-chain symbol()/decimals() per indexed token, fetched once
+CREATE TABLE IF NOT EXISTS token_meta (
+    contract TEXT PRIMARY KEY,
+    symbol   TEXT NOT NULL,
+    decimals INTEGER NOT NULL
+);
+
+-- Read-through cache of per-address RPC lookups (code, supply, ...), see METADATA_TTL_SECS
+CREATE TABLE IF NOT EXISTS metadata (
+    address    TEXT NOT NULL,
+    key        TEXT NOT NULL,
+    value      TEXT NOT NULL,
+    fetched_at INTEGER NOT NULL,
+    PRIMARY KEY (address, key)
+);
+
+-- STRICT mode: logs the lenient path would have passed over silently
+CREATE TABLE IF NOT EXISTS skipped_logs (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    ts           INTEGER NOT NULL,
+    tx_hash      TEXT,
+    log_index    INTEGER,
+    block_number INTEGER,
+    reason       TEXT NOT NULL,
+    log_json     TEXT NOT NULL
+);
+
+-- Last row handed to each incremental exporter (e.g. the Parquet sink)
+CREATE TABLE IF NOT EXISTS export_cursor (
+    name       TEXT PRIMARY KEY,
+    last_rowid INTEGER NOT NULL
+);
+
+-- One row per indexed token contract (lowercase hex); rows are seeded at startup
+CREATE TABLE IF NOT EXISTS netflow_state (
+    contract           TEXT PRIMARY KEY,
+    cumulative_in_wei  TEXT NOT NULL DEFAULT '0',
+    cumulative_out_wei TEXT NOT NULL DEFAULT '0',
+    last_block         INTEGER,
+    excluded_in_wei    TEXT NOT NULL DEFAULT '0',
+    excluded_out_wei   TEXT NOT NULL DEFAULT '0',
+    minted_wei         TEXT NOT NULL DEFAULT '0',
+    burned_wei         TEXT NOT NULL DEFAULT '0',
+    seq                INTEGER NOT NULL DEFAULT 0
+);
+
+-- Native POL exchange flow (NATIVE_FLOW), kept apart from the ERC-20 netflow
+CREATE TABLE IF NOT EXISTS native_netflow_state (
+    id                 INTEGER PRIMARY KEY CHECK (id = 1),
+    cumulative_in_wei  TEXT NOT NULL DEFAULT '0',
+    cumulative_out_wei TEXT NOT NULL DEFAULT '0',
+    last_block         INTEGER,
+    seq                INTEGER NOT NULL DEFAULT 0
+);
+
+INSERT OR IGNORE INTO native_netflow_state(id) VALUES (1);
@@ -0,0 +1,9 @@
+-- Running token balance of each exchange address, from the transfers the indexer has
+-- seen (signed: negative when indexing started after the wallet was funded)
+CREATE TABLE address_balances (
+    contract    TEXT NOT NULL,
+    address     TEXT NOT NULL,
+    balance_wei TEXT NOT NULL DEFAULT '0',
+    last_block  INTEGER,
+    PRIMARY KEY (contract, address)
+);
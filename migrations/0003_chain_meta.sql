@@ -0,0 +1,5 @@
+-- The chain this database was indexed from, recorded on first connect
+CREATE TABLE chain_meta (
+    id       INTEGER PRIMARY KEY CHECK (id = 1),
+    chain_id INTEGER NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Settings changed at runtime through the admin API; they win over the environment on restart
+CREATE TABLE runtime_config (
+    key   TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
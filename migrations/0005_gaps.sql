@@ -0,0 +1,10 @@
+-- Block ranges the live log stream skipped, as found when a later log arrived;
+-- healed_at stays NULL until the range has been backfilled
+CREATE TABLE gaps (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    from_block  INTEGER NOT NULL,
+    to_block    INTEGER NOT NULL,
+    detected_at INTEGER NOT NULL,
+    healed_at   INTEGER
+);
+CREATE INDEX idx_gaps_unhealed ON gaps(from_block) WHERE healed_at IS NULL;
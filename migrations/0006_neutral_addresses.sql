@@ -0,0 +1,6 @@
+-- Addresses whose transfers are never exchange flow, whichever side they are on
+-- (e.g. the MATIC -> POL migration contract); seeded from NEUTRAL_ADDRESSES
+CREATE TABLE neutral_addresses (
+    address TEXT PRIMARY KEY,
+    note    TEXT NOT NULL DEFAULT ''
+);
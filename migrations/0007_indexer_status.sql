@@ -0,0 +1,9 @@
+-- Single-row checkpoint of the chain head, written every few seconds while a session
+-- is live so quiet stretches without transfers still move the resume point
+CREATE TABLE indexer_status (
+    id           INTEGER PRIMARY KEY CHECK (id = 1),
+    head         INTEGER NOT NULL,
+    -- Every log at or below this block had been processed or staged
+    synced_block INTEGER,
+    updated_at   INTEGER NOT NULL
+);
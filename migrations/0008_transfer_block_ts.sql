@@ -0,0 +1,8 @@
+-- Block timestamp on each transfer, so time-based queries don't need `blocks`;
+-- NULL while the block's timestamp is unknown (see backfill-timestamps)
+ALTER TABLE erc20_transfers ADD COLUMN block_ts INTEGER;
+
+UPDATE erc20_transfers
+SET block_ts = (SELECT b.ts FROM blocks b WHERE b.number = erc20_transfers.block_number);
+
+CREATE INDEX IF NOT EXISTS idx_transfers_contract_ts ON erc20_transfers(contract, block_ts);
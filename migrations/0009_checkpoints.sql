@@ -0,0 +1,42 @@
+-- Counters as recomputed through `block_number`, saved before the transfers up to that
+-- block are pruned; a recompute starts from the newest one instead of from nothing
+CREATE TABLE checkpoints (
+    contract           TEXT NOT NULL,
+    block_number       INTEGER NOT NULL,
+    cumulative_in_wei  TEXT NOT NULL DEFAULT '0',
+    cumulative_out_wei TEXT NOT NULL DEFAULT '0',
+    excluded_in_wei    TEXT NOT NULL DEFAULT '0',
+    excluded_out_wei   TEXT NOT NULL DEFAULT '0',
+    minted_wei         TEXT NOT NULL DEFAULT '0',
+    burned_wei         TEXT NOT NULL DEFAULT '0',
+    last_block         INTEGER,
+    -- Transfers summarized, pruned ones included
+    transfers          INTEGER NOT NULL DEFAULT 0,
+    created_at         INTEGER NOT NULL,
+    PRIMARY KEY (contract, block_number)
+);
+
+-- The checkpoint's `netflow_by_exchange` rows
+CREATE TABLE checkpoint_exchanges (
+    contract           TEXT NOT NULL,
+    block_number       INTEGER NOT NULL,
+    exchange           TEXT NOT NULL,
+    cumulative_in_wei  TEXT NOT NULL DEFAULT '0',
+    cumulative_out_wei TEXT NOT NULL DEFAULT '0',
+    excluded_in_wei    TEXT NOT NULL DEFAULT '0',
+    excluded_out_wei   TEXT NOT NULL DEFAULT '0',
+    minted_wei         TEXT NOT NULL DEFAULT '0',
+    burned_wei         TEXT NOT NULL DEFAULT '0',
+    last_block         INTEGER,
+    PRIMARY KEY (contract, block_number, exchange)
+);
+
+-- The checkpoint's `address_balances` rows
+CREATE TABLE checkpoint_balances (
+    contract     TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    address      TEXT NOT NULL,
+    balance_wei  TEXT NOT NULL,
+    last_block   INTEGER NOT NULL,
+    PRIMARY KEY (contract, block_number, address)
+);
@@ -0,0 +1,6 @@
+-- START_TIMESTAMP resolved to its first block (clamped to the token's creation), so a
+-- restart on an empty database doesn't search the chain again
+CREATE TABLE start_blocks (
+    start_timestamp INTEGER PRIMARY KEY,
+    block_number    INTEGER NOT NULL
+);
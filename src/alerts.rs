@@ -0,0 +1,383 @@
+
+use anyhow::{bail, Result};
+use rug::Integer;
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn, error};
+
+#[derive(Clone, Default)]
+pub struct AlertCfg {
+    /// Optional webhook receiving a JSON POST per alert (alerts are always logged)
+    pub webhook_url: Option<String>,
+    /// Enable the cumulative_net sign-flip alert with this hysteresis band (wei)
+    pub flip_band_wei: Option<Integer>,
+    /// Per-exchange limits on cumulative net flow, keyed by `exchange_addresses.exchange`
+    pub exchange_thresholds: Vec<ExchangeThreshold>,
+    /// Alert when the net changes by more than this (wei) within `window`
+    pub window_threshold_wei: Option<Integer>,
+    pub window: Duration,
+}
+
+/// Alert once `exchange`'s cumulative net flow in `direction` exceeds `wei`.
+#[derive(Clone, Debug)]
+pub struct ExchangeThreshold {
+    pub exchange: String,
+    pub direction: Sign,
+    pub wei: Integer,
+}
+
+/// Parse `EXCHANGE_ALERT_THRESHOLDS=binance:outflow:5000000000000000000000000,coinbase:inflow:...`.
+pub fn parse_exchange_thresholds(s: &str) -> Result<Vec<ExchangeThreshold>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let parts: Vec<&str> = p.split(':').map(str::trim).collect();
+            let [exchange, direction, wei] = parts[..] else {
+                bail!("invalid exchange threshold {p:?} (exchange:inflow|outflow:wei)");
+            };
+            let direction = match direction {
+                "inflow" => Sign::Inflow,
+                "outflow" => Sign::Outflow,
+                other => bail!("invalid direction {other:?} in {p:?} (inflow|outflow)"),
+            };
+            let wei = Integer::from_str_radix(wei, 10)
+                .map_err(|e| anyhow::anyhow!("invalid threshold {wei:?} in {p:?}: {e}"))?;
+            Ok(ExchangeThreshold { exchange: exchange.to_string(), direction, wei })
+        })
+        .collect()
+}
+
+/// Running per-exchange totals checked against their thresholds. Each exchange and
+/// direction is debounced on its own: it fires once on crossing and re-arms only
+/// after the net falls back within the threshold.
+struct ExchangeAlerts {
+    thresholds: Vec<ExchangeThreshold>,
+    /// exchange -> (in, out)
+    totals: HashMap<String, (Integer, Integer)>,
+    fired: HashSet<(String, Sign)>,
+}
+
+impl ExchangeAlerts {
+    /// Apply one flow to `exchange`; returns the thresholds newly crossed with the net.
+    fn observe(&mut self, exchange: &str, in_wei: &Integer, out_wei: &Integer) -> Vec<(ExchangeThreshold, Integer)> {
+        let (tin, tout) = self.totals.entry(exchange.to_string()).or_default();
+        *tin += in_wei;
+        *tout += out_wei;
+        let net_in = Integer::from(&*tin - &*tout);
+        let mut crossed = Vec::new();
+        for t in self.thresholds.iter().filter(|t| t.exchange == exchange) {
+            let directed = match t.direction {
+                Sign::Inflow => net_in.clone(),
+                Sign::Outflow => Integer::from(-&net_in),
+            };
+            let key = (t.exchange.clone(), t.direction);
+            if directed > t.wei {
+                if self.fired.insert(key) {
+                    crossed.push((t.clone(), directed));
+                }
+            } else {
+                self.fired.remove(&key);
+            }
+        }
+        crossed
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Sign {
+    Inflow,
+    Outflow,
+}
+
+impl Sign {
+    fn as_str(self) -> &'static str {
+        match self {
+            Sign::Inflow => "inflow",
+            Sign::Outflow => "outflow",
+        }
+    }
+}
+
+/// Net change over a sliding wall-clock window. Each direction fires at most once
+/// per window, however long the swing lasts.
+struct WindowDetector {
+    threshold: Integer,
+    window: Duration,
+    /// (seen at, cumulative net) per update, oldest first
+    samples: VecDeque<(Instant, Integer)>,
+    last_fired: HashMap<Sign, Instant>,
+}
+
+impl WindowDetector {
+    /// Feed the latest net; returns the direction and size of a swing newly past the threshold.
+    fn observe(&mut self, now: Instant, net: &Integer) -> Option<(Sign, Integer)> {
+        while self.samples.front().is_some_and(|(t, _)| now.duration_since(*t) > self.window) {
+            self.samples.pop_front();
+        }
+        let change = match self.samples.front() {
+            Some((_, oldest)) => Integer::from(net - oldest),
+            None => Integer::new(),
+        };
+        self.samples.push_back((now, net.clone()));
+        let sign = if change > self.threshold {
+            Sign::Inflow
+        } else if change < -self.threshold.clone() {
+            Sign::Outflow
+        } else {
+            return None;
+        };
+        if self.last_fired.get(&sign).is_some_and(|t| now.duration_since(*t) < self.window) {
+            return None;
+        }
+        self.last_fired.insert(sign, now);
+        Some((sign, change.abs()))
+    }
+}
+
+/// Tracks the sign of the net with a hysteresis band around zero.
+/// The sign only changes once the net leaves the band (`net > band` or
+/// `net < -band`), so oscillation near zero does not fire repeatedly.
+pub struct FlipDetector {
+    band: Integer,
+    sign: Option<Sign>,
+}
+
+impl FlipDetector {
+    pub fn new(band: Integer) -> Self {
+        Self { band: band.abs(), sign: None }
+    }
+
+    /// Feed the latest net; returns `(from, to)` when the sign crossed over.
+    /// The first sign established after startup is not reported as a flip.
+    pub fn observe(&mut self, net: &Integer) -> Option<(Sign, Sign)> {
+        let next = if *net > self.band {
+            Sign::Inflow
+        } else if *net < -self.band.clone() {
+            Sign::Outflow
+        } else {
+            // inside the band: keep the previous sign
+            return None;
+        };
+        match self.sign.replace(next) {
+            Some(prev) if prev != next => Some((prev, next)),
+            _ => None,
+        }
+    }
+}
+
+pub struct Alerts {
+    client: reqwest::Client,
+    webhook_url: Option<String>,
+    flip: Option<Mutex<FlipDetector>>,
+    window: Option<Mutex<WindowDetector>>,
+    exchanges: Option<Mutex<ExchangeAlerts>>,
+}
+
+impl Alerts {
+    pub fn new(cfg: AlertCfg) -> Self {
+        let exchanges = (!cfg.exchange_thresholds.is_empty()).then(|| Mutex::new(ExchangeAlerts {
+            thresholds: cfg.exchange_thresholds,
+            totals: HashMap::new(),
+            fired: HashSet::new(),
+        }));
+        Self {
+            client: reqwest::Client::new(),
+            webhook_url: cfg.webhook_url,
+            flip: cfg.flip_band_wei.map(|b| Mutex::new(FlipDetector::new(b))),
+            window: cfg.window_threshold_wei.map(|t| Mutex::new(WindowDetector {
+                threshold: t.abs(),
+                window: cfg.window,
+                samples: VecDeque::new(),
+                last_fired: HashMap::new(),
+            })),
+            exchanges,
+        }
+    }
+
+    /// Whether per-exchange thresholds are configured (the caller can skip label lookups otherwise).
+    pub fn tracks_exchanges(&self) -> bool {
+        self.exchanges.is_some()
+    }
+
+    /// Load each exchange's totals from `contract`'s `netflow_by_exchange` counters, the
+    /// same classified flows `on_exchange_flow` is fed, so thresholds apply to the
+    /// all-time aggregate, not just this run. Thresholds already exceeded at startup
+    /// count as fired (no alert storm on restart).
+    pub async fn seed_exchange_totals(&self, db: &SqlitePool, contract: &str) -> Result<()> {
+        let Some(ex) = &self.exchanges else { return Ok(()) };
+        let rows = sqlx::query_as::<_, (String, String, String)>(r#"
+            SELECT exchange, cumulative_in_wei, cumulative_out_wei FROM netflow_by_exchange WHERE contract = ?;
+        "#)
+            .bind(contract)
+            .fetch_all(db).await?;
+        let mut ex = ex.lock().expect("exchange alerts poisoned");
+        for (label, in_wei, out_wei) in rows {
+            ex.observe(&label, &crate::units::parse_amount(&in_wei)?, &crate::units::parse_amount(&out_wei)?);
+        }
+        info!("Seeded per-exchange alert totals for {} exchanges", ex.totals.len());
+        Ok(())
+    }
+
+    /// Called for each counted flow touching `exchange`.
+    pub fn on_exchange_flow(&self, exchange: &str, in_wei: &Integer, out_wei: &Integer, block: i64) {
+        let Some(ex) = &self.exchanges else { return };
+        let crossed = ex.lock().expect("exchange alerts poisoned").observe(exchange, in_wei, out_wei);
+        for (t, amount) in crossed {
+            self.fire(json!({
+                "alert": "exchange_netflow_threshold",
+                "exchange": t.exchange,
+                "direction": t.direction.as_str(),
+                "amount_wei": amount.to_string(),
+                "threshold_wei": t.wei.to_string(),
+                "block": block,
+            }));
+        }
+    }
+
+    /// Called after each netflow update with the fresh cumulative totals.
+    pub fn on_netflow(&self, in_wei: &Integer, out_wei: &Integer, block: i64) {
+        let net = Integer::from(in_wei - out_wei);
+        if let Some(window) = &self.window {
+            let mut window = window.lock().expect("window detector poisoned");
+            if let Some((direction, amount)) = window.observe(Instant::now(), &net) {
+                let secs = window.window.as_secs();
+                let threshold = window.threshold.to_string();
+                drop(window);
+                self.fire(json!({
+                    "alert": "netflow_window_threshold",
+                    "direction": direction.as_str(),
+                    "amount_wei": amount.to_string(),
+                    "threshold_wei": threshold,
+                    "window_secs": secs,
+                    "block": block,
+                    "timestamp": SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+                }));
+            }
+        }
+        let Some(flip) = &self.flip else { return };
+        let crossed = flip.lock().expect("flip detector poisoned").observe(&net);
+        if let Some((from, to)) = crossed {
+            self.fire(json!({
+                "alert": "netflow_sign_flip",
+                "from": from.as_str(),
+                "to": to.as_str(),
+                "block": block,
+                "cumulative_in_wei": in_wei.to_string(),
+                "cumulative_out_wei": out_wei.to_string(),
+                "cumulative_net_wei": net.to_string(),
+            }));
+        }
+    }
+
+    /// Log the alert and, if configured, POST it to the webhook in the background
+    /// so a slow receiver never stalls log processing.
+    fn fire(&self, payload: serde_json::Value) {
+        warn!("ALERT {}", payload);
+        let Some(url) = self.webhook_url.clone() else { return };
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            match client.post(&url).json(&payload).send().await {
+                Ok(resp) if !resp.status().is_success() => {
+                    error!("alert webhook returned {}", resp.status());
+                }
+                Ok(_) => {}
+                Err(e) => error!("alert webhook failed: {e:#}"),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn threshold(exchange: &str, direction: Sign, wei: u32) -> ExchangeThreshold {
+        ExchangeThreshold { exchange: exchange.into(), direction, wei: Integer::from(wei) }
+    }
+
+    fn exchange_alerts(thresholds: Vec<ExchangeThreshold>) -> ExchangeAlerts {
+        ExchangeAlerts { thresholds, totals: HashMap::new(), fired: HashSet::new() }
+    }
+
+    #[test]
+    fn one_exchange_crossing_leaves_the_others_armed() {
+        let mut ex = exchange_alerts(vec![
+            threshold("binance", Sign::Outflow, 100),
+            threshold("coinbase", Sign::Outflow, 100),
+        ]);
+        let zero = Integer::new();
+        let out = |ex: &mut ExchangeAlerts, label: &str, out_wei: u32| -> Vec<String> {
+            ex.observe(label, &zero, &Integer::from(out_wei)).into_iter().map(|(t, _)| t.exchange).collect()
+        };
+        assert_eq!(out(&mut ex, "binance", 150), ["binance"]);
+        assert!(out(&mut ex, "coinbase", 50).is_empty());
+        // Still past its threshold: binance doesn't fire again, coinbase fires on its own
+        assert!(out(&mut ex, "binance", 10).is_empty());
+        assert_eq!(out(&mut ex, "coinbase", 60), ["coinbase"]);
+        // Back within the threshold re-arms binance only
+        assert!(ex.observe("binance", &Integer::from(100), &zero).is_empty());
+        assert_eq!(out(&mut ex, "binance", 50), ["binance"]);
+        assert!(out(&mut ex, "coinbase", 1).is_empty());
+    }
+
+    #[tokio::test]
+    async fn exchange_totals_are_seeded_from_the_counters() {
+        use crate::indexer::tests::{ingest, test_cfg, test_indexer, token, transfer_log, BINANCE, COINBASE, USER};
+        use crate::indexer::{IndexerCfg, ZeroAddressRule};
+
+        let cfg = IndexerCfg { zero_address_rule: ZeroAddressRule::Exclude, ..test_cfg() };
+        let ix = test_indexer(cfg, &[(BINANCE, "binance"), (COINBASE, "coinbase")]).await;
+        ingest(&ix, [
+            // Stored but not counted: a mint into the exchange wallet
+            transfer_log("0x0000000000000000000000000000000000000000", BINANCE, 1000, 1, 0),
+            transfer_log(BINANCE, USER, 300, 2, 0),
+            transfer_log(USER, COINBASE, 200, 3, 0),
+        ]).await;
+
+        let alerts = Alerts::new(AlertCfg {
+            exchange_thresholds: vec![
+                threshold("binance", Sign::Inflow, 500),
+                threshold("binance", Sign::Outflow, 250),
+                threshold("coinbase", Sign::Inflow, 100),
+            ],
+            ..AlertCfg::default()
+        });
+        alerts.seed_exchange_totals(&ix.db, &format!("{:#x}", token())).await.expect("seed");
+
+        let ex = alerts.exchanges.as_ref().expect("exchange alerts").lock().unwrap();
+        assert_eq!(ex.totals["binance"], (Integer::new(), Integer::from(300)));
+        assert_eq!(ex.totals["coinbase"], (Integer::from(200), Integer::new()));
+        let fired: HashSet<(String, Sign)> = [("binance".into(), Sign::Outflow), ("coinbase".into(), Sign::Inflow)].into();
+        assert_eq!(ex.fired, fired);
+    }
+
+    #[test]
+    fn flip_needs_the_net_to_cross_the_whole_band() {
+        let mut flip = FlipDetector::new(Integer::from(-100));
+        let mut observe = |net: i64| flip.observe(&Integer::from(net));
+        // Inside the band nothing is established; the first sign outside it is not a flip
+        assert_eq!(observe(50), None);
+        assert_eq!(observe(101), None);
+        // Dipping back into the band, even below zero, keeps the sign
+        assert_eq!(observe(-100), None);
+        assert_eq!(observe(100), None);
+        assert_eq!(observe(-101), Some((Sign::Inflow, Sign::Outflow)));
+        assert_eq!(observe(-500), None);
+        assert_eq!(observe(0), None);
+        assert_eq!(observe(250), Some((Sign::Outflow, Sign::Inflow)));
+    }
+
+    #[test]
+    fn flip_without_a_band_fires_on_every_crossing_of_zero() {
+        let mut flip = FlipDetector::new(Integer::new());
+        let mut observe = |net: i64| flip.observe(&Integer::from(net));
+        assert_eq!(observe(-1), None);
+        assert_eq!(observe(0), None);
+        assert_eq!(observe(1), Some((Sign::Outflow, Sign::Inflow)));
+        assert_eq!(observe(-1), Some((Sign::Inflow, Sign::Outflow)));
+    }
+}
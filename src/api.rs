@@ -1,72 +1,2718 @@
 
-use axum::{routing::get, Router, response::IntoResponse};
-use serde::Serialize;
+use axum::{
+    body::Body,
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, Request, State,
+    },
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, patch, post},
+    Router,
+};
+use ethers::types::Address;
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
+use rug::ops::Pow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
 use std::net::SocketAddr;
-use tracing::info;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{info, warn};
+use crate::blocks;
+use crate::db::{
+    remove_exchange_address, remove_neutral_address, save_confirmations, upsert_exchange_addresses, upsert_neutral_addresses,
+};
+use crate::env_template;
+use crate::error::ApiError;
+use crate::field_case::{camel_keys, recase_json, FieldCase};
+use crate::indexer::{
+    counters_at_block, exchange_labels, pruned_through, refresh_exchange_cache, resume_block, start_recompute_job,
+    unix_now, Indexer, IndexerStatus, NetflowUpdate, Sampling, StoredClassifier,
+};
+use crate::jobs;
+use crate::native::NativeMode;
+use crate::recent::{RecentColumns, RecentTransfers};
+use crate::reserves;
+use crate::ratelimit::{rate_limit, RateLimitCfg, RateLimiter};
+use crate::token_meta::TokenMeta;
+use crate::units::{checksum_str, format_fixed, format_units, parse_amount, to_checksum, DisplayCfg};
+
+/// A head within this many blocks past the confirmation depth counts as synced
+const SYNC_SLACK_BLOCKS: u64 = 5;
+
+#[derive(Clone)]
+pub struct ApiState {
+    pub db: SqlitePool,
+    pub status: Arc<IndexerStatus>,
+    pub tracked_tokens: usize,
+    pub started_at: Instant,
+    pub display: DisplayCfg,
+    /// Display symbol (override > on-chain > fallback) and decimals per tracked token
+    pub token_meta: HashMap<Address, TokenMeta>,
+    pub rate_limits: RateLimitCfg,
+    /// JSON key style of response bodies (`API_FIELD_CASE`)
+    pub field_case: FieldCase,
+    pub recent: Arc<RecentTransfers>,
+    pub sampling: Sampling,
+    /// Tolerance for flagging reserve vs netflow divergence
+    pub reserve_divergence_wei: rug::Integer,
+    /// Bearer token for `/admin/*`; admin routes are refused when unset
+    pub api_key: Option<String>,
+    /// Handle for admin jobs that run indexer-side logic (e.g. recompute)
+    pub indexer: Indexer,
+    /// Distinct exchanges needed before netflow is considered representative
+    pub min_exchanges: i64,
+    /// Cumulative totals per token when this process started, for `/netflow/session`
+    pub session: HashMap<Address, SessionBaseline>,
+    /// Checkpoint age beyond which `/netflow` is flagged `stale`
+    pub max_age: MaxNetflowAge,
+    /// Browser origins allowed on the public GET routes; no CORS headers when unset
+    pub cors_origins: Option<CorsOrigins>,
+    /// One permit per open `/ws/netflow` socket
+    pub ws_slots: Arc<Semaphore>,
+}
+
+impl ApiState {
+    /// The token a request is about: `?token=0x..` when given (it must be indexed),
+    /// else the first configured token.
+    fn token(&self, raw: Option<&str>) -> Result<Address, ApiError> {
+        let Some(raw) = raw.map(str::trim).filter(|s| !s.is_empty()) else {
+            return Ok(self.indexer.cfg.primary_token());
+        };
+        let token: Address = raw.parse()
+            .map_err(|_| ApiError::BadRequest(format!("invalid token address {raw:?}")))?;
+        if !self.indexer.cfg.tokens.contains(&token) {
+            return Err(ApiError::NotFound(format!("token {raw} is not indexed")));
+        }
+        Ok(token)
+    }
+
+    fn meta(&self, token: Address) -> TokenMeta {
+        self.token_meta.get(&token).cloned().unwrap_or_default()
+    }
+}
+
+/// Largest `?decimals=` accepted on `/netflow`
+const MAX_FIXED_DECIMALS: u32 = 36;
+
+#[derive(Deserialize)]
+struct NetflowQuery {
+    token: Option<String>,
+    /// Exactly this many fractional digits, rounded per ROUNDING
+    decimals: Option<u32>,
+    /// Also return the exact wei strings
+    #[serde(default)]
+    raw: bool,
+    /// Totals as of this block, recomputed from stored transfers
+    at_block: Option<u64>,
+}
+
+/// How `/netflow` renders amounts; the default is the configured `DisplayCfg`.
+#[derive(Clone, Copy, Default)]
+struct AmountFormat {
+    places: Option<u32>,
+    raw: bool,
+}
+
+impl AmountFormat {
+    fn from_query(q: &NetflowQuery) -> Result<Self, ApiError> {
+        if q.decimals.is_some_and(|d| d > MAX_FIXED_DECIMALS) {
+            return Err(ApiError::BadRequest(format!("decimals must be <= {MAX_FIXED_DECIMALS}")));
+        }
+        Ok(Self { places: q.decimals, raw: q.raw })
+    }
+}
+
+/// `?token=0x..` on routes that take nothing else
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// `MAX_NETFLOW_AGE_BLOCKS` / `MAX_NETFLOW_AGE_SECS`; either limit (when set) can mark data stale.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MaxNetflowAge {
+    /// Blocks the checkpoint may trail the head, beyond the confirmation depth
+    pub blocks: Option<u64>,
+    /// Seconds since the checkpoint last advanced
+    pub secs: Option<u64>,
+}
+
+/// Snapshot of one token's `netflow_state` row taken once at startup (memory only).
+#[derive(Clone, Debug)]
+pub struct SessionBaseline {
+    pub in_wei: rug::Integer,
+    pub out_wei: rug::Integer,
+    pub last_block: Option<i64>,
+    pub seq: i64,
+}
+
+impl SessionBaseline {
+    pub async fn load(db: &SqlitePool, token: Address) -> anyhow::Result<Self> {
+        let (in_wei, out_wei, last_block, seq) = sqlx::query_as::<_, (String, String, Option<i64>, i64)>(
+            "SELECT cumulative_in_wei, cumulative_out_wei, last_block, seq FROM netflow_state WHERE contract = ?;")
+            .bind(format!("{:#x}", token))
+            .fetch_one(db).await?;
+        Ok(Self {
+            in_wei: rug::Integer::from_str_radix(&in_wei, 10)?,
+            out_wei: rug::Integer::from_str_radix(&out_wei, 10)?,
+            last_block,
+            seq,
+        })
+    }
+}
 
 #[derive(Serialize)]
 struct NetflowOut {
+    /// Contract these totals belong to
+    token: String,
+    /// Exchange label the totals are scoped to (`/netflow/{exchange}` only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exchange: Option<String>,
+    symbol: String,
+    decimals: u8,
+    cumulative_in: String,
+    cumulative_out: String,
+    /// `in - out`, with a leading `-` when more left exchanges than arrived
+    cumulative_net: String,
+    /// The sign of the exact (unrounded) net, spelled out
+    direction: Direction,
+    /// `|cumulative_net|`
+    cumulative_net_abs: String,
+    /// Exchange flows set aside because the counterparty is a contract
+    excluded_contract_in: String,
+    excluded_contract_out: String,
+    /// Mints to / burns from exchange wallets (ZERO_ADDRESS_RULE=separate)
+    exchange_minted: String,
+    exchange_burned: String,
+    last_block: Option<i64>,
+    /// Increments by one on every netflow update; a gap means an update was missed
+    seq: i64,
+    coverage: CoverageOut,
+    /// The processed checkpoint is older than MAX_NETFLOW_AGE_BLOCKS/_SECS allow
+    stale: bool,
+    /// `cumulative_net` as a percentage of `totalSupply()` (TOTAL_SUPPLY_REFRESH_SECS)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    net_pct_of_supply: Option<String>,
+    /// `?raw=true`: the counters as stored, in base units
+    #[serde(flatten)]
+    raw: Option<RawCountersOut>,
+    /// `?at_block=N`: which block the totals are as of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    at: Option<AtBlockOut>,
+    /// `--dry-run`: what this process classified without storing it; the fields
+    /// above are the stored totals, untouched by the dry run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dry_run: Option<DryRunOut>,
+}
+
+#[derive(Serialize)]
+struct DryRunOut {
+    /// Always `false`: these totals exist only in this process's memory
+    persisted: bool,
+    cumulative_in: String,
+    cumulative_out: String,
+    cumulative_net: String,
+    /// Transfers counted since the dry run started
+    transfers: u64,
+    last_block: Option<i64>,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Direction {
+    /// Net inflow to exchanges
+    Inflow,
+    Outflow,
+    /// Net exactly zero
+    Neutral,
+}
+
+impl Direction {
+    fn of(net: &rug::Integer) -> Self {
+        match net.cmp0() {
+            std::cmp::Ordering::Greater => Self::Inflow,
+            std::cmp::Ordering::Less => Self::Outflow,
+            std::cmp::Ordering::Equal => Self::Neutral,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AtBlockOut {
+    requested_block: u64,
+    /// The requested block, or the newest indexed one when it is further ahead
+    block: u64,
+    clamped: bool,
+}
+
+#[derive(Serialize)]
+struct RawCountersOut {
+    cumulative_in_wei: String,
+    cumulative_out_wei: String,
+    cumulative_net_wei: String,
+    excluded_in_wei: String,
+    excluded_out_wei: String,
+    minted_wei: String,
+    burned_wei: String,
+}
+
+#[derive(Serialize)]
+struct CoverageOut {
+    /// Distinct canonical exchanges that have seen at least one flow
+    exchanges: i64,
+    min_exchanges: i64,
+    /// Fewer exchanges than MIN_EXCHANGES: netflow may not be representative yet
+    low_coverage: bool,
+}
+
+#[derive(Serialize)]
+struct SummaryOut {
+    netflow: NetflowOut,
+    head: Option<u64>,
+    last_processed_block: Option<u64>,
+    blocks_behind: Option<u64>,
+    synced: bool,
+    transfer_count: i64,
+    tracked_tokens: usize,
+    exchange_addresses: i64,
+    uptime_secs: u64,
+    /// Set when non-exchange transfers are sampled (netflow itself stays exact)
+    sampling: Option<SamplingOut>,
+    /// STRICT mode recorded at least `STRICT_DEGRADED_AFTER` questionable logs
+    degraded: bool,
+}
+
+/// Sync state for operators; `head` comes from the indexer's live view of the chain.
+#[derive(Serialize)]
+struct StatusOut {
+    token: String,
+    /// As recorded in `chain_meta` on the first connect
+    chain_id: Option<i64>,
+    confirmations: u64,
+    /// Last block folded into this token's `netflow_state`
+    last_block: Option<i64>,
+    head: Option<u64>,
+    /// `head - last_block`
+    lag: Option<u64>,
+    /// `lag` within CONFIRMATIONS + a few blocks of slack
+    synced: bool,
+    /// A `gaps` range the live stream skipped is still waiting to be backfilled
+    unresolved_gaps: bool,
+    /// Last `indexer_status` row, `null` before the first one was written
+    checkpoint: Option<CheckpointOut>,
+    throughput: ThroughputOut,
+}
+
+/// The periodically persisted head
+#[derive(Serialize)]
+struct CheckpointOut {
+    head: i64,
+    /// Every log up to here was processed; where the next start resumes at the latest
+    synced_block: Option<i64>,
+    /// Unix seconds
+    updated_at: i64,
+}
+
+/// Ingestion rates across all tokens
+#[derive(Serialize)]
+struct ThroughputOut {
+    /// Over the last `THROUGHPUT_LOG_SECS`; `null` until the first interval ends
+    transfers_per_sec: Option<f64>,
+    blocks_per_sec: Option<f64>,
+    /// Since this process started
+    avg_transfers_per_sec: f64,
+}
+
+#[derive(Serialize)]
+struct SamplingOut {
+    sampled: bool,
+    rate: f64,
+    by: &'static str,
+    /// Exchange transfers (exact) + stored non-exchange transfers scaled by 1/rate
+    transfer_count_estimate: i64,
+}
+
+#[derive(Deserialize)]
+struct WindowQuery {
+    token: Option<String>,
+    from_block: Option<i64>,
+    to_block: Option<i64>,
+    /// Window by block timestamp instead: the last N hours up to now
+    hours: Option<u32>,
+    /// Drop the N largest exchange transfers (by amount) before summing
+    #[serde(default)]
+    exclude_top: usize,
+}
+
+#[derive(Deserialize)]
+struct MissingTimestampsQuery {
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct MissingTimestampsOut {
+    /// All transfer blocks without a timestamp
+    missing: i64,
+    /// Oldest first, up to `limit`
+    blocks: Vec<i64>,
+}
+
+/// Most rows `/netflow/by-block` returns per request
+const MAX_BY_BLOCK_ROWS: i64 = 10_000;
+
+#[derive(Deserialize)]
+struct ByBlockQuery {
+    token: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct BlockFlowOut {
+    block_number: i64,
+    block_in: String,
+    block_out: String,
+    block_net: String,
+}
+
+/// Most buckets `/netflow/history` returns per request
+const MAX_HISTORY_BUCKETS: i64 = 10_000;
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    token: Option<String>,
+    /// Bucket width: a count and a unit, `m`, `h`, `d` or `w` (e.g. `15m`, `1h`, `1d`)
+    bucket: Option<String>,
+    /// Unix seconds; defaults to the last 24 buckets up to now
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct HistoryOut {
+    symbol: String,
+    decimals: u8,
+    bucket: String,
+    bucket_secs: i64,
+    /// `from` rounded down to a bucket boundary
+    from_ts: i64,
+    to_ts: i64,
+    buckets: Vec<BucketOut>,
+}
+
+#[derive(Serialize)]
+struct BucketOut {
+    /// Unix seconds, inclusive; the bucket ends at `start_ts + bucket_secs`
+    start_ts: i64,
+    bucket_in: String,
+    bucket_out: String,
+    bucket_net: String,
+    /// Net of every timestamped exchange transfer up to the end of this bucket
+    cumulative_net: String,
+}
+
+#[derive(Serialize)]
+struct LabelFlowOut {
+    label: String,
+    cumulative_in: String,
+    cumulative_out: String,
+    cumulative_net: String,
+}
+
+#[derive(Serialize)]
+struct ExchangeFlowOut {
+    exchange: String,
+    cumulative_in: String,
+    cumulative_out: String,
+    cumulative_net: String,
+    /// Raw labels rolled up into this canonical exchange
+    labels: Vec<LabelFlowOut>,
+}
+
+/// Most addresses `/exchanges/top` returns per request
+const MAX_TOP_ADDRESSES: i64 = 1000;
+
+#[derive(Deserialize)]
+struct TopQuery {
+    token: Option<String>,
+    /// Default 10
+    limit: Option<i64>,
+    /// By block timestamp, the last span up to now (`24h`, `7d`, ...); all history when unset
+    window: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TopOut {
+    symbol: String,
+    decimals: u8,
+    window: Option<String>,
+    /// Start of the window (unix seconds); `null` without one
+    from_ts: Option<i64>,
+    addresses: Vec<TopAddressOut>,
+}
+
+#[derive(Serialize)]
+struct TopAddressOut {
+    address: String,
+    exchange: String,
+    /// Everything sent plus everything received
+    volume: String,
+    transfers: i64,
+}
+
+/// One exchange label: how many addresses it has and its running totals.
+#[derive(Serialize)]
+struct ExchangeStatsOut {
+    exchange: String,
+    addresses: i64,
+    cumulative_in: String,
+    cumulative_out: String,
+    cumulative_net: String,
+    last_block: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct RawLogOut {
+    topics: Vec<String>,
+    data: String,
+}
+
+#[derive(Serialize)]
+struct TransferDetailOut {
+    tx_hash: String,
+    log_index: i64,
+    block_number: i64,
+    block_ts: Option<i64>,
+    contract: String,
+    from: String,
+    to: String,
+    amount_wei: String,
+    /// Present when the log was stored with STORE_RAW_LOGS
+    raw: Option<RawLogOut>,
+}
+
+#[derive(Deserialize)]
+struct ReserveQuery {
+    limit: Option<i64>,
+}
+
+/// Page size bounds of `/transfers`
+const TRANSFERS_DEFAULT_LIMIT: i64 = 100;
+const TRANSFERS_MAX_LIMIT: i64 = 1000;
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Deserialize)]
+struct TransfersQuery {
+    token: Option<String>,
+    limit: Option<i64>,
+    /// Exclusive block bounds
+    before_block: Option<i64>,
+    after_block: Option<i64>,
+    /// Matches either side of the transfer
+    address: Option<String>,
+    #[serde(default)]
+    order: SortOrder,
+    /// `next_cursor` of the previous page
+    cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TransferOut {
+    tx_hash: String,
+    log_index: i64,
+    block_number: i64,
+    /// Unix seconds; `null` while the block's timestamp is unknown
+    block_ts: Option<i64>,
+    from: String,
+    to: String,
+    amount_wei: String,
+}
+
+#[derive(Serialize)]
+struct TransfersPage {
+    transfers: Vec<TransferOut>,
+    /// Pass as `?cursor=` for the next page; absent on the last one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ExchangeIn {
+    address: String,
+    exchange: String,
+}
+
+#[derive(Deserialize)]
+struct NeutralIn {
+    address: String,
+    #[serde(default)]
+    note: String,
+}
+
+#[derive(Serialize)]
+struct NeutralOut {
+    address: String,
+    note: String,
+}
+
+#[derive(Serialize)]
+struct NeutralChangeOut {
+    address: String,
+    /// `false` after a delete
+    neutral: bool,
+    /// Background recompute reclassifying stored transfers, if one was started
+    recompute_job: Option<i64>,
+}
+
+/// Deepest `PATCH /config/confirmations` accepted; reorgs are only followed this far
+const MAX_CONFIRMATIONS: u64 = 1000;
+
+#[derive(Deserialize)]
+struct ConfirmationsIn {
+    confirmations: u64,
+}
+
+#[derive(Serialize)]
+struct ConfirmationsOut {
+    previous: u64,
+    confirmations: u64,
+    /// Staged logs the new depth makes final; the next promotion pass counts them
+    pending_now_final: i64,
+}
+
+#[derive(Serialize)]
+struct ExchangeChangeOut {
+    address: String,
+    /// Label now registered; `None` after a delete
+    exchange: Option<String>,
+    /// Background recompute reclassifying stored transfers, if one was started
+    recompute_job: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct RecentQuery {
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct WindowOut {
+    symbol: String,
+    decimals: u8,
+    from_block: i64,
+    to_block: i64,
+    transfers: usize,
+    excluded_top: usize,
+    window_in: String,
+    window_out: String,
+    window_net: String,
+    /// `?hours=` only: covered timestamp range, clamped to the earliest indexed transfer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    span: Option<TimeSpan>,
+}
+
+#[derive(Serialize)]
+struct TimeSpan {
+    hours: u32,
+    from_ts: i64,
+    to_ts: i64,
+    /// The window reaches back before the first indexed transfer
+    clamped: bool,
+    /// Transfers in the block range left out for lack of a stored `block_ts`
+    untimed: i64,
+}
+
+#[derive(Serialize)]
+struct SessionOut {
+    symbol: String,
+    decimals: u8,
+    started_at_block: Option<i64>,
+    uptime_secs: u64,
+    /// Netflow updates applied since startup
+    updates: i64,
+    session_in: String,
+    session_out: String,
+    session_net: String,
+    last_block: Option<i64>,
+}
+
+/// One exchange address's running balance, from indexed transfers only.
+#[derive(Serialize)]
+struct BalanceOut {
+    address: String,
+    exchange: String,
+    token: String,
+    symbol: String,
+    decimals: u8,
+    /// Negative when the wallet held funds before indexing started
+    balance: String,
+    balance_wei: String,
+    last_block: Option<i64>,
+}
+
+/// A `/ws/netflow` message: one token's cumulative totals after a change.
+#[derive(Serialize)]
+struct NetflowUpdateOut {
+    token: String,
+    symbol: String,
+    decimals: u8,
+    cumulative_in: String,
+    cumulative_out: String,
+    cumulative_net: String,
+    cumulative_in_wei: String,
+    cumulative_out_wei: String,
+    cumulative_net_wei: String,
+    last_block: Option<i64>,
+}
+
+/// Native POL exchange flow, a separate aggregate from the ERC-20 netflow.
+#[derive(Serialize)]
+struct NativeNetflowOut {
+    /// Always `native`, to tell it apart from the token netflow
+    asset: &'static str,
     symbol: &'static str,
     decimals: u8,
+    /// `tx` (top-level values only) or `trace` (includes internal sends)
+    source: &'static str,
     cumulative_in: String,
     cumulative_out: String,
     cumulative_net: String,
     last_block: Option<i64>,
+    seq: i64,
 }
 
-async fn netflow_handler(db: SqlitePool) -> impl IntoResponse {
-    let (in_wei, out_wei, last_block) = sqlx::query_as::<_, (String, String, Option<i64>)>(r#"
-        SELECT cumulative_in_wei, cumulative_out_wei, last_block FROM netflow_state WHERE id=1;
-    "#).fetch_one(&db).await.unwrap_or(("0".into(), "0".into(), None));
-
-    let in_int = rug::Integer::from_str_radix(&in_wei, 10).unwrap_or_default();
-    let out_int = rug::Integer::from_str_radix(&out_wei, 10).unwrap_or_default();
-    let net = &in_int - &out_int;
-
-    // Present as decimal POL with 18 decimals (configurable if desired)
-    let decimals: u32 = 18;
-    let fmt = |x: &rug::Integer| -> String {
-        let ten = rug::Integer::from(10);
-        let scale = ten.pow(decimals);
-        let (q, r) = x.clone().div_rem(scale);
-        if r == 0 {
-            format!("{}", q)
-        } else {
-            let mut frac = r.to_string_radix(10);
-            // pad leading zeros in fractional part
-            if frac.len() < decimals as usize {
-                let pad = (decimals as usize) - frac.len();
-                frac = "0".repeat(pad) + &frac;
-            }
-            // trim trailing zeros
-            while frac.ends_with('0') { frac.pop(); }
-            format!("{}.{}", q, frac)
+/// Strong ETag over the netflow state version. `seq` bumps on every update, so the
+/// tag changes exactly when the served numbers do; `stale` and the supply behind
+/// `net_pct_of_supply` are part of the body too.
+fn netflow_etag(seq: i64, last_block: Option<i64>, supply: Option<&rug::Integer>, stale: bool) -> String {
+    use std::hash::{Hash, Hasher};
+    let supply = supply.map_or(String::new(), |s| {
+        let mut h = std::collections::hash_map::DefaultHasher::new();
+        s.hash(&mut h);
+        format!("-{:x}", h.finish())
+    });
+    let suffix = if stale { "-stale" } else { "" };
+    format!("\"{}-{}{}{}\"", seq, last_block.unwrap_or(0), supply, suffix)
+}
+
+/// The `totalSupply()` `net_pct_of_supply` is taken against; only tracked for the
+/// primary token.
+fn current_supply(st: &ApiState, token: Address) -> Option<rug::Integer> {
+    if token != st.indexer.cfg.primary_token() {
+        return None;
+    }
+    st.indexer.supply.read().expect("supply lock poisoned").clone()
+}
+
+/// Whether the processed checkpoint is older than the configured limits. With a limit
+/// set, no checkpoint yet (nothing processed since startup) counts as stale.
+fn is_stale(st: &ApiState) -> bool {
+    let lim = st.max_age;
+    let behind = match (st.status.head(), st.status.last_processed_block()) {
+        (Some(h), Some(p)) => Some(h.saturating_sub(p).saturating_sub(st.indexer.confirmations())),
+        _ => None,
+    };
+    let by_blocks = lim.blocks.is_some_and(|max| !matches!(behind, Some(b) if b <= max));
+    let by_secs = lim.secs.is_some_and(|max| !matches!(st.status.processed_age_secs(), Some(a) if a <= max));
+    by_blocks || by_secs
+}
+
+/// RFC 7234 `Warning` sent along with `stale: true`
+const STALE_WARNING: &str = "110 - \"Response is Stale\"";
+
+async fn netflow_handler(
+    State(st): State<ApiState>,
+    headers: axum::http::HeaderMap,
+    q: Result<Query<NetflowQuery>, QueryRejection>,
+) -> Result<Response, ApiError> {
+    let Query(q) = q?;
+    let token = st.token(q.token.as_deref())?;
+    netflow_response(&st, &headers, token, None, AmountFormat::from_query(&q)?, q.at_block).await
+}
+
+/// `/netflow` scoped to one exchange label (`netflow_by_exchange`). A configured
+/// exchange without flow yet reads as zero; an unknown label is a 404.
+async fn exchange_netflow_handler(
+    State(st): State<ApiState>,
+    headers: axum::http::HeaderMap,
+    exchange: Result<Path<String>, PathRejection>,
+    q: Result<Query<NetflowQuery>, QueryRejection>,
+) -> Result<Response, ApiError> {
+    let Path(exchange) = exchange?;
+    let Query(q) = q?;
+    let token = st.token(q.token.as_deref())?;
+    let amounts = AmountFormat::from_query(&q)?;
+    let known = sqlx::query_scalar::<_, i64>("SELECT 1 FROM exchange_addresses WHERE exchange = ? LIMIT 1;")
+        .bind(&exchange)
+        .fetch_optional(&st.db).await?;
+    if known.is_none() {
+        return Err(ApiError::NotFound(format!("no exchange labelled {exchange:?}")));
+    }
+    netflow_response(&st, &headers, token, Some(&exchange), amounts, q.at_block).await
+}
+
+/// `(seq, last_block)` of the row `load_netflow` would read, for the ETag.
+async fn netflow_version(db: &SqlitePool, contract: &str, exchange: Option<&str>) -> Result<(i64, Option<i64>), ApiError> {
+    let version = match exchange {
+        None => sqlx::query_as::<_, (i64, Option<i64>)>("SELECT seq, last_block FROM netflow_state WHERE contract = ?;")
+            .bind(contract)
+            .fetch_one(db).await?,
+        Some(label) => sqlx::query_as::<_, (i64, Option<i64>)>(
+            "SELECT seq, last_block FROM netflow_by_exchange WHERE contract = ? AND exchange = ?;")
+            .bind(contract)
+            .bind(label)
+            .fetch_optional(db).await?
+            .unwrap_or((0, None)),
+    };
+    Ok(version)
+}
+
+async fn netflow_response(
+    st: &ApiState,
+    headers: &axum::http::HeaderMap,
+    token: Address,
+    exchange: Option<&str>,
+    amounts: AmountFormat,
+    at_block: Option<u64>,
+) -> Result<Response, ApiError> {
+    // Historical totals have no version to probe, and dry-run totals change without
+    // the stored one moving
+    if at_block.is_some() || st.indexer.dry_run.is_some() {
+        return Ok(axum::Json(load_netflow(st, token, exchange, amounts, at_block).await?).into_response());
+    }
+    // Cheap version probe first so unchanged polls skip the full load
+    let (seq, last_block) = netflow_version(&st.db, &format!("{:#x}", token), exchange).await?;
+    let etag = netflow_etag(seq, last_block, current_supply(st, token).as_ref(), is_stale(st));
+    let matches = headers.get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|t| t.trim() == etag || t.trim() == "*"));
+    if matches {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+    let out = load_netflow(st, token, exchange, amounts, None).await?;
+    let stale = out.stale;
+    let etag = netflow_etag(out.seq, out.last_block, current_supply(st, token).as_ref(), stale);
+    let mut resp = ([(header::ETAG, etag)], axum::Json(out)).into_response();
+    if stale {
+        resp.headers_mut().insert(header::WARNING, HeaderValue::from_static(STALE_WARNING));
+    }
+    Ok(resp)
+}
+
+/// Netflow accumulated since this process started: current totals minus the startup
+/// baseline. Resets on every restart.
+async fn session_handler(
+    State(st): State<ApiState>,
+    q: Result<Query<TokenQuery>, QueryRejection>,
+) -> Result<axum::Json<SessionOut>, ApiError> {
+    let Query(q) = q?;
+    let token = st.token(q.token.as_deref())?;
+    let now = SessionBaseline::load(&st.db, token).await?;
+    let base = st.session.get(&token)
+        .ok_or_else(|| ApiError::Internal(format!("no session baseline for {:#x}", token)))?;
+    let in_int = rug::Integer::from(&now.in_wei - &base.in_wei);
+    let out_int = rug::Integer::from(&now.out_wei - &base.out_wei);
+    let net = rug::Integer::from(&in_int - &out_int);
+    let meta = st.meta(token);
+    let fmt = |x: &rug::Integer| format_units(x, meta.decimals.into(), &st.display);
+    Ok(axum::Json(SessionOut {
+        decimals: meta.decimals,
+        symbol: meta.symbol.clone(),
+        started_at_block: base.last_block,
+        uptime_secs: st.started_at.elapsed().as_secs(),
+        updates: now.seq - base.seq,
+        session_in: fmt(&in_int),
+        session_out: fmt(&out_int),
+        session_net: fmt(&net),
+        last_block: now.last_block,
+    }))
+}
+
+async fn balance_handler(
+    State(st): State<ApiState>,
+    address: Result<Path<String>, PathRejection>,
+    q: Result<Query<TokenQuery>, QueryRejection>,
+) -> Result<axum::Json<BalanceOut>, ApiError> {
+    let Path(raw) = address?;
+    let Query(q) = q?;
+    let token = st.token(q.token.as_deref())?;
+    let address = parse_address(&raw)?;
+    let exchange = sqlx::query_scalar::<_, String>("SELECT exchange FROM exchange_addresses WHERE address = ?;")
+        .bind(&address)
+        .fetch_optional(&st.db).await?
+        .ok_or_else(|| ApiError::NotFound(format!("{raw} is not an exchange address")))?;
+    // No row yet: no transfer has touched it
+    let (balance_wei, last_block) = sqlx::query_as::<_, (String, Option<i64>)>(
+        "SELECT balance_wei, last_block FROM address_balances WHERE contract = ? AND address = ?;")
+        .bind(format!("{:#x}", token))
+        .bind(&address)
+        .fetch_optional(&st.db).await?
+        .unwrap_or_else(|| ("0".into(), None));
+    // Signed, so not parse_amount
+    let balance = rug::Integer::from_str_radix(&balance_wei, 10)?;
+    let meta = st.meta(token);
+    Ok(axum::Json(BalanceOut {
+        address: checksum_str(&address),
+        exchange,
+        token: to_checksum(&token),
+        symbol: meta.symbol.clone(),
+        decimals: meta.decimals,
+        balance: format_units(&balance, meta.decimals.into(), &st.display),
+        balance_wei,
+        last_block,
+    }))
+}
+
+/// Upgrade to a WebSocket streaming `?token=`'s totals. Refused with 503 once
+/// `WS_MAX_SUBSCRIBERS` sockets are open.
+async fn ws_netflow_handler(
+    State(st): State<ApiState>,
+    ws: WebSocketUpgrade,
+    q: Result<Query<TokenQuery>, QueryRejection>,
+) -> Result<Response, ApiError> {
+    let Query(q) = q?;
+    let token = st.token(q.token.as_deref())?;
+    let permit = st.ws_slots.clone().try_acquire_owned()
+        .map_err(|_| ApiError::Unavailable("too many /ws/netflow subscribers".into()))?;
+    let updates = st.indexer.updates.subscribe();
+    Ok(ws.on_upgrade(move |socket| stream_netflow(st, socket, token, updates, permit)))
+}
+
+/// Send the current totals, then every update for `token` until either side closes.
+/// A client too slow to keep up misses the updates it fell behind on. The permit is
+/// released when this returns.
+async fn stream_netflow(
+    st: ApiState,
+    mut socket: WebSocket,
+    token: Address,
+    mut updates: broadcast::Receiver<NetflowUpdate>,
+    _permit: OwnedSemaphorePermit,
+) {
+    let current = async {
+        let (in_wei, out_wei, last_block) = sqlx::query_as::<_, (String, String, Option<i64>)>(
+            "SELECT cumulative_in_wei, cumulative_out_wei, last_block FROM netflow_state WHERE contract = ?;")
+            .bind(format!("{:#x}", token))
+            .fetch_one(&st.db).await?;
+        let in_wei = rug::Integer::from_str_radix(&in_wei, 10)?;
+        let out_wei = rug::Integer::from_str_radix(&out_wei, 10)?;
+        Ok::<_, ApiError>(update_out(&st, token, &in_wei, &out_wei, last_block))
+    }.await;
+    let mut next = match current {
+        Ok(out) => Some(out),
+        Err(e) => {
+            warn!("/ws/netflow: cannot read current totals: {e:?}");
+            return;
         }
     };
+    loop {
+        if let Some(out) = next.take() {
+            let mut body = serde_json::to_value(out).expect("update serializes");
+            if st.field_case == FieldCase::Camel {
+                body = camel_keys(body);
+            }
+            if socket.send(Message::Text(body.to_string())).await.is_err() {
+                return;
+            }
+        }
+        tokio::select! {
+            update = updates.recv() => match update {
+                Ok(u) if u.contract == token => {
+                    next = Some(update_out(&st, token, &u.in_wei, &u.out_wei, Some(u.block)));
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            },
+            // Only closing matters; pings are answered by axum and other frames are ignored
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+fn update_out(st: &ApiState, token: Address, in_wei: &rug::Integer, out_wei: &rug::Integer, last_block: Option<i64>) -> NetflowUpdateOut {
+    let net = rug::Integer::from(in_wei - out_wei);
+    let meta = st.meta(token);
+    let fmt = |x: &rug::Integer| format_units(x, meta.decimals.into(), &st.display);
+    NetflowUpdateOut {
+        token: to_checksum(&token),
+        symbol: meta.symbol.clone(),
+        decimals: meta.decimals,
+        cumulative_in: fmt(in_wei),
+        cumulative_out: fmt(out_wei),
+        cumulative_net: fmt(&net),
+        cumulative_in_wei: in_wei.to_string(),
+        cumulative_out_wei: out_wei.to_string(),
+        cumulative_net_wei: net.to_string(),
+        last_block,
+    }
+}
 
-    let out = NetflowOut {
+async fn native_handler(State(st): State<ApiState>) -> Result<axum::Json<NativeNetflowOut>, ApiError> {
+    let mode = st.indexer.cfg.native;
+    if mode == NativeMode::Off {
+        return Err(ApiError::NotFound("native flow tracking is disabled (set NATIVE_FLOW)".into()));
+    }
+    let (in_wei, out_wei, last_block, seq) = sqlx::query_as::<_, (String, String, Option<i64>, i64)>(
+        "SELECT cumulative_in_wei, cumulative_out_wei, last_block, seq FROM native_netflow_state WHERE id=1;")
+        .fetch_one(&st.db).await?;
+    let in_int = parse_amount(&in_wei)?;
+    let out_int = parse_amount(&out_wei)?;
+    let net = rug::Integer::from(&in_int - &out_int);
+    let fmt = |x: &rug::Integer| format_units(x, 18, &st.display);
+    Ok(axum::Json(NativeNetflowOut {
+        asset: "native",
         symbol: "POL",
         decimals: 18,
+        source: mode.as_str(),
         cumulative_in: fmt(&in_int),
         cumulative_out: fmt(&out_int),
         cumulative_net: fmt(&net),
         last_block,
+        seq,
+    }))
+}
+
+/// One-call overview for dashboards (netflow of the first configured token); the
+/// individual endpoints stay for detail.
+async fn summary_handler(State(st): State<ApiState>) -> Result<axum::Json<SummaryOut>, ApiError> {
+    let netflow = load_netflow(&st, st.indexer.cfg.primary_token(), None, AmountFormat::default(), None).await?;
+    let head = st.status.head();
+    let last_processed_block = st.status.last_processed_block();
+    let blocks_behind = match (head, last_processed_block) {
+        (Some(h), Some(p)) => Some(h.saturating_sub(p)),
+        _ => None,
+    };
+    let synced = blocks_behind.is_some_and(|b| b <= st.indexer.confirmations() + SYNC_SLACK_BLOCKS);
+
+    let transfer_count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM erc20_transfers;")
+        .fetch_one(&st.db).await?;
+    let exchange_addresses = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM exchange_addresses;")
+        .fetch_one(&st.db).await?;
+
+    let sampling = if st.sampling.enabled() {
+        let exchange_transfers = sqlx::query_scalar::<_, i64>(r#"
+            SELECT COUNT(*) FROM erc20_transfers t
+            WHERE EXISTS(SELECT 1 FROM exchange_addresses e WHERE e.address = t."to")
+               OR EXISTS(SELECT 1 FROM exchange_addresses e WHERE e.address = t."from");
+        "#).fetch_one(&st.db).await?;
+        let others = (transfer_count - exchange_transfers) as f64 / st.sampling.rate;
+        Some(SamplingOut {
+            sampled: true,
+            rate: st.sampling.rate,
+            by: if st.sampling.by_block { "block" } else { "transfer" },
+            transfer_count_estimate: exchange_transfers + others.round() as i64,
+        })
+    } else {
+        None
+    };
+
+    Ok(axum::Json(SummaryOut {
+        sampling,
+        netflow,
+        head,
+        last_processed_block,
+        blocks_behind,
+        synced,
+        transfer_count,
+        tracked_tokens: st.tracked_tokens,
+        exchange_addresses,
+        uptime_secs: st.started_at.elapsed().as_secs(),
+        degraded: st.status.degraded(),
+    }))
+}
+
+/// Liveness/readiness: the database answers. Says nothing about the chain (see `/status`).
+async fn healthz_handler(State(st): State<ApiState>) -> Result<axum::Json<serde_json::Value>, ApiError> {
+    sqlx::query("SELECT 1;").execute(&st.db).await
+        .map_err(|e| ApiError::Unavailable(format!("database unreachable: {e}")))?;
+    Ok(axum::Json(serde_json::json!({ "status": "ok" })))
+}
+
+async fn status_handler(
+    State(st): State<ApiState>,
+    q: Result<Query<TokenQuery>, QueryRejection>,
+) -> Result<axum::Json<StatusOut>, ApiError> {
+    let Query(q) = q?;
+    let token = st.token(q.token.as_deref())?;
+    let contract = format!("{:#x}", token);
+    let (_, last_block) = netflow_version(&st.db, &contract, None).await?;
+    let checkpoint = sqlx::query_as::<_, (i64, Option<i64>, i64)>(
+        "SELECT head, NULLIF(synced_block, 0), updated_at FROM indexer_status WHERE id = 1;")
+        .fetch_optional(&st.db).await?
+        .map(|(head, synced_block, updated_at)| CheckpointOut { head, synced_block, updated_at });
+    // Before this process has seen a head (or without a live indexer), the persisted one
+    let head = st.status.head().or_else(|| checkpoint.as_ref().map(|c| c.head.max(0) as u64));
+    let lag = match (head, last_block) {
+        (Some(h), Some(b)) => Some(h.saturating_sub(b.max(0) as u64)),
+        _ => None,
+    };
+    let chain_id = sqlx::query_scalar::<_, i64>("SELECT chain_id FROM chain_meta WHERE id = 1;")
+        .fetch_optional(&st.db).await?;
+    let unresolved_gaps: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM gaps WHERE healed_at IS NULL);")
+        .fetch_one(&st.db).await?;
+    Ok(axum::Json(StatusOut {
+        token: to_checksum(&token),
+        chain_id,
+        confirmations: st.indexer.confirmations(),
+        last_block,
+        head,
+        lag,
+        synced: lag.is_some_and(|l| l <= st.indexer.confirmations() + SYNC_SLACK_BLOCKS),
+        unresolved_gaps,
+        checkpoint,
+        throughput: throughput(&st),
+    }))
+}
+
+fn throughput(st: &ApiState) -> ThroughputOut {
+    let rates = st.status.rates();
+    let uptime = st.started_at.elapsed().as_secs_f64().max(1.0);
+    let round = |x: f64| (x * 100.0).round() / 100.0;
+    ThroughputOut {
+        transfers_per_sec: rates.map(|r| round(r.0)),
+        blocks_per_sec: rates.map(|r| round(r.1)),
+        avg_transfers_per_sec: round(st.status.transfers_processed.load(Ordering::Relaxed) as f64 / uptime),
+    }
+}
+
+/// Netflow over a block range, recomputed from `erc20_transfers` against the current
+/// exchange set. `exclude_top` only exists here: the all-time cumulative is a running
+/// total and cannot drop individual transfers.
+async fn window_handler(
+    State(st): State<ApiState>,
+    q: Result<Query<WindowQuery>, QueryRejection>,
+) -> Result<axum::Json<WindowOut>, ApiError> {
+    let Query(q) = q?;
+    let token = st.token(q.token.as_deref())?;
+    let contract = format!("{:#x}", token);
+    let (from_block, to_block, span) = match q.hours {
+        None => (q.from_block.unwrap_or(0), q.to_block.unwrap_or(i64::MAX), None),
+        Some(_) if q.from_block.is_some() || q.to_block.is_some() => {
+            return Err(ApiError::BadRequest("hours cannot be combined with from_block/to_block".into()));
+        }
+        Some(0) => return Err(ApiError::BadRequest("hours must be > 0".into())),
+        Some(hours) => {
+            let (from_block, to_block, span) = time_window(&st.db, &contract, hours).await?;
+            (from_block, to_block, Some(span))
+        }
+    };
+    if from_block > to_block {
+        return Err(ApiError::BadRequest("from_block must be <= to_block".into()));
+    }
+
+    // With `hours`, transfers in blocks without a stored timestamp can't be placed and are left out
+    let since = span.as_ref().map(|s| s.from_ts);
+    let rows = sqlx::query_as::<_, (String, String, String)>(r#"
+        SELECT t.amount_wei, t."from", t."to"
+        FROM erc20_transfers t
+        WHERE t.contract = ? AND t.block_number BETWEEN ? AND ?
+          AND (?4 IS NULL OR t.block_ts >= ?4)
+          AND EXISTS(SELECT 1 FROM exchange_addresses e WHERE e.address IN (t."from", t."to"));
+    "#)
+        .bind(&contract)
+        .bind(from_block)
+        .bind(to_block)
+        .bind(since)
+        .fetch_all(&st.db).await?;
+
+    // Same rules as the stored counters; excluded, minted and burned amounts aren't flow
+    let exchanges = exchange_labels(&st.db).await?;
+    let mut flows = Vec::with_capacity(rows.len());
+    {
+        let classifier = StoredClassifier::new(&st.indexer, &exchanges);
+        for (amt, from, to) in &rows {
+            let Some(c) = classifier.classify(from, to, amt)? else { continue };
+            if c.flow.inflow || c.flow.outflow {
+                flows.push((parse_amount(amt)?, c.flow.inflow, c.flow.outflow));
+            }
+        }
+    }
+
+    // Largest first, then skip the top N
+    flows.sort_by(|a, b| b.0.cmp(&a.0));
+    let excluded_top = q.exclude_top.min(flows.len());
+    let mut in_int = rug::Integer::new();
+    let mut out_int = rug::Integer::new();
+    for (amt, to_ex, from_ex) in &flows[excluded_top..] {
+        if *to_ex { in_int += amt; }
+        if *from_ex { out_int += amt; }
+    }
+    let net = rug::Integer::from(&in_int - &out_int);
+
+    let meta = st.meta(token);
+    let fmt = |x: &rug::Integer| format_units(x, meta.decimals.into(), &st.display);
+    Ok(axum::Json(WindowOut {
+        decimals: meta.decimals,
+        symbol: meta.symbol.clone(),
+        from_block,
+        to_block,
+        transfers: flows.len() - excluded_top,
+        excluded_top,
+        window_in: fmt(&in_int),
+        window_out: fmt(&out_int),
+        window_net: fmt(&net),
+        span,
+    }))
+}
+
+/// Block range and timestamps covered by "the last `hours` hours", from the transfers'
+/// own `block_ts`: from the first transfer at or after the cutoff (the cutoff is clamped
+/// to the first timestamped transfer, when that is later) to the checkpoint.
+async fn time_window(db: &SqlitePool, contract: &str, hours: u32) -> Result<(i64, i64, TimeSpan), ApiError> {
+    let now = unix_now() as i64;
+    let cutoff = now - i64::from(hours) * 3600;
+    let earliest = sqlx::query_scalar::<_, i64>(r#"
+        SELECT block_ts FROM erc20_transfers
+        WHERE contract = ? AND block_ts IS NOT NULL ORDER BY block_number LIMIT 1;
+    "#)
+        .bind(contract)
+        .fetch_optional(db).await?;
+    let from_ts = earliest.map_or(cutoff, |e| e.max(cutoff));
+    let (_, checkpoint) = netflow_version(db, contract, None).await?;
+    let to_block = checkpoint.unwrap_or(0);
+    let from_block = sqlx::query_scalar::<_, Option<i64>>(
+        "SELECT MIN(block_number) FROM erc20_transfers WHERE contract = ? AND block_ts >= ?;")
+        .bind(contract)
+        .bind(from_ts)
+        .fetch_one(db).await?
+        .map_or(to_block, |b| b.min(to_block));
+    let untimed = sqlx::query_scalar::<_, i64>(r#"
+        SELECT COUNT(*) FROM erc20_transfers
+        WHERE contract = ? AND block_number BETWEEN ? AND ? AND block_ts IS NULL;
+    "#)
+        .bind(contract)
+        .bind(from_block)
+        .bind(to_block)
+        .fetch_one(db).await?;
+    Ok((from_block, to_block, TimeSpan {
+        hours,
+        from_ts,
+        to_ts: now,
+        clamped: earliest.map_or(true, |e| e > cutoff),
+        untimed,
+    }))
+}
+
+/// Per-block exchange flow from `netflow_by_block` (only blocks that had any), oldest
+/// first, capped at `MAX_BY_BLOCK_ROWS`. 404 unless `NETFLOW_BY_BLOCK` is on.
+async fn by_block_handler(
+    State(st): State<ApiState>,
+    q: Result<Query<ByBlockQuery>, QueryRejection>,
+) -> Result<axum::Json<Vec<BlockFlowOut>>, ApiError> {
+    let Query(q) = q?;
+    if !st.indexer.cfg.netflow_by_block {
+        return Err(ApiError::NotFound("per-block netflow disabled: set NETFLOW_BY_BLOCK=true".into()));
+    }
+    let token = st.token(q.token.as_deref())?;
+    let from = q.from.unwrap_or(0);
+    let to = q.to.unwrap_or(i64::MAX);
+    if from > to {
+        return Err(ApiError::BadRequest("from must be <= to".into()));
+    }
+    let rows = sqlx::query_as::<_, (i64, String, String)>(r#"
+        SELECT block_number, in_wei, out_wei FROM netflow_by_block
+        WHERE contract = ? AND block_number BETWEEN ? AND ?
+        ORDER BY block_number LIMIT ?;
+    "#)
+        .bind(format!("{:#x}", token))
+        .bind(from)
+        .bind(to)
+        .bind(MAX_BY_BLOCK_ROWS)
+        .fetch_all(&st.db).await?;
+
+    let decimals = st.meta(token).decimals.into();
+    let fmt = |x: &rug::Integer| format_units(x, decimals, &st.display);
+    rows.into_iter().map(|(block_number, in_wei, out_wei)| {
+        let in_int = rug::Integer::from_str_radix(&in_wei, 10)?;
+        let out_int = rug::Integer::from_str_radix(&out_wei, 10)?;
+        Ok(BlockFlowOut {
+            block_number,
+            block_net: fmt(&rug::Integer::from(&in_int - &out_int)),
+            block_in: fmt(&in_int),
+            block_out: fmt(&out_int),
+        })
+    })
+        .collect::<Result<Vec<_>, ApiError>>()
+        .map(axum::Json)
+}
+
+/// `15m`, `1h`, `1d`, `1w` -> seconds; `param` names the query parameter in errors.
+fn parse_span(param: &str, raw: &str) -> Result<i64, ApiError> {
+    let raw = raw.trim();
+    let bad = || ApiError::BadRequest(format!("invalid {param} {raw:?} (e.g. 15m, 1h, 1d, 1w)"));
+    let split = raw.find(|c: char| !c.is_ascii_digit()).ok_or_else(bad)?;
+    let (n, unit) = raw.split_at(split);
+    let unit_secs = match unit {
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        "w" => 604_800,
+        _ => return Err(bad()),
+    };
+    match n.parse::<i64>() {
+        Ok(n) if n > 0 => n.checked_mul(unit_secs).ok_or_else(bad),
+        _ => Err(bad()),
+    }
+}
+
+/// Exchange netflow per time bucket between `from` and `to`, recomputed from
+/// `erc20_transfers` by block timestamp with the counters' classification, with a running
+/// net carried in from everything before `from`. Buckets without flow are included as
+/// zeros. Transfers in blocks without a stored timestamp can't be placed and are left out.
+async fn history_handler(
+    State(st): State<ApiState>,
+    q: Result<Query<HistoryQuery>, QueryRejection>,
+) -> Result<axum::Json<HistoryOut>, ApiError> {
+    let Query(q) = q?;
+    let token = st.token(q.token.as_deref())?;
+    let bucket = q.bucket.unwrap_or_else(|| "1h".to_string());
+    let width = parse_span("bucket", &bucket)?;
+    let to_ts = q.to.unwrap_or(unix_now() as i64);
+    let out_of_range = || ApiError::BadRequest("from/to out of range for this bucket".into());
+    let from_ts = q.from.unwrap_or(to_ts.saturating_sub(width.saturating_mul(24)))
+        .div_euclid(width)
+        .checked_mul(width)
+        .ok_or_else(out_of_range)?;
+    if from_ts > to_ts {
+        return Err(ApiError::BadRequest("from must be <= to".into()));
+    }
+    let count = to_ts.saturating_sub(from_ts) / width + 1;
+    if count > MAX_HISTORY_BUCKETS {
+        return Err(ApiError::BadRequest(format!(
+            "{count} buckets requested, at most {MAX_HISTORY_BUCKETS}; use a wider bucket or a shorter range"
+        )));
+    }
+    let end_ts = count.checked_mul(width).and_then(|w| from_ts.checked_add(w)).ok_or_else(out_of_range)?;
+
+    let rows = sqlx::query_as::<_, (i64, String, String, String)>(r#"
+        SELECT t.block_ts, t.amount_wei, t."from", t."to"
+        FROM erc20_transfers t
+        WHERE t.contract = ? AND t.block_ts < ?
+          AND EXISTS(SELECT 1 FROM exchange_addresses e WHERE e.address IN (t."from", t."to"));
+    "#)
+        .bind(format!("{:#x}", token))
+        .bind(end_ts)
+        .fetch_all(&st.db).await?;
+
+    // Same rules as the stored counters; excluded, minted and burned amounts aren't flow
+    let exchanges = exchange_labels(&st.db).await?;
+    let mut carried = rug::Integer::new();
+    let mut sums = vec![(rug::Integer::new(), rug::Integer::new()); count as usize];
+    {
+        let classifier = StoredClassifier::new(&st.indexer, &exchanges);
+        for (ts, amt, from, to) in &rows {
+            let Some(c) = classifier.classify(from, to, amt)? else { continue };
+            let (to_ex, from_ex) = (c.flow.inflow, c.flow.outflow);
+            if !(to_ex || from_ex) {
+                continue;
+            }
+            let amt = parse_amount(amt)?;
+            if *ts < from_ts {
+                if to_ex { carried += &amt; }
+                if from_ex { carried -= &amt; }
+                continue;
+            }
+            let (in_int, out_int) = &mut sums[((ts - from_ts) / width) as usize];
+            if to_ex { *in_int += &amt; }
+            if from_ex { *out_int += &amt; }
+        }
+    }
+
+    let meta = st.meta(token);
+    let fmt = |x: &rug::Integer| format_units(x, meta.decimals.into(), &st.display);
+    let mut cumulative = carried;
+    let buckets = sums.into_iter().enumerate().map(|(i, (in_int, out_int))| {
+        let net = rug::Integer::from(&in_int - &out_int);
+        cumulative += &net;
+        BucketOut {
+            start_ts: from_ts + i as i64 * width,
+            bucket_in: fmt(&in_int),
+            bucket_out: fmt(&out_int),
+            bucket_net: fmt(&net),
+            cumulative_net: fmt(&cumulative),
+        }
+    }).collect();
+    Ok(axum::Json(HistoryOut {
+        symbol: meta.symbol.clone(),
+        decimals: meta.decimals,
+        bucket,
+        bucket_secs: width,
+        from_ts,
+        to_ts,
+        buckets,
+    }))
+}
+
+/// Netflow grouped by canonical exchange (via `exchange_aliases`), with the
+/// per-raw-label breakdown preserved. Read from the `netflow_by_exchange` counters, so
+/// it sums to `/netflow`.
+async fn by_exchange_handler(
+    State(st): State<ApiState>,
+    q: Result<Query<TokenQuery>, QueryRejection>,
+) -> Result<axum::Json<Vec<ExchangeFlowOut>>, ApiError> {
+    let Query(q) = q?;
+    let token = st.token(q.token.as_deref())?;
+    let rows = sqlx::query_as::<_, (String, String, String, String)>(r#"
+        SELECT n.exchange, COALESCE(a.canonical, n.exchange), n.cumulative_in_wei, n.cumulative_out_wei
+        FROM netflow_by_exchange n
+        LEFT JOIN exchange_aliases a ON a.label = n.exchange
+        WHERE n.contract = ?;
+    "#)
+        .bind(format!("{:#x}", token))
+        .fetch_all(&st.db).await?;
+
+    // canonical -> label -> (in, out)
+    let mut groups: BTreeMap<String, BTreeMap<String, (rug::Integer, rug::Integer)>> = BTreeMap::new();
+    for (label, canonical, in_wei, out_wei) in rows {
+        groups.entry(canonical).or_default().insert(label, (parse_amount(&in_wei)?, parse_amount(&out_wei)?));
+    }
+
+    let decimals = st.meta(token).decimals.into();
+    let fmt = |x: &rug::Integer| format_units(x, decimals, &st.display);
+    let out = groups.into_iter().map(|(exchange, labels)| {
+        let mut total_in = rug::Integer::new();
+        let mut total_out = rug::Integer::new();
+        let labels = labels.into_iter().map(|(label, (in_int, out_int))| {
+            total_in += &in_int;
+            total_out += &out_int;
+            LabelFlowOut {
+                label,
+                cumulative_net: fmt(&rug::Integer::from(&in_int - &out_int)),
+                cumulative_in: fmt(&in_int),
+                cumulative_out: fmt(&out_int),
+            }
+        }).collect();
+        ExchangeFlowOut {
+            exchange,
+            cumulative_net: fmt(&rug::Integer::from(&total_in - &total_out)),
+            cumulative_in: fmt(&total_in),
+            cumulative_out: fmt(&total_out),
+            labels,
+        }
+    }).collect();
+    Ok(axum::Json(out))
+}
+
+/// Exchange addresses by transfer volume (sent plus received) for `?token=`, highest
+/// first, over `?window=` by block timestamp or all indexed history. A transfer
+/// between two exchange addresses counts for both; transfers in blocks without a
+/// stored timestamp drop out of windowed results.
+async fn top_exchanges_handler(
+    State(st): State<ApiState>,
+    q: Result<Query<TopQuery>, QueryRejection>,
+) -> Result<axum::Json<TopOut>, ApiError> {
+    let Query(q) = q?;
+    let token = st.token(q.token.as_deref())?;
+    let limit = q.limit.unwrap_or(10);
+    if !(1..=MAX_TOP_ADDRESSES).contains(&limit) {
+        return Err(ApiError::BadRequest(format!("limit must be between 1 and {MAX_TOP_ADDRESSES}")));
+    }
+    let from_ts = match q.window.as_deref() {
+        Some(w) => Some((unix_now() as i64).saturating_sub(parse_span("window", w)?)),
+        None => None,
+    };
+    let rows = sqlx::query_as::<_, (String, String, String)>(r#"
+        SELECT e.address, e.exchange, t.amount_wei
+        FROM erc20_transfers t JOIN exchange_addresses e ON e.address = t."to"
+        WHERE t.contract = ?1 AND (?2 IS NULL OR t.block_ts >= ?2)
+        UNION ALL
+        SELECT e.address, e.exchange, t.amount_wei
+        FROM erc20_transfers t JOIN exchange_addresses e ON e.address = t."from"
+        WHERE t.contract = ?1 AND (?2 IS NULL OR t.block_ts >= ?2) AND t."to" != t."from";
+    "#)
+        .bind(format!("{:#x}", token))
+        .bind(from_ts)
+        .fetch_all(&st.db).await?;
+
+    // address -> (label, volume, transfers)
+    let mut volumes: HashMap<String, (String, rug::Integer, i64)> = HashMap::new();
+    for (address, label, amt) in rows {
+        let amt = parse_amount(&amt)?;
+        let (_, volume, transfers) = volumes.entry(address).or_insert_with(|| (label, rug::Integer::new(), 0));
+        *volume += amt;
+        *transfers += 1;
+    }
+    let mut top: Vec<_> = volumes.into_iter().collect();
+    top.sort_by(|(a, (_, a_vol, _)), (b, (_, b_vol, _))| b_vol.cmp(a_vol).then_with(|| a.cmp(b)));
+    top.truncate(limit as usize);
+
+    let meta = st.meta(token);
+    Ok(axum::Json(TopOut {
+        symbol: meta.symbol.clone(),
+        decimals: meta.decimals,
+        window: q.window,
+        from_ts,
+        addresses: top.into_iter().map(|(address, (exchange, volume, transfers))| TopAddressOut {
+            address,
+            exchange,
+            volume: format_units(&volume, meta.decimals.into(), &st.display),
+            transfers,
+        }).collect(),
+    }))
+}
+
+/// Every exchange label with its address count and `netflow_by_exchange` totals for
+/// `?token=`, largest net inflow first. Labels without flow yet read as zero.
+async fn exchanges_handler(
+    State(st): State<ApiState>,
+    q: Result<Query<TokenQuery>, QueryRejection>,
+) -> Result<axum::Json<Vec<ExchangeStatsOut>>, ApiError> {
+    let Query(q) = q?;
+    let token = st.token(q.token.as_deref())?;
+    let rows = sqlx::query_as::<_, (String, i64, Option<String>, Option<String>, Option<i64>)>(r#"
+        SELECT e.exchange, e.addresses, n.cumulative_in_wei, n.cumulative_out_wei, n.last_block
+        FROM (SELECT exchange, COUNT(*) AS addresses FROM exchange_addresses GROUP BY exchange) e
+        LEFT JOIN netflow_by_exchange n ON n.exchange = e.exchange AND n.contract = ?;
+    "#)
+        .bind(format!("{:#x}", token))
+        .fetch_all(&st.db).await?;
+
+    let mut stats = rows.into_iter().map(|(exchange, addresses, in_wei, out_wei, last_block)| {
+        let in_int = parse_amount(in_wei.as_deref().unwrap_or("0"))?;
+        let out_int = parse_amount(out_wei.as_deref().unwrap_or("0"))?;
+        let net = rug::Integer::from(&in_int - &out_int);
+        Ok((exchange, addresses, in_int, out_int, net, last_block))
+    }).collect::<Result<Vec<_>, ApiError>>()?;
+    stats.sort_by(|a, b| b.4.cmp(&a.4).then_with(|| a.0.cmp(&b.0)));
+
+    let decimals = st.meta(token).decimals.into();
+    let fmt = |x: &rug::Integer| format_units(x, decimals, &st.display);
+    Ok(axum::Json(stats.into_iter().map(|(exchange, addresses, in_int, out_int, net, last_block)| ExchangeStatsOut {
+        exchange,
+        addresses,
+        cumulative_net: fmt(&net),
+        cumulative_in: fmt(&in_int),
+        cumulative_out: fmt(&out_int),
+        last_block,
+    }).collect()))
+}
+
+/// All transfers in one transaction, with the raw log when it was stored.
+async fn transfer_handler(
+    State(st): State<ApiState>,
+    tx_hash: Result<Path<String>, PathRejection>,
+) -> Result<axum::Json<Vec<TransferDetailOut>>, ApiError> {
+    let Path(tx_hash) = tx_hash?;
+    let rows = sqlx::query_as::<_, (String, i64, i64, Option<i64>, String, String, String, String, Option<String>, Option<String>)>(r#"
+        SELECT t.tx_hash, t.log_index, t.block_number, t.block_ts, t.contract, t."from", t."to", t.amount_wei,
+               r.topics, r.data_hex
+        FROM erc20_transfers t
+        LEFT JOIN erc20_transfer_raw r ON r.tx_hash = t.tx_hash AND r.log_index = t.log_index
+        WHERE t.tx_hash = ?
+        ORDER BY t.log_index;
+    "#)
+        .bind(tx_hash.to_lowercase())
+        .fetch_all(&st.db).await?;
+    if rows.is_empty() {
+        return Err(ApiError::NotFound(format!("no transfers for {tx_hash}")));
+    }
+
+    rows.into_iter().map(|(tx_hash, log_index, block_number, block_ts, contract, from, to, amount_wei, topics, data)| {
+        let raw = match (topics, data) {
+            (Some(topics), Some(data)) => Some(RawLogOut {
+                topics: serde_json::from_str(&topics)
+                    .map_err(|e| ApiError::Internal(format!("corrupt raw topics: {e}")))?,
+                data,
+            }),
+            _ => None,
+        };
+        Ok(TransferDetailOut {
+            tx_hash,
+            log_index,
+            block_number,
+            block_ts,
+            contract: checksum_str(&contract),
+            from: checksum_str(&from),
+            to: checksum_str(&to),
+            amount_wei,
+            raw,
+        })
+    }).collect::<Result<Vec<_>, _>>().map(axum::Json)
+}
+
+/// A hex address from the client, normalized to the stored lowercase form.
+fn parse_address(raw: &str) -> Result<String, ApiError> {
+    raw.trim().parse::<Address>()
+        .map(|a| format!("{:#x}", a))
+        .map_err(|_| ApiError::BadRequest(format!("invalid address {raw:?}")))
+}
+
+/// `block_number:log_index` of the last row returned
+fn parse_cursor(raw: &str) -> Result<(i64, i64), ApiError> {
+    raw.split_once(':')
+        .and_then(|(b, l)| Some((b.parse().ok()?, l.parse().ok()?)))
+        .ok_or_else(|| ApiError::BadRequest(format!("invalid cursor {raw:?}")))
+}
+
+/// Stored transfers of one token, newest first by default, keyset-paginated on
+/// `(block_number, log_index)`.
+async fn transfers_handler(
+    State(st): State<ApiState>,
+    q: Result<Query<TransfersQuery>, QueryRejection>,
+) -> Result<axum::Json<TransfersPage>, ApiError> {
+    let Query(q) = q?;
+    let token = st.token(q.token.as_deref())?;
+    let limit = q.limit.unwrap_or(TRANSFERS_DEFAULT_LIMIT).clamp(1, TRANSFERS_MAX_LIMIT);
+    let address = q.address.as_deref().map(parse_address).transpose()?;
+    let cursor = q.cursor.as_deref().map(parse_cursor).transpose()?;
+
+    let sql = transfers_sql(q.order, address.is_some());
+    let rows = sqlx::query_as::<_, (String, i64, i64, Option<i64>, String, String, String)>(&sql)
+        .bind(format!("{:#x}", token))
+        .bind(q.before_block)
+        .bind(q.after_block)
+        .bind(address)
+        .bind(cursor.map(|c| c.0))
+        .bind(cursor.map(|c| c.1))
+        .bind(limit)
+        .fetch_all(&st.db).await?;
+
+    let next_cursor = (rows.len() as i64 == limit)
+        .then(|| rows.last().map(|r| format!("{}:{}", r.2, r.1)))
+        .flatten();
+    let transfers = rows.into_iter()
+        .map(|(tx_hash, log_index, block_number, block_ts, from, to, amount_wei)| TransferOut {
+            tx_hash,
+            log_index,
+            block_number,
+            block_ts,
+            from: checksum_str(&from),
+            to: checksum_str(&to),
+            amount_wei,
+        })
+        .collect();
+    Ok(axum::Json(TransfersPage { transfers, next_cursor }))
+}
+
+/// The `/transfers` page query: `?1` contract, `?2`/`?3` before/after block, `?4`
+/// address, `?5`/`?6` cursor, `?7` limit.
+fn transfers_sql(order: SortOrder, by_address: bool) -> String {
+    let (cmp, dir) = match order {
+        SortOrder::Asc => (">", "ASC"),
+        SortOrder::Desc => ("<", "DESC"),
+    };
+    format!(r#"
+        SELECT tx_hash, log_index, block_number, block_ts, "from", "to", amount_wei FROM erc20_transfers
+        WHERE {}
+          AND (?2 IS NULL OR block_number < ?2)
+          AND (?3 IS NULL OR block_number > ?3)
+          AND (?5 IS NULL OR (block_number, log_index) {cmp} (?5, ?6))
+        ORDER BY block_number {dir}, log_index {dir}
+        LIMIT ?7;
+    "#, token_filter(by_address))
+}
+
+/// `?1` contract and `?4` address (either side) filters. The address one is only
+/// spelled out when given, since behind `?4 IS NULL OR ...` SQLite can't search
+/// `idx_transfers_from`/`idx_transfers_to`; the unary `+` then keeps it off
+/// `idx_transfers_contract_ts`, which it would pick without `ANALYZE` stats even
+/// though one token matches far more rows than one address.
+fn token_filter(by_address: bool) -> &'static str {
+    if by_address {
+        r#"+contract = ?1 AND ("from" = ?4 OR "to" = ?4)"#
+    } else {
+        "contract = ?1 AND ?4 IS NULL"
+    }
+}
+
+/// Rows per query while streaming `/transfers.csv`
+const CSV_PAGE_ROWS: i64 = 5000;
+
+#[derive(Deserialize)]
+struct TransfersCsvQuery {
+    token: Option<String>,
+    /// Inclusive block bounds
+    from_block: Option<i64>,
+    to_block: Option<i64>,
+    /// Matches either side of the transfer
+    address: Option<String>,
+}
+
+/// One CSV page of transfers after `cursor`, oldest first, and the cursor to continue
+/// from (`None` on the last page).
+async fn csv_page(
+    db: &SqlitePool,
+    q: &TransfersCsvQuery,
+    token: &str,
+    address: Option<&str>,
+    cursor: (i64, i64),
+) -> Result<(String, Option<(i64, i64)>), sqlx::Error> {
+    let sql = format!(r#"
+        SELECT tx_hash, log_index, block_number, "from", "to", amount_wei FROM erc20_transfers
+        WHERE {}
+          AND (?2 IS NULL OR block_number >= ?2)
+          AND (?3 IS NULL OR block_number <= ?3)
+          AND (block_number, log_index) > (?5, ?6)
+        ORDER BY block_number, log_index
+        LIMIT ?7;
+    "#, token_filter(address.is_some()));
+    let rows = sqlx::query_as::<_, (String, i64, i64, String, String, String)>(&sql)
+        .bind(token)
+        .bind(q.from_block)
+        .bind(q.to_block)
+        .bind(address)
+        .bind(cursor.0)
+        .bind(cursor.1)
+        .bind(CSV_PAGE_ROWS)
+        .fetch_all(db).await?;
+    let next = (rows.len() as i64 == CSV_PAGE_ROWS)
+        .then(|| rows.last().map(|r| (r.2, r.1)))
+        .flatten();
+    let mut chunk = String::new();
+    for (tx_hash, log_index, block_number, from, to, amount_wei) in rows {
+        // Every field is hex or decimal, so nothing needs quoting
+        let _ = writeln!(chunk, "{},{},{},{},{},{}",
+            tx_hash, log_index, block_number, checksum_str(&from), checksum_str(&to), amount_wei);
+    }
+    Ok((chunk, next))
+}
+
+/// Every matching transfer as a CSV download, oldest first. Rows are read a page at
+/// a time as the client consumes the body, so a large range is never buffered. A
+/// database error mid-stream cuts the download short (and is logged).
+async fn transfers_csv_handler(
+    State(st): State<ApiState>,
+    q: Result<Query<TransfersCsvQuery>, QueryRejection>,
+) -> Result<Response, ApiError> {
+    let Query(q) = q?;
+    let token = format!("{:#x}", st.token(q.token.as_deref())?);
+    let address = q.address.as_deref().map(parse_address).transpose()?;
+    if let (Some(from), Some(to)) = (q.from_block, q.to_block) {
+        if from > to {
+            return Err(ApiError::BadRequest(format!("from_block {from} is after to_block {to}")));
+        }
+    }
+
+    let header = stream::once(async { Ok::<_, sqlx::Error>("tx_hash,log_index,block_number,from,to,amount_wei\n".to_string()) });
+    let q = Arc::new(q);
+    let rows = stream::unfold(Some((-1, -1)), move |cursor| {
+        let (db, q, token, address) = (st.db.clone(), q.clone(), token.clone(), address.clone());
+        async move {
+            match csv_page(&db, &q, &token, address.as_deref(), cursor?).await {
+                Ok((chunk, _)) if chunk.is_empty() => None,
+                Ok((chunk, next)) => Some((Ok(chunk), next)),
+                Err(e) => {
+                    warn!("/transfers.csv aborted: {e}");
+                    Some((Err(e), None))
+                }
+            }
+        }
+    });
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"transfers.csv\""),
+        ],
+        Body::from_stream(header.chain(rows)),
+    ).into_response())
+}
+
+/// Exchange-held reserves over time next to netflow, as a data-quality cross-check.
+async fn reserves_handler(
+    State(st): State<ApiState>,
+    q: Result<Query<ReserveQuery>, QueryRejection>,
+) -> Result<axum::Json<Vec<reserves::ReservePoint>>, ApiError> {
+    let Query(q) = q?;
+    let limit = q.limit.unwrap_or(100).clamp(1, 10_000);
+    Ok(axum::Json(reserves::history(&st.db, limit, &st.reserve_divergence_wei).await?))
+}
+
+/// Transfer blocks absent from `blocks` (no timestamp); repair with `backfill-timestamps`.
+async fn missing_timestamps_handler(
+    State(st): State<ApiState>,
+    q: Result<Query<MissingTimestampsQuery>, QueryRejection>,
+) -> Result<axum::Json<MissingTimestampsOut>, ApiError> {
+    let Query(q) = q?;
+    let limit = q.limit.unwrap_or(1000).clamp(1, 100_000);
+    Ok(axum::Json(MissingTimestampsOut {
+        missing: blocks::count_missing_timestamps(&st.db).await?,
+        blocks: blocks::missing_timestamps(&st.db, -1, limit).await?,
+    }))
+}
+
+/// Newest transfers in columnar form, served from memory (never touches SQLite).
+async fn recent_handler(
+    State(st): State<ApiState>,
+    q: Result<Query<RecentQuery>, QueryRejection>,
+) -> Result<axum::Json<RecentColumns>, ApiError> {
+    let Query(q) = q?;
+    Ok(axum::Json(st.recent.snapshot(q.limit.unwrap_or(usize::MAX))))
+}
+
+/// Reject admin requests without `Authorization: Bearer $API_KEY`.
+async fn require_api_key(State(st): State<ApiState>, req: Request, next: Next) -> Response {
+    let Some(key) = st.api_key.as_deref() else {
+        return ApiError::Forbidden("admin API disabled: set API_KEY".into()).into_response();
+    };
+    let presented = req.headers().get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if presented != Some(key) {
+        return ApiError::Unauthorized("missing or invalid bearer token".into()).into_response();
+    }
+    next.run(req).await
+}
+
+/// Kick off a background `recompute_netflow`; only one may run at a time.
+async fn recompute_handler(State(st): State<ApiState>) -> Result<impl IntoResponse, ApiError> {
+    let id = start_recompute_job(&st.indexer).await?
+        .ok_or_else(|| ApiError::Conflict("a recompute job is already running".into()))?;
+    Ok((StatusCode::ACCEPTED, axum::Json(serde_json::json!({ "job_id": id }))))
+}
+
+async fn job_handler(
+    State(st): State<ApiState>,
+    id: Result<Path<i64>, PathRejection>,
+) -> Result<axum::Json<jobs::Job>, ApiError> {
+    let Path(id) = id?;
+    jobs::get(&st.db, id).await?
+        .map(axum::Json)
+        .ok_or_else(|| ApiError::NotFound(format!("no job {id}")))
+}
+
+/// Apply an exchange set change: new transfers see it at once, history is
+/// reclassified by a background recompute like the exchange feed does.
+async fn reclassify(st: &ApiState) -> Result<Option<i64>, ApiError> {
+    refresh_exchange_cache(&st.indexer).await?;
+    let job = start_recompute_job(&st.indexer).await?;
+    match job {
+        Some(id) => info!("Reclassifying history after exchange set change (job {id})"),
+        None => warn!("exchange set changed while a recompute is running; trigger another once it finishes"),
+    }
+    Ok(job)
+}
+
+/// Change the confirmation depth at runtime and persist it. Lowering it lets staged
+/// logs that are now deep enough be promoted within a couple of seconds; raising it
+/// only holds back logs from here on, since counted transfers are never un-counted.
+async fn confirmations_handler(
+    State(st): State<ApiState>,
+    body: Result<axum::Json<ConfirmationsIn>, JsonRejection>,
+) -> Result<axum::Json<ConfirmationsOut>, ApiError> {
+    let axum::Json(body) = body?;
+    let n = body.confirmations;
+    if n > MAX_CONFIRMATIONS {
+        return Err(ApiError::BadRequest(format!("confirmations must be <= {MAX_CONFIRMATIONS}")));
+    }
+    save_confirmations(&st.db, n).await?;
+    let previous = st.indexer.set_confirmations(n);
+    if previous != n {
+        info!("CONFIRMATIONS changed from {} to {} via the admin API", previous, n);
+    }
+    let pending_now_final = match st.status.head().and_then(|h| h.checked_sub(n)) {
+        Some(final_to) => sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM pending_transfers WHERE block_number <= ?;")
+            .bind(final_to as i64)
+            .fetch_one(&st.db).await?,
+        None => 0,
+    };
+    Ok(axum::Json(ConfirmationsOut { previous, confirmations: n, pending_now_final }))
+}
+
+/// Register an exchange address at runtime. Posting a known pair again is a no-op; a
+/// different label for a known address is a conflict (delete it first).
+async fn add_exchange_handler(
+    State(st): State<ApiState>,
+    body: Result<axum::Json<ExchangeIn>, JsonRejection>,
+) -> Result<impl IntoResponse, ApiError> {
+    let axum::Json(body) = body?;
+    let address = parse_address(&body.address)?;
+    let exchange = body.exchange.trim();
+    if exchange.is_empty() {
+        return Err(ApiError::BadRequest("exchange label must not be empty".into()));
+    }
+    let existing = sqlx::query_scalar::<_, String>("SELECT exchange FROM exchange_addresses WHERE address = ?;")
+        .bind(&address)
+        .fetch_optional(&st.db).await?;
+    if let Some(existing) = existing {
+        if existing != exchange {
+            return Err(ApiError::Conflict(format!("{address} is already registered as {existing:?}")));
+        }
+        let out = ExchangeChangeOut { address: checksum_str(&address), exchange: Some(existing), recompute_job: None };
+        return Ok((StatusCode::OK, axum::Json(out)));
+    }
+
+    upsert_exchange_addresses(&st.db, &[(&address, exchange)]).await?;
+    info!("Exchange address {} added as {:?} via the API", address, exchange);
+    let recompute_job = reclassify(&st).await?;
+    let out = ExchangeChangeOut { address: checksum_str(&address), exchange: Some(exchange.to_string()), recompute_job };
+    Ok((StatusCode::CREATED, axum::Json(out)))
+}
+
+async fn remove_exchange_handler(
+    State(st): State<ApiState>,
+    address: Result<Path<String>, PathRejection>,
+) -> Result<axum::Json<ExchangeChangeOut>, ApiError> {
+    let Path(raw) = address?;
+    let address = parse_address(&raw)?;
+    if !remove_exchange_address(&st.db, &address).await? {
+        return Err(ApiError::NotFound(format!("{address} is not a registered exchange address")));
+    }
+    info!("Exchange address {} removed via the API", address);
+    let recompute_job = reclassify(&st).await?;
+    Ok(axum::Json(ExchangeChangeOut { address: checksum_str(&address), exchange: None, recompute_job }))
+}
+
+async fn neutral_handler(State(st): State<ApiState>) -> Result<axum::Json<Vec<NeutralOut>>, ApiError> {
+    let rows = sqlx::query_as::<_, (String, String)>("SELECT address, note FROM neutral_addresses ORDER BY address;")
+        .fetch_all(&st.db).await?;
+    Ok(axum::Json(rows.into_iter()
+        .map(|(address, note)| NeutralOut { address: checksum_str(&address), note })
+        .collect()))
+}
+
+/// Mark an address neutral: its transfers stop counting as exchange flow, for new
+/// transfers right away and for history once the recompute finishes. Posting a known
+/// address again is a no-op.
+async fn add_neutral_handler(
+    State(st): State<ApiState>,
+    body: Result<axum::Json<NeutralIn>, JsonRejection>,
+) -> Result<impl IntoResponse, ApiError> {
+    let axum::Json(body) = body?;
+    let address = parse_address(&body.address)?;
+    if upsert_neutral_addresses(&st.db, &[(&address, body.note.trim())]).await? == 0 {
+        let out = NeutralChangeOut { address: checksum_str(&address), neutral: true, recompute_job: None };
+        return Ok((StatusCode::OK, axum::Json(out)));
+    }
+    info!("Address {} marked neutral via the API", address);
+    let recompute_job = reclassify(&st).await?;
+    let out = NeutralChangeOut { address: checksum_str(&address), neutral: true, recompute_job };
+    Ok((StatusCode::CREATED, axum::Json(out)))
+}
+
+async fn remove_neutral_handler(
+    State(st): State<ApiState>,
+    address: Result<Path<String>, PathRejection>,
+) -> Result<axum::Json<NeutralChangeOut>, ApiError> {
+    let Path(raw) = address?;
+    let address = parse_address(&raw)?;
+    if !remove_neutral_address(&st.db, &address).await? {
+        return Err(ApiError::NotFound(format!("{address} is not a neutral address")));
+    }
+    info!("Address {} no longer neutral via the API", address);
+    let recompute_job = reclassify(&st).await?;
+    Ok(axum::Json(NeutralChangeOut { address: checksum_str(&address), neutral: false, recompute_job }))
+}
+
+/// The running configuration as a `.env` template (secrets redacted).
+async fn env_template_handler() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], env_template::render())
+}
+
+/// Prometheus text exposition of indexer gauges.
+async fn metrics_handler(State(st): State<ApiState>) -> impl IntoResponse {
+    let mut out = String::new();
+    let mut gauge = |name: &str, help: &str, value: u64| {
+        out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n"));
+    };
+    gauge("indexer_head_block", "Latest chain head reported by the RPC node", st.status.head().unwrap_or(0));
+    gauge("indexer_last_block", "Highest block processed by this process", st.status.last_processed_block().unwrap_or(0));
+    gauge("indexer_lag_blocks", "Head minus last processed block (includes the CONFIRMATIONS delay)", st.status.lag_blocks().unwrap_or(0));
+    gauge("indexer_head_age_seconds", "Seconds since the reported head last advanced", st.status.head_age_secs().unwrap_or(0));
+    gauge("indexer_head_stale", "1 if logs arrive while the head is stuck (lagging RPC node)", st.status.head_stale() as u64);
+    gauge("indexer_queue_depth", "Logs received but not yet processed", st.status.queue_depth.load(Ordering::Relaxed));
+    gauge("indexer_degraded", "1 once STRICT mode has recorded STRICT_DEGRADED_AFTER skipped logs", st.status.degraded() as u64);
+    gauge("indexer_queue_max", "MAX_IN_FLIGHT_LOGS: queue size at which subscription reads pause", st.indexer.cfg.max_in_flight as u64);
+    let mut counter = |name: &str, help: &str, value: &AtomicU64| {
+        out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} counter\n{name} {}\n", value.load(Ordering::Relaxed)));
+    };
+    counter("indexer_backpressure_events_total", "Times the log queue filled up", &st.status.backpressure_events);
+    counter("indexer_skipped_logs_total", "Logs recorded to skipped_logs (STRICT mode)", &st.status.skipped_logs);
+    counter("malformed_logs_total", "Transfer logs skipped for a non-standard shape (topics or data)", &st.status.malformed_logs);
+    counter("transfers_processed_total", "Transfers newly stored", &st.status.transfers_processed);
+    counter("exchange_transfers_total", "Stored transfers with an exchange on either side", &st.status.exchange_transfers);
+    counter("rpc_errors_total", "Failed RPC calls on the indexing path", &st.status.rpc_errors);
+    ([(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")], out)
+}
+
+type CounterRow = (String, String, Option<i64>, String, String, String, String, i64);
+
+/// Cumulative netflow of `token`: across all exchanges, or only `exchange`'s share.
+async fn load_netflow(
+    st: &ApiState,
+    token: Address,
+    exchange: Option<&str>,
+    amounts: AmountFormat,
+    at_block: Option<u64>,
+) -> Result<NetflowOut, ApiError> {
+    let db = &st.db;
+    let contract = format!("{:#x}", token);
+    if let Some(requested) = at_block {
+        let newest = resume_block(db).await?.unwrap_or(0);
+        let block = requested.min(newest);
+        if let Some(pruned) = pruned_through(db, &contract).await?.filter(|p| (block as i64) < *p) {
+            return Err(ApiError::BadRequest(format!(
+                "at_block {block} is before the prune cutoff; transfers through block {pruned} were deleted"
+            )));
+        }
+        let mut c = counters_at_block(&st.indexer, &contract, block).await?;
+        let (sums, last_block) = match exchange {
+            None => (c.sums, c.last_block),
+            Some(label) => c.by_exchange.remove(label).unwrap_or_default(),
+        };
+        let at = AtBlockOut { requested_block: requested, block, clamped: block < requested };
+        return netflow_out(st, token, exchange, amounts, Counters { sums, last_block, seq: 0 }, Some(at)).await;
+    }
+
+    const COLUMNS: &str = "cumulative_in_wei, cumulative_out_wei, last_block, excluded_in_wei, excluded_out_wei, minted_wei, burned_wei, seq";
+    let row = match exchange {
+        None => sqlx::query_as::<_, CounterRow>(&format!("SELECT {COLUMNS} FROM netflow_state WHERE contract = ?;"))
+            .bind(&contract)
+            .fetch_one(db).await?,
+        Some(label) => sqlx::query_as::<_, CounterRow>(
+            &format!("SELECT {COLUMNS} FROM netflow_by_exchange WHERE contract = ? AND exchange = ?;"))
+            .bind(&contract)
+            .bind(label)
+            .fetch_optional(db).await?
+            .unwrap_or_else(|| {
+                let zero = || "0".to_string();
+                (zero(), zero(), None, zero(), zero(), zero(), zero(), 0)
+            }),
     };
-    axum::Json(out)
+    let (in_wei, out_wei, last_block, ex_in_wei, ex_out_wei, minted_wei, burned_wei, seq) = row;
+    let mut sums: [rug::Integer; 6] = Default::default();
+    for (sum, s) in sums.iter_mut().zip([&in_wei, &out_wei, &ex_in_wei, &ex_out_wei, &minted_wei, &burned_wei]) {
+        *sum = rug::Integer::from_str_radix(s, 10)?;
+    }
+    netflow_out(st, token, exchange, amounts, Counters { sums, last_block, seq }, None).await
+}
+
+/// One counter row, parsed; `sums` in `COUNTER_COLUMNS` order.
+struct Counters {
+    sums: [rug::Integer; 6],
+    last_block: Option<i64>,
+    seq: i64,
+}
+
+async fn netflow_out(
+    st: &ApiState,
+    token: Address,
+    exchange: Option<&str>,
+    amounts: AmountFormat,
+    counters: Counters,
+    at: Option<AtBlockOut>,
+) -> Result<NetflowOut, ApiError> {
+    let db = &st.db;
+    let display = &st.display;
+    let Counters { sums, last_block, seq } = counters;
+    let [in_int, out_int, ex_in_int, ex_out_int, minted_int, burned_int] = sums;
+    let net = rug::Integer::from(&in_int - &out_int);
+
+    let exchanges = sqlx::query_scalar::<_, i64>(r#"
+        SELECT COUNT(DISTINCT COALESCE(a.canonical, c.exchange))
+        FROM exchange_coverage c
+        LEFT JOIN exchange_aliases a ON a.label = c.exchange;
+    "#).fetch_one(db).await?;
+
+    // Only the current supply is known
+    let supply = at.is_none().then(|| current_supply(st, token)).flatten();
+
+    // Present in token units, per the token's own decimals()
+    let meta = st.meta(token);
+    let fmt = |x: &rug::Integer| match amounts.places {
+        Some(places) => format_fixed(x, meta.decimals.into(), places, display.rounding),
+        None => format_units(x, meta.decimals.into(), display),
+    };
+    let dry_run = st.indexer.dry_run.as_ref().filter(|_| at.is_none()).map(|dry| {
+        let t = dry.totals(token, exchange);
+        DryRunOut {
+            persisted: false,
+            cumulative_in: fmt(&t.in_wei),
+            cumulative_out: fmt(&t.out_wei),
+            cumulative_net: fmt(&rug::Integer::from(&t.in_wei - &t.out_wei)),
+            transfers: t.transfers,
+            last_block: t.last_block,
+        }
+    });
+
+    Ok(NetflowOut {
+        token: to_checksum(&token),
+        exchange: exchange.map(str::to_string),
+        decimals: meta.decimals,
+        symbol: meta.symbol.clone(),
+        cumulative_in: fmt(&in_int),
+        cumulative_out: fmt(&out_int),
+        cumulative_net: fmt(&net),
+        direction: Direction::of(&net),
+        cumulative_net_abs: fmt(&rug::Integer::from(net.abs_ref())),
+        excluded_contract_in: fmt(&ex_in_int),
+        excluded_contract_out: fmt(&ex_out_int),
+        exchange_minted: fmt(&minted_int),
+        exchange_burned: fmt(&burned_int),
+        last_block,
+        seq,
+        coverage: CoverageOut {
+            exchanges,
+            min_exchanges: st.min_exchanges,
+            low_coverage: exchanges < st.min_exchanges,
+        },
+        // Historical totals don't go stale
+        stale: at.is_none() && is_stale(st),
+        net_pct_of_supply: supply.and_then(|s| pct_of(&net, &s)),
+        raw: amounts.raw.then(|| RawCountersOut {
+            cumulative_net_wei: net.to_string(),
+            cumulative_in_wei: in_int.to_string(),
+            cumulative_out_wei: out_int.to_string(),
+            excluded_in_wei: ex_in_int.to_string(),
+            excluded_out_wei: ex_out_int.to_string(),
+            minted_wei: minted_int.to_string(),
+            burned_wei: burned_int.to_string(),
+        }),
+        at,
+        dry_run,
+    })
+}
+
+/// Fractional digits kept in supply percentages
+const PCT_DECIMALS: u32 = 6;
+
+/// `100 * x / total` with `PCT_DECIMALS` digits, truncated toward zero; `None` for a
+/// zero `total` (a supply not yet read, or a token with nothing minted).
+fn pct_of(x: &rug::Integer, total: &rug::Integer) -> Option<String> {
+    if total.cmp0().is_eq() {
+        return None;
+    }
+    let scaled = rug::Integer::from(x * 100u32) * rug::Integer::from(10u32).pow(PCT_DECIMALS);
+    let q = scaled / total;
+    Some(format_units(&q, PCT_DECIMALS, &DisplayCfg::default()))
+}
+
+/// `API_CORS_ORIGINS`: `*` or a comma-separated list of exact origins.
+#[derive(Clone, Debug)]
+pub enum CorsOrigins {
+    Any,
+    List(Vec<HeaderValue>),
+}
+
+impl std::str::FromStr for CorsOrigins {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s.trim() == "*" {
+            return Ok(Self::Any);
+        }
+        let mut origins = Vec::new();
+        for raw in s.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            // Browsers send `scheme://host[:port]`; anything else would never match
+            if !(raw.starts_with("http://") || raw.starts_with("https://")) || raw.ends_with('/') {
+                anyhow::bail!("invalid origin {raw:?} (expected e.g. https://dash.example.com)");
+            }
+            origins.push(HeaderValue::from_str(raw)?);
+        }
+        if origins.is_empty() {
+            anyhow::bail!("no origins given");
+        }
+        Ok(Self::List(origins))
+    }
+}
+
+impl CorsOrigins {
+    fn layer(&self) -> CorsLayer {
+        let origin = match self {
+            Self::Any => AllowOrigin::any(),
+            Self::List(origins) => AllowOrigin::list(origins.clone()),
+        };
+        CorsLayer::new()
+            .allow_origin(origin)
+            .allow_methods([Method::GET])
+            // Let scripts read the staleness and rate-limit hints
+            .expose_headers([header::WARNING, header::RETRY_AFTER, header::ETAG])
+    }
 }
 
-pub async fn serve(db: SqlitePool) -> anyhow::Result<()> {
-    let app = Router::new()
-        .route("/netflow", get({
-            let db = db.clone();
-            move || netflow_handler(db.clone())
-        }));
+/// Every route with its rate limits, auth and CORS layers.
+fn router(state: ApiState) -> Router {
+    let limits = state.rate_limits;
+    let field_case = state.field_case;
+    let cors = state.cors_origins.clone();
+
+    // Cheap reads of precomputed state
+    let mut cheap = Router::new()
+        .route("/netflow", get(netflow_handler))
+        .route("/netflow/session", get(session_handler))
+        .route("/netflow/native", get(native_handler))
+        .route("/netflow/:exchange", get(exchange_netflow_handler))
+        .route("/summary", get(summary_handler))
+        .route("/status", get(status_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/transfers/recent", get(recent_handler))
+        .route("/transfers/:tx_hash", get(transfer_handler))
+        .route("/reserves/history", get(reserves_handler))
+        .route("/balances/:address", get(balance_handler))
+        .route("/ws/netflow", get(ws_netflow_handler))
+        .route("/exchanges", get(exchanges_handler))
+        .route("/neutral-addresses", get(neutral_handler));
+    if let Some(n) = limits.cheap_per_min {
+        cheap = cheap.route_layer(middleware::from_fn_with_state(RateLimiter::new(n, limits.global), rate_limit));
+    }
+
+    // Routes that scan erc20_transfers
+    let mut expensive = Router::new()
+        .route("/netflow/window", get(window_handler))
+        .route("/netflow/by-exchange", get(by_exchange_handler))
+        .route("/netflow/by-block", get(by_block_handler))
+        .route("/netflow/history", get(history_handler))
+        .route("/exchanges/top", get(top_exchanges_handler))
+        .route("/transfers", get(transfers_handler))
+        .route("/transfers.csv", get(transfers_csv_handler))
+        .route("/debug/missing-timestamps", get(missing_timestamps_handler));
+    if let Some(n) = limits.expensive_per_min {
+        expensive = expensive.route_layer(middleware::from_fn_with_state(RateLimiter::new(n, limits.global), rate_limit));
+    }
+
+    // Mutating and config-revealing operator endpoints, bearer-token gated
+    let admin = Router::new()
+        .route("/admin/recompute", post(recompute_handler))
+        .route("/admin/jobs/:id", get(job_handler))
+        .route("/config/confirmations", patch(confirmations_handler))
+        .route("/exchanges", post(add_exchange_handler))
+        .route("/exchanges/:address", delete(remove_exchange_handler))
+        .route("/neutral-addresses", post(add_neutral_handler))
+        .route("/neutral-addresses/:address", delete(remove_neutral_handler))
+        .route("/debug/env-template", get(env_template_handler))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_api_key));
+
+    // Probes are never rate limited
+    let probes = Router::new().route("/healthz", get(healthz_handler));
+
+    // Only the public reads are offered to browsers; admin routes never get CORS headers
+    let mut public = cheap.merge(expensive);
+    if let Some(origins) = &cors {
+        public = public.layer(origins.layer());
+        info!("CORS enabled for {:?}", origins);
+    }
+
+    public.merge(admin).merge(probes).with_state(state)
+        .layer(middleware::from_fn_with_state(field_case, recase_json))
+}
 
+/// Serve until `shutdown` is cancelled, then stop accepting and let open requests finish.
+pub async fn serve(state: ApiState, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let app = router(state);
     let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
     info!("HTTP API listening on http://{}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown.cancelled_owned())
         .await?;
+    info!("HTTP API stopped");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::upsert_exchange_aliases;
+    use crate::indexer::tests::{ingest, set_progress, test_cfg, test_indexer, token, transfer_log, BINANCE, COINBASE, USER, USER_B};
+    use crate::indexer::IndexerCfg;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    /// State as `main` builds it from the defaults, over `ix`'s database.
+    async fn test_state(ix: Indexer) -> ApiState {
+        let token = ix.cfg.primary_token();
+        let baseline = SessionBaseline::load(&ix.db, token).await.expect("session baseline");
+        ApiState {
+            db: ix.db.clone(),
+            status: ix.status.clone(),
+            tracked_tokens: ix.cfg.tokens.len(),
+            started_at: Instant::now(),
+            display: DisplayCfg::default(),
+            token_meta: HashMap::from([(token, TokenMeta { symbol: "POL".into(), decimals: 18 })]),
+            rate_limits: RateLimitCfg::default(),
+            field_case: FieldCase::default(),
+            recent: ix.recent.clone(),
+            sampling: Sampling::default(),
+            reserve_divergence_wei: rug::Integer::new(),
+            api_key: Some("test-key".into()),
+            indexer: ix,
+            min_exchanges: 1,
+            session: HashMap::from([(token, baseline)]),
+            max_age: MaxNetflowAge::default(),
+            cors_origins: None,
+            ws_slots: Arc::new(Semaphore::new(1)),
+        }
+    }
+
+    async fn get_with(app: &Router, uri: &str, headers: &[(header::HeaderName, &str)]) -> Response {
+        let mut req = axum::http::Request::builder().uri(uri);
+        for (name, value) in headers {
+            req = req.header(name, *value);
+        }
+        app.clone().oneshot(req.body(Body::empty()).expect("request")).await.expect("infallible")
+    }
+
+    async fn json_body(resp: Response) -> Value {
+        let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX).await.expect("body");
+        serde_json::from_slice(&bytes).expect("JSON body")
+    }
+
+    fn etag(resp: &Response) -> String {
+        resp.headers()[header::ETAG].to_str().expect("ASCII ETag").to_string()
+    }
+
+    #[tokio::test]
+    async fn netflow_is_not_modified_until_the_state_changes() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        ingest(&ix, [transfer_log(USER, BINANCE, 500, 10, 0)]).await;
+        let app = router(test_state(ix.clone()).await);
+
+        let first = get_with(&app, "/netflow", &[]).await;
+        assert_eq!(first.status(), StatusCode::OK);
+        let tag = etag(&first);
+        assert_eq!(json_body(first).await["seq"], 1);
+
+        let unchanged = get_with(&app, "/netflow", &[(header::IF_NONE_MATCH, &tag)]).await;
+        assert_eq!(unchanged.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(etag(&unchanged), tag);
+        let star = get_with(&app, "/netflow", &[(header::IF_NONE_MATCH, "*")]).await;
+        assert_eq!(star.status(), StatusCode::NOT_MODIFIED);
+
+        ingest(&ix, [transfer_log(BINANCE, USER, 200, 11, 0)]).await;
+        let updated = get_with(&app, "/netflow", &[(header::IF_NONE_MATCH, &tag)]).await;
+        assert_eq!(updated.status(), StatusCode::OK);
+        let updated_tag = etag(&updated);
+        assert_ne!(updated_tag, tag);
+        let tag = updated_tag;
+        assert_eq!(json_body(updated).await["seq"], 2);
+
+        // A supply refresh changes net_pct_of_supply without moving seq
+        *ix.supply.write().unwrap() = Some(rug::Integer::from(1000));
+        let refreshed = get_with(&app, "/netflow", &[(header::IF_NONE_MATCH, &tag)]).await;
+        assert_eq!(refreshed.status(), StatusCode::OK);
+        let supply_tag = etag(&refreshed);
+        assert_ne!(supply_tag, tag);
+        assert_eq!(json_body(refreshed).await["net_pct_of_supply"], "30");
+        let unchanged = get_with(&app, "/netflow", &[(header::IF_NONE_MATCH, &supply_tag)]).await;
+        assert_eq!(unchanged.status(), StatusCode::NOT_MODIFIED);
+        *ix.supply.write().unwrap() = Some(rug::Integer::from(2000));
+        let again = get_with(&app, "/netflow", &[(header::IF_NONE_MATCH, &supply_tag)]).await;
+        assert_eq!(again.status(), StatusCode::OK);
+
+        // The per-exchange view is versioned by its own row
+        let scoped = get_with(&app, "/netflow/binance", &[]).await;
+        assert_eq!(scoped.status(), StatusCode::OK);
+        let scoped_tag = etag(&scoped);
+        let again = get_with(&app, "/netflow/binance", &[(header::IF_NONE_MATCH, &scoped_tag)]).await;
+        assert_eq!(again.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[tokio::test]
+    async fn max_uint256_transfer_is_exact_from_decode_to_display() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        let mut max = transfer_log(USER, BINANCE, 0, 10, 0);
+        max.data = vec![0xff; 32].into();
+        ingest(&ix, [max, transfer_log(BINANCE, USER, 1, 11, 0)]).await;
+        let app = router(test_state(ix).await);
+
+        let resp = get_with(&app, "/netflow?raw=true", &[]).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = json_body(resp).await;
+        assert_eq!(body["cumulative_in_wei"], "115792089237316195423570985008687907853269984665640564039457584007913129639935");
+        assert_eq!(body["cumulative_net_wei"], "115792089237316195423570985008687907853269984665640564039457584007913129639934");
+        assert_eq!(body["cumulative_in"], "115792089237316195423570985008687907853269984665640564039457.584007913129639935");
+        assert_eq!(body["cumulative_out"], "0.000000000000000001");
+        assert_eq!(body["direction"], "inflow");
+    }
+
+    #[tokio::test]
+    async fn net_direction_and_magnitude_follow_the_sign() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        ingest(&ix, [transfer_log(USER, BINANCE, 500, 10, 0), transfer_log(BINANCE, USER, 500, 11, 0)]).await;
+        let app = router(test_state(ix.clone()).await);
+
+        let body = json_body(get_with(&app, "/netflow", &[]).await).await;
+        assert_eq!(body["cumulative_net"], "0");
+        assert_eq!(body["direction"], "neutral");
+        assert_eq!(body["cumulative_net_abs"], "0");
+
+        // Half a token out: the fraction keeps its sign
+        ingest(&ix, [transfer_log(BINANCE, USER, 500_000_000_000_000_000, 12, 0)]).await;
+        let body = json_body(get_with(&app, "/netflow", &[]).await).await;
+        assert_eq!(body["cumulative_net"], "-0.5");
+        assert_eq!(body["direction"], "outflow");
+        assert_eq!(body["cumulative_net_abs"], "0.5");
+    }
+
+    #[tokio::test]
+    async fn healthz_reports_whether_the_database_answers() {
+        let ix = test_indexer(test_cfg(), &[]).await;
+        let app = router(test_state(ix.clone()).await);
+
+        let resp = get_with(&app, "/healthz", &[]).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(json_body(resp).await, json!({"status": "ok"}));
+
+        ix.db.close().await;
+        assert_eq!(get_with(&app, "/healthz", &[]).await.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn address_lookups_search_the_from_and_to_indexes() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        ingest(&ix, (0..50).map(|i| transfer_log(USER, BINANCE, 1, 10 + i, 0))).await;
+        let address = format!("{:#x}", USER.parse::<Address>().unwrap());
+
+        for order in [SortOrder::Asc, SortOrder::Desc] {
+            let plan = sqlx::query_as::<_, (i64, i64, i64, String)>(
+                &format!("EXPLAIN QUERY PLAN {}", transfers_sql(order, true)),
+            )
+                .bind(format!("{:#x}", token()))
+                .bind(None::<i64>)
+                .bind(None::<i64>)
+                .bind(&address)
+                .bind(None::<i64>)
+                .bind(None::<i64>)
+                .bind(10)
+                .fetch_all(&ix.db).await.expect("query plan");
+            let details: Vec<&str> = plan.iter().map(|r| r.3.as_str()).collect();
+            assert!(details.iter().any(|d| d.contains("idx_transfers_from")), "{details:?}");
+            assert!(details.iter().any(|d| d.contains("idx_transfers_to")), "{details:?}");
+            assert!(!details.iter().any(|d| d.starts_with("SCAN")), "{details:?}");
+        }
+    }
+
+    #[test]
+    fn supply_percentages_truncate_toward_zero() {
+        let pct = |x: i64, total: i64| pct_of(&rug::Integer::from(x), &rug::Integer::from(total));
+        assert_eq!(pct(300, 1000).as_deref(), Some("30"));
+        assert_eq!(pct(1, 3).as_deref(), Some("33.333333"));
+        assert_eq!(pct(-1, 3).as_deref(), Some("-33.333333"));
+        assert_eq!(pct(2, 3).as_deref(), Some("66.666666"));
+        // Below the last digit in either direction
+        assert_eq!(pct(1, 1_000_000_000).as_deref(), Some("0"));
+        assert_eq!(pct(-1, 1_000_000_000).as_deref(), Some("0"));
+        assert_eq!(pct(5, 0), None);
+    }
+
+    #[tokio::test]
+    async fn netflow_goes_stale_past_either_age_limit() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        let mut st = test_state(ix.clone()).await;
+        st.max_age = MaxNetflowAge { blocks: Some(5), secs: Some(60) };
+        let app = router(st);
+        let check = |head, processed, age_secs| {
+            set_progress(&ix.status, head, processed, age_secs);
+            let app = app.clone();
+            async move {
+                let resp = get_with(&app, "/netflow", &[]).await;
+                let stale_tag = etag(&resp).ends_with("-stale\"");
+                let warning = resp.headers().get(header::WARNING).map(|v| v.to_str().unwrap().to_string());
+                let stale = json_body(resp).await["stale"].as_bool().expect("stale");
+                assert_eq!(stale_tag, stale, "ETag suffix");
+                assert_eq!(warning.is_some(), stale, "Warning header");
+                if let Some(w) = warning {
+                    assert_eq!(w, STALE_WARNING);
+                }
+                stale
+            }
+        };
+
+        // Nothing processed since startup yet
+        assert!(check(100, 0, 0).await, "no checkpoint");
+        assert!(!check(100, 95, 30).await, "within both limits");
+        assert!(check(100, 94, 0).await, "6 blocks behind");
+        assert!(check(100, 95, 61).await, "61s old");
+        // Blocks held back for CONFIRMATIONS don't count as lag
+        ix.set_confirmations(3);
+        assert!(!check(100, 92, 0).await, "5 blocks behind beyond confirmations");
+    }
+
+    /// A GET as `serve` hands it over, from client `ip`.
+    async fn get_from(app: &Router, uri: &str, ip: [u8; 4]) -> Response {
+        let mut req = axum::http::Request::builder().uri(uri).body(Body::empty()).expect("request");
+        req.extensions_mut().insert(axum::extract::ConnectInfo(SocketAddr::from((ip, 40_000))));
+        app.clone().oneshot(req).await.expect("infallible")
+    }
+
+    #[tokio::test]
+    async fn expensive_routes_are_limited_per_client_and_the_rest_stay_open() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        let mut st = test_state(ix).await;
+        st.rate_limits = RateLimitCfg { expensive_per_min: Some(2), cheap_per_min: None, global: false };
+        let app = router(st);
+        let client = [10, 0, 0, 1];
+
+        for _ in 0..2 {
+            assert_eq!(get_from(&app, "/transfers", client).await.status(), StatusCode::OK);
+        }
+        let limited = get_from(&app, "/transfers", client).await;
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = limited.headers()[header::RETRY_AFTER].to_str().unwrap().parse().expect("seconds");
+        assert!((1..=60).contains(&retry_after), "Retry-After {retry_after}");
+        assert_eq!(json_body(limited).await["error"]["code"], "rate_limited");
+        // The budget is shared by every expensive route, and only this client's is spent
+        assert_eq!(get_from(&app, "/netflow/window", client).await.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(get_from(&app, "/transfers", [10, 0, 0, 2]).await.status(), StatusCode::OK);
+
+        for _ in 0..5 {
+            assert_eq!(get_from(&app, "/netflow", client).await.status(), StatusCode::OK);
+            assert_eq!(get_from(&app, "/healthz", client).await.status(), StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn transfer_detail_returns_the_stored_raw_log() {
+        for store_raw_logs in [true, false] {
+            let ix = test_indexer(IndexerCfg { store_raw_logs, ..test_cfg() }, &[(BINANCE, "binance")]).await;
+            let lg = transfer_log(USER, BINANCE, 500, 10, 2);
+            let uri = format!("/transfers/{:#x}", lg.transaction_hash.unwrap());
+            let expected = json!({
+                "topics": lg.topics.iter().map(|t| format!("{t:#x}")).collect::<Vec<_>>(),
+                "data": format!("0x{:064x}", 500),
+            });
+            ingest(&ix, [lg]).await;
+            let app = router(test_state(ix).await);
+
+            let resp = get_with(&app, &uri, &[]).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = json_body(resp).await;
+            assert_eq!(body[0]["amount_wei"], "500");
+            assert_eq!(body[0]["raw"], if store_raw_logs { expected } else { Value::Null }, "STORE_RAW_LOGS={store_raw_logs}");
+        }
+    }
+
+    #[tokio::test]
+    async fn session_totals_count_from_the_startup_baseline() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        // Before this process started
+        ingest(&ix, [transfer_log(USER, BINANCE, 500, 10, 0)]).await;
+        let started = |ix: Indexer| async move {
+            let mut st = test_state(ix).await;
+            st.token_meta.insert(token(), TokenMeta { symbol: "POL".into(), decimals: 0 });
+            router(st)
+        };
+        let app = started(ix.clone()).await;
+        ingest(&ix, [transfer_log(BINANCE, USER, 200, 11, 0), transfer_log(USER, BINANCE, 50, 12, 0)]).await;
+
+        let body = json_body(get_with(&app, "/netflow/session", &[]).await).await;
+        assert_eq!(
+            (&body["session_in"], &body["session_out"], &body["session_net"]),
+            (&json!("50"), &json!("200"), &json!("-150")),
+        );
+        assert_eq!((&body["updates"], &body["started_at_block"], &body["last_block"]), (&json!(2), &json!(10), &json!(12)));
+
+        // A restart takes a new baseline
+        let app = started(ix.clone()).await;
+        let body = json_body(get_with(&app, "/netflow/session", &[]).await).await;
+        assert_eq!(
+            (&body["session_in"], &body["session_out"], &body["session_net"], &body["updates"]),
+            (&json!("0"), &json!("0"), &json!("0"), &json!(0)),
+        );
+        assert_eq!(body["started_at_block"], 12);
+    }
+
+    #[tokio::test]
+    async fn missing_timestamps_lists_unstamped_transfer_blocks() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        ingest(&ix, [10, 11, 11, 12].into_iter().enumerate().map(|(i, b)| transfer_log(USER, BINANCE, 1, b, i as u64))).await;
+        blocks::store_block(&ix.db, 11, None, 1011).await.expect("store_block");
+        let app = router(test_state(ix).await);
+
+        let resp = get_with(&app, "/debug/missing-timestamps", &[]).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(json_body(resp).await, json!({"missing": 2, "blocks": [10, 12]}));
+        let page = json_body(get_with(&app, "/debug/missing-timestamps?limit=1", &[]).await).await;
+        assert_eq!(page, json!({"missing": 2, "blocks": [10]}));
+    }
+
+    #[tokio::test]
+    async fn errors_share_one_json_shape() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        let mut st = test_state(ix).await;
+        // Without a baseline for the token `/netflow/session` fails internally
+        st.session.clear();
+        let app = router(st);
+        for (uri, status, code) in [
+            ("/netflow?token=nope", StatusCode::BAD_REQUEST, "bad_request"),
+            ("/netflow?decimals=lots", StatusCode::BAD_REQUEST, "bad_request"),
+            ("/netflow?token=0x0000000000000000000000000000000000000001", StatusCode::NOT_FOUND, "not_found"),
+            ("/netflow/kraken", StatusCode::NOT_FOUND, "not_found"),
+            ("/admin/jobs/1", StatusCode::UNAUTHORIZED, "unauthorized"),
+            ("/netflow/session", StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+        ] {
+            let resp = get_with(&app, uri, &[]).await;
+            assert_eq!(resp.status(), status, "{uri}");
+            let body = json_body(resp).await;
+            assert_eq!(body.as_object().map(|o| o.len()), Some(1), "{uri}: {body}");
+            assert_eq!(body["error"]["code"], code, "{uri}");
+            assert!(body["error"]["message"].as_str().is_some_and(|m| !m.is_empty()), "{uri}: {body}");
+        }
+        // Internal details stay in the log
+        let body = json_body(get_with(&app, "/netflow/session", &[]).await).await;
+        assert_eq!(body["error"]["message"], "internal error");
+    }
+
+    /// `(block_number, log_index)` of every transfer, following `next_cursor` from `uri`.
+    async fn walk_transfers(app: &Router, uri: &str) -> Vec<(i64, i64)> {
+        let mut seen = Vec::new();
+        let mut next = uri.to_string();
+        loop {
+            let resp = get_with(app, &next, &[]).await;
+            assert_eq!(resp.status(), StatusCode::OK, "{next}");
+            let page = json_body(resp).await;
+            for t in page["transfers"].as_array().expect("transfers") {
+                seen.push((t["block_number"].as_i64().unwrap(), t["log_index"].as_i64().unwrap()));
+            }
+            let Some(cursor) = page["next_cursor"].as_str() else { return seen };
+            next = format!("{uri}&cursor={cursor}");
+        }
+    }
+
+    #[tokio::test]
+    async fn transfers_pages_cover_every_row_once() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        ingest(&ix, [
+            transfer_log(USER, BINANCE, 1, 10, 0),
+            transfer_log(USER, BINANCE, 2, 10, 1),
+            transfer_log(BINANCE, USER, 3, 11, 0),
+            transfer_log(USER, USER, 4, 12, 0),
+            transfer_log(USER, BINANCE, 5, 13, 7),
+        ]).await;
+        let app = router(test_state(ix).await);
+        let all = vec![(10, 0), (10, 1), (11, 0), (12, 0), (13, 7)];
+
+        assert_eq!(walk_transfers(&app, "/transfers?order=asc&limit=2").await, all);
+        let newest_first: Vec<_> = all.iter().rev().copied().collect();
+        assert_eq!(walk_transfers(&app, "/transfers?limit=2").await, newest_first);
+        // A full last page leaves one empty page to fetch
+        assert_eq!(walk_transfers(&app, "/transfers?order=asc&limit=5").await, all);
+        assert_eq!(walk_transfers(&app, "/transfers?order=asc&limit=2&after_block=10").await, &all[2..]);
+
+        let bad = get_with(&app, "/transfers?cursor=10", &[]).await;
+        assert_eq!(bad.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn by_exchange_rolls_aliased_labels_up_from_the_counters() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance-hot"), (COINBASE, "binance-cold"), (USER_B, "kraken")]).await;
+        upsert_exchange_aliases(&ix.db, &[("binance-hot", "binance"), ("binance-cold", "binance")]).await.expect("aliases");
+        ingest(&ix, [
+            transfer_log(USER, BINANCE, 500, 10, 0),
+            transfer_log(COINBASE, USER, 200, 11, 0),
+            transfer_log(USER, USER_B, 70, 12, 0),
+            transfer_log(BINANCE, USER, 100, 13, 0),
+        ]).await;
+        let mut st = test_state(ix).await;
+        st.token_meta.insert(token(), TokenMeta { symbol: "POL".into(), decimals: 0 });
+        let app = router(st);
+
+        let resp = get_with(&app, "/netflow/by-exchange", &[]).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let flow = |label: &str, i: &str, o: &str, n: &str| json!({
+            "label": label, "cumulative_in": i, "cumulative_out": o, "cumulative_net": n,
+        });
+        assert_eq!(json_body(resp).await, json!([
+            {
+                "exchange": "binance",
+                "cumulative_in": "500", "cumulative_out": "300", "cumulative_net": "200",
+                "labels": [flow("binance-cold", "0", "200", "-200"), flow("binance-hot", "500", "100", "400")],
+            },
+            {
+                "exchange": "kraken",
+                "cumulative_in": "70", "cumulative_out": "0", "cumulative_net": "70",
+                "labels": [flow("kraken", "70", "0", "70")],
+            },
+        ]));
+    }
+
+    #[tokio::test]
+    async fn coverage_counts_aliased_labels_as_one_exchange() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance-hot"), (COINBASE, "binance-cold"), (USER_B, "kraken")]).await;
+        upsert_exchange_aliases(&ix.db, &[("binance-hot", "binance"), ("binance-cold", "binance")]).await.expect("aliases");
+        ingest(&ix, [transfer_log(USER, BINANCE, 500, 10, 0), transfer_log(COINBASE, USER, 200, 11, 0)]).await;
+        let mut st = test_state(ix.clone()).await;
+        st.min_exchanges = 2;
+        let app = router(st);
+
+        let body = json_body(get_with(&app, "/netflow", &[]).await).await;
+        assert_eq!(body["coverage"], json!({"exchanges": 1, "min_exchanges": 2, "low_coverage": true}));
+
+        ingest(&ix, [transfer_log(USER, USER_B, 70, 12, 0)]).await;
+        let body = json_body(get_with(&app, "/netflow", &[]).await).await;
+        assert_eq!(body["coverage"], json!({"exchanges": 2, "min_exchanges": 2, "low_coverage": false}));
+    }
+
+    #[tokio::test]
+    async fn by_block_rows_add_up_to_the_cumulative_counters() {
+        let ix = test_indexer(IndexerCfg { netflow_by_block: true, ..test_cfg() }, &[(BINANCE, "binance")]).await;
+        ingest(&ix, [
+            transfer_log(USER, BINANCE, 500, 10, 0),
+            transfer_log(BINANCE, USER, 200, 10, 1),
+            transfer_log(USER, USER_B, 999, 11, 0),
+            transfer_log(BINANCE, USER, 40, 12, 0),
+            transfer_log(USER_B, BINANCE, 7, 14, 0),
+        ]).await;
+        let mut st = test_state(ix.clone()).await;
+        st.token_meta.insert(token(), TokenMeta { symbol: "POL".into(), decimals: 0 });
+        let app = router(st.clone());
+
+        let resp = get_with(&app, "/netflow/by-block", &[]).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let rows = json_body(resp).await;
+        let row = |b: i64, i: &str, o: &str, n: &str| json!({"block_number": b, "block_in": i, "block_out": o, "block_net": n});
+        assert_eq!(rows, json!([row(10, "500", "200", "300"), row(12, "0", "40", "-40"), row(14, "7", "0", "7")]));
+        let sum = |field: &str| rows.as_array().unwrap().iter()
+            .map(|r| r[field].as_str().unwrap().parse::<i64>().unwrap())
+            .sum::<i64>()
+            .to_string();
+        let (in_wei, out_wei, _, _) = crate::indexer::tests::state(&ix).await;
+        assert_eq!((sum("block_in"), sum("block_out")), (in_wei, out_wei));
+
+        st.indexer.cfg.netflow_by_block = false;
+        let resp = get_with(&router(st), "/netflow/by-block", &[]).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(json_body(resp).await["error"]["code"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn window_counts_what_the_counters_count_and_drops_the_top() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        ingest(&ix, [
+            transfer_log(USER, BINANCE, 500, 10, 0),
+            transfer_log(BINANCE, USER, 200, 11, 0),
+            transfer_log(USER, BINANCE, 40, 12, 0),
+            // Stored, but not flow: zero value, self transfer, no exchange side
+            transfer_log(USER, BINANCE, 0, 12, 1),
+            transfer_log(BINANCE, BINANCE, 900, 12, 2),
+            transfer_log(USER, USER_B, 800, 13, 0),
+        ]).await;
+        let mut st = test_state(ix).await;
+        st.token_meta.insert(token(), TokenMeta { symbol: "POL".into(), decimals: 0 });
+        let app = router(st);
+        let window = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let resp = get_with(&app, uri, &[]).await;
+                assert_eq!(resp.status(), StatusCode::OK, "{uri}");
+                let w = json_body(resp).await;
+                (w["transfers"].as_u64().unwrap(), w["window_in"].clone(), w["window_out"].clone(), w["window_net"].clone())
+            }
+        };
+
+        assert_eq!(window("/netflow/window").await, (3, json!("540"), json!("200"), json!("340")));
+        assert_eq!(window("/netflow/window?exclude_top=1").await, (2, json!("40"), json!("200"), json!("-160")));
+        assert_eq!(window("/netflow/window?exclude_top=9").await, (0, json!("0"), json!("0"), json!("0")));
+        assert_eq!(window("/netflow/window?from_block=11&to_block=12").await, (2, json!("40"), json!("200"), json!("-160")));
+        let inverted = get_with(&app, "/netflow/window?from_block=12&to_block=11", &[]).await;
+        assert_eq!(inverted.status(), StatusCode::BAD_REQUEST);
+    }
+}
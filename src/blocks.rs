@@ -0,0 +1,134 @@
+
+use crate::error::{IndexerError, Result};
+use crate::transport::Transport;
+use ethers::providers::{Middleware, Provider};
+use sqlx::SqlitePool;
+use tracing::{info, warn};
+
+/// Blocks looked up per backfill round
+const BACKFILL_PAGE: i64 = 500;
+
+/// Blocks after `after_block` referenced by stored transfers that have no timestamp
+/// in `blocks`, oldest first.
+pub async fn missing_timestamps(db: &SqlitePool, after_block: i64, limit: i64) -> Result<Vec<i64>> {
+    Ok(sqlx::query_scalar::<_, i64>(r#"
+        SELECT DISTINCT t.block_number FROM erc20_transfers t
+        LEFT JOIN blocks b ON b.number = t.block_number
+        WHERE b.ts IS NULL AND t.block_number > ?
+        ORDER BY t.block_number LIMIT ?;
+    "#)
+        .bind(after_block)
+        .bind(limit)
+        .fetch_all(db).await?)
+}
+
+pub async fn count_missing_timestamps(db: &SqlitePool) -> Result<i64> {
+    Ok(sqlx::query_scalar::<_, i64>(r#"
+        SELECT COUNT(DISTINCT t.block_number) FROM erc20_transfers t
+        LEFT JOIN blocks b ON b.number = t.block_number
+        WHERE b.ts IS NULL;
+    "#).fetch_one(db).await?)
+}
+
+/// Record a block's timestamp (and hash, unless one is already stored), and stamp the
+/// block's stored transfers with it.
+pub async fn store_block(db: &SqlitePool, number: i64, hash: Option<String>, ts: i64) -> Result<()> {
+    sqlx::query(r#"
+        INSERT INTO blocks(number, hash, ts) VALUES (?, ?, ?)
+        ON CONFLICT(number) DO UPDATE SET ts = excluded.ts, hash = COALESCE(blocks.hash, excluded.hash);
+    "#)
+        .bind(number)
+        .bind(hash)
+        .bind(ts)
+        .execute(db).await?;
+    stamp_transfers(db, number, ts).await
+}
+
+async fn stamp_transfers(db: &SqlitePool, number: i64, ts: i64) -> Result<()> {
+    sqlx::query("UPDATE erc20_transfers SET block_ts = ? WHERE block_number = ? AND block_ts IS NOT ?;")
+        .bind(ts)
+        .bind(number)
+        .bind(ts)
+        .execute(db).await?;
+    Ok(())
+}
+
+/// Stored hash of block `number`, if it was recorded.
+pub async fn hash_at(db: &SqlitePool, number: u64) -> Result<Option<String>> {
+    Ok(sqlx::query_scalar::<_, Option<String>>("SELECT hash FROM blocks WHERE number = ?;")
+        .bind(number as i64)
+        .fetch_optional(db).await?
+        .flatten())
+}
+
+/// Record a processed block as canonical, replacing any hash stored for that height
+/// (and the timestamp on its stored transfers).
+pub async fn set_block(db: &SqlitePool, number: u64, hash: &str, ts: i64) -> Result<()> {
+    sqlx::query(r#"
+        INSERT INTO blocks(number, hash, ts) VALUES (?, ?, ?)
+        ON CONFLICT(number) DO UPDATE SET hash = excluded.hash, ts = excluded.ts;
+    "#)
+        .bind(number as i64)
+        .bind(hash)
+        .bind(ts)
+        .execute(db).await?;
+    stamp_transfers(db, number as i64, ts).await
+}
+
+/// Fetch and store timestamps for every transfer block missing one. Blocks the
+/// node doesn't return are logged and skipped; returns how many were repaired.
+pub async fn backfill_timestamps(db: &SqlitePool, provider: &Provider<Transport>) -> Result<usize> {
+    let mut repaired = 0;
+    let mut after = -1i64;
+    loop {
+        // Paging by block number also steps past blocks the node couldn't return
+        let page = missing_timestamps(db, after, BACKFILL_PAGE).await?;
+        let Some(&last) = page.last() else { break };
+        for n in page {
+            let block = provider.get_block(n as u64).await
+                .map_err(IndexerError::rpc(format!("failed to fetch block {n}")))?;
+            match block {
+                Some(b) => {
+                    store_block(db, n, b.hash.map(|h| format!("{:#x}", h)), b.timestamp.as_u64() as i64).await?;
+                    repaired += 1;
+                }
+                None => warn!("block {n} not returned by the node; leaving it missing"),
+            }
+        }
+        after = last;
+        info!("Backfilled timestamps through block {} ({} so far)", last, repaired);
+    }
+    Ok(repaired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::tests::{ingest, rpc_stub, test_cfg, test_indexer, transfer_log, Chain, BINANCE, USER};
+    use serde_json::Value;
+
+    #[tokio::test]
+    async fn transfer_blocks_without_a_timestamp_are_listed_and_backfilled() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        // Offline ingestion records no block timestamps
+        let logs: Vec<_> = [10, 10, 11, 12, 14].into_iter().enumerate()
+            .map(|(i, block)| transfer_log(USER, BINANCE, 1, block, i as u64))
+            .collect();
+        ingest(&ix, logs.clone()).await;
+        store_block(&ix.db, 12, None, 1012).await.expect("store_block");
+        // A hash on its own still leaves the block missing
+        sqlx::query("INSERT INTO blocks(number, hash) VALUES (11, '0xab');").execute(&ix.db).await.expect("hash only");
+
+        assert_eq!(count_missing_timestamps(&ix.db).await.expect("count"), 3);
+        assert_eq!(missing_timestamps(&ix.db, -1, 10).await.expect("page"), vec![10, 11, 14]);
+        assert_eq!(missing_timestamps(&ix.db, 10, 1).await.expect("page"), vec![11]);
+
+        let chain = Chain::new(logs);
+        let provider = rpc_stub(move |method: &str, params: &Value| chain.reply(method, params)).await;
+        assert_eq!(backfill_timestamps(&ix.db, &provider).await.expect("backfill"), 3);
+        assert_eq!(count_missing_timestamps(&ix.db).await.expect("count"), 0);
+        let stamped = sqlx::query_scalar::<_, Option<i64>>("SELECT block_ts FROM erc20_transfers ORDER BY block_number, log_index;")
+            .fetch_all(&ix.db).await.expect("block_ts");
+        assert_eq!(stamped, [1010, 1010, 1011, 1012, 1014].map(Some));
+    }
+}
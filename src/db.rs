@@ -1,71 +1,391 @@
 
-use anyhow::Result;
-use sqlx::{sqlite::{SqliteConnectOptions, SqliteJournalMode}, SqlitePool};
+use crate::error::{IndexerError, Result};
+use ethers::types::Address;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous};
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
 use std::str::FromStr;
+use std::time::Duration;
+use tracing::{info, warn};
 
 pub type Db = SqlitePool;
 
-pub async fn init_db(db_path: &str) -> Result<Db> {
-    let opts = SqliteConnectOptions::from_str(&format!("sqlite://{}", db_path))?
-        .create_if_missing(true);
-    let pool = SqlitePool::connect_with(opts).await?;
-
-    // Pragmas
-    sqlx::query("PRAGMA journal_mode=WAL;").execute(&pool).await?;
-    sqlx::query("PRAGMA synchronous=NORMAL;").execute(&pool).await?;
-    sqlx::query("PRAGMA foreign_keys=ON;").execute(&pool).await?;
+/// Schema history in `migrations/`, embedded at build time. Applied files must never
+/// change (their checksums are recorded); evolve the schema with a new numbered file.
+static MIGRATOR: Migrator = sqlx::migrate!();
 
-    // Schema
-    sqlx::query(r#"
-    CREATE TABLE IF NOT EXISTS blocks (
-        number INTEGER PRIMARY KEY,
-        hash   TEXT,
-        ts     INTEGER
+/// One `netflow_state` row per indexed token contract (lowercase hex), as in
+/// `0001_init.sql`; used to rebuild pre-multi-token tables
+const NETFLOW_STATE_SCHEMA: &str = r#"
+    CREATE TABLE IF NOT EXISTS netflow_state (
+        contract           TEXT PRIMARY KEY,
+        cumulative_in_wei  TEXT NOT NULL DEFAULT '0',
+        cumulative_out_wei TEXT NOT NULL DEFAULT '0',
+        last_block         INTEGER,
+        excluded_in_wei    TEXT NOT NULL DEFAULT '0',
+        excluded_out_wei   TEXT NOT NULL DEFAULT '0',
+        minted_wei         TEXT NOT NULL DEFAULT '0',
+        burned_wei         TEXT NOT NULL DEFAULT '0',
+        seq                INTEGER NOT NULL DEFAULT 0
     );
-    "#).execute(&pool).await?;
+"#;
 
-    sqlx::query(r#"
-    CREATE TABLE IF NOT EXISTS erc20_transfers (
-        tx_hash      TEXT NOT NULL,
-        log_index    INTEGER NOT NULL,
-        block_number INTEGER NOT NULL,
-        contract     TEXT NOT NULL,
-        "from"       TEXT NOT NULL,
-        "to"         TEXT NOT NULL,
-        amount_wei   TEXT NOT NULL,
-        PRIMARY KEY (tx_hash, log_index)
-    );
-    "#).execute(&pool).await?;
+/// Connection pool shared by the indexer, the netflow writer and the API.
+#[derive(Clone, Debug)]
+pub struct PoolCfg {
+    /// `DB_MAX_CONNECTIONS`
+    pub max_connections: u32,
+    /// `DB_BUSY_TIMEOUT_MS`: how long a statement waits on another connection's lock
+    /// before failing with "database is locked"
+    pub busy_timeout: Duration,
+}
 
-    sqlx::query(r#"
-    CREATE TABLE IF NOT EXISTS exchange_addresses (
-        address  TEXT PRIMARY KEY,
-        exchange TEXT NOT NULL
-    );
-    "#).execute(&pool).await?;
+impl Default for PoolCfg {
+    fn default() -> Self {
+        Self { max_connections: 5, busy_timeout: Duration::from_secs(5) }
+    }
+}
 
-    sqlx::query(r#"
-    CREATE TABLE IF NOT EXISTS netflow_state (
-        id INTEGER PRIMARY KEY CHECK (id = 1),
-        cumulative_in_wei  TEXT NOT NULL DEFAULT '0',
-        cumulative_out_wei TEXT NOT NULL DEFAULT '0',
-        last_block         INTEGER
+/// Open (creating if needed) the database and make sure `netflow_state` has a row
+/// for every token in `tokens`.
+pub async fn init_db(db_path: &str, tokens: &[Address], pool_cfg: &PoolCfg) -> Result<Db> {
+    // Pragmas, applied to every connection the pool opens
+    let opts = SqliteConnectOptions::from_str(&format!("sqlite://{}", db_path))?
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal)
+        .synchronous(SqliteSynchronous::Normal)
+        .foreign_keys(true)
+        .busy_timeout(pool_cfg.busy_timeout);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(pool_cfg.max_connections)
+        .connect_with(opts).await?;
+    info!(
+        "Opened {} (pool of up to {} connections, busy_timeout {} ms)",
+        db_path, pool_cfg.max_connections, pool_cfg.busy_timeout.as_millis(),
     );
-    "#).execute(&pool).await?;
 
-    // Seed single-row netflow_state if empty
-    sqlx::query("INSERT OR IGNORE INTO netflow_state(id) VALUES (1);")
-        .execute(&pool).await?;
+    // Schema: only migrations not yet recorded in `_sqlx_migrations` run
+    MIGRATOR.run(&pool).await.map_err(sqlx::Error::from)?;
+
+    // Databases created before migrations existed adopt 0001 as already applied, so
+    // add the columns their tables may predate. New columns belong in a migration.
+    // The netflow_state ones only matter for pre-multi-token tables, migrated below
+    ensure_column(&pool, "netflow_state", "excluded_in_wei", "TEXT NOT NULL DEFAULT '0'").await?;
+    ensure_column(&pool, "netflow_state", "excluded_out_wei", "TEXT NOT NULL DEFAULT '0'").await?;
+    ensure_column(&pool, "netflow_state", "minted_wei", "TEXT NOT NULL DEFAULT '0'").await?;
+    ensure_column(&pool, "netflow_state", "burned_wei", "TEXT NOT NULL DEFAULT '0'").await?;
+    // Bumped once per applied update so pollers can detect missed changes
+    ensure_column(&pool, "netflow_state", "seq", "INTEGER NOT NULL DEFAULT 0").await?;
+    // Who owns an exchange address row: `seed` (env/startup) or `feed` (EXCHANGE_LIST_URL)
+    ensure_column(&pool, "exchange_addresses", "source", "TEXT NOT NULL DEFAULT 'seed'").await?;
 
+    normalize_exchange_addresses(&pool).await?;
+    migrate_singleton_netflow_state(&pool, tokens).await?;
+
+    // Seed a zero netflow_state row for each token not seen before
+    for token in tokens {
+        sqlx::query("INSERT OR IGNORE INTO netflow_state(contract) VALUES (?);")
+            .bind(format!("{:#x}", token))
+            .execute(&pool).await?;
+    }
     Ok(pool)
 }
 
-pub async fn upsert_exchange_addresses(db: &Db, addrs: &[(&str, &str)]) -> Result<()> {
-    for (addr, ex) in addrs {
-        sqlx::query(r#"INSERT OR IGNORE INTO exchange_addresses(address, exchange) VALUES(?, ?);"#)
-            .bind(addr.to_lowercase())
+/// Add `column` to `table` on databases created before it existed.
+async fn ensure_column(pool: &Db, table: &str, column: &str, decl: &str) -> Result<()> {
+    let cols = sqlx::query_scalar::<_, String>(&format!("SELECT name FROM pragma_table_info('{table}');"))
+        .fetch_all(pool).await?;
+    if !cols.iter().any(|c| c == column) {
+        sqlx::query(&format!(r#"ALTER TABLE {table} ADD COLUMN {column} {decl};"#))
+            .execute(pool).await?;
+    }
+    Ok(())
+}
+
+/// Databases from before multi-token support keep their totals in a single `id = 1`
+/// row. Rebuild the table keyed by `contract` and carry that row over to the token its
+/// transfers belong to, or the first configured token when that can't be told.
+async fn migrate_singleton_netflow_state(pool: &Db, tokens: &[Address]) -> Result<()> {
+    let cols = sqlx::query_scalar::<_, String>("SELECT name FROM pragma_table_info('netflow_state');")
+        .fetch_all(pool).await?;
+    if !cols.iter().any(|c| c == "id") {
+        return Ok(());
+    }
+    let contracts = sqlx::query_scalar::<_, String>("SELECT DISTINCT contract FROM erc20_transfers LIMIT 2;")
+        .fetch_all(pool).await?;
+    let contract = match (&contracts[..], tokens.first()) {
+        ([only], _) => only.to_lowercase(),
+        (_, Some(first)) => format!("{:#x}", first),
+        (_, None) => return Err(IndexerError::Config("cannot migrate netflow_state: no token configured".into())),
+    };
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("ALTER TABLE netflow_state RENAME TO netflow_state_singleton;")
+        .execute(&mut *tx).await?;
+    sqlx::query(NETFLOW_STATE_SCHEMA).execute(&mut *tx).await?;
+    sqlx::query(r#"
+        INSERT INTO netflow_state (contract, cumulative_in_wei, cumulative_out_wei, last_block,
+                                   excluded_in_wei, excluded_out_wei, minted_wei, burned_wei, seq)
+        SELECT ?, cumulative_in_wei, cumulative_out_wei, last_block,
+               excluded_in_wei, excluded_out_wei, minted_wei, burned_wei, seq
+        FROM netflow_state_singleton WHERE id = 1;
+    "#)
+        .bind(&contract)
+        .execute(&mut *tx).await?;
+    sqlx::query("DROP TABLE netflow_state_singleton;").execute(&mut *tx).await?;
+    tx.commit().await?;
+    info!("Migrated single-row netflow_state to per-contract rows (existing totals kept under {})", contract);
+    Ok(())
+}
+
+/// Lowercase `exchange_addresses` rows written by external tools and merge the
+/// duplicates that only differed in case. The merged row keeps the label (and source)
+/// of the already-lowercase row when it has one, else the first non-empty label.
+/// Returns the number of addresses that changed.
+pub async fn normalize_exchange_addresses(db: &Db) -> Result<usize> {
+    let rows = sqlx::query_as::<_, (String, String, String)>(r#"
+        SELECT address, exchange, source FROM exchange_addresses
+        WHERE lower(address) IN (SELECT lower(address) FROM exchange_addresses WHERE address != lower(address))
+        ORDER BY address;
+    "#).fetch_all(db).await?;
+    if rows.is_empty() {
+        return Ok(0);
+    }
+
+    let mut groups: BTreeMap<String, Vec<(String, String, String)>> = BTreeMap::new();
+    for row in rows {
+        groups.entry(row.0.to_lowercase()).or_default().push(row);
+    }
+
+    let mut tx = db.begin().await?;
+    for (lower, dupes) in &groups {
+        let keep = dupes.iter()
+            .find(|(a, ex, _)| a == lower && !ex.trim().is_empty())
+            .or_else(|| dupes.iter().find(|(_, ex, _)| !ex.trim().is_empty()))
+            .unwrap_or(&dupes[0]);
+        sqlx::query("DELETE FROM exchange_addresses WHERE lower(address) = ?;")
+            .bind(lower)
+            .execute(&mut *tx).await?;
+        sqlx::query("INSERT INTO exchange_addresses(address, exchange, source) VALUES(?, ?, ?);")
+            .bind(lower)
+            .bind(&keep.1)
+            .bind(&keep.2)
+            .execute(&mut *tx).await?;
+        let seen: Vec<String> = dupes.iter().map(|(a, ex, _)| format!("{a} ({ex:?})")).collect();
+        info!("Normalized exchange address {} -> {:?} from {}", lower, keep.1, seen.join(", "));
+    }
+    tx.commit().await?;
+    info!("Normalized {} mixed-case exchange addresses", groups.len());
+    Ok(groups.len())
+}
+
+/// What `upsert_exchange_addresses` made of its input.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AddressCounts {
+    /// Valid addresses, whether new or already known
+    pub accepted: u64,
+    /// Not a 20-byte hex address; logged and skipped
+    pub rejected: u64,
+    /// Accepted addresses that weren't stored yet
+    pub added: u64,
+}
+
+/// Add exchange addresses not known yet (existing rows keep their label), stored in
+/// canonical lowercase form. Anything that doesn't parse as an address is warned about
+/// and skipped rather than stored as a row no transfer could ever match.
+pub async fn upsert_exchange_addresses(db: &Db, addrs: &[(&str, &str)]) -> Result<AddressCounts> {
+    let mut counts = AddressCounts::default();
+    for (raw, ex) in addrs {
+        let Ok(addr) = Address::from_str(raw.trim()) else {
+            warn!("ignoring invalid {ex} exchange address {raw:?} (expected 0x + 40 hex digits)");
+            counts.rejected += 1;
+            continue;
+        };
+        counts.accepted += 1;
+        counts.added += sqlx::query(r#"INSERT OR IGNORE INTO exchange_addresses(address, exchange) VALUES(?, ?);"#)
+            .bind(format!("{:#x}", addr))
             .bind(*ex)
+            .execute(db).await?
+            .rows_affected();
+    }
+    Ok(counts)
+}
+
+/// Forget one exchange address, whatever its source; `false` if it wasn't known.
+pub async fn remove_exchange_address(db: &Db, addr: &str) -> Result<bool> {
+    let removed = sqlx::query("DELETE FROM exchange_addresses WHERE address = ?;")
+        .bind(addr.to_lowercase())
+        .execute(db).await?
+        .rows_affected();
+    Ok(removed > 0)
+}
+
+/// Add neutral addresses not known yet (existing rows keep their note); returns how
+/// many were new.
+pub async fn upsert_neutral_addresses(db: &Db, addrs: &[(&str, &str)]) -> Result<u64> {
+    let mut added = 0;
+    for (addr, note) in addrs {
+        added += sqlx::query("INSERT OR IGNORE INTO neutral_addresses(address, note) VALUES(?, ?);")
+            .bind(addr.to_lowercase())
+            .bind(*note)
+            .execute(db).await?
+            .rows_affected();
+    }
+    Ok(added)
+}
+
+/// `false` if `addr` wasn't a neutral address.
+pub async fn remove_neutral_address(db: &Db, addr: &str) -> Result<bool> {
+    let removed = sqlx::query("DELETE FROM neutral_addresses WHERE address = ?;")
+        .bind(addr.to_lowercase())
+        .execute(db).await?
+        .rows_affected();
+    Ok(removed > 0)
+}
+
+/// Refuse a node on another network than `expected` (when set) or than the one this
+/// database was built from; the first successful check records the chain id (unless
+/// `record` is off, as in a dry run).
+pub async fn check_chain_id(db: &Db, actual: u64, expected: Option<u64>, record: bool) -> Result<()> {
+    if let Some(expected) = expected.filter(|e| *e != actual) {
+        return Err(IndexerError::WrongChain { actual, expected, by: "EXPECTED_CHAIN_ID" });
+    }
+    let stored = sqlx::query_scalar::<_, i64>("SELECT chain_id FROM chain_meta WHERE id = 1;")
+        .fetch_optional(db).await?;
+    match stored {
+        Some(stored) if stored as u64 != actual => {
+            Err(IndexerError::WrongChain { actual, expected: stored as u64, by: "the database" })
+        }
+        Some(_) => Ok(()),
+        None if !record => Ok(()),
+        None => {
+            sqlx::query("INSERT INTO chain_meta (id, chain_id) VALUES (1, ?);")
+                .bind(actual as i64)
+                .execute(db).await?;
+            info!("Recorded chain id {} for this database", actual);
+            Ok(())
+        }
+    }
+}
+
+/// `CONFIRMATIONS` as last set through `PATCH /config/confirmations`, if ever.
+pub async fn load_confirmations(db: &Db) -> Result<Option<u64>> {
+    let stored = sqlx::query_scalar::<_, String>("SELECT value FROM runtime_config WHERE key = 'confirmations';")
+        .fetch_optional(db).await?;
+    stored.map(|v| v.parse().map_err(|_| IndexerError::Decode(format!("corrupt runtime_config confirmations {v:?}"))))
+        .transpose()
+}
+
+pub async fn save_confirmations(db: &Db, n: u64) -> Result<()> {
+    sqlx::query(r#"
+        INSERT INTO runtime_config (key, value) VALUES ('confirmations', ?)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value;
+    "#)
+        .bind(n.to_string())
+        .execute(db).await?;
+    Ok(())
+}
+
+/// Map raw exchange labels (e.g. `binance-hot`) onto a canonical group name.
+pub async fn upsert_exchange_aliases(db: &Db, aliases: &[(&str, &str)]) -> Result<()> {
+    for (label, canonical) in aliases {
+        sqlx::query(r#"
+            INSERT INTO exchange_aliases(label, canonical) VALUES(?, ?)
+            ON CONFLICT(label) DO UPDATE SET canonical = excluded.canonical;
+        "#)
+            .bind(*label)
+            .bind(*canonical)
             .execute(db).await?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::tests::{token, BINANCE, COINBASE};
+
+    /// A database file of its own under the temp dir, removed (with its WAL) on drop.
+    struct TempDb(std::path::PathBuf);
+
+    impl TempDb {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("netflow-{name}-{}.db", std::process::id())))
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().expect("utf-8 temp path")
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{suffix}", self.path()));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn singleton_netflow_state_migrates_once_and_keeps_its_totals() {
+        let file = TempDb::new("legacy");
+        let legacy = SqlitePool::connect(&format!("sqlite://{}?mode=rwc", file.path())).await.expect("legacy db");
+        sqlx::query(r#"
+            CREATE TABLE netflow_state (
+                id                 INTEGER PRIMARY KEY CHECK (id = 1),
+                cumulative_in_wei  TEXT NOT NULL DEFAULT '0',
+                cumulative_out_wei TEXT NOT NULL DEFAULT '0',
+                last_block         INTEGER
+            );
+        "#).execute(&legacy).await.expect("legacy schema");
+        sqlx::query("INSERT INTO netflow_state (id, cumulative_in_wei, cumulative_out_wei, last_block) VALUES (1, '1500', '700', 42);")
+            .execute(&legacy).await.expect("legacy row");
+        legacy.close().await;
+
+        for run in 0..2 {
+            let db = init_db(file.path(), &[token()], &PoolCfg::default()).await.expect("init_db");
+            let rows = sqlx::query_as::<_, (String, String, String, Option<i64>)>(
+                "SELECT contract, cumulative_in_wei, cumulative_out_wei, last_block FROM netflow_state;",
+            )
+                .fetch_all(&db).await.expect("netflow_state");
+            assert_eq!(rows, vec![(format!("{:#x}", token()), "1500".into(), "700".into(), Some(42))], "run {run}");
+            let cols = sqlx::query_scalar::<_, String>("SELECT name FROM pragma_table_info('netflow_state');")
+                .fetch_all(&db).await.expect("columns");
+            assert!(!cols.iter().any(|c| c == "id"), "run {run}: {cols:?}");
+            db.close().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn mixed_case_exchange_addresses_merge_into_one_row() {
+        let db = init_db("sqlite::memory:", &[token()], &PoolCfg { max_connections: 1, ..PoolCfg::default() }).await
+            .expect("in-memory database");
+        let upper = |a: &str| format!("0x{}", a[2..].to_uppercase());
+        for (address, label, source) in [
+            // The lowercase row's label wins
+            (BINANCE.to_string(), "binance", "seed"),
+            (upper(BINANCE), "binance-old", "feed"),
+            // Without one, the first non-empty label (and its source)
+            (COINBASE.to_string(), "", "seed"),
+            (upper(COINBASE), "coinbase", "feed"),
+        ] {
+            sqlx::query("INSERT INTO exchange_addresses(address, exchange, source) VALUES (?, ?, ?);")
+                .bind(address)
+                .bind(label)
+                .bind(source)
+                .execute(&db).await.expect("seed row");
+        }
+
+        assert_eq!(normalize_exchange_addresses(&db).await.expect("normalize"), 2);
+        let rows = sqlx::query_as::<_, (String, String, String)>(
+            "SELECT address, exchange, source FROM exchange_addresses ORDER BY address;",
+        )
+            .fetch_all(&db).await.expect("exchange_addresses");
+        assert_eq!(rows, vec![
+            (BINANCE.to_string(), "binance".to_string(), "seed".to_string()),
+            (COINBASE.to_string(), "coinbase".to_string(), "feed".to_string()),
+        ]);
+        assert_eq!(normalize_exchange_addresses(&db).await.expect("second pass"), 0);
+    }
+}
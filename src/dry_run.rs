@@ -0,0 +1,117 @@
+
+use ethers::types::{Address, H256};
+use rug::Integer;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+use crate::indexer::Flow;
+
+/// `--dry-run` stand-in for the tables the indexer would write: which transfers were
+/// applied, the totals they add up to and the logs waiting for confirmations. Starts
+/// empty with the process and is never persisted.
+#[derive(Default)]
+pub struct DryRun {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// `(tx hash, log index)` already applied, like `erc20_transfers`' key
+    seen: HashSet<(H256, u64)>,
+    /// `(token, exchange label)` -> totals; the `None` label is the token-wide row
+    totals: HashMap<(Address, Option<String>), DryRunTotals>,
+    /// `(block, log index, block hash)` -> log JSON, like `pending_transfers`
+    pending: BTreeMap<(i64, i64, String), String>,
+}
+
+/// In/out totals of the transfers classified during this dry run.
+#[derive(Clone, Debug, Default)]
+pub struct DryRunTotals {
+    pub in_wei: Integer,
+    pub out_wei: Integer,
+    /// Transfers that added to `in_wei` or `out_wei`
+    pub transfers: u64,
+    pub last_block: Option<i64>,
+}
+
+impl DryRunTotals {
+    fn add(&mut self, inflow: bool, outflow: bool, amount: &Integer, block: i64) {
+        if !(inflow || outflow) {
+            return;
+        }
+        if inflow {
+            self.in_wei += amount;
+        }
+        if outflow {
+            self.out_wei += amount;
+        }
+        self.transfers += 1;
+        self.last_block = self.last_block.max(Some(block));
+    }
+}
+
+impl DryRun {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("dry-run state poisoned")
+    }
+
+    /// `false` if the transfer was already applied (a backfill overlapping the stream).
+    pub fn first_sight(&self, tx_hash: H256, log_index: u64) -> bool {
+        self.lock().seen.insert((tx_hash, log_index))
+    }
+
+    /// Add a classified transfer to the token's totals and to each touched exchange's,
+    /// returning the token-wide totals. A move between two wallets of one exchange
+    /// nets to zero there.
+    pub fn apply(
+        &self,
+        contract: Address,
+        flow: &Flow,
+        from_ex: Option<&str>,
+        to_ex: Option<&str>,
+        amount: &Integer,
+        block: i64,
+    ) -> DryRunTotals {
+        let mut st = self.lock();
+        let mut add = |label: Option<&str>, inflow: bool, outflow: bool| {
+            st.totals.entry((contract, label.map(str::to_string))).or_default()
+                .add(inflow, outflow, amount, block);
+        };
+        add(None, flow.inflow, flow.outflow);
+        if to_ex.is_some() && to_ex == from_ex {
+            add(to_ex, flow.inflow, flow.outflow);
+        } else {
+            if to_ex.is_some() {
+                add(to_ex, flow.inflow, false);
+            }
+            if from_ex.is_some() {
+                add(from_ex, false, flow.outflow);
+            }
+        }
+        st.totals.get(&(contract, None)).cloned().unwrap_or_default()
+    }
+
+    /// Totals for `contract`, optionally scoped to one exchange label; zero if untouched.
+    pub fn totals(&self, contract: Address, exchange: Option<&str>) -> DryRunTotals {
+        self.lock().totals.get(&(contract, exchange.map(str::to_string))).cloned().unwrap_or_default()
+    }
+
+    pub fn stage(&self, block_hash: String, log_index: i64, block: i64, log_json: String) {
+        self.lock().pending.entry((block, log_index, block_hash)).or_insert(log_json);
+    }
+
+    /// Drop a staged log; `false` if it wasn't staged.
+    pub fn unstage(&self, block_hash: &str, log_index: i64) -> bool {
+        let mut st = self.lock();
+        let before = st.pending.len();
+        st.pending.retain(|(_, i, h), _| !(*i == log_index && h == block_hash));
+        st.pending.len() < before
+    }
+
+    /// Staged logs up to `block`, oldest first, as `(block hash, log index, block, log JSON)`.
+    pub fn staged(&self, block: i64) -> Vec<(String, i64, i64, String)> {
+        self.lock().pending.iter()
+            .take_while(|((bn, _, _), _)| *bn <= block)
+            .map(|((bn, i, h), json)| (h.clone(), *i, *bn, json.clone()))
+            .collect()
+    }
+}
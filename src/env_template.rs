@@ -0,0 +1,150 @@
+
+use std::env;
+
+/// How a variable's current value is shown in the template.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Secret {
+    No,
+    /// Replaced wholesale
+    Yes,
+    /// Scheme and host kept; credentials, path and query replaced (API keys often live there)
+    Url,
+    /// Comma-separated `Url`s, each redacted on its own
+    Urls,
+}
+
+struct EnvVar {
+    key: &'static str,
+    /// Value used when unset; `None` means the feature is off or the var is required
+    default: Option<&'static str>,
+    help: &'static str,
+    secret: Secret,
+}
+
+const fn var(key: &'static str, default: Option<&'static str>, help: &'static str) -> EnvVar {
+    EnvVar { key, default, help, secret: Secret::No }
+}
+
+const REDACTED: &str = "<redacted>";
+
+/// Every variable read at startup, in `.env.example` order. Keep in sync with `main.rs`.
+const VARS: &[EnvVar] = &[
+    EnvVar { secret: Secret::Urls, ..var("RPC_URLS", None, "Polygon RPC endpoints (wss:// or https://) in failover order, comma-separated") },
+    EnvVar { secret: Secret::Url, ..var("RPC_URL", None, "Single RPC endpoint (wss:// or https://), used when RPC_URLS is unset (one of the two is required)") },
+    var("POL_TOKEN_ADDRESS", None, "ERC-20 contract(s) to index, comma-separated; the first is the API default (required)"),
+    var("CONFIRMATIONS", Some("20"), "Blocks a log must be buried under before it is counted"),
+    var("HEAD_STALE_SECS", Some("60"), "Warn if logs keep arriving but the head hasn't advanced for this many seconds"),
+    var("BACKFILL_CHUNK_BLOCKS", Some("2000"), "Blocks per eth_getLogs request when catching up after downtime"),
+    var("START_BLOCK", None, "On an empty database, backfill from this block instead of starting at the head"),
+    var("START_TIMESTAMP", None, "Unix seconds; used when START_BLOCK is unset, resolved to the first block at or after it"),
+    var("INDEXER_MODE", Some("subscribe"), "subscribe (eth_subscribe logs, polling if unsupported) or poll"),
+    var("POLL_INTERVAL_SECS", Some("5"), "Poll mode: seconds between eth_getLogs rounds"),
+    var("EXPECTED_CHAIN_ID", None, "Refuse RPC endpoints on another chain (137 = Polygon PoS)"),
+    var("THROUGHPUT_LOG_SECS", Some("60"), "Interval of the throughput log line and /status rates (0 = off)"),
+    var("WS_MAX_RETRIES", None, "Consecutive failed WebSocket reconnects before exiting (unset = retry forever)"),
+    var("RPC_RETRIES", Some("3"), "Retries of a head/block/eth_getLogs call after a transport error or rate limit"),
+    var("DB_PATH", Some("./netflow.sqlite"), "SQLite database file"),
+    var("DB_MAX_CONNECTIONS", Some("5"), "SQLite connections shared by the indexer and the API"),
+    var("DB_BUSY_TIMEOUT_MS", Some("5000"), "How long a write waits for the database lock before failing"),
+    var("RETENTION_BLOCKS", None, "Hourly prune transfers older than this many blocks (checkpointed first; unset = keep all)"),
+    var("EXCLUDE_CONTRACT_COUNTERPARTIES", Some("false"), "Set aside exchange transfers whose counterparty is a contract"),
+    var("STORE_RAW_LOGS", Some("false"), "Keep raw log topics/data per transfer"),
+    var("VERIFY_RECEIPT_STATUS", Some("false"), "Skip logs whose transaction did not succeed (extra RPC per tx)"),
+    var("ZERO_ADDRESS_RULE", Some("count"), "Exchange<->0x0 transfers: count | exclude | separate"),
+    var("COUNT_ZERO_TRANSFERS", Some("false"), "Classify zero-value and self transfers like any other"),
+    var("EXCHANGE_ALIASES", None, "Label rollups for /netflow/by-exchange (label:canonical,...)"),
+    var("BINANCE_ADDRESSES", None, "Comma-separated exchange addresses seeded at startup"),
+    var("EXCHANGES_FILE", None, "Labeled exchange addresses (.json or .csv) seeded at startup; --exchanges-file overrides"),
+    var("NEUTRAL_ADDRESSES", None, "Addresses never counted as exchange flow (default: the MATIC -> POL migration contract)"),
+    EnvVar { secret: Secret::Url, ..var("ALERT_WEBHOOK_URL", None, "Webhook receiving alert JSON POSTs") },
+    var("FLIP_ALERT_BAND_WEI", None, "Enable the cumulative_net sign-flip alert; hysteresis band in wei"),
+    var("ALERT_THRESHOLD_POL", None, "Enable the windowed netflow alert; net change in whole tokens"),
+    var("ALERT_WINDOW_SECS", Some("3600"), "Window for ALERT_THRESHOLD_POL, and its per-direction debounce"),
+    var("EXCHANGE_ALERT_THRESHOLDS", None, "Per-exchange net flow alerts: label:inflow|outflow:wei,..."),
+    var("DISPLAY_DECIMALS", None, "Cap fractional digits in API amounts (unset = full precision)"),
+    var("ROUNDING", Some("truncate"), "truncate | half_up | half_even"),
+    var("RATE_LIMIT_EXPENSIVE_PER_MIN", Some("60"), "Requests per minute on scanning routes (0 = off)"),
+    var("RATE_LIMIT_CHEAP_PER_MIN", Some("0"), "Requests per minute on cheap routes (0 = off)"),
+    var("RATE_LIMIT_SCOPE", Some("ip"), "ip | global"),
+    var("API_FIELD_CASE", Some("snake"), "JSON response key style: snake | camel"),
+    var("API_CORS_ORIGINS", None, "Browser origins allowed on GET routes: * or comma-separated (unset = no CORS)"),
+    var("WS_MAX_SUBSCRIBERS", Some("100"), "Open /ws/netflow sockets allowed at once"),
+    var("RECENT_TRANSFERS_CAP", Some("1000"), "In-memory ring served by /transfers/recent (0 = off)"),
+    EnvVar { secret: Secret::Yes, ..var("API_KEY", None, "Bearer token for admin routes (disabled when unset)") },
+    var("SAMPLE_RATE", Some("1"), "Fraction of non-exchange transfers kept"),
+    var("SAMPLE_BY", Some("transfer"), "transfer | block"),
+    var("RESERVE_SNAPSHOT_SECS", Some("0"), "Exchange reserve snapshots via balanceOf (0 = off)"),
+    var("RESERVE_DIVERGENCE_WEI", Some("1000000000000000000000"), "Reserve vs netflow divergence tolerance in wei"),
+    var("MIN_EXCHANGES", Some("1"), "Distinct exchanges with flow before coverage is not low"),
+    var("MAX_NETFLOW_AGE_BLOCKS", None, "Flag /netflow stale when the checkpoint trails head by more blocks (beyond CONFIRMATIONS)"),
+    var("MAX_NETFLOW_AGE_SECS", None, "Flag /netflow stale when the checkpoint hasn't advanced for this long"),
+    var("NETFLOW_BY_BLOCK", Some("false"), "Per-block exchange flow table for /netflow/by-block"),
+    var("NETFLOW_BY_BLOCK_KEEP_BLOCKS", None, "Prune /netflow/by-block rows to the last N blocks"),
+    EnvVar { secret: Secret::Url, ..var("EXCHANGE_LIST_URL", None, "Exchange list feed (JSON) re-pulled periodically") },
+    var("EXCHANGE_LIST_REFRESH_SECS", Some("3600"), "Exchange list feed refresh interval"),
+    var("EXCHANGE_LIST_PRUNE", Some("false"), "Remove feed addresses that disappear from the feed"),
+    var("PARQUET_DIR", None, "Parquet export directory (requires --features parquet-sink)"),
+    var("PARQUET_FLUSH_SECS", Some("60"), "Parquet export interval"),
+    var("MAX_IN_FLIGHT_LOGS", Some("10000"), "Queued logs before subscription reads pause"),
+    var("LOG_WORKERS", Some("1"), "Logs prefetching receipts/code concurrently; commits stay in order"),
+    var("NETFLOW_FLUSH_EVERY", Some("100"), "Transfers (rows plus netflow_state updates) batched per commit"),
+    var("NETFLOW_FLUSH_MS", Some("250"), "Longest a transfer waits before being committed"),
+    var("TOTAL_SUPPLY_REFRESH_SECS", Some("0"), "totalSupply() refresh for net_pct_of_supply (0 = off)"),
+    var("STRICT", Some("false"), "Record silently skipped logs to skipped_logs"),
+    var("STRICT_DEGRADED_AFTER", Some("3"), "Skipped logs before status reports degraded"),
+    var("METADATA_TTL_SECS", Some("86400"), "Seconds a cached RPC lookup is trusted"),
+    var("NATIVE_FLOW", Some("off"), "Native POL exchange flow: off | tx | trace (trace needs the node's trace API)"),
+];
+
+/// Prefix of per-token display label overrides (`DISPLAY_SYMBOL_<address>`)
+const DISPLAY_SYMBOL_PREFIX: &str = "DISPLAY_SYMBOL_";
+
+pub(crate) fn redact_url(raw: &str) -> String {
+    match reqwest::Url::parse(raw) {
+        Ok(u) => match u.host_str() {
+            Some(host) => format!("{}://{}/{REDACTED}", u.scheme(), host),
+            None => REDACTED.into(),
+        },
+        Err(_) => REDACTED.into(),
+    }
+}
+
+fn shown(v: &EnvVar, value: &str) -> String {
+    match v.secret {
+        Secret::No => value.to_string(),
+        Secret::Yes => REDACTED.into(),
+        Secret::Url => redact_url(value),
+        Secret::Urls => value.split(',').map(|u| redact_url(u.trim())).collect::<Vec<_>>().join(","),
+    }
+}
+
+/// `.env` text covering every recognized variable: set ones with their current
+/// (redacted) value, unset ones with their default, or commented out when there is none.
+pub fn render() -> String {
+    let mut out = String::from("# Generated from the running configuration; secrets are redacted\n");
+    for v in VARS {
+        out.push_str(&format!("\n# {}", v.help));
+        if let Some(d) = v.default {
+            out.push_str(&format!(" (default: {d})"));
+        }
+        out.push('\n');
+        match (env::var(v.key).ok().filter(|s| !s.is_empty()), v.default) {
+            (Some(value), _) => out.push_str(&format!("{}={}\n", v.key, shown(v, &value))),
+            (None, Some(d)) => out.push_str(&format!("{}={d}\n", v.key)),
+            (None, None) => out.push_str(&format!("# {}=\n", v.key)),
+        }
+    }
+
+    let mut symbols: Vec<(String, String)> = env::vars()
+        .filter(|(k, _)| k.starts_with(DISPLAY_SYMBOL_PREFIX))
+        .collect();
+    symbols.sort();
+    out.push_str("\n# Per-token display label override in API responses\n");
+    if symbols.is_empty() {
+        out.push_str(&format!("# {DISPLAY_SYMBOL_PREFIX}0x0000000000000000000000000000000000000000=POL\n"));
+    }
+    for (k, v) in symbols {
+        out.push_str(&format!("{k}={v}\n"));
+    }
+    out
+}
@@ -0,0 +1,199 @@
+
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use ethers::providers::ProviderError;
+use serde_json::json;
+use tracing::error;
+
+/// Indexer and database results; `E` is overridable for raw provider calls.
+pub type Result<T, E = IndexerError> = std::result::Result<T, E>;
+
+/// Why an indexer or database operation failed, so callers can tell a node problem
+/// (worth retrying or reconnecting) from bad data or a database fault.
+#[derive(Debug, thiserror::Error)]
+pub enum IndexerError {
+    /// The node couldn't be reached or rejected a call
+    #[error("{context}: {source}")]
+    Rpc {
+        context: String,
+        #[source]
+        source: ProviderError,
+    },
+    /// The node answered without something it should have (a block, a receipt)
+    #[error("{0}")]
+    MissingData(String),
+    /// A log, stored row or file line that doesn't parse
+    #[error("{0}")]
+    Decode(String),
+    #[error("database: {0}")]
+    Db(#[from] sqlx::Error),
+    /// Settings that turn out unusable at runtime
+    #[error("config: {0}")]
+    Config(String),
+    #[error("reorg deeper than {max} blocks below {block}; refusing to roll back automatically")]
+    ReorgTooDeep { block: u64, max: u64 },
+    /// The node serves another network than the one expected; never retried
+    #[error("RPC endpoint is on chain {actual}, but {by} expects chain {expected}")]
+    WrongChain { actual: u64, expected: u64, by: &'static str },
+    #[error("I/O: {0}")]
+    Io(#[from] std::io::Error),
+    /// From helpers that still report through anyhow (metadata, alerts, jobs, ...)
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl IndexerError {
+    /// `map_err` adapter attaching what was being asked of the node.
+    pub fn rpc(context: impl Into<String>) -> impl FnOnce(ProviderError) -> Self {
+        let context = context.into();
+        move |source| IndexerError::Rpc { context, source }
+    }
+
+    pub fn decode(msg: impl std::fmt::Display) -> Self {
+        IndexerError::Decode(msg.to_string())
+    }
+
+    /// Short label for logs and per-kind counting
+    pub fn kind(&self) -> &'static str {
+        match self {
+            IndexerError::Rpc { .. } => "rpc",
+            IndexerError::MissingData(_) => "missing_data",
+            IndexerError::Decode(_) => "decode",
+            IndexerError::Db(_) => "db",
+            IndexerError::Config(_) => "config",
+            IndexerError::ReorgTooDeep { .. } => "reorg",
+            IndexerError::WrongChain { .. } => "chain",
+            IndexerError::Io(_) => "io",
+            IndexerError::Other(_) => "other",
+        }
+    }
+}
+
+impl From<ProviderError> for IndexerError {
+    fn from(source: ProviderError) -> Self {
+        IndexerError::Rpc { context: "RPC call failed".into(), source }
+    }
+}
+
+impl From<serde_json::Error> for IndexerError {
+    fn from(e: serde_json::Error) -> Self {
+        IndexerError::Decode(format!("JSON: {e}"))
+    }
+}
+
+/// Every API failure, rendered as `{"error": {"code": ..., "message": ...}}`.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    RateLimited { retry_after: u64 },
+    /// A dependency (the database) is not answering; the message is sent as is
+    Unavailable(String),
+    /// Details are logged, never sent to the client
+    Internal(String),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable machine-readable code
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            ApiError::BadRequest(m)
+            | ApiError::Unauthorized(m)
+            | ApiError::Forbidden(m)
+            | ApiError::NotFound(m)
+            | ApiError::Conflict(m)
+            | ApiError::Unavailable(m) => m.clone(),
+            ApiError::RateLimited { retry_after } => format!("rate limit exceeded, retry in {retry_after}s"),
+            ApiError::Internal(_) => "internal error".into(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let ApiError::Internal(detail) = &self {
+            error!("API internal error: {detail}");
+        }
+        let body = axum::Json(json!({ "error": { "code": self.code(), "message": self.message() } }));
+        let mut resp = (self.status(), body).into_response();
+        if let ApiError::RateLimited { retry_after } = self {
+            resp.headers_mut().insert(header::RETRY_AFTER, retry_after.into());
+        }
+        resp
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        ApiError::Internal(format!("database: {e}"))
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError::Internal(format!("{e:#}"))
+    }
+}
+
+impl From<IndexerError> for ApiError {
+    fn from(e: IndexerError) -> Self {
+        ApiError::Internal(format!("{e:#}"))
+    }
+}
+
+/// Stored big integers that fail to parse mean a corrupt row, not bad input.
+impl From<rug::integer::ParseIntegerError> for ApiError {
+    fn from(e: rug::integer::ParseIntegerError) -> Self {
+        ApiError::Internal(format!("corrupt stored amount: {e}"))
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(e: QueryRejection) -> Self {
+        ApiError::BadRequest(e.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(e: PathRejection) -> Self {
+        ApiError::BadRequest(e.body_text())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(e: JsonRejection) -> Self {
+        ApiError::BadRequest(e.body_text())
+    }
+}
@@ -0,0 +1,215 @@
+
+use anyhow::{Context, Result};
+use ethers::types::Address;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use crate::indexer::{refresh_exchange_cache, start_recompute_job, Indexer};
+
+/// `source` of rows owned by the feed; seeded/manual rows are never touched by it
+const FEED_SOURCE: &str = "feed";
+
+#[derive(Clone, Debug)]
+pub struct ExchangeFeedCfg {
+    /// JSON list of `{"address": "0x..", "exchange": "label"}`; `None` disables the refresh
+    pub url: Option<String>,
+    pub interval_secs: u64,
+    /// Delete feed-sourced addresses that disappeared from the feed
+    pub prune: bool,
+}
+
+#[derive(Deserialize)]
+struct FeedEntry {
+    address: String,
+    #[serde(alias = "label")]
+    exchange: String,
+}
+
+/// Changes needed to bring `exchange_addresses` in line with the feed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Reconcile {
+    pub add: Vec<(String, String)>,
+    pub relabel: Vec<(String, String)>,
+    pub remove: Vec<String>,
+}
+
+impl Reconcile {
+    pub fn is_empty(&self) -> bool {
+        self.add.is_empty() && self.relabel.is_empty() && self.remove.is_empty()
+    }
+}
+
+/// Diff `current` (address -> (label, source)) against `feed` (address -> label).
+/// Addresses not owned by the feed are left alone even when the feed lists them.
+pub fn plan(current: &HashMap<String, (String, String)>, feed: &HashMap<String, String>, prune: bool) -> Reconcile {
+    let mut r = Reconcile::default();
+    for (addr, label) in feed {
+        match current.get(addr) {
+            None => r.add.push((addr.clone(), label.clone())),
+            Some((old, source)) if source == FEED_SOURCE && old != label => {
+                r.relabel.push((addr.clone(), label.clone()))
+            }
+            Some(_) => {}
+        }
+    }
+    if prune {
+        r.remove = current.iter()
+            .filter(|(addr, (_, source))| source == FEED_SOURCE && !feed.contains_key(*addr))
+            .map(|(addr, _)| addr.clone())
+            .collect();
+    }
+    r.add.sort();
+    r.relabel.sort();
+    r.remove.sort();
+    r
+}
+
+async fn fetch(client: &reqwest::Client, url: &str) -> Result<HashMap<String, String>> {
+    let entries: Vec<FeedEntry> = client.get(url).send().await?
+        .error_for_status()?
+        .json().await
+        .context("exchange feed is not a JSON list of {address, exchange}")?;
+    let mut out = HashMap::new();
+    for e in entries {
+        match e.address.trim().parse::<Address>() {
+            Ok(a) => { out.insert(format!("{:#x}", a), e.exchange); }
+            Err(_) => warn!("exchange feed: skipping invalid address {:?}", e.address),
+        }
+    }
+    Ok(out)
+}
+
+/// Apply one feed pull; returns the applied changes.
+async fn refresh(db: &SqlitePool, client: &reqwest::Client, cfg: &ExchangeFeedCfg, url: &str) -> Result<Reconcile> {
+    let feed = fetch(client, url).await?;
+    let current: HashMap<String, (String, String)> =
+        sqlx::query_as::<_, (String, String, String)>("SELECT lower(address), exchange, source FROM exchange_addresses;")
+            .fetch_all(db).await?
+            .into_iter().map(|(a, l, s)| (a, (l, s))).collect();
+    let r = plan(&current, &feed, cfg.prune);
+    if r.is_empty() {
+        return Ok(r);
+    }
+
+    let mut tx = db.begin().await?;
+    for (addr, label) in &r.add {
+        sqlx::query("INSERT OR IGNORE INTO exchange_addresses(address, exchange, source) VALUES (?, ?, ?);")
+            .bind(addr)
+            .bind(label)
+            .bind(FEED_SOURCE)
+            .execute(&mut *tx).await?;
+    }
+    for (addr, label) in &r.relabel {
+        sqlx::query("UPDATE exchange_addresses SET exchange = ? WHERE address = ? AND source = ?;")
+            .bind(label)
+            .bind(addr)
+            .bind(FEED_SOURCE)
+            .execute(&mut *tx).await?;
+    }
+    for addr in &r.remove {
+        sqlx::query("DELETE FROM exchange_addresses WHERE address = ? AND source = ?;")
+            .bind(addr)
+            .bind(FEED_SOURCE)
+            .execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(r)
+}
+
+/// Periodically reconcile `exchange_addresses` with the feed and, when membership
+/// changed, reclassify history with a background recompute.
+pub async fn run(ix: Indexer, cfg: ExchangeFeedCfg) {
+    let Some(url) = cfg.url.clone() else { return };
+    let client = reqwest::Client::new();
+    let mut tick = tokio::time::interval(Duration::from_secs(cfg.interval_secs.max(1)));
+    loop {
+        tick.tick().await;
+        let r = match refresh(&ix.db, &client, &cfg, &url).await {
+            Ok(r) => r,
+            Err(e) => {
+                error!("exchange feed refresh failed: {e:#}");
+                continue;
+            }
+        };
+        if r.is_empty() {
+            continue;
+        }
+        if let Err(e) = refresh_exchange_cache(&ix).await {
+            error!("failed to reload exchange set: {e:#}");
+        }
+        info!(
+            "Exchange feed: {} added, {} relabelled, {} removed",
+            r.add.len(), r.relabel.len(), r.remove.len()
+        );
+        match start_recompute_job(&ix).await {
+            Ok(Some(id)) => info!("Reclassifying history after exchange set change (job {id})"),
+            Ok(None) => warn!("exchange set changed while a recompute is running; trigger another once it finishes"),
+            Err(e) => error!("failed to start recompute: {e:#}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::tests::{test_cfg, test_indexer, BINANCE, COINBASE, USER_B};
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    /// Serves whatever `feed` holds at the time of each request; returns its URL.
+    async fn feed_server(feed: Arc<Mutex<Value>>) -> String {
+        let app = axum::Router::new().route("/", axum::routing::get(move || {
+            let body = feed.lock().unwrap().clone();
+            async move { axum::Json(body) }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind feed server");
+        let addr = listener.local_addr().expect("feed address");
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}/")
+    }
+
+    async fn rows(db: &SqlitePool) -> Vec<(String, String, String)> {
+        sqlx::query_as("SELECT address, exchange, source FROM exchange_addresses ORDER BY address;")
+            .fetch_all(db).await.expect("exchange_addresses")
+    }
+
+    #[tokio::test]
+    async fn refresh_adds_relabels_and_prunes_only_feed_rows() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        let feed = Arc::new(Mutex::new(json!([
+            {"address": COINBASE.to_uppercase().replace("0X", "0x"), "exchange": "coinbase"},
+            // Seeded rows belong to the operator, whatever the feed says
+            {"address": BINANCE, "exchange": "not-binance"},
+            {"address": "0xnot-an-address", "exchange": "junk"},
+        ])));
+        let url = feed_server(feed.clone()).await;
+        let client = reqwest::Client::new();
+        let mut cfg = ExchangeFeedCfg { url: Some(url.clone()), interval_secs: 60, prune: false };
+        let row = |a: &str, l: &str, s: &str| (a.to_string(), l.to_string(), s.to_string());
+
+        let r = refresh(&ix.db, &client, &cfg, &url).await.expect("first pull");
+        assert_eq!(r.add, vec![(COINBASE.to_string(), "coinbase".to_string())]);
+        assert_eq!(rows(&ix.db).await, vec![row(BINANCE, "binance", "seed"), row(COINBASE, "coinbase", "feed")]);
+
+        // Without EXCHANGE_LIST_PRUNE an address leaving the feed stays
+        *feed.lock().unwrap() = json!([{"address": USER_B, "label": "kraken"}, {"address": COINBASE, "exchange": "coinbase-hot"}]);
+        let r = refresh(&ix.db, &client, &cfg, &url).await.expect("second pull");
+        assert_eq!((r.add.len(), r.relabel.len(), r.remove.len()), (1, 1, 0));
+        assert_eq!(rows(&ix.db).await, vec![
+            row(USER_B, "kraken", "feed"),
+            row(BINANCE, "binance", "seed"),
+            row(COINBASE, "coinbase-hot", "feed"),
+        ]);
+
+        cfg.prune = true;
+        *feed.lock().unwrap() = json!([{"address": USER_B, "exchange": "kraken"}]);
+        let r = refresh(&ix.db, &client, &cfg, &url).await.expect("third pull");
+        assert_eq!(r, Reconcile { remove: vec![COINBASE.to_string()], ..Reconcile::default() });
+        assert_eq!(rows(&ix.db).await, vec![row(USER_B, "kraken", "feed"), row(BINANCE, "binance", "seed")]);
+
+        // Nothing left to change
+        assert!(refresh(&ix.db, &client, &cfg, &url).await.expect("fourth pull").is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct Entry {
+    address: String,
+    #[serde(alias = "label")]
+    exchange: String,
+}
+
+/// Read `--exchanges-file` as `(address, exchange label)` pairs: a JSON list of
+/// `{"address": "0x..", "exchange": "label"}` (like the exchange list feed) for `.json`,
+/// `address,exchange` rows for `.csv` (optional header, blank lines and `#` comments
+/// skipped). Addresses are only validated when stored; an empty label is an error here.
+pub fn load(path: &Path) -> Result<Vec<(String, String)>> {
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read exchanges file {}", path.display()))?;
+    let ext = path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+    let pairs = match ext.as_deref() {
+        Some("json") => serde_json::from_str::<Vec<Entry>>(&raw)
+            .with_context(|| format!("{} is not a JSON list of {{address, exchange}}", path.display()))?
+            .into_iter()
+            .map(|e| (e.address.trim().to_string(), e.exchange.trim().to_string()))
+            .collect::<Vec<_>>(),
+        Some("csv") => parse_csv(&raw).with_context(|| format!("in {}", path.display()))?,
+        _ => bail!("unknown exchanges file type {} (expected .json or .csv)", path.display()),
+    };
+    if let Some((addr, _)) = pairs.iter().find(|(_, label)| label.is_empty()) {
+        bail!("{} lists {addr:?} without an exchange label", path.display());
+    }
+    Ok(pairs)
+}
+
+fn parse_csv(raw: &str) -> Result<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    for (i, line) in raw.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((addr, label)) = line.split_once(',') else {
+            bail!("line {}: expected address,exchange", i + 1);
+        };
+        let (addr, label) = (addr.trim(), label.trim().trim_matches('"'));
+        if pairs.is_empty() && addr.eq_ignore_ascii_case("address") {
+            continue;
+        }
+        pairs.push((addr.to_string(), label.to_string()));
+    }
+    Ok(pairs)
+}
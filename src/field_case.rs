@@ -0,0 +1,120 @@
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::Value;
+use crate::error::ApiError;
+
+/// Key style of JSON response bodies. Structs serialize snake_case; `Camel` renames
+/// every object key on the way out, so handlers and error bodies need no changes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FieldCase {
+    #[default]
+    Snake,
+    Camel,
+}
+
+impl std::str::FromStr for FieldCase {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "snake" => Ok(Self::Snake),
+            "camel" => Ok(Self::Camel),
+            other => anyhow::bail!("unknown API_FIELD_CASE {other:?} (snake|camel)"),
+        }
+    }
+}
+
+/// `cumulative_in_wei` -> `cumulativeInWei`
+fn to_camel(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' && !out.is_empty() {
+            upper = true;
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Rename object keys recursively. Only field names are keys in our responses
+/// (labels, addresses and hashes are always values), so this never touches data.
+pub(crate) fn camel_keys(v: Value) -> Value {
+    match v {
+        Value::Object(map) => Value::Object(map.into_iter().map(|(k, v)| (to_camel(&k), camel_keys(v))).collect()),
+        Value::Array(items) => Value::Array(items.into_iter().map(camel_keys).collect()),
+        other => other,
+    }
+}
+
+/// Rewrite JSON response bodies into the configured case.
+pub async fn recase_json(State(case): State<FieldCase>, req: Request, next: Next) -> Response {
+    let res = next.run(req).await;
+    let is_json = res.headers().get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if case == FieldCase::Snake || !is_json {
+        return res;
+    }
+    let (mut parts, body) = res.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(b) => b,
+        Err(e) => return ApiError::Internal(format!("reading response body: {e}")).into_response(),
+    };
+    let out = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(v) => serde_json::to_vec(&camel_keys(v)).expect("JSON value serializes"),
+        Err(_) => bytes.to_vec(),
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get, Router};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    fn body() -> Value {
+        json!({
+            "cumulative_in": "1",
+            "coverage": {"min_exchanges": 1, "low_coverage": false},
+            "labels": [{"label": "binance_hot", "cumulative_net": "-1"}],
+        })
+    }
+
+    async fn get_body(case: FieldCase, uri: &str) -> Vec<u8> {
+        let app = Router::new()
+            .route("/json", get(|| async { axum::Json(body()) }))
+            .route("/text", get(|| async { "cumulative_in,cumulative_out" }))
+            .layer(middleware::from_fn_with_state(case, recase_json));
+        let req = axum::http::Request::builder().uri(uri).body(Body::empty()).expect("request");
+        let resp = app.oneshot(req).await.expect("infallible");
+        axum::body::to_bytes(resp.into_body(), usize::MAX).await.expect("body").to_vec()
+    }
+
+    #[tokio::test]
+    async fn camel_case_renames_nested_keys_and_leaves_values() {
+        let camel: Value = serde_json::from_slice(&get_body(FieldCase::Camel, "/json").await).expect("JSON");
+        assert_eq!(camel, json!({
+            "cumulativeIn": "1",
+            "coverage": {"minExchanges": 1, "lowCoverage": false},
+            "labels": [{"label": "binance_hot", "cumulativeNet": "-1"}],
+        }));
+        let snake: Value = serde_json::from_slice(&get_body(FieldCase::Snake, "/json").await).expect("JSON");
+        assert_eq!(snake, body());
+        // Only JSON bodies are rewritten
+        assert_eq!(get_body(FieldCase::Camel, "/text").await, b"cumulative_in,cumulative_out");
+    }
+}
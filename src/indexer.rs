@@ -1,25 +1,401 @@
 
-use anyhow::{Context, Result};
 use ethers::abi::{AbiDecode};
 use ethers::core::types::{Filter, H160, H256, Log, BlockNumber, Address, U64};
-use ethers::providers::{Middleware, Provider, Ws};
-use sqlx::SqlitePool;
-use tracing::{info, warn, error};
-use std::sync::Arc;
+use ethers::providers::{Middleware, Provider, ProviderError, StreamExt, SubscriptionStream};
+use futures_util::stream::FuturesUnordered;
+use sqlx::{SqliteConnection, SqlitePool};
+use tracing::{debug, info, warn, error};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use crate::alerts::Alerts;
+use crate::blocks;
+use crate::db::check_chain_id;
+use crate::dry_run::DryRun;
+use crate::env_template::redact_url;
+use crate::error::{IndexerError, Result};
+use crate::metadata;
+use crate::native::{self, NativeMode};
+use crate::netflow_writer::{Announce, Counted, NetflowWriter, TransferWrite};
+use crate::recent::RecentTransfers;
+use crate::reserves::{self, ReserveCfg};
+use crate::transport::{self, Transport};
+use crate::units::parse_amount;
 
 const TRANSFER_TOPIC: &str = "ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"; // keccak("Transfer(address,address,uint256)")
+/// Deepest rollback done automatically; anything deeper needs an operator
+pub const MAX_REORG_DEPTH: u64 = 1000;
+/// Receipt statuses kept in memory; the cache is reset when it grows past this
+const RECEIPT_CACHE_CAP: usize = 100_000;
+/// First delay between RPC retries, doubled per attempt up to `RETRY_MAX_DELAY`
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(250);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(5);
+/// JSON-RPC "limit exceeded", what rate-limiting providers answer with
+const RPC_LIMIT_EXCEEDED: i64 = -32005;
+
+/// What to do with mints to / burns from an exchange wallet (the other side is 0x0),
+/// which change supply rather than reflect user deposits or withdrawals.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ZeroAddressRule {
+    /// Count like any other transfer (legacy behavior)
+    #[default]
+    Count,
+    /// Leave out of netflow entirely
+    Exclude,
+    /// Leave out of netflow, accumulate into `minted_wei`/`burned_wei`
+    Separate,
+}
+
+impl std::str::FromStr for ZeroAddressRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "count" => Ok(Self::Count),
+            "exclude" => Ok(Self::Exclude),
+            "separate" => Ok(Self::Separate),
+            other => anyhow::bail!("unknown ZERO_ADDRESS_RULE {other:?} (count|exclude|separate)"),
+        }
+    }
+}
+
+/// How a session receives new logs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IndexerMode {
+    /// `eth_subscribe` logs over the WebSocket; falls back to `Poll` when the node lacks it
+    #[default]
+    Subscribe,
+    /// `eth_getLogs` over each newly final range every `poll_interval_secs`
+    Poll,
+}
+
+impl std::str::FromStr for IndexerMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "subscribe" => Ok(Self::Subscribe),
+            "poll" => Ok(Self::Poll),
+            other => anyhow::bail!("unknown INDEXER_MODE {other:?} (poll|subscribe)"),
+        }
+    }
+}
+
+/// Deterministic sampling of transfers that touch no exchange address, to reduce
+/// storage/processing on very busy tokens. Exchange transfers are always kept,
+/// so netflow stays exact; only non-exchange volume/counts become estimates.
+#[derive(Clone, Copy, Debug)]
+pub struct Sampling {
+    /// Fraction kept, in (0, 1]; 1 disables sampling
+    pub rate: f64,
+    /// Sample whole blocks instead of individual transfers
+    pub by_block: bool,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self { rate: 1.0, by_block: false }
+    }
+}
+
+impl Sampling {
+    pub fn enabled(&self) -> bool {
+        self.rate < 1.0
+    }
+
+    /// Hash-based so the same log is always kept or dropped (replays are reproducible).
+    fn keep(&self, tx_hash: H256, log_index: u64, block: u64) -> bool {
+        if !self.enabled() {
+            return true;
+        }
+        let digest = if self.by_block {
+            ethers::utils::keccak256(block.to_be_bytes())
+        } else {
+            ethers::utils::keccak256([tx_hash.as_bytes(), &log_index.to_be_bytes()[..]].concat())
+        };
+        let x = u64::from_be_bytes(digest[..8].try_into().expect("8 bytes"));
+        (x as f64) < self.rate * (u64::MAX as f64)
+    }
+}
 
 #[derive(Clone)]
 pub struct IndexerCfg {
-    pub rpc_url: String,
-    pub token: Address,
+    /// WebSocket endpoints in failover order; empty only for offline runs (replay, recompute)
+    pub rpc_urls: Vec<String>,
+    /// Token contracts indexed, each with its own `netflow_state` row; never empty
+    pub tokens: Vec<Address>,
+    /// `CONFIRMATIONS` at startup; read the live value with `Indexer::confirmations`
     pub confirmations: u64,
+    /// Exclude exchange transfers whose counterparty is a contract (costs a `get_code` per new address)
+    pub exclude_contract_counterparties: bool,
+    /// Warn when logs keep arriving but the reported head hasn't advanced for this long
+    pub head_stale_secs: u64,
+    /// Size of the in-memory ring of recent transfers (0 disables it)
+    pub recent_cap: usize,
+    pub zero_address_rule: ZeroAddressRule,
+    /// Keep raw topics/data per transfer in `erc20_transfer_raw`
+    pub store_raw_logs: bool,
+    pub sampling: Sampling,
+    /// Periodic exchange reserve snapshots
+    pub reserves: ReserveCfg,
+    /// Check the receipt status before applying a log (one receipt fetch per new tx)
+    pub verify_receipt_status: bool,
+    /// Logs buffered between the subscription and processing before reads pause
+    pub max_in_flight: usize,
+    /// Logs whose RPC lookups run concurrently ahead of the in-order commit
+    pub log_workers: usize,
+    /// Seconds between `totalSupply()` refreshes; 0 disables supply tracking
+    pub supply_refresh_secs: u64,
+    /// Record silently skipped/ambiguous logs to `skipped_logs` instead of ignoring them
+    pub strict: bool,
+    /// Skipped logs after which STRICT mode reports degraded status
+    pub strict_degraded_after: u64,
+    /// How long `metadata` entries (code, supply, ...) are trusted before refetching
+    pub metadata_ttl_secs: u64,
+    /// Maintain `netflow_by_block`
+    pub netflow_by_block: bool,
+    /// Keep only this many blocks of `netflow_by_block` behind the newest row
+    pub by_block_keep_blocks: Option<u64>,
+    /// Separate native POL exchange flow aggregate (`native_netflow_state`)
+    pub native: NativeMode,
+    /// Blocks per `eth_getLogs` request when backfilling
+    pub backfill_chunk_blocks: u64,
+    /// On an empty database, backfill from this block instead of starting at the head
+    pub start_block: Option<u64>,
+    /// Unix seconds; resolved to a `start_block` when that isn't set
+    pub start_timestamp: Option<u64>,
+    /// Consecutive failed reconnects before the indexer gives up; `None` retries forever
+    pub ws_max_retries: Option<u32>,
+    /// Refuse RPC endpoints on any other chain
+    pub expected_chain_id: Option<u64>,
+    pub mode: IndexerMode,
+    /// Poll mode: seconds between `eth_blockNumber` + `eth_getLogs` rounds
+    pub poll_interval_secs: u64,
+    /// Classify zero-value and self transfers like any other (they are always stored)
+    pub count_zero_transfers: bool,
+    /// Extra attempts for a head, block or `eth_getLogs` call failing with a transport error
+    pub rpc_retries: u32,
+    /// `netflow_state` deltas the writer batches before committing
+    pub netflow_flush_every: usize,
+    /// Longest a delta waits in the writer's batch
+    pub netflow_flush_ms: u64,
+    /// `--dry-run`: classify as usual but keep the results in `Indexer::dry_run`
+    pub dry_run: bool,
+}
+
+impl IndexerCfg {
+    /// The first configured token: the API default, and the one reserve snapshots,
+    /// supply tracking and alerts follow.
+    pub fn primary_token(&self) -> Address {
+        self.tokens[0]
+    }
 }
 
 #[derive(Clone)]
 pub struct Indexer {
     pub db: SqlitePool,
     pub cfg: IndexerCfg,
+    pub alerts: Arc<Alerts>,
+    /// address -> has code; contracts rarely self-destruct, so entries never expire
+    pub code_cache: Arc<RwLock<HashMap<Address, bool>>>,
+    /// tx hash -> receipt status was success; bounded by `RECEIPT_CACHE_CAP`
+    pub receipt_cache: Arc<RwLock<HashMap<H256, bool>>>,
+    /// Exchange address -> label, mirrored from `exchange_addresses` so classifying a
+    /// transfer needs no query; reload with `refresh_exchange_cache` after changing the table
+    pub exchanges: Arc<RwLock<HashMap<Address, String>>>,
+    /// Mirror of `neutral_addresses`, reloaded along with `exchanges`
+    pub neutral: Arc<RwLock<HashSet<Address>>>,
+    pub status: Arc<IndexerStatus>,
+    pub recent: Arc<RecentTransfers>,
+    /// `(block, timestamp)` of the last block a transfer was stored for; its other logs
+    /// skip the lookup
+    pub last_block_ts: Arc<RwLock<Option<(u64, i64)>>>,
+    /// Latest `totalSupply()` in base units, when the token provides one
+    pub supply: Arc<RwLock<Option<rug::Integer>>>,
+    /// Fresh totals after every committed `netflow_state` batch, for `/ws/netflow`
+    pub updates: broadcast::Sender<NetflowUpdate>,
+    /// The only writer of live `netflow_state` updates; `flush` before reading totals
+    /// that must include everything sent so far
+    pub netflow: NetflowWriter,
+    /// Effective confirmation depth: `cfg.confirmations` until changed at runtime
+    confirmations: Arc<AtomicU64>,
+    /// Set under `--dry-run`: transfers, totals and staged logs live here instead of
+    /// the database, and nothing on the indexing path writes to it
+    pub dry_run: Option<Arc<DryRun>>,
+}
+
+/// Updates a slow `/ws/netflow` client may fall behind by before it skips ahead
+const UPDATES_CAPACITY: usize = 256;
+
+/// One token's `netflow_state` totals right after a batch with counted transfers was committed.
+#[derive(Clone, Debug)]
+pub struct NetflowUpdate {
+    pub contract: Address,
+    pub in_wei: rug::Integer,
+    pub out_wei: rug::Integer,
+    pub block: i64,
+}
+
+/// Live progress shared with the API. Zero means "not observed yet".
+#[derive(Default)]
+pub struct IndexerStatus {
+    head: AtomicU64,
+    /// Unix seconds when `head` last advanced
+    head_advanced_at: AtomicU64,
+    head_stale: AtomicBool,
+    last_processed_block: AtomicU64,
+    /// Unix seconds when `last_processed_block` last advanced
+    processed_advanced_at: AtomicU64,
+    /// Logs received but not yet processed
+    pub queue_depth: AtomicU64,
+    /// Times the queue filled up and subscription reads paused
+    pub backpressure_events: AtomicU64,
+    /// STRICT mode: logs recorded to `skipped_logs` by this process
+    pub skipped_logs: AtomicU64,
+    /// Transfer logs with the wrong topic count, a non-address topic or non-32-byte data
+    pub malformed_logs: AtomicU64,
+    /// Transfers newly stored by this process, and those touching an exchange
+    pub transfers_processed: AtomicU64,
+    pub exchange_transfers: AtomicU64,
+    /// Failed RPC calls on the indexing path (connect, head, logs, blocks, receipts, code)
+    pub rpc_errors: AtomicU64,
+    degraded: AtomicBool,
+    /// Transfers/s and blocks/s over the last throughput interval (`f64` bits), once measured
+    transfer_rate: AtomicU64,
+    block_rate: AtomicU64,
+    rates_measured: AtomicBool,
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl IndexerStatus {
+    pub fn head(&self) -> Option<u64> {
+        Some(self.head.load(Ordering::Relaxed)).filter(|&b| b > 0)
+    }
+
+    pub fn last_processed_block(&self) -> Option<u64> {
+        Some(self.last_processed_block.load(Ordering::Relaxed)).filter(|&b| b > 0)
+    }
+
+    /// Seconds since the processed checkpoint last advanced, once a block was processed.
+    pub fn processed_age_secs(&self) -> Option<u64> {
+        self.last_processed_block()?;
+        Some(unix_now().saturating_sub(self.processed_advanced_at.load(Ordering::Relaxed)))
+    }
+
+    /// Seconds since the head last advanced, once a head has been seen.
+    pub fn head_age_secs(&self) -> Option<u64> {
+        self.head()?;
+        Some(unix_now().saturating_sub(self.head_advanced_at.load(Ordering::Relaxed)))
+    }
+
+    /// Set while logs keep arriving but the head is stuck (likely a lagging RPC node).
+    pub fn head_stale(&self) -> bool {
+        self.head_stale.load(Ordering::Relaxed)
+    }
+
+    /// STRICT mode found enough questionable logs that the data needs a look.
+    pub fn degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    fn set_head(&self, head: u64) {
+        if self.head.fetch_max(head, Ordering::Relaxed) < head {
+            self.head_advanced_at.store(unix_now(), Ordering::Relaxed);
+            if self.head_stale.swap(false, Ordering::Relaxed) {
+                info!("Head advanced to {}; no longer stale", head);
+            }
+        }
+    }
+
+    /// Called on every incoming log: flag (and warn once) when the head hasn't
+    /// moved for `limit_secs` even though the chain is clearly producing logs.
+    fn check_head_staleness(&self, limit_secs: u64) {
+        let Some(age) = self.head_age_secs() else { return };
+        if age >= limit_secs && !self.head_stale.swap(true, Ordering::Relaxed) {
+            warn!(
+                "Head stuck at {} for {}s while logs keep arriving: the RPC node may be lagging",
+                self.head.load(Ordering::Relaxed), age
+            );
+        }
+    }
+
+    /// Lag of the last processed block behind the head, once both are known.
+    pub fn lag_blocks(&self) -> Option<u64> {
+        Some(self.head()?.saturating_sub(self.last_processed_block()?))
+    }
+
+    /// Count a failed RPC call; passes the result through for `?`.
+    fn rpc<T, E>(&self, r: Result<T, E>) -> Result<T, E> {
+        if r.is_err() {
+            self.rpc_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        r
+    }
+
+    /// `(transfers/s, blocks/s)` over the last `THROUGHPUT_LOG_SECS` interval.
+    pub fn rates(&self) -> Option<(f64, f64)> {
+        self.rates_measured.load(Ordering::Relaxed).then(|| (
+            f64::from_bits(self.transfer_rate.load(Ordering::Relaxed)),
+            f64::from_bits(self.block_rate.load(Ordering::Relaxed)),
+        ))
+    }
+
+    fn set_processed(&self, block: u64) {
+        if self.last_processed_block.fetch_max(block, Ordering::Relaxed) < block {
+            self.processed_advanced_at.store(unix_now(), Ordering::Relaxed);
+        }
+    }
+}
+
+impl Indexer {
+    pub fn new(db: SqlitePool, cfg: IndexerCfg, alerts: Alerts) -> Self {
+        let alerts = Arc::new(alerts);
+        let updates = broadcast::channel(UPDATES_CAPACITY).0;
+        let netflow = NetflowWriter::spawn(
+            db.clone(),
+            cfg.netflow_flush_every,
+            Duration::from_millis(cfg.netflow_flush_ms),
+            cfg.by_block_keep_blocks,
+            Announce { primary: cfg.primary_token(), alerts: alerts.clone(), updates: updates.clone() },
+        );
+        Self {
+            confirmations: Arc::new(AtomicU64::new(cfg.confirmations)),
+            dry_run: cfg.dry_run.then(|| Arc::new(DryRun::default())),
+            db,
+            recent: Arc::new(RecentTransfers::new(cfg.recent_cap)),
+            cfg,
+            alerts,
+            code_cache: Arc::new(RwLock::new(HashMap::new())),
+            receipt_cache: Arc::new(RwLock::new(HashMap::new())),
+            exchanges: Arc::new(RwLock::new(HashMap::new())),
+            neutral: Arc::new(RwLock::new(HashSet::new())),
+            status: Arc::new(IndexerStatus::default()),
+            last_block_ts: Arc::new(RwLock::new(None)),
+            supply: Arc::new(RwLock::new(None)),
+            updates,
+            netflow,
+        }
+    }
+
+    /// Blocks a log must be buried under before it counts.
+    pub fn confirmations(&self) -> u64 {
+        self.confirmations.load(Ordering::Relaxed)
+    }
+
+    /// Change the confirmation depth for everything from the next log on (the caller
+    /// persists it). Staged logs are re-checked against it by the next promotion pass.
+    pub fn set_confirmations(&self, n: u64) -> u64 {
+        self.confirmations.swap(n, Ordering::Relaxed)
+    }
 }
 
 fn topic_h256(hex: &str) -> H256 {
@@ -27,115 +403,3005 @@ fn topic_h256(hex: &str) -> H256 {
 }
 
 #[inline]
-fn to_checksum_lower(a: Address) -> String {
+pub(crate) fn to_lower_hex(a: Address) -> String {
     format!("{:#x}", a)
 }
 
-pub async fn run_indexer(ix: Indexer) -> Result<()> {
-    let ws = Ws::connect(ix.cfg.rpc_url.clone()).await
-        .context("failed to connect WS")?;
-    let provider = Provider::new(ws);
+/// `Transfer` logs emitted by any configured token
+fn transfer_filter(cfg: &IndexerCfg) -> Filter {
+    Filter::new()
+        .address(cfg.tokens.clone())
+        .topic0(topic_h256(TRANSFER_TOPIC))
+}
+
+/// First reconnect delay; doubles per failed attempt up to `MAX_RECONNECT_BACKOFF`
+const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+/// Failed connects to one endpoint before moving on to the next in `rpc_urls`
+const ATTEMPTS_PER_ENDPOINT: u32 = 3;
+/// Head refresh when new-head notifications are unavailable (about one Polygon block)
+const HEAD_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// How often `indexer_status` is rewritten from the live head
+const STATUS_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+/// How often staged `pending_transfers` are checked for finality
+const PROMOTE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Aborts the session's helper tasks when the session ends, however it ends.
+struct AbortOnDrop(Vec<tokio::task::JoinHandle<()>>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        for h in &self.0 {
+            h.abort();
+        }
+    }
+}
+
+/// Keep a live session running, reconnecting with exponential backoff whenever the
+/// WebSocket drops or a session fails. Each new session backfills what was missed.
+/// With several `rpc_urls`, a session that drops fails over to the next endpoint
+/// right away, and so does an endpoint that fails `ATTEMPTS_PER_ENDPOINT` connects
+/// in a row. Gives up only after `ws_max_retries` consecutive failed attempts (if set).
+/// Returns `Ok` once `shutdown` is cancelled and the current session has wound down.
+pub async fn run_indexer(ix: Indexer, shutdown: CancellationToken) -> Result<()> {
+    let urls = &ix.cfg.rpc_urls;
+    if urls.is_empty() {
+        return Err(IndexerError::Config("no RPC endpoint configured (RPC_URLS or RPC_URL)".into()));
+    }
+    let mut endpoint = 0usize;
+    let mut endpoint_failures = 0u32;
+    let mut failures = 0u32;
+    let mut backoff = INITIAL_RECONNECT_BACKOFF;
+    loop {
+        let mut connected = false;
+        let res = run_session(&ix, endpoint, &mut connected, &shutdown).await;
+        if shutdown.is_cancelled() {
+            if let Err(e) = res {
+                warn!("Indexer session failed during shutdown: {e:#}");
+            }
+            info!("Indexer stopped");
+            return Ok(());
+        }
+        // Reconnecting won't move the node to another network
+        if let Err(e @ IndexerError::WrongChain { .. }) = res {
+            return Err(e);
+        }
+        match res {
+            Ok(()) => warn!("Log subscription ended (WebSocket closed)"),
+            Err(e) => warn!("Indexer session failed ({}): {e:#}", e.kind()),
+        }
+        if connected {
+            failures = 0;
+            endpoint_failures = 0;
+            backoff = INITIAL_RECONNECT_BACKOFF;
+        }
+        failures += 1;
+        endpoint_failures += 1;
+        if urls.len() > 1 && (connected || endpoint_failures >= ATTEMPTS_PER_ENDPOINT) {
+            let next = (endpoint + 1) % urls.len();
+            warn!("Failing over from RPC endpoint {} to {}", endpoint_label(urls, endpoint), endpoint_label(urls, next));
+            endpoint = next;
+            endpoint_failures = 0;
+            backoff = INITIAL_RECONNECT_BACKOFF;
+        }
+        if let Some(max) = ix.cfg.ws_max_retries {
+            if failures > max {
+                return Err(IndexerError::Config(format!("giving up after {max} consecutive reconnect attempts (WS_MAX_RETRIES)")));
+            }
+        }
+        warn!("Reconnecting in {}s (attempt {})", backoff.as_secs(), failures);
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = shutdown.cancelled() => {
+                info!("Indexer stopped");
+                return Ok(());
+            }
+        }
+        backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+    }
+}
+
+/// Whether a failed call may succeed if simply repeated: transport trouble (timeouts,
+/// dropped connections) and rate limiting are; a node's error reply or a response
+/// that doesn't decode will come back the same way.
+fn is_retryable(e: &ProviderError) -> bool {
+    if let Some(reply) = e.as_error_response() {
+        // Some nodes use the rate-limit code for oversized log queries too
+        return reply.code == RPC_LIMIT_EXCEEDED && !is_too_many_results(e);
+    }
+    if e.as_serde_error().is_some() {
+        return false;
+    }
+    matches!(e, ProviderError::JsonRpcClientError(_) | ProviderError::HTTPError(_))
+}
+
+/// The node doesn't offer the method at all (`-32601`, or a "not supported" reply).
+fn is_unsupported(e: &ProviderError) -> bool {
+    let Some(reply) = e.as_error_response() else { return false };
+    let msg = reply.message.to_ascii_lowercase();
+    reply.code == -32601 || ["not supported", "does not exist", "not available", "unsupported"].iter().any(|m| msg.contains(m))
+}
+
+/// A provider refusing an `eth_getLogs` range as too large ("query returned more than
+/// 10000 results", "block range too large", ...). Asking again won't help; a smaller
+/// window will.
+fn is_too_many_results(e: &ProviderError) -> bool {
+    let Some(reply) = e.as_error_response() else { return false };
+    let msg = reply.message.to_ascii_lowercase();
+    ["more than", "too many results", "too large", "block range"].iter().any(|m| msg.contains(m))
+}
+
+/// Run `op` again after a backoff while it fails with a retryable error, at most
+/// `rpc_retries` more times. Every failed attempt counts towards `rpc_errors`.
+async fn with_retry<T, F, Fut>(ix: &Indexer, what: &str, mut op: F) -> Result<T, ProviderError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ProviderError>>,
+{
+    let mut delay = RETRY_BASE_DELAY;
+    let mut attempt = 0;
+    loop {
+        match ix.status.rpc(op().await) {
+            Err(e) if attempt < ix.cfg.rpc_retries && is_retryable(&e) => {
+                attempt += 1;
+                warn!("{what} failed (retry {attempt}/{} in {:?}): {e}", ix.cfg.rpc_retries, delay);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(RETRY_MAX_DELAY);
+            }
+            r => return r,
+        }
+    }
+}
+
+/// `n/total (scheme://host)` for logs; paths and queries often carry API keys.
+fn endpoint_label(urls: &[String], i: usize) -> String {
+    format!("{}/{} ({})", i + 1, urls.len(), redact_url(&urls[i]))
+}
+
+/// Connect to `rpc_urls[endpoint]`, subscribe, backfill and consume until the stream
+/// ends or `shutdown` is cancelled. `connected` is set once the session is up, so the
+/// caller can reset its backoff. On shutdown, logs already handed to the worker are
+/// still processed.
+async fn run_session(ix: &Indexer, endpoint: usize, connected: &mut bool, shutdown: &CancellationToken) -> Result<()> {
+    let label = endpoint_label(&ix.cfg.rpc_urls, endpoint);
+    let provider = tokio::select! {
+        p = transport::connect(&ix.cfg.rpc_urls[endpoint]) => ix.status.rpc(p)
+            .map_err(|e| IndexerError::rpc(format!("failed to connect to RPC endpoint {label}"))(e.into()))?,
+        _ = shutdown.cancelled() => return Ok(()),
+    };
+    info!("Connected to RPC endpoint {}", label);
+
+    let chain_id = with_retry(ix, "eth_chainId", || provider.get_chainid()).await
+        .map_err(IndexerError::rpc("eth_chainId failed"))?.as_u64();
+    check_chain_id(&ix.db, chain_id, ix.cfg.expected_chain_id, ix.dry_run.is_none()).await?;
+
+    let head = with_retry(ix, "eth_blockNumber", || provider.get_block_number()).await
+        .map_err(IndexerError::rpc("eth_blockNumber failed"))?.as_u64();
+    ix.status.set_head(head);
+    // A batch the last session couldn't write is dropped; resume from what is stored
+    if let Err(e) = ix.netflow.flush().await {
+        warn!("{e:#}; resuming from the stored transfers");
+    }
+    // Staged logs that went final, or were orphaned, while down are settled before anything new arrives
+    reconcile_pending(ix, &provider).await?;
+
+    // Helpers share this session's connection and stop with it; the ones that only
+    // exist to write their own tables sit out a dry run
+    let mut helpers = vec![tokio::spawn(follow_head(ix.clone(), provider.clone()))];
+    if ix.dry_run.is_none() {
+        helpers.push(tokio::spawn(checkpoint_status(ix.clone())));
+        helpers.push(tokio::spawn(reserves::run_snapshots(ix.clone(), provider.clone())));
+        helpers.push(tokio::spawn(reserves::run_supply_refresh(ix.clone(), provider.clone(), ix.cfg.supply_refresh_secs)));
+        helpers.push(tokio::spawn(native::run(ix.clone(), provider.clone())));
+    }
+    let _helpers = AbortOnDrop(helpers);
+
+    // Subscribe before backfilling so logs emitted meanwhile are buffered, not missed
+    let tokens: Vec<String> = ix.cfg.tokens.iter().map(|t| to_lower_hex(*t)).collect();
+    let sub = match ix.cfg.mode {
+        IndexerMode::Poll => None,
+        IndexerMode::Subscribe if !provider.as_ref().can_subscribe() => {
+            info!("RPC endpoint {label} is HTTP, which can't subscribe; polling instead");
+            None
+        }
+        IndexerMode::Subscribe => match ix.status.rpc(provider.subscribe_logs(&transfer_filter(&ix.cfg)).await) {
+            Ok(sub) => {
+                info!("Subscribed to Transfer logs for tokens {}", tokens.join(", "));
+                Some(sub)
+            }
+            Err(e) if is_unsupported(&e) => {
+                warn!("RPC endpoint {label} doesn't support log subscriptions ({e}); polling instead");
+                None
+            }
+            Err(e) => return Err(IndexerError::rpc("eth_subscribe logs failed")(e)),
+        },
+    };
+
+    // Catch up on final blocks missed while down; newer ones come from the subscription
+    let safe = head.saturating_sub(ix.confirmations());
+    let backfilled_to = catch_up(ix, &provider, head, shutdown).await?;
+    // Gaps a previous session found but couldn't fill
+    heal_recorded_gaps(ix, &provider, shutdown).await?;
+    if shutdown.is_cancelled() {
+        return Ok(());
+    }
+    *connected = true;
+    let mut source = match sub {
+        Some(sub) => LogSource::Subscription(sub),
+        None => {
+            info!("Polling Transfer logs for tokens {} every {}s", tokens.join(", "), ix.cfg.poll_interval_secs);
+            LogSource::Poll {
+                next_block: backfilled_to.unwrap_or(safe) + 1,
+                tick: tokio::time::interval(Duration::from_secs(ix.cfg.poll_interval_secs.max(1))),
+                buffered: VecDeque::new(),
+            }
+        }
+    };
+
+    // A subscription can silently skip blocks; polling covers every block by construction
+    let gaps_from = matches!(source, LogSource::Subscription(_)).then(|| backfilled_to.unwrap_or(safe));
+
+    // Bounded hand-off to a single processor: when it falls behind the reader stops
+    // pulling from the subscription instead of buffering without limit or dropping
+    let (tx, rx) = tokio::sync::mpsc::channel::<Log>(ix.cfg.max_in_flight.max(1));
+    let worker = tokio::spawn(process_logs(ix.clone(), provider.clone(), rx, head, gaps_from));
+
+    let mut backpressured = false;
+    let mut failed = None;
+    loop {
+        let log = tokio::select! {
+            log = source.next(ix, &provider) => match log {
+                Ok(Some(log)) => log,
+                Ok(None) => break,
+                Err(e) => {
+                    failed = Some(e);
+                    break;
+                }
+            },
+            _ = shutdown.cancelled() => {
+                info!("Shutting down: finishing {} queued logs", ix.status.queue_depth.load(Ordering::Relaxed));
+                break;
+            }
+        };
+        // Already covered by the backfill
+        if backfilled_to.is_some_and(|b| log.block_number.is_some_and(|n| n.as_u64() <= b)) {
+            continue;
+        }
+        if !enqueue(ix, &tx, log, &mut backpressured, shutdown).await {
+            break;
+        }
+    }
+    drop(tx);
+    let flushed = worker.await.map_err(|e| anyhow::anyhow!("log processor task failed: {e}"))?;
+    flushed.and(failed.map_or(Ok(()), Err))
+}
+
+/// Hand `log` to the processor, counted in `queue_depth` until it is picked up. While
+/// the queue is full the reader waits for room (pausing subscription reads) instead of
+/// dropping the log. `false` once the processor is gone or `shutdown` is cancelled.
+async fn enqueue(
+    ix: &Indexer,
+    tx: &tokio::sync::mpsc::Sender<Log>,
+    log: Log,
+    backpressured: &mut bool,
+    shutdown: &CancellationToken,
+) -> bool {
+    ix.status.queue_depth.fetch_add(1, Ordering::Relaxed);
+    match tx.try_send(log) {
+        Ok(()) => {
+            if *backpressured {
+                *backpressured = false;
+                info!("Log queue drained below {}; backpressure released", ix.cfg.max_in_flight);
+            }
+            true
+        }
+        Err(tokio::sync::mpsc::error::TrySendError::Full(log)) => {
+            if !*backpressured {
+                *backpressured = true;
+                ix.status.backpressure_events.fetch_add(1, Ordering::Relaxed);
+                warn!("Log queue full ({} in flight): pausing subscription reads", ix.cfg.max_in_flight);
+            }
+            // A full queue still drains on shutdown; the reader just stops waiting
+            tokio::select! {
+                sent = tx.send(log) => sent.is_ok(),
+                _ = shutdown.cancelled() => {
+                    ix.status.queue_depth.fetch_sub(1, Ordering::Relaxed);
+                    false
+                }
+            }
+        }
+        Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => false,
+    }
+}
+
+/// A session's log processor. Up to `log_workers` logs have their RPC lookups prefetched
+/// at once; commits still happen one at a time, in (block, log index) order. Staged logs
+/// are promoted by the same task, so there is still one writer. Returns once `rx` is
+/// closed and drained, after committing the last batch.
+async fn process_logs(
+    ix: Indexer,
+    provider: Provider<Transport>,
+    mut rx: tokio::sync::mpsc::Receiver<Log>,
+    head: u64,
+    gaps_from: Option<u64>,
+) -> Result<()> {
+    let workers = ix.cfg.log_workers.max(1);
+    let mut promote = tokio::time::interval(PROMOTE_INTERVAL);
+    let mut prefetching = FuturesUnordered::new();
+    let mut order = ReorderBuffer::default();
+    let mut seq = 0u64;
+    let mut contiguous = gaps_from;
+    let mut open = true;
+    while open || !prefetching.is_empty() {
+        tokio::select! {
+            log = rx.recv(), if open && prefetching.len() < workers => {
+                let Some(log) = log else {
+                    open = false;
+                    continue;
+                };
+                ix.status.queue_depth.fetch_sub(1, Ordering::Relaxed);
+                seq += 1;
+                let key = log_key(&log, seq);
+                order.start(key);
+                let (ix, provider) = (ix.clone(), provider.clone());
+                prefetching.push(async move {
+                    prefetch(&ix, &provider, &log).await;
+                    (key, log)
+                });
+            }
+            Some((key, log)) = prefetching.next(), if !prefetching.is_empty() => {
+                order.finish(key, log);
+                while let Some(log) = order.pop() {
+                    if let Some(contiguous) = contiguous.as_mut() {
+                        if let Err(e) = close_gap(&ix, &provider, contiguous, head, &log).await {
+                            error!("gap check failed: {e:#}");
+                        }
+                    }
+                    if let Err(e) = handle_log(&ix, &provider, log).await {
+                        // Later logs can't be stored either; restart from what is
+                        if ix.netflow.is_failing() {
+                            error!("handle_log error: {e:#}; ending the session");
+                            return ix.netflow.flush().await.and(Err(e));
+                        }
+                        error!("handle_log error: {e:#}");
+                    }
+                }
+            }
+            _ = promote.tick() => {
+                if let Err(e) = promote_pending(&ix, &provider).await {
+                    error!("promoting pending transfers failed: {e:#}");
+                }
+            }
+        }
+    }
+    // Commit this session's last batch before a reconnect or shutdown
+    ix.netflow.flush().await
+}
+
+/// Where a session's live logs come from.
+enum LogSource<'a> {
+    Subscription(SubscriptionStream<'a, Transport, Log>),
+    /// Final blocks from `next_block` on, fetched every `tick`
+    Poll {
+        next_block: u64,
+        tick: tokio::time::Interval,
+        buffered: VecDeque<Log>,
+    },
+}
+
+impl LogSource<'_> {
+    /// The next log; `None` once the subscription ends. Cancel-safe: a dropped poll
+    /// round leaves `next_block` where it was.
+    async fn next(&mut self, ix: &Indexer, provider: &Provider<Transport>) -> Result<Option<Log>> {
+        let (next_block, tick, buffered) = match self {
+            Self::Subscription(sub) => return Ok(sub.next().await),
+            Self::Poll { next_block, tick, buffered } => (next_block, tick, buffered),
+        };
+        loop {
+            if let Some(lg) = buffered.pop_front() {
+                return Ok(Some(lg));
+            }
+            tick.tick().await;
+            let head = with_retry(ix, "eth_blockNumber", || provider.get_block_number()).await
+                .map_err(IndexerError::rpc("eth_blockNumber failed"))?.as_u64();
+            ix.status.set_head(head);
+            let Some(safe) = head.checked_sub(ix.confirmations()).filter(|s| *s >= *next_block) else { continue };
+            let from = *next_block;
+            let to = safe.min(from.saturating_add(ix.cfg.backfill_chunk_blocks.max(1) - 1));
+            let filter = transfer_filter(&ix.cfg).from_block(from).to_block(to);
+            let logs = with_retry(ix, "eth_getLogs", || provider.get_logs(&filter)).await
+                .map_err(IndexerError::rpc(format!("eth_getLogs failed for blocks {from}..={to}")))?;
+            buffered.extend(logs);
+            *next_block = to + 1;
+        }
+    }
+}
+
+/// Every `every_secs`, log how many transfers and blocks were processed since the last
+/// tick and publish the rates for `/status`. Only reads the counters the hot path
+/// already bumps.
+pub async fn report_throughput(status: Arc<IndexerStatus>, every_secs: u64) {
+    if every_secs == 0 {
+        return;
+    }
+    let mut tick = tokio::time::interval(Duration::from_secs(every_secs));
+    tick.tick().await;
+    let mut last_transfers = status.transfers_processed.load(Ordering::Relaxed);
+    let mut last_block = status.last_processed_block();
+    let mut last_at = Instant::now();
+    loop {
+        tick.tick().await;
+        let transfers = status.transfers_processed.load(Ordering::Relaxed);
+        let block = status.last_processed_block();
+        let secs = last_at.elapsed().as_secs_f64().max(f64::EPSILON);
+        let dt = transfers - last_transfers;
+        // The first processed block only sets the baseline
+        let blocks = match (last_block, block) {
+            (Some(a), Some(b)) => b.saturating_sub(a),
+            _ => 0,
+        };
+        let tps = dt as f64 / secs;
+        status.transfer_rate.store(tps.to_bits(), Ordering::Relaxed);
+        status.block_rate.store((blocks as f64 / secs).to_bits(), Ordering::Relaxed);
+        status.rates_measured.store(true, Ordering::Relaxed);
+        let lag = status.lag_blocks().map_or_else(|| "unknown".into(), |l| l.to_string());
+        info!("Processed {} transfers across {} blocks, {:.1} tx/s, lag {} blocks", dt, blocks, tps, lag);
+        (last_transfers, last_block, last_at) = (transfers, block, Instant::now());
+    }
+}
+
+/// Keep `status.head` current from new-head notifications, or by polling
+/// `eth_blockNumber` every `HEAD_POLL_INTERVAL` when the node won't push them.
+async fn follow_head(ix: Indexer, provider: Provider<Transport>) {
+    let subscribed = if provider.as_ref().can_subscribe() {
+        provider.subscribe_blocks().await.map(Some)
+    } else {
+        Ok(None)
+    };
+    match subscribed {
+        // HTTP: polling from the start
+        Ok(None) => {}
+        Ok(Some(mut heads)) => {
+            while let Some(block) = heads.next().await {
+                if let Some(n) = block.number {
+                    ix.status.set_head(n.as_u64());
+                }
+            }
+            warn!("New-heads subscription ended; polling the head instead");
+        }
+        Err(e) => {
+            ix.status.rpc_errors.fetch_add(1, Ordering::Relaxed);
+            warn!("Cannot subscribe to new heads, polling the head instead: {e:#}");
+        }
+    }
+    let mut tick = tokio::time::interval(HEAD_POLL_INTERVAL);
+    loop {
+        tick.tick().await;
+        if let Ok(n) = ix.status.rpc(provider.get_block_number().await) {
+            ix.status.set_head(n.as_u64());
+        }
+    }
+}
+
+/// Persist the head to `indexer_status` every `STATUS_CHECKPOINT_INTERVAL`. A block
+/// becomes `synced_block` once it was final at the previous checkpoint and the log queue
+/// has been empty at both, so its logs had a full interval (two poll rounds in poll
+/// mode) to be processed. A failed write is retried on the next tick.
+async fn checkpoint_status(ix: Indexer) {
+    let every = STATUS_CHECKPOINT_INTERVAL.max(Duration::from_secs(2 * ix.cfg.poll_interval_secs));
+    let mut tick = tokio::time::interval(every);
+    let mut candidate = None;
+    let mut dropped = ix.netflow.dropped_batches();
+    loop {
+        tick.tick().await;
+        let Some(head) = ix.status.head() else { continue };
+        // A batch dropped since the last tick may be below the candidate
+        let seen = std::mem::replace(&mut dropped, ix.netflow.dropped_batches());
+        let idle = ix.status.queue_depth.load(Ordering::Relaxed) == 0 && ix.netflow.is_idle() && seen == dropped;
+        let synced = if idle { candidate } else { None };
+        candidate = idle.then(|| head.checked_sub(ix.confirmations())).flatten();
+        let written = sqlx::query(r#"
+            INSERT INTO indexer_status (id, head, synced_block, updated_at)
+            VALUES (1, ?, ?, strftime('%s','now'))
+            ON CONFLICT(id) DO UPDATE SET
+                head = excluded.head,
+                synced_block = MAX(COALESCE(excluded.synced_block, 0), COALESCE(synced_block, 0)),
+                updated_at = excluded.updated_at;
+        "#)
+            .bind(head as i64)
+            .bind(synced.map(|b| b as i64))
+            .execute(&ix.db).await;
+        if let Err(e) = written {
+            warn!("head checkpoint failed: {e:#}");
+        }
+    }
+}
+
+/// Highest block already reflected in the database: the netflow checkpoint, the newest
+/// stored transfer or the `indexer_status` synced block, whichever is later. `None` on a
+/// fresh database.
+pub async fn resume_block(db: &SqlitePool) -> Result<Option<u64>> {
+    let last = sqlx::query_scalar::<_, Option<i64>>(r#"
+        SELECT MAX(b) FROM (
+            SELECT last_block AS b FROM netflow_state
+            UNION ALL
+            SELECT MAX(block_number) FROM erc20_transfers
+            UNION ALL
+            SELECT NULLIF(synced_block, 0) FROM indexer_status
+        );
+    "#).fetch_one(db).await?;
+    Ok(last.map(|b| b as u64))
+}
+
+/// Backfill the final blocks missed while down, up to `head - CONFIRMATIONS`, and
+/// return the last block covered (`None` on a fresh database). The resume block itself
+/// is fetched again: a writer batch can end partway through a block, and the logs that
+/// were already committed are skipped as duplicates.
+async fn catch_up(ix: &Indexer, provider: &Provider<Transport>, head: u64, shutdown: &CancellationToken) -> Result<Option<u64>> {
+    let safe = head.saturating_sub(ix.confirmations());
+    Ok(match resume_block(&ix.db).await? {
+        Some(last) if last <= safe => {
+            backfill(ix, provider, last, safe, shutdown).await?;
+            Some(safe)
+        }
+        Some(last) => Some(last),
+        None => match start_block(ix, provider, head).await? {
+            Some(start) if start <= safe => {
+                info!("Empty database: backfilling from start block {}", start);
+                backfill(ix, provider, start, safe, shutdown).await?;
+                Some(safe)
+            }
+            _ => {
+                info!("Empty database: starting from head block {}", head);
+                None
+            }
+        },
+    })
+}
+
+/// `START_BLOCK`, else `START_TIMESTAMP` resolved with `resolve_start_timestamp`.
+async fn start_block(ix: &Indexer, provider: &Provider<Transport>, head: u64) -> Result<Option<u64>> {
+    match (ix.cfg.start_block, ix.cfg.start_timestamp) {
+        (Some(block), _) => Ok(Some(block)),
+        (None, Some(ts)) => Ok(Some(resolve_start_timestamp(ix, provider, ts, head).await?)),
+        (None, None) => Ok(None),
+    }
+}
+
+/// The first block mined at or after unix time `ts`, by binary search over block
+/// timestamps, but no earlier than the first block where a configured token has
+/// code. Cached in `start_blocks`; a `ts` newer than the head resolves to the head
+/// and isn't cached.
+pub async fn resolve_start_timestamp(ix: &Indexer, provider: &Provider<Transport>, ts: u64, head: u64) -> Result<u64> {
+    let cached = sqlx::query_scalar::<_, i64>("SELECT block_number FROM start_blocks WHERE start_timestamp = ?;")
+        .bind(ts as i64)
+        .fetch_optional(&ix.db).await?;
+    if let Some(block) = cached {
+        return Ok(block as u64);
+    }
+    if block_timestamp(ix, provider, head).await? < ts {
+        return Ok(head);
+    }
+    let (mut lo, mut hi) = (0, head);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if block_timestamp(ix, provider, mid).await? >= ts {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+
+    // Before deployment there is nothing to index: clamp to the earliest creation block
+    let mut start = lo;
+    let mut deployed = false;
+    for &token in &ix.cfg.tokens {
+        deployed |= has_code_at(ix, provider, token, lo).await?;
+    }
+    if !deployed {
+        start = head;
+        for &token in &ix.cfg.tokens {
+            if !has_code_at(ix, provider, token, head).await? {
+                continue;
+            }
+            let (mut lo, mut hi) = (lo + 1, head);
+            while lo < hi {
+                let mid = lo + (hi - lo) / 2;
+                if has_code_at(ix, provider, token, mid).await? {
+                    hi = mid;
+                } else {
+                    lo = mid + 1;
+                }
+            }
+            start = start.min(lo);
+        }
+        info!("START_TIMESTAMP {} is before the token was deployed; starting at creation block {}", ts, start);
+    }
 
-    // Start from latest block (no backfill)
-    let head = provider.get_block_number().await?.as_u64();
-    info!("Starting from head block {}", head);
+    sqlx::query("INSERT OR REPLACE INTO start_blocks (start_timestamp, block_number) VALUES (?, ?);")
+        .bind(ts as i64)
+        .bind(start as i64)
+        .execute(&ix.db).await?;
+    info!("Resolved START_TIMESTAMP {} to block {}", ts, start);
+    Ok(start)
+}
 
-    // Subscribe to logs for Transfer events for the token
-    let filter = Filter::new()
-        .address(ix.cfg.token)
-        .topic0(topic_h256(TRANSFER_TOPIC));
+async fn block_timestamp(ix: &Indexer, provider: &Provider<Transport>, n: u64) -> Result<u64> {
+    let block = with_retry(ix, "eth_getBlockByNumber", || provider.get_block(n)).await
+        .map_err(IndexerError::rpc(format!("failed to fetch block {n}")))?
+        .ok_or_else(|| IndexerError::MissingData(format!("block {n} not returned by the node")))?;
+    Ok(block.timestamp.as_u64())
+}
 
-    let mut sub = provider.subscribe_logs(&filter).await?;
-    info!("Subscribed to Transfer logs for token {}", to_checksum_lower(ix.cfg.token));
+async fn has_code_at(ix: &Indexer, provider: &Provider<Transport>, addr: Address, n: u64) -> Result<bool> {
+    let code = with_retry(ix, "eth_getCode", || provider.get_code(addr, Some(n.into()))).await
+        .map_err(IndexerError::rpc(format!("failed to fetch code of {:#x} at block {n}", addr)))?;
+    Ok(!code.as_ref().is_empty())
+}
 
-    while let Some(log) = sub.next().await {
-        if let Err(e) = handle_log(&ix, &provider, log).await {
-            error!("handle_log error: {e:#}");
+/// Feed every Transfer log in `[from, to]` through `handle_log`, fetched with
+/// `eth_getLogs` in windows of `backfill_chunk_blocks`. A window the provider refuses
+/// as returning too many results is halved and retried (down to a single block); the
+/// next window starts again from the configured size. Callers keep `to` at or below
+/// `head - CONFIRMATIONS`, so nothing non-final is persisted. Stops between windows
+/// once `shutdown` is cancelled, so a window is either fully applied or not at all.
+pub async fn backfill(ix: &Indexer, provider: &Provider<Transport>, from: u64, to: u64, shutdown: &CancellationToken) -> Result<()> {
+    let chunk = ix.cfg.backfill_chunk_blocks.max(1);
+    let total = to.saturating_sub(from) + 1;
+    info!("Backfilling blocks {}..={} ({} blocks)", from, to, total);
+    let mut start = from;
+    let mut window = chunk;
+    while start <= to {
+        let end = start.saturating_add(window - 1).min(to);
+        let filter = transfer_filter(&ix.cfg).from_block(start).to_block(end);
+        let logs = tokio::select! {
+            logs = with_retry(ix, "eth_getLogs", || provider.get_logs(&filter)) => logs,
+            _ = shutdown.cancelled() => {
+                info!("Backfill interrupted by shutdown before block {}", start);
+                return Ok(());
+            }
+        };
+        let logs = match logs {
+            Err(e) if window > 1 && is_too_many_results(&e) => {
+                window = (end - start + 1) / 2;
+                warn!("eth_getLogs for blocks {start}..={end} refused ({e}); retrying with {window}-block windows");
+                continue;
+            }
+            r => r.map_err(IndexerError::rpc(format!("eth_getLogs failed for blocks {start}..={end}")))?,
+        };
+        let n = logs.len();
+        for lg in logs {
+            handle_log(ix, provider, lg).await?;
         }
+        let done = end - from + 1;
+        info!("Backfilled blocks {}..={} ({} logs, {:.1}%)", start, end, n, done as f64 * 100.0 / total as f64);
+        start = end + 1;
+        window = chunk;
     }
     Ok(())
 }
 
-async fn handle_log(ix: &Indexer, provider: &Provider<Ws>, lg: Log) -> Result<()> {
-    // Basic finality lag
-    let head = provider.get_block_number().await?.as_u64();
-    let Some(bn) = lg.block_number.map(|b| b.as_u64()) else {
+/// Called for each live log, in order, before it is handled. `contiguous` is the last
+/// block the subscription is known to have delivered in full; when `lg` is further
+/// ahead, the blocks in between either had no Transfer or were dropped, and
+/// `eth_getLogs` tells which. Dropped logs are recorded as a gap in `gaps` and fed
+/// through `handle_log` first; a range that can't be checked is recorded unhealed
+/// for the next session to backfill. Blocks up to `opened_at`, the head when the
+/// subscription was opened, were never going to be delivered: their logs are the end
+/// of the startup catch-up and are handled without recording a gap.
+async fn close_gap(
+    ix: &Indexer,
+    provider: &Provider<Transport>,
+    contiguous: &mut u64,
+    opened_at: u64,
+    lg: &Log,
+) -> Result<()> {
+    let Some(bn) = lg.block_number.map(|n| n.as_u64()).filter(|_| lg.removed != Some(true)) else {
         return Ok(());
     };
-    if head.saturating_sub(bn) < ix.cfg.confirmations {
-        // not final enough
+    if bn <= *contiguous + 1 {
+        *contiguous = (*contiguous).max(bn);
         return Ok(());
     }
-
-    // Decode topics:
-    // topic0 = Transfer(...)
-    // topic1 = from, topic2 = to, data = value
-    if lg.topics.len() < 3 {
+    let (from, to) = (*contiguous + 1, bn - 1);
+    *contiguous = bn;
+    let chunk = ix.cfg.backfill_chunk_blocks.max(1);
+    let mut missed = Vec::new();
+    let mut start = from;
+    while start <= to {
+        let end = start.saturating_add(chunk - 1).min(to);
+        let filter = transfer_filter(&ix.cfg).from_block(start).to_block(end);
+        match with_retry(ix, "eth_getLogs", || provider.get_logs(&filter)).await {
+            Ok(logs) => missed.extend(logs),
+            Err(e) => {
+                warn!("Cannot check blocks {from}..={to} for missed logs, leaving them to the next session: {e}");
+                record_gap(ix, from, to).await?;
+                return Ok(());
+            }
+        }
+        start = end + 1;
+    }
+    let gap_from = from.max(opened_at.saturating_add(1));
+    let (catch_up, missed): (Vec<Log>, Vec<Log>) = missed.into_iter()
+        .partition(|l| l.block_number.is_some_and(|n| n.as_u64() < gap_from));
+    for lg in catch_up {
+        handle_log(ix, provider, lg).await?;
+    }
+    // Quiet blocks, not a gap
+    if missed.is_empty() {
         return Ok(());
     }
-    let from = H160::from_slice(&lg.topics[1].as_bytes()[12..]);
-    let to   = H160::from_slice(&lg.topics[2].as_bytes()[12..]);
-    let amount = ethers::abi::Uint::decode(lg.data.as_ref())?; // value
-    let amount_str = amount.to_string();
+    warn!("Log stream skipped blocks {}..={} ({} logs); backfilling them", gap_from, to, missed.len());
+    let id = record_gap(ix, gap_from, to).await?;
+    for lg in missed {
+        handle_log(ix, provider, lg).await?;
+    }
+    resolve_gap(ix, id).await
+}
 
-    let tx_hash = lg.transaction_hash.unwrap_or_default();
-    let log_index = lg.log_index.unwrap_or_default().as_u64() as i64;
-    let block_number = bn as i64;
-    let contract = ix.cfg.token;
+/// Insert an unhealed `gaps` row; `None` (and nothing written) in a dry run.
+async fn record_gap(ix: &Indexer, from: u64, to: u64) -> Result<Option<i64>> {
+    if ix.dry_run.is_some() {
+        return Ok(None);
+    }
+    let id = sqlx::query_scalar::<_, i64>(r#"
+        INSERT INTO gaps (from_block, to_block, detected_at) VALUES (?, ?, strftime('%s','now'))
+        RETURNING id;
+    "#)
+        .bind(from as i64)
+        .bind(to as i64)
+        .fetch_one(&ix.db).await?;
+    Ok(Some(id))
+}
+
+async fn resolve_gap(ix: &Indexer, id: Option<i64>) -> Result<()> {
+    let Some(id) = id else { return Ok(()) };
+    sqlx::query("UPDATE gaps SET healed_at = strftime('%s','now') WHERE id = ?;")
+        .bind(id)
+        .execute(&ix.db).await?;
+    Ok(())
+}
+
+/// Backfill every unhealed `gaps` range, oldest first, marking each healed once done.
+async fn heal_recorded_gaps(ix: &Indexer, provider: &Provider<Transport>, shutdown: &CancellationToken) -> Result<()> {
+    let gaps = sqlx::query_as::<_, (i64, i64, i64)>(
+        "SELECT id, from_block, to_block FROM gaps WHERE healed_at IS NULL ORDER BY from_block;")
+        .fetch_all(&ix.db).await?;
+    for (id, from, to) in gaps {
+        info!("Healing recorded gap in blocks {}..={}", from, to);
+        backfill(ix, provider, from as u64, to as u64, shutdown).await?;
+        if shutdown.is_cancelled() {
+            break;
+        }
+        resolve_gap(ix, ix.dry_run.is_none().then_some(id)).await?;
+    }
+    Ok(())
+}
+
+/// `(block_number, log_index, arrival)`; the arrival count keeps a log and its later
+/// retraction (same position) apart and in order.
+type LogKey = (u64, u64, u64);
+
+fn log_key(lg: &Log, seq: u64) -> LogKey {
+    (
+        lg.block_number.map_or(0, |n| n.as_u64()),
+        lg.log_index.map_or(0, |i| i.as_u64()),
+        seq,
+    )
+}
+
+/// Logs whose prefetch is done, held until every log still being prefetched sorts
+/// after them, then released lowest `(block_number, log_index)` first.
+#[derive(Default)]
+struct ReorderBuffer {
+    in_flight: BTreeSet<LogKey>,
+    ready: BTreeMap<LogKey, Log>,
+}
+
+impl ReorderBuffer {
+    fn start(&mut self, key: LogKey) {
+        self.in_flight.insert(key);
+    }
+
+    fn finish(&mut self, key: LogKey, lg: Log) {
+        self.in_flight.remove(&key);
+        self.ready.insert(key, lg);
+    }
+
+    fn pop(&mut self) -> Option<Log> {
+        let (&next, _) = self.ready.first_key_value()?;
+        if self.in_flight.first().is_some_and(|k| *k < next) {
+            return None;
+        }
+        self.ready.remove(&next)
+    }
+}
+
+/// Warm the receipt and code caches with the lookups `ingest_log` will make for a
+/// final log, so concurrent prefetches overlap their round-trips. Errors are dropped:
+/// the commit makes the same call again and reports it.
+async fn prefetch(ix: &Indexer, provider: &Provider<Transport>, lg: &Log) {
+    let Some(bn) = lg.block_number.map(|n| n.as_u64()) else { return };
+    let head = ix.status.head().unwrap_or(0).max(bn);
+    // Non-final logs are only staged; their receipts may still change
+    if lg.removed == Some(true) || lg.topics.len() != 3 || head.saturating_sub(bn) < ix.confirmations() {
+        return;
+    }
+    if let (true, Some(hash)) = (ix.cfg.verify_receipt_status, lg.transaction_hash) {
+        let _ = tx_succeeded(ix, provider, hash).await;
+    }
+    if ix.cfg.exclude_contract_counterparties {
+        let (Some(from), Some(to)) = (topic_address(&lg.topics[1]), topic_address(&lg.topics[2])) else { return };
+        let (from_is_ex, to_is_ex) = {
+            let ex = ix.exchanges.read().expect("exchange cache poisoned");
+            (ex.contains_key(&from), ex.contains_key(&to))
+        };
+        if to_is_ex && !from_is_ex && !from.is_zero() {
+            let _ = is_contract(ix, provider, from).await;
+        }
+        if from_is_ex && !to_is_ex && !to.is_zero() {
+            let _ = is_contract(ix, provider, to).await;
+        }
+    }
+}
+
+async fn handle_log(ix: &Indexer, provider: &Provider<Transport>, lg: Log) -> Result<()> {
+    // Basic finality lag against the head `follow_head` keeps current (no RPC per log);
+    // the log's own block is a lower bound if its head notification hasn't landed yet
+    let bn = lg.block_number.map_or(0, |n| n.as_u64());
+    let head = ix.status.head().unwrap_or(0).max(bn);
+    ix.status.check_head_staleness(ix.cfg.head_stale_secs);
+    // The node retracting a log from a block that was reorged out
+    if lg.removed == Some(true) {
+        return unstage_pending(ix, &lg).await;
+    }
+    apply_log(ix, Some(provider), head, lg).await
+}
 
-    // Persist raw transfer (idempotent)
+/// Park a log that isn't final yet in `pending_transfers` until `promote_pending`
+/// can apply it. Staging twice is harmless.
+async fn stage_pending(ix: &Indexer, lg: &Log, bn: u64) -> Result<()> {
+    let (Some(hash), Some(log_index)) = (lg.block_hash, lg.log_index) else {
+        return record_skip(ix, lg, "missing block hash or log index").await;
+    };
+    if let Some(dry) = &ix.dry_run {
+        dry.stage(format!("{:#x}", hash), log_index.as_u64() as i64, bn as i64, serde_json::to_string(lg)?);
+        return Ok(());
+    }
     sqlx::query(r#"
-        INSERT OR IGNORE INTO erc20_transfers
-            (tx_hash, log_index, block_number, contract, "from", "to", amount_wei)
-        VALUES (?, ?, ?, ?, ?, ?, ?);
+        INSERT OR IGNORE INTO pending_transfers (block_hash, log_index, block_number, log_json)
+        VALUES (?, ?, ?, ?);
     "#)
-        .bind(format!("{:#x}", tx_hash))
-        .bind(log_index)
-        .bind(block_number)
-        .bind(format!("{:#x}", contract))
-        .bind(to_checksum_lower(from))
-        .bind(to_checksum_lower(to))
-        .bind(amount_str.clone())
+        .bind(format!("{:#x}", hash))
+        .bind(log_index.as_u64() as i64)
+        .bind(bn as i64)
+        .bind(serde_json::to_string(lg)?)
         .execute(&ix.db).await?;
+    Ok(())
+}
 
-    // Classify in/out relative to exchange set
-    let from_is_ex = is_exchange(&ix.db, &from).await?;
-    let to_is_ex   = is_exchange(&ix.db, &to).await?;
+async fn unstage_pending(ix: &Indexer, lg: &Log) -> Result<()> {
+    let (Some(hash), Some(log_index)) = (lg.block_hash, lg.log_index) else { return Ok(()) };
+    let dropped = match &ix.dry_run {
+        Some(dry) => dry.unstage(&format!("{:#x}", hash), log_index.as_u64() as i64),
+        None => sqlx::query("DELETE FROM pending_transfers WHERE block_hash = ? AND log_index = ?;")
+            .bind(format!("{:#x}", hash))
+            .bind(log_index.as_u64() as i64)
+            .execute(&ix.db).await?
+            .rows_affected() > 0,
+    };
+    if dropped {
+        info!("Dropped pending log {:#x}:{} retracted by the node", hash, log_index);
+    }
+    Ok(())
+}
 
-    if from_is_ex || to_is_ex {
-        // Single-row state update
-        if to_is_ex {
-            sqlx::query(r#"
-                UPDATE netflow_state
-                SET cumulative_in_wei = CAST((CAST(cumulative_in_wei AS INTEGER) + CAST(? AS INTEGER)) AS TEXT),
-                    last_block = MAX(COALESCE(last_block, 0), ?)
-                WHERE id = 1;
-            "#)
-            .bind(amount_str.clone())
-            .bind(block_number)
-            .execute(&ix.db).await?;
+/// Apply every staged log whose block now has `confirmations`, oldest first. A log
+/// whose block hash is no longer the canonical one at its height was orphaned and
+/// is discarded; its re-mined copy (if any) is staged under the new hash.
+async fn promote_pending(ix: &Indexer, provider: &Provider<Transport>) -> Result<()> {
+    let Some(head) = ix.status.head() else { return Ok(()) };
+    let Some(final_to) = head.checked_sub(ix.confirmations()) else { return Ok(()) };
+    let rows = match &ix.dry_run {
+        Some(dry) => dry.staged(final_to as i64),
+        None => sqlx::query_as::<_, (String, i64, i64, String)>(r#"
+            SELECT block_hash, log_index, block_number, log_json FROM pending_transfers
+            WHERE block_number <= ? ORDER BY block_number, log_index;
+        "#)
+            .bind(final_to as i64)
+            .fetch_all(&ix.db).await?,
+    };
+
+    // Canonical hash of the block being promoted; a block the node can't return yet is
+    // retried on the next tick rather than taken for orphaned
+    let mut canonical: Option<(i64, String)> = None;
+    for (hash, log_index, bn, log_json) in rows {
+        if canonical.as_ref().map(|c| c.0) != Some(bn) {
+            let block_hash = with_retry(ix, "eth_getBlockByNumber", || provider.get_block(bn as u64)).await
+                .map_err(IndexerError::rpc(format!("failed to fetch block {bn}")))?
+                .and_then(|b| b.hash)
+                .ok_or_else(|| IndexerError::MissingData(format!("block {bn} not returned by the node")))?;
+            canonical = Some((bn, format!("{:#x}", block_hash)));
         }
-        if from_is_ex {
-            sqlx::query(r#"
-                UPDATE netflow_state
-                SET cumulative_out_wei = CAST((CAST(cumulative_out_wei AS INTEGER) + CAST(? AS INTEGER)) AS TEXT),
-                    last_block = MAX(COALESCE(last_block, 0), ?)
-                WHERE id = 1;
-            "#)
-            .bind(amount_str.clone())
-            .bind(block_number)
-            .execute(&ix.db).await?;
+        if canonical.as_ref().is_some_and(|c| c.1 == hash) {
+            let lg: Log = serde_json::from_str(&log_json)
+                .map_err(|e| IndexerError::Decode(format!("corrupt pending log {hash}:{log_index}: {e}")))?;
+            apply_log(ix, Some(provider), head, lg).await?;
+        } else {
+            warn!("Discarding pending log {}:{} from block {}: block was orphaned", hash, log_index, bn);
+        }
+        if let Some(dry) = &ix.dry_run {
+            dry.unstage(&hash, log_index);
+            continue;
         }
+        sqlx::query("DELETE FROM pending_transfers WHERE block_hash = ? AND log_index = ?;")
+            .bind(&hash)
+            .bind(log_index)
+            .execute(&ix.db).await?;
     }
+    Ok(())
+}
 
+/// Settle what the previous session left in `pending_transfers`: entries now final
+/// are promoted and those from orphaned blocks dropped, as `promote_pending` does.
+async fn reconcile_pending(ix: &Indexer, provider: &Provider<Transport>) -> Result<()> {
+    let count = || sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM pending_transfers;").fetch_one(&ix.db);
+    let staged = count().await?;
+    if staged == 0 {
+        return Ok(());
+    }
+    promote_pending(ix, provider).await?;
+    ix.netflow.flush().await?;
+    let left = count().await?;
+    info!("Settled {} staged logs from the last session; {} not final yet", staged - left, left);
     Ok(())
 }
 
-async fn is_exchange(db: &SqlitePool, addr: &Address) -> Result<bool> {
-    let a = format!("{:#x}", addr);
-    let rec = sqlx::query_scalar::<_, Option<i64>>(
-        r#"SELECT 1 FROM exchange_addresses WHERE lower(address)=lower(?) LIMIT 1;"#)
-        .bind(a)
-        .fetch_optional(db).await?;
-    Ok(rec.is_some())
+/// Replay captured logs (NDJSON, one `eth_getLogs` entry per line) through the
+/// same processing path as the live stream. No RPC is involved, so every log is
+/// treated as final and contract detection is unavailable (counterparties are
+/// treated as EOAs). Returns the number of logs fed.
+pub async fn replay_logs(ix: &Indexer, path: &Path) -> Result<usize> {
+    if ix.cfg.exclude_contract_counterparties {
+        warn!("replay has no RPC: contract counterparties will not be excluded");
+    }
+    let file = tokio::fs::File::open(path).await
+        .map_err(|e| anyhow::anyhow!("failed to open {}: {e}", path.display()))?;
+    let mut lines = BufReader::new(file).lines();
+    let mut n = 0usize;
+    let mut line_no = 0usize;
+    while let Some(line) = lines.next_line().await? {
+        line_no += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let lg: Log = serde_json::from_str(&line)
+            .map_err(|e| IndexerError::Decode(format!("line {line_no}: not a valid log: {e}")))?;
+        if !ix.cfg.tokens.contains(&lg.address) {
+            warn!("line {line_no}: log from {} ignored (not a configured token)", to_lower_hex(lg.address));
+            continue;
+        }
+        if let Err(e) = apply_log(ix, None, u64::MAX, lg).await {
+            error!("replay stopped at line {line_no}");
+            return Err(e);
+        }
+        n += 1;
+    }
+    ix.netflow.flush().await?;
+    Ok(n)
+}
+
+/// Process a log given the current chain head (used for the finality check).
+/// `provider` is needed for reorg detection and contract checks and may be `None` offline.
+/// A dry run skips reorg detection, which records block hashes and rolls back tables.
+async fn apply_log(ix: &Indexer, provider: Option<&Provider<Transport>>, head: u64, lg: Log) -> Result<()> {
+    if let (Some(p), Some(bn), Some(hash), None) = (provider, lg.block_number, lg.block_hash, &ix.dry_run) {
+        let bn = bn.as_u64();
+        if head.saturating_sub(bn) >= ix.confirmations() {
+            check_reorg(ix, p, head, bn, hash).await?;
+        }
+    }
+    ingest_log(ix, provider, head, lg).await
+}
+
+/// Compare block `bn` (hash from the log) against the recorded chain: a different
+/// hash at the same height, or a parent that doesn't match the stored previous block,
+/// means blocks we counted were orphaned. Walks back to the fork point, rolls back
+/// with `handle_reorg` and re-ingests the canonical logs up to `bn - 1`.
+async fn check_reorg(ix: &Indexer, provider: &Provider<Transport>, head: u64, bn: u64, hash: H256) -> Result<()> {
+    let hash_s = format!("{:#x}", hash);
+    let mut first_bad = match blocks::hash_at(&ix.db, bn).await? {
+        Some(stored) if stored == hash_s => return Ok(()),
+        Some(_) => Some(bn),
+        None => None,
+    };
+
+    let block = with_retry(ix, "eth_getBlockByHash", || provider.get_block(hash)).await
+        .map_err(IndexerError::rpc(format!("failed to fetch block {hash_s}")))?
+        .ok_or_else(|| IndexerError::MissingData(format!("block {hash_s} not returned by the node")))?;
+    if first_bad.is_none() && bn > 0 {
+        let parent = format!("{:#x}", block.parent_hash);
+        if blocks::hash_at(&ix.db, bn - 1).await?.is_some_and(|stored| stored != parent) {
+            first_bad = Some(bn - 1);
+        }
+    }
+
+    if let Some(mut fork) = first_bad {
+        // Extend the rollback over every older recorded block that is no longer canonical
+        let mut canonical = Vec::new();
+        while fork > 0 {
+            let Some(stored) = blocks::hash_at(&ix.db, fork - 1).await? else { break };
+            let b = with_retry(ix, "eth_getBlockByNumber", || provider.get_block(fork - 1)).await?
+                .ok_or_else(|| IndexerError::MissingData(format!("block {} not returned by the node", fork - 1)))?;
+            let Some(h) = b.hash.map(|h| format!("{:#x}", h)) else { break };
+            if h == stored {
+                break;
+            }
+            if bn - fork >= MAX_REORG_DEPTH {
+                return Err(IndexerError::ReorgTooDeep { block: bn, max: MAX_REORG_DEPTH });
+            }
+            canonical.push((fork - 1, h, b.timestamp.as_u64() as i64));
+            fork -= 1;
+        }
+        warn!("Reorg detected at block {}: rolling back from block {}", bn, fork);
+        handle_reorg(ix, fork).await?;
+
+        if fork < bn {
+            let filter = transfer_filter(&ix.cfg).from_block(fork).to_block(bn - 1);
+            let logs = with_retry(ix, "eth_getLogs", || provider.get_logs(&filter)).await
+                .map_err(IndexerError::rpc(format!("eth_getLogs failed re-ingesting blocks {fork}..={}", bn - 1)))?;
+            for (n, h, ts) in canonical {
+                blocks::set_block(&ix.db, n, &h, ts).await?;
+            }
+            info!("Re-ingesting {} canonical logs in blocks {}..={}", logs.len(), fork, bn - 1);
+            for lg in logs {
+                ingest_log(ix, Some(provider), head, lg).await?;
+            }
+        }
+    }
+    blocks::set_block(&ix.db, bn, &hash_s, block.timestamp.as_u64() as i64).await
+}
+
+/// Roll back everything recorded from `from_block` on (an orphaned fork) and rebuild
+/// the cumulative counters from the transfers that remain.
+pub async fn handle_reorg(ix: &Indexer, from_block: u64) -> Result<RecomputeReport> {
+    let from = from_block as i64;
+    // Batched deltas may be for the transfers about to go; the recompute drops them all
+    ix.netflow.flush().await?;
+    let mut tx = ix.db.begin().await?;
+    sqlx::query(r#"
+        DELETE FROM erc20_transfer_raw WHERE (tx_hash, log_index) IN
+            (SELECT tx_hash, log_index FROM erc20_transfers WHERE block_number >= ?);
+    "#)
+        .bind(from)
+        .execute(&mut *tx).await?;
+    let removed = sqlx::query("DELETE FROM erc20_transfers WHERE block_number >= ?;")
+        .bind(from)
+        .execute(&mut *tx).await?
+        .rows_affected();
+    sqlx::query("DELETE FROM netflow_by_block WHERE block_number >= ?;")
+        .bind(from)
+        .execute(&mut *tx).await?;
+    sqlx::query("DELETE FROM blocks WHERE number >= ?;")
+        .bind(from)
+        .execute(&mut *tx).await?;
+    sqlx::query("UPDATE indexer_status SET synced_block = MIN(synced_block, ?);")
+        .bind(from - 1)
+        .execute(&mut *tx).await?;
+    tx.commit().await?;
+    warn!("Rolled back {} transfers from block {} on", removed, from_block);
+    recompute_netflow(ix, None).await
+}
+
+/// The finality check and everything after it, without reorg detection.
+async fn ingest_log(ix: &Indexer, provider: Option<&Provider<Transport>>, head: u64, lg: Log) -> Result<()> {
+    let Some(bn) = lg.block_number.map(|b| b.as_u64()) else {
+        return record_skip(ix, &lg, "missing block number").await;
+    };
+    if head.saturating_sub(bn) < ix.confirmations() {
+        // Not final yet: held back until promote_pending sees it buried deep enough
+        return stage_pending(ix, &lg, bn).await;
+    }
+
+    // Decode topics:
+    // topic0 = Transfer(...)
+    // topic1 = from, topic2 = to, data = value
+    // Anything else (e.g. an ERC-721 Transfer with the id in topic3 and empty data, or a
+    // token putting the value in a topic) is not an amount we can trust
+    if lg.topics.len() != 3 {
+        return skip_malformed(ix, &lg, bn, &format!("{} topics, expected 3", lg.topics.len())).await;
+    }
+    let (Some(from), Some(to)) = (topic_address(&lg.topics[1]), topic_address(&lg.topics[2])) else {
+        return skip_malformed(ix, &lg, bn, "from/to topic is not a left-padded address").await;
+    };
+    if lg.data.len() != 32 {
+        return skip_malformed(ix, &lg, bn, &format!("data is {} bytes, expected one 32-byte uint256 word", lg.data.len())).await;
+    }
+    // value, 0 ..= 2^256-1 by construction
+    let amount = match ethers::abi::Uint::decode(lg.data.as_ref()) {
+        Ok(a) => a,
+        Err(e) => return skip_malformed(ix, &lg, bn, &format!("amount decode failed: {e}")).await,
+    };
+
+    let tx_hash = lg.transaction_hash.unwrap_or_default();
+    let log_index = lg.log_index.unwrap_or_default().as_u64() as i64;
+    let block_number = bn as i64;
+    // Each log counts towards the token that emitted it
+    let contract = lg.address;
+    if !ix.cfg.tokens.contains(&contract) {
+        ix.status.set_processed(bn);
+        return record_skip(ix, &lg, "not from a configured token").await;
+    }
+
+    if let (true, Some(p)) = (ix.cfg.verify_receipt_status, provider) {
+        if !tx_succeeded(ix, p, tx_hash).await? {
+            warn!("Skipping log {:#x}:{} in block {}: transaction did not succeed", tx_hash, log_index, bn);
+            ix.status.set_processed(bn);
+            return Ok(());
+        }
+    }
+
+    // Classify in/out relative to exchange set
+    let from_ex = exchange_of(ix, &from);
+    let to_ex   = exchange_of(ix, &to);
+    let from_is_ex = from_ex.is_some();
+    let to_is_ex   = to_ex.is_some();
+    let (from_neutral, to_neutral) = {
+        let neutral = ix.neutral.read().expect("neutral cache poisoned");
+        (neutral.contains(&from), neutral.contains(&to))
+    };
+
+    // Sampling only ever drops transfers that can't affect netflow
+    if !(from_is_ex || to_is_ex) && !ix.cfg.sampling.keep(tx_hash, log_index as u64, bn) {
+        ix.status.set_processed(bn);
+        return Ok(());
+    }
+
+    // Only a transfer not stored yet goes on (idempotent); the netflow writer stores it
+    let fresh = match &ix.dry_run {
+        Some(dry) => {
+            debug!("dry-run: would store transfer {:#x}:{} in block {} ({} -> {}, {})",
+                tx_hash, log_index, bn, to_lower_hex(from), to_lower_hex(to), amount);
+            dry.first_sight(tx_hash, log_index as u64)
+        }
+        None => ix.netflow.is_new(tx_hash, log_index).await?,
+    };
+    ix.status.set_processed(bn);
+    // Already stored or queued (backfill overlapping the stream, a replayed block, ...):
+    // it has been counted once, so nothing below may run again
+    if !fresh {
+        return Ok(());
+    }
+    ix.status.transfers_processed.fetch_add(1, Ordering::Relaxed);
+    ix.recent.push(tx_hash, log_index as u64, bn, from, to, amount);
+    let row = match &ix.dry_run {
+        Some(_) => None,
+        None => Some(TransferWrite {
+            tx_hash,
+            log_index,
+            block: block_number,
+            contract,
+            from,
+            to,
+            amount: crate::units::u256_to_integer(amount),
+            block_ts: block_ts(ix, provider, bn).await?,
+            raw: if ix.cfg.store_raw_logs {
+                Some((serde_json::to_string(&lg.topics)?, format!("0x{}", hex::encode(&lg.data))))
+            } else {
+                None
+            },
+            counted: None,
+        }),
+    };
+    // Zero-value and self transfers move nothing: stored for completeness only
+    let moves = ix.cfg.count_zero_transfers || !(amount.is_zero() || from == to);
+    if !(moves && (from_is_ex || to_is_ex)) {
+        if let Some(row) = row {
+            ix.netflow.send(row).await?;
+        }
+        return Ok(());
+    }
+
+    ix.status.exchange_transfers.fetch_add(1, Ordering::Relaxed);
+    // Contract detection only matters for the non-exchange side of a flow
+    let mut from_is_contract = false;
+    let mut to_is_contract = false;
+    if ix.cfg.exclude_contract_counterparties {
+        if let Some(p) = provider {
+            if to_is_ex && !from_is_ex && !from.is_zero() {
+                from_is_contract = is_contract(ix, p, from).await?;
+            }
+            if from_is_ex && !to_is_ex && !to.is_zero() {
+                to_is_contract = is_contract(ix, p, to).await?;
+            }
+        }
+    }
+    let flow = classify(
+        &ix.cfg,
+        Party { addr: from, is_exchange: from_is_ex, is_contract: from_is_contract, is_neutral: from_neutral },
+        Party { addr: to, is_exchange: to_is_ex, is_contract: to_is_contract, is_neutral: to_neutral },
+    );
+    if from_neutral || to_neutral {
+        debug!("Transfer {:#x}:{} touches a neutral address; not counted as exchange flow", tx_hash, log_index);
+    }
+
+    let Some(mut row) = row else {
+        // Counted in memory only; alerts and `/ws/netflow` follow the stored totals
+        if let Some(dry) = &ix.dry_run {
+            let amount = crate::units::u256_to_integer(amount);
+            let totals = dry.apply(contract, &flow, from_ex.as_deref(), to_ex.as_deref(), &amount, block_number);
+            info!(
+                "dry-run: would apply {:?} of {} in block {} ({} -> {}); dry-run totals in {} out {}",
+                flow.columns().collect::<Vec<_>>(), amount, bn, from_ex.as_deref().unwrap_or("-"),
+                to_ex.as_deref().unwrap_or("-"), totals.in_wei, totals.out_wei,
+            );
+        }
+        return Ok(());
+    };
+
+    // The row and every counter it adds to are committed together by the writer
+    row.counted = Some(Counted {
+        flow,
+        from_ex,
+        to_ex,
+        by_block: ix.cfg.netflow_by_block,
+    });
+    // Alerts and `/ws/netflow` follow the writer's commits
+    ix.netflow.send(row).await
+}
+
+/// Timestamp of block `bn` for `erc20_transfers.block_ts`: from `blocks` (the reorg
+/// check records every final block it sees), else fetched once and stored there so the
+/// block's other logs find it. `None` offline, in a dry run without a provider or when
+/// the node doesn't return the block; `backfill-timestamps` fills those in later.
+async fn block_ts(ix: &Indexer, provider: Option<&Provider<Transport>>, bn: u64) -> Result<Option<i64>> {
+    if let Some((n, ts)) = *ix.last_block_ts.read().expect("block ts cache poisoned") {
+        if n == bn {
+            return Ok(Some(ts));
+        }
+    }
+    let stored = sqlx::query_scalar::<_, Option<i64>>("SELECT ts FROM blocks WHERE number = ?;")
+        .bind(bn as i64)
+        .fetch_optional(&ix.db).await?
+        .flatten();
+    let ts = match (stored, provider) {
+        (Some(ts), _) => Some(ts),
+        (None, Some(p)) => {
+            let block = with_retry(ix, "eth_getBlockByNumber", || p.get_block(bn)).await
+                .map_err(IndexerError::rpc(format!("failed to fetch block {bn} for its timestamp")))?;
+            match block {
+                Some(b) => {
+                    let ts = b.timestamp.as_u64() as i64;
+                    if ix.dry_run.is_none() {
+                        blocks::store_block(&ix.db, bn as i64, b.hash.map(|h| format!("{:#x}", h)), ts).await?;
+                    }
+                    Some(ts)
+                }
+                None => None,
+            }
+        }
+        (None, None) => None,
+    };
+    if let Some(ts) = ts {
+        *ix.last_block_ts.write().expect("block ts cache poisoned") = Some((bn, ts));
+    }
+    Ok(ts)
+}
+
+/// An indexed address topic: 12 zero bytes, then the address. `None` for anything else.
+fn topic_address(topic: &H256) -> Option<Address> {
+    let (pad, addr) = topic.as_bytes().split_at(12);
+    pad.iter().all(|b| *b == 0).then(|| H160::from_slice(addr))
+}
+
+/// A Transfer log that doesn't have the standard shape: warn, count it in
+/// `malformed_logs_total` and skip it (recorded in STRICT mode) instead of failing.
+async fn skip_malformed(ix: &Indexer, lg: &Log, bn: u64, reason: &str) -> Result<()> {
+    warn!(
+        "Skipping malformed Transfer log {:#x}:{} in block {}: {}",
+        lg.transaction_hash.unwrap_or_default(), lg.log_index.unwrap_or_default(), bn, reason,
+    );
+    ix.status.malformed_logs.fetch_add(1, Ordering::Relaxed);
+    ix.status.set_processed(bn);
+    record_skip(ix, lg, reason).await
+}
+
+/// STRICT mode: persist a log the lenient path would silently pass over, and mark
+/// the indexer degraded once `strict_degraded_after` have piled up. No-op otherwise.
+async fn record_skip(ix: &Indexer, lg: &Log, reason: &str) -> Result<()> {
+    if !ix.cfg.strict {
+        return Ok(());
+    }
+    if ix.dry_run.is_some() {
+        warn!("dry-run: would record skipped log ({reason})");
+        return Ok(());
+    }
+    sqlx::query(r#"
+        INSERT INTO skipped_logs (ts, tx_hash, log_index, block_number, reason, log_json)
+        VALUES (strftime('%s','now'), ?, ?, ?, ?, ?);
+    "#)
+        .bind(lg.transaction_hash.map(|h| format!("{:#x}", h)))
+        .bind(lg.log_index.map(|i| i.as_u64() as i64))
+        .bind(lg.block_number.map(|b| b.as_u64() as i64))
+        .bind(reason)
+        .bind(serde_json::to_string(lg)?)
+        .execute(&ix.db).await?;
+    let n = ix.status.skipped_logs.fetch_add(1, Ordering::Relaxed) + 1;
+    warn!("STRICT: recorded skipped log ({reason}), {n} so far");
+    if n >= ix.cfg.strict_degraded_after && !ix.status.degraded.swap(true, Ordering::Relaxed) {
+        warn!("STRICT: {n} skipped logs, marking indexer degraded (see skipped_logs)");
+    }
+    Ok(())
+}
+
+/// One side of a transfer as seen by the classifier.
+#[derive(Clone, Copy, Debug)]
+pub struct Party {
+    pub addr: Address,
+    pub is_exchange: bool,
+    /// Only meaningful when contract exclusion is enabled
+    pub is_contract: bool,
+    /// Listed in `neutral_addresses`
+    pub is_neutral: bool,
+}
+
+/// Which `netflow_state` counters a transfer adds its amount to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Flow {
+    pub inflow: bool,
+    pub outflow: bool,
+    pub excluded_in: bool,
+    pub excluded_out: bool,
+    pub minted: bool,
+    pub burned: bool,
+}
+
+/// Every wei counter in `netflow_state`, in `Flow` field order.
+pub const COUNTER_COLUMNS: [&str; 6] = [
+    "cumulative_in_wei",
+    "cumulative_out_wei",
+    "excluded_in_wei",
+    "excluded_out_wei",
+    "minted_wei",
+    "burned_wei",
+];
+
+impl Flow {
+    pub(crate) fn flags(&self) -> [bool; 6] {
+        [self.inflow, self.outflow, self.excluded_in, self.excluded_out, self.minted, self.burned]
+    }
+
+    /// The counters this transfer adds to.
+    pub fn columns(&self) -> impl Iterator<Item = &'static str> {
+        self.flags().into_iter().zip(COUNTER_COLUMNS).filter(|(on, _)| *on).map(|(_, c)| c)
+    }
+
+    /// The counters charged to the receiving exchange (in, excluded in, minted).
+    pub fn in_side(&self) -> Flow {
+        Flow { inflow: self.inflow, excluded_in: self.excluded_in, minted: self.minted, ..Flow::default() }
+    }
+
+    /// The counters charged to the sending exchange (out, excluded out, burned).
+    pub fn out_side(&self) -> Flow {
+        Flow { outflow: self.outflow, excluded_out: self.excluded_out, burned: self.burned, ..Flow::default() }
+    }
+}
+
+/// Apply the neutral-address, exchange membership, zero-address and contract rules to one transfer.
+/// Shared by the live path and `recompute_netflow` so both always agree.
+pub fn classify(cfg: &IndexerCfg, from: Party, to: Party) -> Flow {
+    // Moves to or from a neutral address (a migration contract, ...) aren't flow at all
+    if from.is_neutral || to.is_neutral {
+        return Flow::default();
+    }
+    let mut flow = Flow { inflow: to.is_exchange, outflow: from.is_exchange, ..Flow::default() };
+
+    // Mint to / burn from an exchange wallet
+    let minted = to.is_exchange && from.addr.is_zero();
+    let burned = from.is_exchange && to.addr.is_zero();
+    if cfg.zero_address_rule != ZeroAddressRule::Count {
+        let separate = cfg.zero_address_rule == ZeroAddressRule::Separate;
+        if minted {
+            flow.inflow = false;
+            flow.minted = separate;
+        }
+        if burned {
+            flow.outflow = false;
+            flow.burned = separate;
+        }
+    }
+
+    // Optionally set aside flows whose non-exchange side is a contract (e.g. DEX routers)
+    if cfg.exclude_contract_counterparties {
+        if flow.inflow && !from.is_exchange && from.is_contract {
+            flow.inflow = false;
+            flow.excluded_in = true;
+        }
+        if flow.outflow && !to.is_exchange && to.is_contract {
+            flow.outflow = false;
+            flow.excluded_out = true;
+        }
+    }
+    flow
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RecomputeReport {
+    pub transfers: u64,
+    /// contract -> counter -> value
+    pub before: BTreeMap<String, HashMap<&'static str, String>>,
+    pub after: BTreeMap<String, HashMap<&'static str, String>>,
+    pub last_block: Option<i64>,
+}
+
+const RECOMPUTE_PAGE: i64 = 5000;
+/// Transfers deleted per transaction while pruning, so the write lock is released often
+const PRUNE_BATCH: i64 = 5000;
+/// How often `RETENTION_BLOCKS` pruning runs
+const RETENTION_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Running per-counter totals of one token while recomputing.
+#[derive(Default)]
+struct Totals {
+    sums: [rug::Integer; 6],
+    last_block: Option<i64>,
+    transfers: u64,
+    /// block -> (in, out); only filled with `netflow_by_block` on
+    by_block: BTreeMap<i64, (rug::Integer, rug::Integer)>,
+    /// exchange label -> (counters, last block)
+    by_exchange: BTreeMap<String, ([rug::Integer; 6], Option<i64>)>,
+    /// lowercase exchange address -> (balance, last block)
+    balances: BTreeMap<String, (rug::Integer, i64)>,
+    /// Block of the checkpoint these totals started from; transfers up to it are in already
+    checkpoint: Option<i64>,
+}
+
+/// Rebuild every `netflow_state` counter from `erc20_transfers`, re-classifying each
+/// transfer against the current exchange set and rules. Contract detection uses only
+/// the in-memory code cache (unknown counterparties count as EOAs), so no RPC is needed.
+/// Every configured token gets its row rebuilt; transfers of other contracts are ignored.
+///
+/// Rows are read in pages outside any transaction; the write lock is only taken for
+/// the final catch-up over rows inserted meanwhile plus the swap, so the live indexer
+/// is never blocked for long. Progress is persisted to `job` when given.
+pub async fn recompute_netflow(ix: &Indexer, job: Option<i64>) -> Result<RecomputeReport> {
+    rebuild_netflow(ix, job, true).await
+}
+
+/// `recompute_netflow` with every read inside the one write transaction: a consistent
+/// snapshot, at the cost of holding the write lock throughout (`--recompute`, offline).
+pub async fn recompute_netflow_single_tx(ix: &Indexer) -> Result<RecomputeReport> {
+    rebuild_netflow(ix, None, false).await
+}
+
+/// With `prescan`, most rows are read before the write transaction starts; without it
+/// the in-transaction catch-up reads all of them.
+async fn rebuild_netflow(ix: &Indexer, job: Option<i64>, prescan: bool) -> Result<RecomputeReport> {
+    let exchanges = exchange_labels(&ix.db).await?;
+    let total = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM erc20_transfers;")
+        .fetch_one(&ix.db).await?.max(1) as f64;
+    let before = read_counters(&ix.db).await?;
+
+    // contract -> totals, from the newest checkpoint if transfers were pruned
+    let mut totals: BTreeMap<String, Totals> = BTreeMap::new();
+    for token in &ix.cfg.tokens {
+        let contract = format!("{:#x}", token);
+        let start = load_checkpoint(&ix.db, &contract).await?.unwrap_or_default();
+        totals.insert(contract, start);
+    }
+    let mut cursor = 0i64;
+    let mut seen = 0u64;
+    while prescan {
+        let page = recompute_page(&ix.db, cursor).await?;
+        if page.is_empty() {
+            break;
+        }
+        seen += page.len() as u64;
+        cursor = accumulate(ix, &exchanges, &mut totals, &page)?;
+        if let Some(id) = job {
+            crate::jobs::set_progress(&ix.db, id, (seen as f64 / total).min(0.99)).await?;
+        }
+    }
+
+    // Deltas still batched in the writer are for transfers the catch-up below counts
+    ix.netflow.flush().await?;
+    let mut tx = ix.db.begin().await?;
+    // Write first so the transaction holds the write lock before the catch-up reads
+    sqlx::query("UPDATE netflow_state SET last_block = last_block;")
+        .execute(&mut *tx).await?;
+    loop {
+        let page = recompute_page(&mut *tx, cursor).await?;
+        if page.is_empty() {
+            break;
+        }
+        cursor = accumulate(ix, &exchanges, &mut totals, &page)?;
+    }
+    // A prune since the totals were seeded deleted transfers they may be missing
+    for (contract, t) in &totals {
+        if newest_checkpoint(&mut *tx, contract).await? != t.checkpoint {
+            return Err(IndexerError::Other(anyhow::anyhow!(
+                "transfers of {contract} were pruned during the recompute; run it again"
+            )));
+        }
+    }
+    let assignments = COUNTER_COLUMNS.iter().map(|c| format!("{c} = ?")).collect::<Vec<_>>().join(", ");
+    let sql = format!("UPDATE netflow_state SET {assignments}, last_block = ?, seq = seq + 1 WHERE contract = ?;");
+    for (contract, t) in &totals {
+        let mut update = sqlx::query(&sql);
+        for sum in &t.sums {
+            update = update.bind(sum.to_string());
+        }
+        update.bind(t.last_block).bind(contract).execute(&mut *tx).await?;
+
+        // Zero every exchange row (bumping seq, so ETags change) and refill those with flow
+        let zeros = COUNTER_COLUMNS.iter().map(|c| format!("{c} = '0'")).collect::<Vec<_>>().join(", ");
+        sqlx::query(&format!("UPDATE netflow_by_exchange SET {zeros}, last_block = NULL, seq = seq + 1 WHERE contract = ?;"))
+            .bind(contract)
+            .execute(&mut *tx).await?;
+        let columns = COUNTER_COLUMNS.join(", ");
+        let updates = COUNTER_COLUMNS.iter().map(|c| format!("{c} = excluded.{c}")).collect::<Vec<_>>().join(", ");
+        let sql = format!(r#"
+            INSERT INTO netflow_by_exchange (contract, exchange, {columns}, last_block, seq)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, 1)
+            ON CONFLICT(contract, exchange) DO UPDATE SET {updates}, last_block = excluded.last_block;
+        "#);
+        for (label, (sums, last_block)) in &t.by_exchange {
+            let mut insert = sqlx::query(&sql).bind(contract).bind(label);
+            for sum in sums {
+                insert = insert.bind(sum.to_string());
+            }
+            insert.bind(*last_block).execute(&mut *tx).await?;
+        }
+
+        sqlx::query("DELETE FROM address_balances WHERE contract = ?;")
+            .bind(contract)
+            .execute(&mut *tx).await?;
+        for (address, (balance, last_block)) in &t.balances {
+            sqlx::query("INSERT INTO address_balances (contract, address, balance_wei, last_block) VALUES (?, ?, ?, ?);")
+                .bind(contract)
+                .bind(address)
+                .bind(balance.to_string())
+                .bind(*last_block)
+                .execute(&mut *tx).await?;
+        }
+    }
+    // Coverage follows the same transfer set, against the current exchange labels;
+    // first sightings in pruned blocks can't be rechecked and stay
+    sqlx::query("DELETE FROM exchange_coverage WHERE first_block > COALESCE((SELECT MAX(block_number) FROM checkpoints), -1);")
+        .execute(&mut *tx).await?;
+    sqlx::query(r#"
+        INSERT OR IGNORE INTO exchange_coverage (exchange, first_block)
+        SELECT e.exchange, MIN(t.block_number)
+        FROM erc20_transfers t
+        JOIN exchange_addresses e ON e.address = t."to" OR e.address = t."from"
+        GROUP BY e.exchange;
+    "#).execute(&mut *tx).await?;
+    if ix.cfg.netflow_by_block {
+        for (contract, t) in &totals {
+            // Rows of pruned blocks have no transfers left to rebuild them from
+            sqlx::query("DELETE FROM netflow_by_block WHERE contract = ? AND block_number > ?;")
+                .bind(contract)
+                .bind(t.checkpoint.unwrap_or(i64::MIN))
+                .execute(&mut *tx).await?;
+            let keep_from = match (ix.cfg.by_block_keep_blocks, t.last_block) {
+                (Some(keep), Some(last)) => last.saturating_sub(keep as i64),
+                _ => i64::MIN,
+            };
+            for (block, (in_int, out_int)) in t.by_block.range(keep_from..) {
+                sqlx::query("INSERT INTO netflow_by_block (contract, block_number, in_wei, out_wei) VALUES (?, ?, ?, ?);")
+                    .bind(contract)
+                    .bind(*block)
+                    .bind(in_int.to_string())
+                    .bind(out_int.to_string())
+                    .execute(&mut *tx).await?;
+            }
+        }
+    }
+    tx.commit().await?;
+
+    let after = read_counters(&ix.db).await?;
+    let transfers = totals.values().map(|t| t.transfers).sum();
+    let last_block = totals.values().filter_map(|t| t.last_block).max();
+    info!("Recomputed netflow from {} transfers: before {:?}, after {:?}", transfers, before, after);
+    Ok(RecomputeReport { transfers, before, after, last_block })
+}
+
+/// Run `recompute_netflow` as a background `recompute` job; `None` if one is already running.
+pub async fn start_recompute_job(ix: &Indexer) -> Result<Option<i64>> {
+    let Some(id) = crate::jobs::try_start(&ix.db, "recompute").await? else {
+        return Ok(None);
+    };
+    let ix = ix.clone();
+    tokio::spawn(async move {
+        let outcome = recompute_netflow(&ix, Some(id)).await
+            .and_then(|report| Ok(serde_json::to_string(&report)?))
+            .map_err(anyhow::Error::from);
+        if let Err(e) = crate::jobs::finish(&ix.db, id, outcome).await {
+            error!("failed to record recompute job {id}: {e:#}");
+        }
+    });
+    Ok(Some(id))
+}
+
+/// One token's counters as of a past block (`netflow_state` column order).
+pub struct CountersAt {
+    pub sums: [rug::Integer; 6],
+    /// Newest block with an exchange flow at or below the requested one
+    pub last_block: Option<i64>,
+    /// Same, per exchange label
+    pub by_exchange: BTreeMap<String, ([rug::Integer; 6], Option<i64>)>,
+}
+
+/// Rebuild `contract`'s counters from its stored transfers up to and including block
+/// `at`, classified like `recompute_netflow`: against the exchange set and rules in
+/// force now, not as they were at that block. Read-only; scans every earlier transfer
+/// after the newest checkpoint, and fails for a block before it (see `pruned_through`).
+pub async fn counters_at_block(ix: &Indexer, contract: &str, at: u64) -> Result<CountersAt> {
+    let contract = contract.to_lowercase();
+    let start = match load_checkpoint(&ix.db, &contract).await? {
+        Some(t) if t.checkpoint > Some(at as i64) => {
+            return Err(IndexerError::Config(format!(
+                "block {at} is before the prune cutoff: transfers through block {} were deleted",
+                t.checkpoint.unwrap_or_default(),
+            )));
+        }
+        start => start.unwrap_or_default(),
+    };
+    let t = totals_through(ix, &contract, at as i64, start).await?;
+    Ok(CountersAt { sums: t.sums, last_block: t.last_block, by_exchange: t.by_exchange })
+}
+
+/// `start` plus every stored transfer of `contract` up to and including `through`.
+async fn totals_through(ix: &Indexer, contract: &str, through: i64, start: Totals) -> Result<Totals> {
+    let exchanges = exchange_labels(&ix.db).await?;
+    let mut totals = BTreeMap::from([(contract.to_string(), start)]);
+    let mut cursor = 0i64;
+    loop {
+        let page = sqlx::query_as::<_, TransferRow>(r#"
+            SELECT rowid, contract, "from", "to", amount_wei, block_number FROM erc20_transfers
+            WHERE rowid > ? AND contract = ? AND block_number <= ? ORDER BY rowid LIMIT ?;
+        "#)
+            .bind(cursor)
+            .bind(contract)
+            .bind(through)
+            .bind(RECOMPUTE_PAGE)
+            .fetch_all(&ix.db).await?;
+        if page.is_empty() {
+            break;
+        }
+        cursor = accumulate(ix, &exchanges, &mut totals, &page)?;
+    }
+    Ok(totals.into_values().next().unwrap_or_default())
+}
+
+/// Newest block whose transfers of `contract` may have been pruned; `None` if none were.
+pub async fn pruned_through(db: &SqlitePool, contract: &str) -> Result<Option<i64>> {
+    newest_checkpoint(db, contract).await
+}
+
+async fn newest_checkpoint<'e, E: sqlx::SqliteExecutor<'e>>(e: E, contract: &str) -> Result<Option<i64>> {
+    Ok(sqlx::query_scalar::<_, Option<i64>>("SELECT MAX(block_number) FROM checkpoints WHERE contract = ?;")
+        .bind(contract)
+        .fetch_one(e).await?)
+}
+
+type CounterTexts = (String, String, String, String, String, String);
+
+fn parse_counters(stored: CounterTexts) -> Result<[rug::Integer; 6]> {
+    let (c0, c1, c2, c3, c4, c5) = stored;
+    let mut sums: [rug::Integer; 6] = Default::default();
+    for ((sum, s), column) in sums.iter_mut().zip([c0, c1, c2, c3, c4, c5]).zip(COUNTER_COLUMNS) {
+        *sum = parse_total(&s, column)?;
+    }
+    Ok(sums)
+}
+
+/// `contract`'s newest checkpoint (with its exchange and balance rows) as starting
+/// totals; `None` if nothing was pruned.
+async fn load_checkpoint(db: &SqlitePool, contract: &str) -> Result<Option<Totals>> {
+    let columns = COUNTER_COLUMNS.join(", ");
+    let row = sqlx::query_as::<_, (i64, Option<i64>, i64, String, String, String, String, String, String)>(&format!(r#"
+        SELECT block_number, last_block, transfers, {columns} FROM checkpoints
+        WHERE contract = ? ORDER BY block_number DESC LIMIT 1;
+    "#))
+        .bind(contract)
+        .fetch_optional(db).await?;
+    let Some((block, last_block, transfers, c0, c1, c2, c3, c4, c5)) = row else {
+        return Ok(None);
+    };
+    let mut t = Totals {
+        sums: parse_counters((c0, c1, c2, c3, c4, c5))?,
+        last_block,
+        transfers: transfers as u64,
+        checkpoint: Some(block),
+        ..Totals::default()
+    };
+    let exchanges = sqlx::query_as::<_, (String, Option<i64>, String, String, String, String, String, String)>(&format!(r#"
+        SELECT exchange, last_block, {columns} FROM checkpoint_exchanges WHERE contract = ? AND block_number = ?;
+    "#))
+        .bind(contract)
+        .bind(block)
+        .fetch_all(db).await?;
+    for (label, last_block, c0, c1, c2, c3, c4, c5) in exchanges {
+        t.by_exchange.insert(label, (parse_counters((c0, c1, c2, c3, c4, c5))?, last_block));
+    }
+    let balances = sqlx::query_as::<_, (String, String, i64)>(
+        "SELECT address, balance_wei, last_block FROM checkpoint_balances WHERE contract = ? AND block_number = ?;")
+        .bind(contract)
+        .bind(block)
+        .fetch_all(db).await?;
+    for (address, balance, last_block) in balances {
+        t.balances.insert(address, (parse_total(&balance, "balance_wei")?, last_block));
+    }
+    Ok(Some(t))
+}
+
+/// Save `t` as `contract`'s checkpoint through `block`.
+async fn store_checkpoint(db: &SqlitePool, contract: &str, block: i64, t: &Totals) -> Result<()> {
+    let columns = COUNTER_COLUMNS.join(", ");
+    let mut tx = db.begin().await?;
+    let mut insert = sqlx::query(&format!(r#"
+        INSERT OR REPLACE INTO checkpoints (contract, block_number, {columns}, last_block, transfers, created_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, strftime('%s','now'));
+    "#))
+        .bind(contract)
+        .bind(block);
+    for sum in &t.sums {
+        insert = insert.bind(sum.to_string());
+    }
+    insert.bind(t.last_block).bind(t.transfers as i64).execute(&mut *tx).await?;
+    let sql = format!(r#"
+        INSERT OR REPLACE INTO checkpoint_exchanges (contract, block_number, exchange, {columns}, last_block)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?);
+    "#);
+    for (label, (sums, last_block)) in &t.by_exchange {
+        let mut insert = sqlx::query(&sql).bind(contract).bind(block).bind(label);
+        for sum in sums {
+            insert = insert.bind(sum.to_string());
+        }
+        insert.bind(*last_block).execute(&mut *tx).await?;
+    }
+    for (address, (balance, last_block)) in &t.balances {
+        sqlx::query(r#"
+            INSERT OR REPLACE INTO checkpoint_balances (contract, block_number, address, balance_wei, last_block)
+            VALUES (?, ?, ?, ?, ?);
+        "#)
+            .bind(contract)
+            .bind(block)
+            .bind(address)
+            .bind(balance.to_string())
+            .bind(*last_block)
+            .execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct PruneReport {
+    /// Transfers below this block were deleted
+    pub before: u64,
+    /// Tokens that got a new checkpoint
+    pub checkpoints: usize,
+    pub deleted: u64,
+}
+
+/// Delete every configured token's transfers (and raw logs) below block `before`,
+/// leaving `netflow_state` and the other live counters alone. Each token first gets a
+/// checkpoint through `before - 1`: its counters, per-exchange totals and balances as a
+/// recompute would have them there, which recomputes then start from. Deletes run in
+/// `PRUNE_BATCH`-row transactions so the indexer and the API never wait long on the
+/// lock; rerunning after an interruption finishes the job.
+pub async fn prune_before_block(ix: &Indexer, before: u64) -> Result<PruneReport> {
+    let mut report = PruneReport { before, checkpoints: 0, deleted: 0 };
+    let newest = resume_block(&ix.db).await?.unwrap_or(0);
+    if before.saturating_add(MAX_REORG_DEPTH) > newest.saturating_add(1) {
+        return Err(IndexerError::Config(format!(
+            "refusing to prune before block {before}: within {MAX_REORG_DEPTH} blocks of the newest indexed block \
+             {newest}, where a reorg can still roll transfers back"
+        )));
+    }
+    let Some(cutoff) = (before as i64).checked_sub(1).filter(|c| *c >= 0) else {
+        return Ok(report);
+    };
+    // Transfers still batched in the writer belong in the checkpoint
+    ix.netflow.flush().await?;
+    for token in &ix.cfg.tokens {
+        let contract = format!("{:#x}", token);
+        let start = load_checkpoint(&ix.db, &contract).await?.unwrap_or_default();
+        // An earlier prune already covered this cutoff; only leftover deletes to do
+        if start.checkpoint < Some(cutoff) {
+            let t = totals_through(ix, &contract, cutoff, start).await?;
+            store_checkpoint(&ix.db, &contract, cutoff, &t).await?;
+            report.checkpoints += 1;
+        }
+        loop {
+            let mut tx = ix.db.begin().await?;
+            sqlx::query(r#"
+                DELETE FROM erc20_transfer_raw WHERE (tx_hash, log_index) IN
+                    (SELECT tx_hash, log_index FROM erc20_transfers
+                     WHERE contract = ? AND block_number < ? ORDER BY rowid LIMIT ?);
+            "#)
+                .bind(&contract)
+                .bind(before as i64)
+                .bind(PRUNE_BATCH)
+                .execute(&mut *tx).await?;
+            let removed = sqlx::query(r#"
+                DELETE FROM erc20_transfers WHERE rowid IN
+                    (SELECT rowid FROM erc20_transfers
+                     WHERE contract = ? AND block_number < ? ORDER BY rowid LIMIT ?);
+            "#)
+                .bind(&contract)
+                .bind(before as i64)
+                .bind(PRUNE_BATCH)
+                .execute(&mut *tx).await?
+                .rows_affected();
+            tx.commit().await?;
+            if removed == 0 {
+                break;
+            }
+            report.deleted += removed;
+            tokio::task::yield_now().await;
+        }
+    }
+    info!("Pruned {} transfers below block {} ({} new checkpoints)", report.deleted, before, report.checkpoints);
+    Ok(report)
+}
+
+/// Every `RETENTION_INTERVAL`, prune transfers more than `keep` blocks behind the newest
+/// indexed block (`RETENTION_BLOCKS`). Failures are logged and retried next time.
+pub async fn run_retention(ix: Indexer, keep: u64) {
+    let mut tick = tokio::time::interval(RETENTION_INTERVAL);
+    loop {
+        tick.tick().await;
+        let newest = match resume_block(&ix.db).await {
+            Ok(Some(n)) => n,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("retention: reading the newest block failed: {e:#}");
+                continue;
+            }
+        };
+        let Some(before) = (newest + 1).checked_sub(keep).filter(|b| *b > 0) else { continue };
+        if let Err(e) = prune_before_block(&ix, before).await {
+            warn!("retention: pruning before block {before} failed: {e:#}");
+        }
+    }
+}
+
+/// lowercase address -> exchange label
+pub(crate) async fn exchange_labels(db: &SqlitePool) -> Result<HashMap<String, String>> {
+    Ok(sqlx::query_as::<_, (String, String)>("SELECT lower(address), exchange FROM exchange_addresses;")
+        .fetch_all(db).await?
+        .into_iter().collect())
+}
+
+type TransferRow = (i64, String, String, String, String, i64);
+
+async fn recompute_page<'e, E: sqlx::SqliteExecutor<'e>>(e: E, after_rowid: i64) -> Result<Vec<TransferRow>> {
+    Ok(sqlx::query_as::<_, TransferRow>(r#"
+        SELECT rowid, contract, "from", "to", amount_wei, block_number FROM erc20_transfers
+        WHERE rowid > ? ORDER BY rowid LIMIT ?;
+    "#)
+        .bind(after_rowid)
+        .bind(RECOMPUTE_PAGE)
+        .fetch_all(e).await?)
+}
+
+/// Classifies stored transfers the way the live path does, against `exchanges` (from
+/// `exchange_labels`), the neutral addresses and the cached contract checks. Used by
+/// `recompute_netflow` and the API's ranged sums so they all apply the same rules.
+/// Holds the caches' read locks: don't keep one across an `.await`.
+pub(crate) struct StoredClassifier<'a> {
+    cfg: &'a IndexerCfg,
+    exchanges: &'a HashMap<String, String>,
+    contracts: RwLockReadGuard<'a, HashMap<Address, bool>>,
+    neutral: RwLockReadGuard<'a, HashSet<Address>>,
+}
+
+/// A stored transfer touching an exchange, as `StoredClassifier` sees it.
+pub(crate) struct Classified<'a> {
+    pub flow: Flow,
+    pub from: Party,
+    pub to: Party,
+    pub from_ex: Option<&'a str>,
+    pub to_ex: Option<&'a str>,
+}
+
+impl<'a> StoredClassifier<'a> {
+    pub(crate) fn new(ix: &'a Indexer, exchanges: &'a HashMap<String, String>) -> Self {
+        Self {
+            cfg: &ix.cfg,
+            exchanges,
+            contracts: ix.code_cache.read().expect("code cache poisoned"),
+            neutral: ix.neutral.read().expect("neutral cache poisoned"),
+        }
+    }
+
+    /// `None` when neither side is an exchange, or when the transfer moves nothing
+    /// (zero value or self transfer) and `COUNT_ZERO_TRANSFERS` is off.
+    pub(crate) fn classify(&self, from: &str, to: &str, amount: &str) -> Result<Option<Classified<'a>>> {
+        // Same short-circuit as the live path
+        if !self.cfg.count_zero_transfers && (amount == "0" || from.eq_ignore_ascii_case(to)) {
+            return Ok(None);
+        }
+        let exchanges: &'a HashMap<String, String> = self.exchanges;
+        let from_ex = exchanges.get(&from.to_lowercase()).map(String::as_str);
+        let to_ex = exchanges.get(&to.to_lowercase()).map(String::as_str);
+        if from_ex.is_none() && to_ex.is_none() {
+            return Ok(None);
+        }
+        let (from_p, to_p) = (self.party(from, from_ex.is_some())?, self.party(to, to_ex.is_some())?);
+        Ok(Some(Classified { flow: classify(self.cfg, from_p, to_p), from: from_p, to: to_p, from_ex, to_ex }))
+    }
+
+    fn party(&self, addr: &str, is_exchange: bool) -> Result<Party> {
+        let a: Address = addr.parse()
+            .map_err(|_| IndexerError::Decode(format!("bad address {addr} in erc20_transfers")))?;
+        Ok(Party {
+            addr: a,
+            is_exchange,
+            is_contract: self.contracts.get(&a).copied().unwrap_or(false),
+            is_neutral: self.neutral.contains(&a),
+        })
+    }
+}
+
+/// Classify a page into each token's `totals`; returns the last rowid seen.
+fn accumulate(
+    ix: &Indexer,
+    exchanges: &HashMap<String, String>,
+    totals: &mut BTreeMap<String, Totals>,
+    page: &[TransferRow],
+) -> Result<i64> {
+    let classifier = StoredClassifier::new(ix, exchanges);
+    let mut last = 0;
+    for (rowid, contract, from, to, amount, block) in page {
+        last = *rowid;
+        let Some(totals) = totals.get_mut(&contract.to_lowercase()) else { continue };
+        // Already summed into the checkpoint the totals started from
+        if totals.checkpoint.is_some_and(|c| *block <= c) {
+            continue;
+        }
+        totals.transfers += 1;
+        let Some(c) = classifier.classify(from, to, amount)? else { continue };
+        let amount = parse_amount(amount)
+            .map_err(|e| IndexerError::Decode(format!("bad amount_wei at rowid {rowid}: {e:#}")))?;
+        // Balances move whatever the counters make of the transfer
+        for (p, addr, incoming) in [(c.to, to, true), (c.from, from, false)] {
+            if p.is_exchange {
+                let (balance, last_block) = totals.balances.entry(addr.to_lowercase()).or_default();
+                if incoming { *balance += &amount } else { *balance -= &amount }
+                *last_block = (*last_block).max(*block);
+            }
+        }
+        let flow = c.flow;
+        if flow == Flow::default() {
+            continue;
+        }
+        for (sum, on) in totals.sums.iter_mut().zip(flow.flags()) {
+            if on {
+                *sum += &amount;
+            }
+        }
+        for (label, part) in [(c.to_ex, flow.in_side()), (c.from_ex, flow.out_side())] {
+            let Some(label) = label else { continue };
+            if part == Flow::default() {
+                continue;
+            }
+            let (sums, last_block) = totals.by_exchange.entry(label.to_owned()).or_default();
+            for (sum, on) in sums.iter_mut().zip(part.flags()) {
+                if on {
+                    *sum += &amount;
+                }
+            }
+            *last_block = (*last_block).max(Some(*block));
+        }
+        if ix.cfg.netflow_by_block && (flow.inflow || flow.outflow) {
+            let (in_int, out_int) = totals.by_block.entry(*block).or_default();
+            if flow.inflow { *in_int += &amount; }
+            if flow.outflow { *out_int += &amount; }
+        }
+        totals.last_block = totals.last_block.max(Some(*block));
+    }
+    Ok(last)
+}
+
+async fn read_counters(db: &SqlitePool) -> Result<BTreeMap<String, HashMap<&'static str, String>>> {
+    let mut out: BTreeMap<String, HashMap<&'static str, String>> = BTreeMap::new();
+    for c in COUNTER_COLUMNS {
+        let rows = sqlx::query_as::<_, (String, String)>(&format!("SELECT contract, {c} FROM netflow_state;"))
+            .fetch_all(db).await?;
+        for (contract, v) in rows {
+            out.entry(contract).or_default().insert(c, v);
+        }
+    }
+    Ok(out)
+}
+
+/// Stored running total. Sums of uint256 amounts can outgrow a uint256, so only
+/// integer-ness is checked (unlike `parse_amount`).
+pub(crate) fn parse_total(s: &str, column: &str) -> Result<rug::Integer> {
+    rug::Integer::from_str_radix(s, 10).map_err(|e| IndexerError::Decode(format!("corrupt {column} {s:?}: {e}")))
+}
+
+/// Per-exchange counterpart of the `netflow_state` update: the in-side counters go to the receiving
+/// exchange's `netflow_by_exchange` row and the out-side ones to the sender's, on the writer's
+/// transaction. A move between two wallets of the same exchange nets to zero there.
+pub(crate) async fn apply_exchange_flow(
+    conn: &mut SqliteConnection,
+    contract: &str,
+    flow: &Flow,
+    from_ex: Option<&str>,
+    to_ex: Option<&str>,
+    amount: &str,
+    block_number: i64,
+) -> Result<()> {
+    let sides = [(to_ex, flow.in_side()), (from_ex, flow.out_side())];
+    if sides.iter().all(|(label, part)| label.is_none() || part.columns().next().is_none()) {
+        return Ok(());
+    }
+    let amount = parse_amount(amount).map_err(IndexerError::decode)?;
+
+    for (label, part) in sides {
+        let Some(label) = label else { continue };
+        let columns: Vec<&str> = part.columns().collect();
+        if columns.is_empty() {
+            continue;
+        }
+        // Write first (creating the row on first flow) to hold the lock before reading
+        sqlx::query(r#"
+            INSERT INTO netflow_by_exchange (contract, exchange, last_block, seq) VALUES (?, ?, ?, 1)
+            ON CONFLICT(contract, exchange) DO UPDATE
+            SET last_block = MAX(COALESCE(last_block, 0), excluded.last_block), seq = seq + 1;
+        "#)
+            .bind(contract)
+            .bind(label)
+            .bind(block_number)
+            .execute(&mut *conn).await?;
+        let mut sums = Vec::with_capacity(columns.len());
+        for c in &columns {
+            let current = sqlx::query_scalar::<_, String>(&format!(
+                "SELECT {c} FROM netflow_by_exchange WHERE contract = ? AND exchange = ?;"))
+                .bind(contract)
+                .bind(label)
+                .fetch_one(&mut *conn).await?;
+            sums.push((parse_total(&current, c)? + &amount).to_string());
+        }
+        let sets = columns.iter().map(|c| format!("{c} = ?")).collect::<Vec<_>>().join(", ");
+        let sql = format!("UPDATE netflow_by_exchange SET {sets} WHERE contract = ? AND exchange = ?;");
+        let mut update = sqlx::query(&sql);
+        for sum in &sums {
+            update = update.bind(sum);
+        }
+        update.bind(contract).bind(label).execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+/// Add a counted transfer to its block's row in `netflow_by_block`, then drop rows
+/// that fell out of the `keep_blocks` retention window.
+pub(crate) async fn apply_block_flow(
+    conn: &mut SqliteConnection,
+    contract: &str,
+    flow: &Flow,
+    amount: &str,
+    block_number: i64,
+    keep_blocks: Option<u64>,
+) -> Result<()> {
+    let amount = parse_amount(amount).map_err(IndexerError::decode)?;
+
+    sqlx::query("INSERT OR IGNORE INTO netflow_by_block (contract, block_number, in_wei, out_wei) VALUES (?, ?, '0', '0');")
+        .bind(contract)
+        .bind(block_number)
+        .execute(&mut *conn).await?;
+    let (in_wei, out_wei) = sqlx::query_as::<_, (String, String)>(
+        "SELECT in_wei, out_wei FROM netflow_by_block WHERE contract = ? AND block_number = ?;")
+        .bind(contract)
+        .bind(block_number)
+        .fetch_one(&mut *conn).await?;
+    let mut in_int = parse_total(&in_wei, "in_wei")?;
+    let mut out_int = parse_total(&out_wei, "out_wei")?;
+    if flow.inflow { in_int += &amount; }
+    if flow.outflow { out_int += &amount; }
+    sqlx::query("UPDATE netflow_by_block SET in_wei = ?, out_wei = ? WHERE contract = ? AND block_number = ?;")
+        .bind(in_int.to_string())
+        .bind(out_int.to_string())
+        .bind(contract)
+        .bind(block_number)
+        .execute(&mut *conn).await?;
+    if let Some(keep) = keep_blocks {
+        sqlx::query("DELETE FROM netflow_by_block WHERE contract = ? AND block_number < ?;")
+            .bind(contract)
+            .bind(block_number.saturating_sub(keep as i64))
+            .execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+/// Add `amount` to the receiving exchange address's `address_balances` row and take
+/// it off the sender's (`None` for a side that isn't an exchange).
+pub(crate) async fn apply_balances(
+    conn: &mut SqliteConnection,
+    contract: &str,
+    from: Option<&Address>,
+    to: Option<&Address>,
+    amount: &str,
+    block_number: i64,
+) -> Result<()> {
+    let amount = parse_total(amount, "amount_wei")?;
+    for (addr, incoming) in [(to, true), (from, false)] {
+        let Some(addr) = addr else { continue };
+        let addr = to_lower_hex(*addr);
+        let stored = sqlx::query_scalar::<_, String>(
+            "SELECT balance_wei FROM address_balances WHERE contract = ? AND address = ?;")
+            .bind(contract)
+            .bind(&addr)
+            .fetch_optional(&mut *conn).await?;
+        let mut balance = match stored {
+            Some(s) => parse_total(&s, "balance_wei")?,
+            None => rug::Integer::new(),
+        };
+        if incoming { balance += &amount } else { balance -= &amount }
+        sqlx::query(r#"
+            INSERT INTO address_balances (contract, address, balance_wei, last_block) VALUES (?, ?, ?, ?)
+            ON CONFLICT(contract, address) DO UPDATE SET balance_wei = excluded.balance_wei, last_block = excluded.last_block;
+        "#)
+            .bind(contract)
+            .bind(&addr)
+            .bind(balance.to_string())
+            .bind(block_number)
+            .execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+/// Remember which exchange labels this transfer touched, for `/netflow` coverage.
+pub(crate) async fn record_coverage(conn: &mut SqliteConnection, from: &Address, to: &Address, block_number: i64) -> Result<()> {
+    sqlx::query(r#"
+        INSERT OR IGNORE INTO exchange_coverage (exchange, first_block)
+        SELECT exchange, ? FROM exchange_addresses WHERE address IN (?, ?);
+    "#)
+        .bind(block_number)
+        .bind(to_lower_hex(*from))
+        .bind(to_lower_hex(*to))
+        .execute(conn).await?;
+    Ok(())
+}
+
+/// Whether `addr` has deployed code: memory, then the `metadata` table, then `eth_getCode`.
+async fn is_contract(ix: &Indexer, provider: &Provider<Transport>, addr: Address) -> Result<bool> {
+    if let Some(hit) = ix.code_cache.read().expect("code cache poisoned").get(&addr) {
+        return Ok(*hit);
+    }
+    let fetch = || async {
+        let code = ix.status.rpc(provider.get_code(addr, None).await)?;
+        anyhow::Ok((!code.as_ref().is_empty()).to_string())
+    };
+    // A dry run reads the cache but doesn't fill it
+    let has_code = match &ix.dry_run {
+        Some(_) => match metadata::get(&ix.db, addr, metadata::HAS_CODE, ix.cfg.metadata_ttl_secs).await? {
+            Some(hit) => hit,
+            None => fetch().await?,
+        },
+        None => metadata::get_or_fetch(&ix.db, addr, metadata::HAS_CODE, ix.cfg.metadata_ttl_secs, fetch).await?,
+    } == "true";
+    ix.code_cache.write().expect("code cache poisoned").insert(addr, has_code);
+    Ok(has_code)
+}
+
+/// Whether `tx_hash` has a successful receipt, cached per transaction. A missing receipt
+/// (tx dropped since the log was served) counts as failed; pre-Byzantium receipts
+/// without a status field count as success.
+pub(crate) async fn tx_succeeded(ix: &Indexer, provider: &Provider<Transport>, tx_hash: H256) -> Result<bool> {
+    if let Some(hit) = ix.receipt_cache.read().expect("receipt cache poisoned").get(&tx_hash) {
+        return Ok(*hit);
+    }
+    let receipt = ix.status.rpc(provider.get_transaction_receipt(tx_hash).await)
+        .map_err(IndexerError::rpc(format!("failed to fetch receipt for {:#x}", tx_hash)))?;
+    let ok = match receipt {
+        Some(r) => r.status != Some(U64::zero()),
+        // Not cached: the tx may still be re-included
+        None => return Ok(false),
+    };
+    let mut cache = ix.receipt_cache.write().expect("receipt cache poisoned");
+    if cache.len() >= RECEIPT_CACHE_CAP {
+        cache.clear();
+    }
+    cache.insert(tx_hash, ok);
+    Ok(ok)
+}
+
+/// The `exchange` label of `addr`, if it is a known exchange address.
+fn exchange_of(ix: &Indexer, addr: &Address) -> Option<String> {
+    ix.exchanges.read().expect("exchange cache poisoned").get(addr).cloned()
+}
+
+/// Reload the in-memory exchange set from `exchange_addresses`, and the neutral set from
+/// `neutral_addresses`. Call after every change to either table; returns the number of
+/// exchange addresses loaded.
+pub async fn refresh_exchange_cache(ix: &Indexer) -> Result<usize> {
+    let rows = sqlx::query_as::<_, (String, String)>("SELECT lower(address), exchange FROM exchange_addresses;")
+        .fetch_all(&ix.db).await?;
+    let exchanges: HashMap<Address, String> = rows.into_iter()
+        .filter_map(|(addr, label)| match addr.parse() {
+            Ok(a) => Some((a, label)),
+            Err(_) => {
+                warn!("ignoring malformed exchange address {addr:?}");
+                None
+            }
+        })
+        .collect();
+    let neutral: HashSet<Address> = sqlx::query_scalar::<_, String>("SELECT lower(address) FROM neutral_addresses;")
+        .fetch_all(&ix.db).await?
+        .into_iter()
+        .filter_map(|addr| match addr.parse() {
+            Ok(a) => Some(a),
+            Err(_) => {
+                warn!("ignoring malformed neutral address {addr:?}");
+                None
+            }
+        })
+        .collect();
+    let n = exchanges.len();
+    *ix.exchanges.write().expect("exchange cache poisoned") = exchanges;
+    *ix.neutral.write().expect("neutral cache poisoned") = neutral;
+    Ok(n)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::alerts::AlertCfg;
+    use crate::db::{init_db, upsert_exchange_addresses, upsert_neutral_addresses, PoolCfg};
+    use ethers::types::{Bytes, U256};
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+
+    pub(crate) const BINANCE: &str = "0x28c6c06298d514db089934071355e5743bf21d60";
+    pub(crate) const COINBASE: &str = "0x71660c4005ba85c37ccec55d0c4493e66fe775d3";
+    pub(crate) const USER: &str = "0x1111111111111111111111111111111111111111";
+    pub(crate) const USER_B: &str = "0x2222222222222222222222222222222222222222";
+
+    /// The indexed token (POL)
+    pub(crate) fn token() -> Address {
+        "0x455e53cbb86018ac2b8092fdcd39d8444affc3f6".parse().expect("token address")
+    }
+
+    fn topic(addr: &str) -> H256 {
+        let a: Address = addr.parse().expect("test address");
+        let mut word = [0u8; 32];
+        word[12..].copy_from_slice(a.as_bytes());
+        H256(word)
+    }
+
+    /// Synthetic hash of `block` on `fork`, so a stub node can tell both from it.
+    pub(crate) fn block_hash_on(fork: u64, block: u64) -> H256 {
+        H256::from_low_u64_be((fork << 32) | (block + 1))
+    }
+
+    pub(crate) fn block_hash(block: u64) -> H256 {
+        block_hash_on(0, block)
+    }
+
+    /// A `Transfer(from, to, amount)` log of `token()`; the tx hash follows from block and index.
+    pub(crate) fn transfer_log(from: &str, to: &str, amount: u64, block: u64, log_index: u64) -> Log {
+        let mut data = [0u8; 32];
+        U256::from(amount).to_big_endian(&mut data);
+        Log {
+            address: token(),
+            topics: vec![topic_h256(TRANSFER_TOPIC), topic(from), topic(to)],
+            data: Bytes::from(data.to_vec()),
+            block_hash: Some(block_hash(block)),
+            block_number: Some(U64::from(block)),
+            transaction_hash: Some(H256::from_low_u64_be(block * 1000 + log_index)),
+            log_index: Some(U256::from(log_index)),
+            ..Default::default()
+        }
+    }
+
+    /// Defaults as in `.env.example`, offline, with no confirmations and no RPC retries.
+    pub(crate) fn test_cfg() -> IndexerCfg {
+        IndexerCfg {
+            rpc_urls: Vec::new(),
+            tokens: vec![token()],
+            confirmations: 0,
+            exclude_contract_counterparties: false,
+            head_stale_secs: 60,
+            recent_cap: 100,
+            zero_address_rule: ZeroAddressRule::Count,
+            store_raw_logs: false,
+            sampling: Sampling::default(),
+            reserves: ReserveCfg { interval_secs: 0, divergence_wei: rug::Integer::new() },
+            verify_receipt_status: false,
+            max_in_flight: 100,
+            log_workers: 1,
+            supply_refresh_secs: 0,
+            strict: false,
+            strict_degraded_after: 3,
+            metadata_ttl_secs: 86_400,
+            netflow_by_block: false,
+            by_block_keep_blocks: None,
+            native: NativeMode::Off,
+            backfill_chunk_blocks: 2000,
+            start_block: None,
+            start_timestamp: None,
+            ws_max_retries: None,
+            expected_chain_id: None,
+            mode: IndexerMode::Subscribe,
+            poll_interval_secs: 1,
+            count_zero_transfers: false,
+            rpc_retries: 0,
+            netflow_flush_every: 100,
+            netflow_flush_ms: 10,
+            dry_run: false,
+        }
+    }
+
+    /// An indexer on a fresh in-memory database with `exchanges` (address, label) seeded.
+    pub(crate) async fn test_indexer(cfg: IndexerCfg, exchanges: &[(&str, &str)]) -> Indexer {
+        // One connection: every connection to `:memory:` shares the database, but its
+        // shared cache locks whole tables instead of waiting out `busy_timeout`
+        let pool = PoolCfg { max_connections: 1, ..PoolCfg::default() };
+        let db = init_db("sqlite::memory:", &cfg.tokens, &pool).await.expect("in-memory database");
+        upsert_exchange_addresses(&db, exchanges).await.expect("seed exchanges");
+        let ix = Indexer::new(db, cfg, Alerts::new(AlertCfg::default()));
+        refresh_exchange_cache(&ix).await.expect("exchange cache");
+        ix
+    }
+
+    /// Apply `logs` offline, as `replay-logs` does, and wait for the writer to commit them.
+    pub(crate) async fn ingest(ix: &Indexer, logs: impl IntoIterator<Item = Log>) {
+        for lg in logs {
+            apply_log(ix, None, u64::MAX, lg).await.expect("apply_log");
+        }
+        ix.netflow.flush().await.expect("flush");
+    }
+
+    /// Pretend a session saw `head` and last advanced the processed checkpoint to
+    /// `processed` `age_secs` ago.
+    pub(crate) fn set_progress(status: &IndexerStatus, head: u64, processed: u64, age_secs: u64) {
+        status.set_head(head);
+        status.last_processed_block.store(processed, Ordering::Relaxed);
+        status.processed_advanced_at.store(unix_now() - age_secs, Ordering::Relaxed);
+    }
+
+    /// `(cumulative_in_wei, cumulative_out_wei, last_block, seq)` of `token()`.
+    pub(crate) async fn state(ix: &Indexer) -> (String, String, Option<i64>, i64) {
+        sqlx::query_as(r#"
+            SELECT cumulative_in_wei, cumulative_out_wei, last_block, seq FROM netflow_state WHERE contract = ?;
+        "#)
+            .bind(format!("{:#x}", token()))
+            .fetch_one(&ix.db).await.expect("netflow_state row")
+    }
+
+    /// `(cumulative_in_wei, cumulative_out_wei)` of `label`, if it has a row.
+    pub(crate) async fn exchange_state(ix: &Indexer, label: &str) -> Option<(String, String)> {
+        sqlx::query_as(r#"
+            SELECT cumulative_in_wei, cumulative_out_wei FROM netflow_by_exchange WHERE contract = ? AND exchange = ?;
+        "#)
+            .bind(format!("{:#x}", token()))
+            .bind(label)
+            .fetch_optional(&ix.db).await.expect("netflow_by_exchange")
+    }
+
+    /// `(block_number, log_index, amount_wei)` of every stored transfer, in chain order.
+    pub(crate) async fn stored_transfers(ix: &Indexer) -> Vec<(i64, i64, String)> {
+        sqlx::query_as("SELECT block_number, log_index, amount_wei FROM erc20_transfers ORDER BY block_number, log_index;")
+            .fetch_all(&ix.db).await.expect("erc20_transfers")
+    }
+
+    type Reply = std::result::Result<Value, (i64, String)>;
+
+    /// A JSON-RPC node over HTTP answering each call with `reply(method, params)`; an
+    /// `Err((code, message))` goes out as a JSON-RPC error.
+    pub(crate) async fn rpc_stub(reply: impl Fn(&str, &Value) -> Reply + Send + Sync + 'static) -> Provider<Transport> {
+        let reply = Arc::new(reply);
+        let app = axum::Router::new().route("/", axum::routing::post(move |axum::Json(req): axum::Json<Value>| {
+            let reply = reply.clone();
+            async move {
+                axum::Json(match reply(req["method"].as_str().unwrap_or_default(), &req["params"]) {
+                    Ok(result) => json!({"jsonrpc": "2.0", "id": req["id"], "result": result}),
+                    Err((code, message)) => json!({"jsonrpc": "2.0", "id": req["id"], "error": {"code": code, "message": message}}),
+                })
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind stub node");
+        let addr = listener.local_addr().expect("stub address");
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        transport::connect(&format!("http://{addr}")).await.expect("stub provider")
+    }
+
+    /// The `[fromBlock, toBlock]` of an `eth_getLogs` filter.
+    fn filter_range(params: &Value) -> (u64, u64) {
+        let block = |key: &str| {
+            let hex = params[0][key].as_str().expect("block bound");
+            u64::from_str_radix(hex.trim_start_matches("0x"), 16).expect("hex block")
+        };
+        (block("fromBlock"), block("toBlock"))
+    }
+
+    /// A stub node's chain: fork 0, or fork 1 from block `forked_at` on. Blocks have
+    /// timestamp 1000 + number; `eth_getLogs` returns the logs of canonical blocks.
+    #[derive(Default)]
+    pub(crate) struct Chain {
+        pub logs: Vec<Log>,
+        pub forked_at: Option<u64>,
+    }
+
+    impl Chain {
+        pub(crate) fn new(logs: Vec<Log>) -> Self {
+            Self { logs, forked_at: None }
+        }
+
+        fn fork_of(&self, branch: u64, block: u64) -> u64 {
+            u64::from(branch == 1 && self.forked_at.is_some_and(|f| block >= f))
+        }
+
+        fn block(&self, fork: u64, n: u64) -> Value {
+            let parent = if n == 0 { H256::zero() } else { block_hash_on(self.fork_of(fork, n - 1), n - 1) };
+            json!({
+                "hash": block_hash_on(fork, n),
+                "parentHash": parent,
+                "number": U64::from(n),
+                "timestamp": U256::from(1000 + n),
+            })
+        }
+
+        pub(crate) fn reply(&self, method: &str, params: &Value) -> Reply {
+            match method {
+                "eth_getBlockByHash" => {
+                    let hash: H256 = serde_json::from_value(params[0].clone()).expect("block hash");
+                    let v = hash.to_low_u64_be();
+                    Ok(self.block(v >> 32, (v & 0xffff_ffff) - 1))
+                }
+                "eth_getBlockByNumber" => {
+                    let hex = params[0].as_str().expect("block number");
+                    let n = u64::from_str_radix(hex.trim_start_matches("0x"), 16).expect("hex block");
+                    Ok(self.block(self.fork_of(1, n), n))
+                }
+                "eth_getLogs" => {
+                    let (from, to) = filter_range(params);
+                    let hits: Vec<&Log> = self.logs.iter()
+                        .filter(|l| l.block_number.map(|b| b.as_u64()).is_some_and(|b| {
+                            (from..=to).contains(&b) && l.block_hash == Some(block_hash_on(self.fork_of(1, b), b))
+                        }))
+                        .collect();
+                    Ok(serde_json::to_value(hits).expect("logs json"))
+                }
+                other => Err((-32601, format!("method {other} not stubbed"))),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn deposits_and_withdrawals_update_every_counter() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance"), (COINBASE, "coinbase")]).await;
+        ingest(&ix, [
+            transfer_log(USER, BINANCE, 500, 10, 0),
+            transfer_log(COINBASE, USER, 200, 11, 3),
+            // Neither side is an exchange: stored, not counted
+            transfer_log(USER, USER_B, 999, 12, 0),
+        ]).await;
+
+        let (in_wei, out_wei, last_block, seq) = state(&ix).await;
+        assert_eq!((in_wei.as_str(), out_wei.as_str(), last_block, seq), ("500", "200", Some(11), 2));
+        assert_eq!(exchange_state(&ix, "binance").await, Some(("500".into(), "0".into())));
+        assert_eq!(exchange_state(&ix, "coinbase").await, Some(("0".into(), "200".into())));
+        assert_eq!(stored_transfers(&ix).await, vec![
+            (10, 0, "500".into()),
+            (11, 3, "200".into()),
+            (12, 0, "999".into()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn totals_past_i64_max_are_exact() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        // 1e19 wei is past SQLite's 64-bit INTEGER (i64::MAX is about 9.2e18)
+        ingest(&ix, [
+            transfer_log(USER, BINANCE, 5_000_000_000_000_000_000, 10, 0),
+            transfer_log(USER_B, BINANCE, 5_000_000_000_000_000_000, 11, 0),
+        ]).await;
+
+        assert_eq!(state(&ix).await.0, "10000000000000000000");
+        assert_eq!(exchange_state(&ix, "binance").await, Some(("10000000000000000000".into(), "0".into())));
+    }
+
+    #[tokio::test]
+    async fn duplicate_logs_are_stored_and_counted_once() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        let lg = transfer_log(USER, BINANCE, 700, 20, 1);
+        // Twice in one batch (caught as pending), then again once committed
+        ingest(&ix, [lg.clone(), lg.clone()]).await;
+        ingest(&ix, [lg]).await;
+
+        let (in_wei, _, _, seq) = state(&ix).await;
+        assert_eq!((in_wei.as_str(), seq), ("700", 1));
+        assert_eq!(exchange_state(&ix, "binance").await, Some(("700".into(), "0".into())));
+        assert_eq!(stored_transfers(&ix).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn reorg_rolls_back_orphaned_transfers_and_their_counters() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        ingest(&ix, [
+            transfer_log(USER, BINANCE, 100, 30, 0),
+            transfer_log(USER, BINANCE, 40, 31, 0),
+            transfer_log(BINANCE, USER, 25, 32, 0),
+        ]).await;
+        let (_, _, _, seq_before) = state(&ix).await;
+
+        let report = handle_reorg(&ix, 31).await.expect("reorg");
+        assert_eq!(report.transfers, 1);
+        let (in_wei, out_wei, last_block, seq) = state(&ix).await;
+        assert_eq!((in_wei.as_str(), out_wei.as_str(), last_block), ("100", "0", Some(30)));
+        assert!(seq > seq_before, "a recompute bumps seq ({seq} after {seq_before})");
+        assert_eq!(exchange_state(&ix, "binance").await, Some(("100".into(), "0".into())));
+        assert_eq!(stored_transfers(&ix).await, vec![(30, 0, "100".into())]);
+
+        // The canonical fork's logs for the same blocks then count as new
+        ingest(&ix, [transfer_log(USER, BINANCE, 60, 31, 0)]).await;
+        assert_eq!(state(&ix).await.0, "160");
+    }
+
+    #[tokio::test]
+    async fn reorg_rolls_back_orphaned_transfers_and_reingests_the_canonical_ones() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        let orphaned = transfer_log(USER, BINANCE, 50, 2, 0);
+        let mut replacement = transfer_log(USER, BINANCE, 70, 2, 1);
+        replacement.block_hash = Some(block_hash_on(1, 2));
+        let mut next = transfer_log(BINANCE, USER, 10, 3, 0);
+        next.block_hash = Some(block_hash_on(1, 3));
+        let chain = Arc::new(Mutex::new(Chain::new(vec![
+            transfer_log(USER, BINANCE, 100, 1, 0),
+            orphaned.clone(),
+            replacement,
+            next.clone(),
+        ])));
+        let provider = rpc_stub({
+            let chain = chain.clone();
+            move |method: &str, params: &Value| chain.lock().unwrap().reply(method, params)
+        }).await;
+
+        handle_log(&ix, &provider, transfer_log(USER, BINANCE, 100, 1, 0)).await.expect("block 1");
+        handle_log(&ix, &provider, orphaned).await.expect("block 2");
+        ix.netflow.flush().await.expect("flush");
+        let (in_wei, _, _, seq_before) = state(&ix).await;
+        assert_eq!(in_wei, "150");
+
+        // Block 2 is replaced; the first log of the new fork's block 3 reveals it
+        chain.lock().unwrap().forked_at = Some(2);
+        handle_log(&ix, &provider, next).await.expect("block 3");
+        ix.netflow.flush().await.expect("flush");
+
+        assert_eq!(stored_transfers(&ix).await, vec![
+            (1, 0, "100".into()),
+            (2, 1, "70".into()),
+            (3, 0, "10".into()),
+        ]);
+        let (in_wei, out_wei, last_block, seq) = state(&ix).await;
+        assert_eq!((in_wei.as_str(), out_wei.as_str(), last_block), ("170", "10", Some(3)));
+        assert!(seq > seq_before, "a rollback bumps seq ({seq} after {seq_before})");
+        assert_eq!(exchange_state(&ix, "binance").await, Some(("170".into(), "10".into())));
+        let stored = blocks::hash_at(&ix.db, 2).await.expect("blocks");
+        assert_eq!(stored, Some(format!("{:#x}", block_hash_on(1, 2))));
+    }
+
+    /// Run `logs`, in the given order, through a session's log processor.
+    async fn process(ix: &Indexer, provider: &Provider<Transport>, logs: Vec<Log>) {
+        let (tx, rx) = tokio::sync::mpsc::channel(logs.len().max(1));
+        let worker = tokio::spawn(process_logs(ix.clone(), provider.clone(), rx, 0, None));
+        for lg in logs {
+            ix.status.queue_depth.fetch_add(1, Ordering::Relaxed);
+            tx.send(lg).await.expect("processor running");
+        }
+        drop(tx);
+        worker.await.expect("processor task").expect("processor");
+    }
+
+    #[tokio::test]
+    async fn concurrent_prefetch_with_shuffled_logs_matches_sequential_totals() {
+        let parties = [(USER, BINANCE), (BINANCE, USER), (USER_B, COINBASE), (COINBASE, USER_B), (USER, USER_B)];
+        let logs: Vec<Log> = (0..40u64)
+            .map(|i| {
+                let (from, to) = parties[i as usize % parties.len()];
+                transfer_log(from, to, 10 + 3 * i, 1 + i / 4, i % 4)
+            })
+            .collect();
+        // 17 is coprime with 40, so this visits every log once, far out of order
+        let mut shuffled: Vec<Log> = (0..40).map(|i| logs[i * 17 % 40].clone()).collect();
+        shuffled.push(logs[5].clone());
+
+        let mut totals = Vec::new();
+        for (workers, input) in [(1, logs.clone()), (4, shuffled)] {
+            let cfg = IndexerCfg { log_workers: workers, exclude_contract_counterparties: true, ..test_cfg() };
+            let ix = test_indexer(cfg, &[(BINANCE, "binance"), (COINBASE, "coinbase")]).await;
+            let chain = Chain::new(logs.clone());
+            // Every counterparty is an EOA; the lookups are what the workers prefetch
+            let provider = rpc_stub(move |method: &str, params: &Value| match method {
+                "eth_getCode" => Ok(json!("0x")),
+                _ => chain.reply(method, params),
+            }).await;
+            process(&ix, &provider, input).await;
+            assert_eq!(stored_transfers(&ix).await.len(), logs.len());
+            totals.push((
+                state(&ix).await,
+                exchange_state(&ix, "binance").await,
+                exchange_state(&ix, "coinbase").await,
+            ));
+        }
+        assert_eq!(totals[0], totals[1]);
+    }
+
+    #[tokio::test]
+    async fn malformed_transfer_logs_are_rejected() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        // ERC-721 style: the token id as a fourth topic, no data
+        let mut nft = transfer_log(USER, BINANCE, 0, 40, 0);
+        nft.topics.push(H256::from_low_u64_be(7));
+        nft.data = Bytes::new();
+        let mut short = transfer_log(USER, BINANCE, 5, 40, 1);
+        short.data = Bytes::from(vec![5u8; 31]);
+        let mut padded = transfer_log(USER, BINANCE, 5, 40, 2);
+        padded.topics[1].0[0] = 1;
+        // A valid amount word with trailing bytes after it
+        let mut oversized = transfer_log(USER, BINANCE, 5, 40, 3);
+        oversized.data = Bytes::from([oversized.data.to_vec(), vec![0u8; 32]].concat());
+        ingest(&ix, [nft, short, padded, oversized]).await;
+
+        assert_eq!(ix.status.malformed_logs.load(Ordering::Relaxed), 4);
+        assert!(stored_transfers(&ix).await.is_empty());
+        assert_eq!(state(&ix).await.0, "0");
+    }
+
+    #[tokio::test]
+    async fn strict_mode_records_degenerate_logs_that_lenient_mode_ignores() {
+        for strict in [false, true] {
+            let cfg = IndexerCfg { strict, strict_degraded_after: 2, ..test_cfg() };
+            let ix = test_indexer(cfg, &[(BINANCE, "binance"), (COINBASE, "coinbase")]).await;
+            let mut nft = transfer_log(USER, BINANCE, 0, 40, 0);
+            nft.topics.push(H256::from_low_u64_be(7));
+            // A sweep between two exchange wallets is normal and never flagged
+            ingest(&ix, [nft, transfer_log(BINANCE, COINBASE, 5, 40, 1)]).await;
+
+            let skipped = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM skipped_logs;")
+                .fetch_one(&ix.db).await.expect("skipped_logs");
+            assert_eq!(skipped, i64::from(strict), "strict = {strict}");
+            assert!(!ix.status.degraded.load(Ordering::Relaxed));
+            if strict {
+                let mut unnumbered = transfer_log(USER, BINANCE, 1, 41, 0);
+                unnumbered.block_number = None;
+                ingest(&ix, [unnumbered]).await;
+                assert!(ix.status.degraded.load(Ordering::Relaxed), "degraded after the second skipped log");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn zero_value_and_self_transfers_are_stored_but_not_counted() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        ingest(&ix, [transfer_log(USER, BINANCE, 0, 50, 0), transfer_log(BINANCE, BINANCE, 80, 50, 1)]).await;
+        let (in_wei, out_wei, _, seq) = state(&ix).await;
+        assert_eq!((in_wei.as_str(), out_wei.as_str(), seq), ("0", "0", 0));
+        assert_eq!(stored_transfers(&ix).await.len(), 2);
+
+        let cfg = IndexerCfg { count_zero_transfers: true, ..test_cfg() };
+        let ix = test_indexer(cfg, &[(BINANCE, "binance")]).await;
+        ingest(&ix, [transfer_log(BINANCE, BINANCE, 80, 50, 1)]).await;
+        let (in_wei, out_wei, _, _) = state(&ix).await;
+        assert_eq!((in_wei.as_str(), out_wei.as_str()), ("80", "80"));
+    }
+
+    const ZERO: &str = "0x0000000000000000000000000000000000000000";
+
+    /// `(minted_wei, burned_wei, excluded_in_wei, excluded_out_wei)` of `token()`.
+    async fn side_counters(ix: &Indexer) -> (String, String, String, String) {
+        sqlx::query_as(r#"
+            SELECT minted_wei, burned_wei, excluded_in_wei, excluded_out_wei FROM netflow_state WHERE contract = ?;
+        "#)
+            .bind(format!("{:#x}", token()))
+            .fetch_one(&ix.db).await.expect("netflow_state row")
+    }
+
+    #[tokio::test]
+    async fn zero_address_rule_exclude_and_separate() {
+        let logs = || [transfer_log(ZERO, BINANCE, 100, 60, 0), transfer_log(BINANCE, ZERO, 40, 61, 0)];
+
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        ingest(&ix, logs()).await;
+        let (in_wei, out_wei, _, _) = state(&ix).await;
+        assert_eq!((in_wei.as_str(), out_wei.as_str()), ("100", "40"));
+
+        let cfg = IndexerCfg { zero_address_rule: ZeroAddressRule::Exclude, ..test_cfg() };
+        let ix = test_indexer(cfg, &[(BINANCE, "binance")]).await;
+        ingest(&ix, logs()).await;
+        let (in_wei, out_wei, _, _) = state(&ix).await;
+        assert_eq!((in_wei.as_str(), out_wei.as_str()), ("0", "0"));
+        assert_eq!(side_counters(&ix).await, ("0".into(), "0".into(), "0".into(), "0".into()));
+
+        let cfg = IndexerCfg { zero_address_rule: ZeroAddressRule::Separate, ..test_cfg() };
+        let ix = test_indexer(cfg, &[(BINANCE, "binance")]).await;
+        ingest(&ix, logs()).await;
+        let (in_wei, out_wei, _, _) = state(&ix).await;
+        assert_eq!((in_wei.as_str(), out_wei.as_str()), ("0", "0"));
+        assert_eq!(side_counters(&ix).await, ("100".into(), "40".into(), "0".into(), "0".into()));
+    }
+
+    #[test]
+    fn classify_rules() {
+        let cfg = IndexerCfg { exclude_contract_counterparties: true, ..test_cfg() };
+        let party = |addr: &str, is_exchange, is_contract, is_neutral| Party {
+            addr: addr.parse().expect("test address"),
+            is_exchange,
+            is_contract,
+            is_neutral,
+        };
+        let ex = party(BINANCE, true, false, false);
+
+        let deposit = classify(&cfg, party(USER, false, false, false), ex);
+        assert_eq!(deposit, Flow { inflow: true, ..Flow::default() });
+        // A router paying into the exchange is set aside, not counted
+        let routed = classify(&cfg, party(USER, false, true, false), ex);
+        assert_eq!(routed, Flow { excluded_in: true, ..Flow::default() });
+        let routed_out = classify(&cfg, ex, party(USER, false, true, false));
+        assert_eq!(routed_out, Flow { excluded_out: true, ..Flow::default() });
+        // Neutral addresses aren't flow in either direction
+        assert_eq!(classify(&cfg, party(USER, false, false, true), ex), Flow::default());
+        // Exchange to exchange: in and out, net zero
+        let internal = classify(&cfg, ex, party(COINBASE, true, true, false));
+        assert_eq!(internal, Flow { inflow: true, outflow: true, ..Flow::default() });
+    }
+
+    #[tokio::test]
+    async fn neutral_addresses_are_stored_but_not_counted() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        upsert_neutral_addresses(&ix.db, &[(USER_B, "migration")]).await.expect("neutral");
+        refresh_exchange_cache(&ix).await.expect("caches");
+        ingest(&ix, [transfer_log(USER_B, BINANCE, 300, 70, 0), transfer_log(USER, BINANCE, 5, 70, 1)]).await;
+        assert_eq!(state(&ix).await.0, "5");
+        assert_eq!(stored_transfers(&ix).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn seq_goes_up_with_every_counted_transfer_and_never_down() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        let mut seen = vec![state(&ix).await.3];
+        for (i, (from, to)) in [(USER, BINANCE), (USER, USER_B), (BINANCE, USER), (USER, BINANCE)].into_iter().enumerate() {
+            ingest(&ix, [transfer_log(from, to, 10, 80 + i as u64, 0)]).await;
+            seen.push(state(&ix).await.3);
+        }
+        handle_reorg(&ix, 83).await.expect("reorg");
+        seen.push(state(&ix).await.3);
+
+        // The non-exchange transfer leaves it alone; a rollback still moves it forward
+        assert_eq!(&seen[..5], &[0, 1, 1, 2, 3]);
+        assert!(seen.windows(2).all(|w| w[0] <= w[1]), "seq went backwards: {seen:?}");
+        assert!(seen[5] > seen[4]);
+    }
+
+    #[tokio::test]
+    async fn backfill_halves_refused_windows_and_restores_the_size() {
+        let mut cfg = test_cfg();
+        cfg.backfill_chunk_blocks = 8;
+        let ix = test_indexer(cfg, &[(BINANCE, "binance")]).await;
+        let logs = vec![
+            transfer_log(USER, BINANCE, 1, 1, 0),
+            transfer_log(USER, BINANCE, 2, 4, 0),
+            transfer_log(BINANCE, USER, 3, 8, 0),
+        ];
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let provider = rpc_stub({
+            let requested = requested.clone();
+            move |method: &str, params: &Value| {
+                if method == "eth_getLogs" {
+                    let (from, to) = filter_range(params);
+                    requested.lock().unwrap().push((from, to));
+                    if to - from + 1 > 2 {
+                        return Err((RPC_LIMIT_EXCEEDED, "query returned more than 10000 results".into()));
+                    }
+                }
+                Chain::new(logs.clone()).reply(method, params)
+            }
+        }).await;
+
+        backfill(&ix, &provider, 1, 8, &CancellationToken::new()).await.expect("backfill");
+        ix.netflow.flush().await.expect("flush");
+
+        let requested = requested.lock().unwrap().clone();
+        assert_eq!(&requested[..4], &[(1, 8), (1, 4), (1, 2), (3, 8)], "halved, then back to 8 blocks");
+        let applied: Vec<_> = requested.iter().filter(|(f, t)| t - f < 2).collect();
+        assert_eq!(applied.first().map(|r| r.0), Some(1));
+        assert!(applied.windows(2).all(|w| w[1].0 == w[0].1 + 1), "windows skip or overlap: {applied:?}");
+        assert_eq!(applied.last().map(|r| r.1), Some(8));
+        assert_eq!(stored_transfers(&ix).await.len(), 3);
+        let (in_wei, out_wei, _, _) = state(&ix).await;
+        assert_eq!((in_wei.as_str(), out_wei.as_str()), ("3", "3"));
+    }
+
+    #[tokio::test]
+    async fn shutdown_mid_backfill_leaves_a_resumable_cursor() {
+        let mut cfg = test_cfg();
+        cfg.backfill_chunk_blocks = 10;
+        let ix = test_indexer(cfg, &[(BINANCE, "binance")]).await;
+        let logs: Vec<Log> = (1..=30).step_by(3).map(|b| transfer_log(USER, BINANCE, b, b, 0)).collect();
+        let shutdown = CancellationToken::new();
+        let provider = rpc_stub({
+            let (logs, shutdown) = (logs.clone(), shutdown.clone());
+            move |method: &str, params: &Value| {
+                // Shut down while the second window is being fetched
+                if method == "eth_getLogs" && filter_range(params).0 == 11 {
+                    shutdown.cancel();
+                }
+                Chain::new(logs.clone()).reply(method, params)
+            }
+        }).await;
+
+        backfill(&ix, &provider, 1, 30, &shutdown).await.expect("interrupted backfill");
+        ix.netflow.flush().await.expect("flush");
+        let cursor = resume_block(&ix.db).await.expect("resume block").expect("some progress");
+        // Whole windows only: the first for sure, the second if its reply won the race
+        assert!(cursor == 10 || cursor == 19, "cursor {cursor}");
+        let stored = stored_transfers(&ix).await;
+        assert!(stored.iter().all(|(b, _, _)| *b as u64 <= cursor));
+        assert_eq!(stored.len(), logs.iter().filter(|l| l.block_number.unwrap().as_u64() <= cursor).count());
+
+        // Picking up after the cursor completes the range without counting anything twice
+        backfill(&ix, &provider, cursor + 1, 30, &CancellationToken::new()).await.expect("resumed backfill");
+        ix.netflow.flush().await.expect("flush");
+        assert_eq!(stored_transfers(&ix).await.len(), logs.len());
+        let sum: u64 = (1..=30).step_by(3).sum();
+        assert_eq!(state(&ix).await.0, sum.to_string());
+    }
+
+    #[tokio::test]
+    async fn restart_refetches_a_block_the_writer_cut_partway() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        let logs = vec![
+            transfer_log(USER, BINANCE, 1, 10, 0),
+            transfer_log(USER, BINANCE, 2, 10, 1),
+            transfer_log(BINANCE, USER, 4, 11, 0),
+        ];
+        // The last batch before the crash ended after the first log of block 10
+        ingest(&ix, [logs[0].clone()]).await;
+        assert_eq!(resume_block(&ix.db).await.expect("resume block"), Some(10));
+
+        let chain = Chain::new(logs.clone());
+        let provider = rpc_stub(move |method: &str, params: &Value| chain.reply(method, params)).await;
+        let covered = catch_up(&ix, &provider, 11, &CancellationToken::new()).await.expect("catch-up");
+        ix.netflow.flush().await.expect("flush");
+
+        assert_eq!(covered, Some(11));
+        assert_eq!(stored_transfers(&ix).await, vec![(10, 0, "1".into()), (10, 1, "2".into()), (11, 0, "4".into())]);
+        let (in_wei, out_wei, last_block, _) = state(&ix).await;
+        assert_eq!((in_wei.as_str(), out_wei.as_str(), last_block), ("3", "4", Some(11)));
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_pauses_the_reader_until_the_processor_catches_up() {
+        let ix = test_indexer(IndexerCfg { max_in_flight: 2, ..test_cfg() }, &[]).await;
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<Log>(ix.cfg.max_in_flight);
+        let shutdown = CancellationToken::new();
+        let mut backpressured = false;
+        for i in 0..2 {
+            assert!(enqueue(&ix, &tx, transfer_log(USER, BINANCE, 1, 10, i), &mut backpressured, &shutdown).await);
+        }
+        assert_eq!(ix.status.backpressure_events.load(Ordering::Relaxed), 0);
+
+        // Nothing is reading: the third log waits for room instead of being dropped
+        let processor = {
+            let third = enqueue(&ix, &tx, transfer_log(USER, BINANCE, 1, 10, 2), &mut backpressured, &shutdown);
+            tokio::pin!(third);
+            assert!(tokio::time::timeout(Duration::from_millis(50), &mut third).await.is_err(), "reader paused");
+            assert_eq!(ix.status.backpressure_events.load(Ordering::Relaxed), 1);
+            assert_eq!(ix.status.queue_depth.load(Ordering::Relaxed), 3);
+
+            // A slow processor makes room one log at a time
+            let processor = tokio::spawn({
+                let ix = ix.clone();
+                async move {
+                    let mut seen = Vec::new();
+                    while let Some(log) = rx.recv().await {
+                        ix.status.queue_depth.fetch_sub(1, Ordering::Relaxed);
+                        seen.push(log.log_index.unwrap().as_u64());
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                    }
+                    seen
+                }
+            });
+            assert!(third.await);
+            processor
+        };
+        assert!(enqueue(&ix, &tx, transfer_log(USER, BINANCE, 1, 10, 3), &mut backpressured, &shutdown).await);
+        drop(tx);
+        assert_eq!(processor.await.expect("processor"), vec![0, 1, 2, 3], "nothing dropped or reordered");
+        assert_eq!(ix.status.queue_depth.load(Ordering::Relaxed), 0);
+        assert_eq!(ix.status.backpressure_events.load(Ordering::Relaxed), 1, "one event per time the queue filled");
+    }
+
+    #[tokio::test]
+    async fn start_timestamp_resolves_to_its_block_clamped_to_the_creation_block() {
+        // Block n is mined at 1000 + n; the token's code appears in block 20
+        let calls = Arc::new(AtomicU64::new(0));
+        let counter = calls.clone();
+        let chain = Chain::new(vec![transfer_log(USER, BINANCE, 5, 30, 0), transfer_log(USER, BINANCE, 7, 60, 0)]);
+        let provider = rpc_stub(move |method: &str, params: &Value| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            match method {
+                "eth_getCode" => {
+                    let hex = params[1].as_str().expect("block number");
+                    let n = u64::from_str_radix(hex.trim_start_matches("0x"), 16).expect("hex block");
+                    Ok(json!(if n >= 20 { "0x6080" } else { "0x" }))
+                }
+                _ => chain.reply(method, params),
+            }
+        }).await;
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+
+        let resolve = |ts| resolve_start_timestamp(&ix, &provider, ts, 100);
+        assert_eq!(resolve(1050).await.expect("resolve"), 50);
+        assert_eq!(resolve(1005).await.expect("resolve"), 20, "before deployment");
+        assert_eq!(resolve(5000).await.expect("resolve"), 100, "past the head");
+
+        let fetched = calls.load(Ordering::Relaxed);
+        assert_eq!(resolve(1050).await.expect("cached"), 50);
+        assert_eq!(resolve(1005).await.expect("cached"), 20);
+        assert_eq!(calls.load(Ordering::Relaxed), fetched, "cached resolutions cost no RPC");
+
+        // An empty database is backfilled from the resolved block
+        let ix = test_indexer(IndexerCfg { start_timestamp: Some(1040), ..test_cfg() }, &[(BINANCE, "binance")]).await;
+        let covered = catch_up(&ix, &provider, 100, &CancellationToken::new()).await.expect("catch-up");
+        ix.netflow.flush().await.expect("flush");
+        assert_eq!(covered, Some(100));
+        assert_eq!(stored_transfers(&ix).await, vec![(60, 0, "7".into())]);
+    }
+
+    #[tokio::test]
+    async fn startup_promotes_final_staged_logs_and_drops_orphaned_ones() {
+        let ix = test_indexer(IndexerCfg { confirmations: 5, ..test_cfg() }, &[(BINANCE, "binance")]).await;
+        let promoted = transfer_log(USER, BINANCE, 100, 10, 0);
+        let mut orphaned = transfer_log(USER, BINANCE, 50, 11, 0);
+        orphaned.block_hash = Some(block_hash_on(1, 11));
+        let recent = transfer_log(USER, BINANCE, 7, 20, 0);
+        for lg in [&promoted, &orphaned, &recent] {
+            stage_pending(&ix, lg, lg.block_number.unwrap().as_u64()).await.expect("stage");
+        }
+
+        let chain = Chain::new(vec![promoted, recent]);
+        let provider = rpc_stub(move |method: &str, params: &Value| chain.reply(method, params)).await;
+        ix.status.set_head(22);
+        reconcile_pending(&ix, &provider).await.expect("reconcile");
+        ix.netflow.flush().await.expect("flush");
+
+        assert_eq!(stored_transfers(&ix).await, vec![(10, 0, "100".into())]);
+        assert_eq!(state(&ix).await.0, "100");
+        let left = sqlx::query_scalar::<_, i64>("SELECT block_number FROM pending_transfers;")
+            .fetch_all(&ix.db).await.expect("pending_transfers");
+        assert_eq!(left, vec![20], "still short of CONFIRMATIONS");
+    }
+
+    #[tokio::test]
+    async fn close_gap_records_only_blocks_after_the_subscription_opened() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        let missed = vec![transfer_log(USER, BINANCE, 5, 11, 0), transfer_log(USER, BINANCE, 7, 13, 0)];
+        let chain = Chain::new(missed);
+        let provider = rpc_stub(move |method: &str, params: &Value| chain.reply(method, params)).await;
+
+        // Backfilled to 10, subscription opened at head 12, next streamed log in block 15
+        let mut contiguous = 10;
+        close_gap(&ix, &provider, &mut contiguous, 12, &transfer_log(USER, BINANCE, 1, 15, 0)).await.expect("gap check");
+        ix.netflow.flush().await.expect("flush");
+
+        assert_eq!(contiguous, 15);
+        let gaps = sqlx::query_as::<_, (i64, i64, bool)>("SELECT from_block, to_block, healed_at IS NOT NULL FROM gaps;")
+            .fetch_all(&ix.db).await.expect("gaps");
+        assert_eq!(gaps, vec![(13, 14, true)]);
+        // Both the catch-up and the gap's logs were applied; the streamed one is the caller's
+        assert_eq!(stored_transfers(&ix).await.iter().map(|t| t.0).collect::<Vec<_>>(), vec![11, 13]);
+
+        // Quiet blocks in between are not a gap
+        close_gap(&ix, &provider, &mut contiguous, 12, &transfer_log(USER, BINANCE, 1, 20, 0)).await.expect("gap check");
+        let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM gaps;").fetch_one(&ix.db).await.expect("gaps");
+        assert_eq!(count, 1);
+    }
 }
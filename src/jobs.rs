@@ -0,0 +1,115 @@
+
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+/// Persisted background job, pollable via `GET /admin/jobs/{id}`.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Job {
+    pub id: i64,
+    pub kind: String,
+    /// `running` | `done` | `failed`
+    pub status: String,
+    /// 0.0 ..= 1.0
+    pub progress: f64,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    /// JSON result on success, message on failure
+    pub result: Option<String>,
+}
+
+/// Start a job of `kind` unless one is already running; returns `None` if so.
+/// The check and the insert are one statement, so concurrent triggers can't both win.
+pub async fn try_start(db: &SqlitePool, kind: &str) -> Result<Option<i64>> {
+    let res = sqlx::query(r#"
+        INSERT INTO jobs(kind, status, progress, started_at)
+        SELECT ?, 'running', 0, strftime('%s','now')
+        WHERE NOT EXISTS (SELECT 1 FROM jobs WHERE kind = ? AND status = 'running');
+    "#)
+        .bind(kind)
+        .bind(kind)
+        .execute(db).await?;
+    Ok((res.rows_affected() == 1).then(|| res.last_insert_rowid()))
+}
+
+pub async fn set_progress(db: &SqlitePool, id: i64, progress: f64) -> Result<()> {
+    sqlx::query("UPDATE jobs SET progress = ? WHERE id = ?;")
+        .bind(progress)
+        .bind(id)
+        .execute(db).await?;
+    Ok(())
+}
+
+pub async fn finish(db: &SqlitePool, id: i64, outcome: Result<String>) -> Result<()> {
+    let (status, result) = match outcome {
+        Ok(r) => ("done", r),
+        Err(e) => ("failed", format!("{e:#}")),
+    };
+    sqlx::query(r#"
+        UPDATE jobs SET status = ?, result = ?, finished_at = strftime('%s','now'),
+               progress = CASE WHEN ? = 'done' THEN 1 ELSE progress END
+        WHERE id = ?;
+    "#)
+        .bind(status)
+        .bind(result)
+        .bind(status)
+        .bind(id)
+        .execute(db).await?;
+    Ok(())
+}
+
+pub async fn get(db: &SqlitePool, id: i64) -> Result<Option<Job>> {
+    Ok(sqlx::query_as::<_, Job>(
+        "SELECT id, kind, status, progress, started_at, finished_at, result FROM jobs WHERE id = ?;")
+        .bind(id)
+        .fetch_optional(db).await?)
+}
+
+/// Jobs still `running` at startup died with the previous process.
+pub async fn fail_interrupted(db: &SqlitePool) -> Result<()> {
+    sqlx::query(r#"
+        UPDATE jobs SET status = 'failed', result = 'interrupted by restart',
+               finished_at = strftime('%s','now')
+        WHERE status = 'running';
+    "#).execute(db).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{init_db, PoolCfg};
+    use crate::indexer::tests::token;
+
+    async fn test_db() -> SqlitePool {
+        let pool = PoolCfg { max_connections: 1, ..PoolCfg::default() };
+        init_db("sqlite::memory:", &[token()], &pool).await.expect("in-memory database")
+    }
+
+    #[tokio::test]
+    async fn concurrent_triggers_start_one_job() {
+        let db = test_db().await;
+        let started: Vec<Option<i64>> = futures_util::future::join_all((0..4).map(|_| try_start(&db, "recompute")))
+            .await.into_iter().map(|r| r.expect("try_start")).collect();
+        let ids: Vec<i64> = started.iter().flatten().copied().collect();
+        assert_eq!(ids.len(), 1, "started {started:?}");
+        // Another kind is not blocked
+        assert!(try_start(&db, "prune").await.expect("try_start").is_some());
+
+        // Once it finishes the next trigger runs
+        finish(&db, ids[0], Ok("{}".into())).await.expect("finish");
+        let job = get(&db, ids[0]).await.expect("get").expect("job row");
+        assert_eq!((job.status.as_str(), job.progress), ("done", 1.0));
+        assert!(try_start(&db, "recompute").await.expect("try_start").is_some());
+    }
+
+    #[tokio::test]
+    async fn restart_fails_running_jobs() {
+        let db = test_db().await;
+        let id = try_start(&db, "recompute").await.expect("try_start").expect("started");
+        fail_interrupted(&db).await.expect("fail_interrupted");
+        let job = get(&db, id).await.expect("get").expect("job row");
+        assert_eq!((job.status.as_str(), job.result.as_deref()), ("failed", Some("interrupted by restart")));
+        assert!(try_start(&db, "recompute").await.expect("try_start").is_some());
+    }
+}
@@ -2,16 +2,54 @@
 mod db;
 mod indexer;
 mod api;
+mod error;
+mod field_case;
+mod alerts;
+mod units;
+mod ratelimit;
+mod recent;
+mod jobs;
+mod reserves;
+mod blocks;
+mod metadata;
+mod exchange_feed;
+mod native;
+mod env_template;
+mod token_meta;
+mod dry_run;
+mod exchanges_file;
+mod netflow_writer;
+mod transport;
+#[cfg(feature = "parquet-sink")]
+mod parquet_sink;
 
-use crate::db::{init_db, upsert_exchange_addresses};
-use crate::indexer::{Indexer, IndexerCfg, run_indexer};
-use anyhow::Result;
-use clap::Parser;
+use crate::alerts::{parse_exchange_thresholds, AlertCfg, Alerts};
+use crate::api::{ApiState, MaxNetflowAge, SessionBaseline};
+use crate::field_case::FieldCase;
+use crate::ratelimit::RateLimitCfg;
+use crate::reserves::ReserveCfg;
+use crate::db::{init_db, load_confirmations, PoolCfg, upsert_exchange_addresses, upsert_exchange_aliases, upsert_neutral_addresses};
+use crate::units::{parse_units, resolve_symbol, symbol_overrides, DisplayCfg, Rounding};
+use crate::indexer::{
+    Indexer, IndexerCfg, IndexerMode, Sampling, ZeroAddressRule, MAX_REORG_DEPTH, prune_before_block, recompute_netflow_single_tx,
+    refresh_exchange_cache, replay_logs, report_throughput, resume_block, run_indexer, run_retention, start_recompute_job,
+};
+use crate::native::NativeMode;
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand};
 use dotenvy::dotenv;
 use ethers::types::Address;
 use once_cell::sync::Lazy;
+use rug::ops::Pow;
+use std::collections::HashMap;
 use std::env;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
 use tokio::try_join;
+use tokio_util::sync::CancellationToken;
 use tracing_subscriber::{EnvFilter, fmt::Subscriber};
 
 #[derive(Parser, Debug)]
@@ -24,6 +62,44 @@ struct Args {
     /// Run only the indexer (skip the API)
     #[arg(long, default_value_t=false)]
     indexer_only: bool,
+
+    /// Rebuild the netflow counters from stored transfers in one transaction, then exit
+    #[arg(long, default_value_t=false)]
+    recompute: bool,
+
+    /// Checkpoint the counters, then delete stored transfers below this block and exit
+    #[arg(long, value_name = "N")]
+    prune_before_block: Option<u64>,
+
+    /// Index and classify live logs without writing to the database; the totals are
+    /// kept in memory and served under `dry_run` in /netflow
+    #[arg(long, default_value_t=false)]
+    dry_run: bool,
+
+    /// Exchange addresses to seed from a labeled `.json` or `.csv` file, on top of
+    /// BINANCE_ADDRESSES (default: EXCHANGES_FILE)
+    #[arg(long)]
+    exchanges_file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Feed captured logs (one JSON `Log` per line) through `handle_log` against a fresh DB
+    ReplayLogs {
+        /// NDJSON file of logs as returned by `eth_getLogs`
+        #[arg(long)]
+        file: PathBuf,
+        /// Fresh SQLite file to replay into (must not exist yet)
+        #[arg(long, default_value = "./replay.sqlite")]
+        db: String,
+    },
+    /// Fetch timestamps for transfer blocks missing from `blocks` (uses RPC_URL and DB_PATH)
+    BackfillTimestamps,
+    /// Refetch every cached `metadata` entry (and the token's supply) ignoring the TTL
+    RefreshMetadata,
 }
 
 fn init_tracing() {
@@ -41,46 +117,438 @@ static DEFAULT_BINANCE: Lazy<Vec<(&'static str, &'static str)>> = Lazy::new(|| v
     ("0x082489A616aB4D46d1947eE3F912e080815b08DA", "binance"),
 ]);
 
+/// Never exchange flow unless NEUTRAL_ADDRESSES says otherwise
+static DEFAULT_NEUTRAL: Lazy<Vec<(&'static str, &'static str)>> = Lazy::new(|| vec![
+    ("0x29e7DF7b6A1B2b07b731457f499E1696c60E2C4e", "MATIC -> POL migration"),
+]);
+
+/// Parse an optional env var, failing loudly on a malformed value.
+fn env_parse<T: FromStr>(key: &str) -> Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    match env::var(key) {
+        Ok(s) if !s.trim().is_empty() => s.trim().parse()
+            .map(Some)
+            .map_err(|e| anyhow!("invalid {key}={s:?}: {e}")),
+        _ => Ok(None),
+    }
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    Ok(env_parse(key)?.unwrap_or(default))
+}
+
+/// `true`/`1` enable a boolean flag; anything else (or unset) leaves it off.
+fn env_flag(key: &str) -> bool {
+    env::var(key).map(|s| s == "true" || s == "1").unwrap_or(false)
+}
+
+/// `RPC_URLS=wss://a,wss://b` in failover order, else the single `RPC_URL`.
+fn rpc_urls() -> Result<Vec<String>> {
+    let raw = ["RPC_URLS", "RPC_URL"].iter()
+        .find_map(|k| env::var(k).ok().filter(|s| !s.trim().is_empty()))
+        .ok_or_else(|| anyhow!("RPC_URLS (or RPC_URL) is required"))?;
+    let urls: Vec<String> = raw.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect();
+    if urls.is_empty() {
+        bail!("RPC_URLS has no endpoints");
+    }
+    Ok(urls)
+}
+
+/// `POL_TOKEN_ADDRESS=0x..,0x..`: one or more token contracts, first one primary.
+/// Duplicates are dropped, keeping the first occurrence.
+fn parse_tokens(csv: &str) -> Result<Vec<Address>> {
+    let mut tokens: Vec<Address> = Vec::new();
+    for raw in csv.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let token: Address = raw.parse().map_err(|e| anyhow!("invalid token address {raw:?} in POL_TOKEN_ADDRESS: {e}"))?;
+        if !tokens.contains(&token) {
+            tokens.push(token);
+        }
+    }
+    if tokens.is_empty() {
+        bail!("POL_TOKEN_ADDRESS lists no token address");
+    }
+    Ok(tokens)
+}
+
+/// Exchange addresses to seed
+/// 1) from .env BINANCE_ADDRESSES (comma-separated), if present
+/// 2) otherwise the baked-in list
+/// plus every labeled entry of `exchanges_file`
+fn seed_addresses(exchanges_file: Option<&Path>) -> Result<Vec<(String, String)>> {
+    let mut pairs = binance_addresses();
+    if let Some(path) = exchanges_file {
+        let listed = exchanges_file::load(path)?;
+        tracing::info!("Read {} exchange addresses from {}", listed.len(), path.display());
+        pairs.extend(listed);
+    }
+    Ok(pairs)
+}
+
+fn binance_addresses() -> Vec<(String, String)> {
+    match env::var("BINANCE_ADDRESSES") {
+        Ok(csv) => csv.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| (s.to_string(), "binance".to_string()))
+            .collect(),
+        Err(_) => DEFAULT_BINANCE.iter()
+            .map(|(a, ex)| (a.to_string(), ex.to_string()))
+            .collect(),
+    }
+}
+
+/// Neutral addresses to seed: NEUTRAL_ADDRESSES (comma-separated, may be empty) if
+/// set, otherwise the baked-in list
+fn seed_neutral() -> Vec<(String, String)> {
+    match env::var("NEUTRAL_ADDRESSES") {
+        Ok(csv) => csv.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| (s.to_string(), "from NEUTRAL_ADDRESSES".to_string()))
+            .collect(),
+        Err(_) => DEFAULT_NEUTRAL.iter()
+            .map(|(a, note)| (a.to_string(), note.to_string()))
+            .collect(),
+    }
+}
+
+async fn seed_exchanges(db: &db::Db, exchanges_file: Option<&Path>) -> Result<()> {
+    let pairs = seed_addresses(exchanges_file)?;
+    let refs: Vec<(&str, &str)> = pairs.iter()
+        .map(|(a, ex)| (a.as_str(), ex.as_str()))
+        .collect();
+    let counts = upsert_exchange_addresses(db, &refs).await?;
+    if counts.rejected > 0 {
+        tracing::warn!(
+            "Seeded {} exchange addresses ({} new), rejected {} invalid ones; fix BINANCE_ADDRESSES or the exchanges file",
+            counts.accepted, counts.added, counts.rejected,
+        );
+    } else {
+        tracing::info!("Seeded {} exchange addresses ({} new)", counts.accepted, counts.added);
+    }
+    let neutral = seed_neutral();
+    let refs: Vec<(&str, &str)> = neutral.iter()
+        .map(|(a, note)| (a.as_str(), note.as_str()))
+        .collect();
+    upsert_neutral_addresses(db, &refs).await?;
+
+    // Label aliases: EXCHANGE_ALIASES=binance-hot:binance,binance-cold:binance
+    if let Ok(csv) = env::var("EXCHANGE_ALIASES") {
+        let pairs: Vec<(&str, &str)> = csv.split(',')
+            .filter_map(|p| p.split_once(':'))
+            .map(|(label, canonical)| (label.trim(), canonical.trim()))
+            .collect();
+        upsert_exchange_aliases(db, &pairs).await?;
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
     init_tracing();
-    let _args = Args::parse();
+    let args = Args::parse();
+    if args.dry_run && (args.recompute || args.prune_before_block.is_some() || args.command.is_some()) {
+        bail!("--dry-run only applies to live indexing");
+    }
+    let exchanges_file = args.exchanges_file.clone()
+        .or_else(|| env::var_os("EXCHANGES_FILE").filter(|v| !v.is_empty()).map(PathBuf::from));
 
-    let rpc_url = env::var("RPC_URL").expect("RPC_URL required");
-    let token_addr = env::var("POL_TOKEN_ADDRESS").expect("POL_TOKEN_ADDRESS required");
-    let token = token_addr.parse::<Address>().expect("invalid POL token address");
-    let confirmations: u64 = env::var("CONFIRMATIONS").ok().and_then(|s| s.parse().ok()).unwrap_or(20);
+    let tokens = parse_tokens(&env::var("POL_TOKEN_ADDRESS").expect("POL_TOKEN_ADDRESS required"))?;
+    let confirmations: u64 = env_or("CONFIRMATIONS", 20)?;
     let db_path = env::var("DB_PATH").unwrap_or_else(|_| "./netflow.sqlite".into());
+    let default_pool = PoolCfg::default();
+    let pool_cfg = PoolCfg {
+        max_connections: env_or("DB_MAX_CONNECTIONS", default_pool.max_connections)?,
+        busy_timeout: env_parse("DB_BUSY_TIMEOUT_MS")?.map(Duration::from_millis).unwrap_or(default_pool.busy_timeout),
+    };
+    if pool_cfg.max_connections == 0 {
+        bail!("DB_MAX_CONNECTIONS must be at least 1");
+    }
+    let display = DisplayCfg {
+        precision: env_parse("DISPLAY_DECIMALS")?,
+        rounding: env_or("ROUNDING", Rounding::default())?,
+    };
+    // 0 disables a limit; expensive routes default to 60/min, cheap routes are exempt
+    let rate_limits = RateLimitCfg {
+        expensive_per_min: Some(env_or("RATE_LIMIT_EXPENSIVE_PER_MIN", 60)?).filter(|&n| n > 0),
+        cheap_per_min: Some(env_or("RATE_LIMIT_CHEAP_PER_MIN", 0)?).filter(|&n| n > 0),
+        global: env::var("RATE_LIMIT_SCOPE").map(|s| s == "global").unwrap_or(false),
+    };
+    let sampling = Sampling {
+        rate: env_or("SAMPLE_RATE", 1.0)?,
+        by_block: env::var("SAMPLE_BY").map(|s| s == "block").unwrap_or(false),
+    };
+    if !(sampling.rate > 0.0 && sampling.rate <= 1.0) {
+        bail!("SAMPLE_RATE must be in (0, 1]");
+    }
+    let reserves = ReserveCfg {
+        interval_secs: env_or("RESERVE_SNAPSHOT_SECS", 0)?,
+        divergence_wei: env_parse("RESERVE_DIVERGENCE_WEI")?
+            .unwrap_or_else(|| rug::Integer::from(10u32).pow(21)),
+    };
 
-    let db = init_db(&db_path).await?;
+    // Alerting: webhook is optional, alerts are always logged
+    let alert_cfg = AlertCfg {
+        webhook_url: env::var("ALERT_WEBHOOK_URL").ok().filter(|s| !s.is_empty()),
+        flip_band_wei: env_parse("FLIP_ALERT_BAND_WEI")?,
+        exchange_thresholds: parse_exchange_thresholds(&env::var("EXCHANGE_ALERT_THRESHOLDS").unwrap_or_default())?,
+        // Whole tokens of the primary token (18 decimals, like POL)
+        window_threshold_wei: env::var("ALERT_THRESHOLD_POL").ok().filter(|s| !s.is_empty())
+            .map(|s| parse_units(&s, 18).map_err(|e| anyhow!("invalid ALERT_THRESHOLD_POL: {e:#}")))
+            .transpose()?,
+        window: Duration::from_secs(env_or("ALERT_WINDOW_SECS", 3600)?),
+    };
 
-    // Seed Binance addresses
-    // 1) from .env BINANCE_ADDRESSES (comma-separated), if present
-    if let Ok(csv) = env::var("BINANCE_ADDRESSES") {
-        let pairs: Vec<(String, String)> = csv.split(',')
-            .map(|s| (s.trim().to_string(), "binance".to_string()))
-            .collect();
-        let refs: Vec<(&str, &str)> = pairs.iter()
-            .map(|(a, ex)| (a.as_str(), ex.as_str()))
-            .collect();
-        upsert_exchange_addresses(&db, &refs).await?;
-    } else {
-        // 2) fallback to baked-in list
-        upsert_exchange_addresses(&db, &DEFAULT_BINANCE).await?;
+    let mut cfg = IndexerCfg {
+        rpc_urls: Vec::new(),
+        tokens: tokens.clone(),
+        confirmations,
+        exclude_contract_counterparties: env_flag("EXCLUDE_CONTRACT_COUNTERPARTIES"),
+        head_stale_secs: env_or("HEAD_STALE_SECS", 60)?,
+        recent_cap: env_or("RECENT_TRANSFERS_CAP", 1000)?,
+        zero_address_rule: env_or("ZERO_ADDRESS_RULE", ZeroAddressRule::default())?,
+        store_raw_logs: env_flag("STORE_RAW_LOGS"),
+        sampling,
+        reserves: reserves.clone(),
+        verify_receipt_status: env_flag("VERIFY_RECEIPT_STATUS"),
+        max_in_flight: env_or("MAX_IN_FLIGHT_LOGS", 10_000)?,
+        log_workers: env_or("LOG_WORKERS", 1)?,
+        supply_refresh_secs: env_or("TOTAL_SUPPLY_REFRESH_SECS", 0)?,
+        strict: env_flag("STRICT"),
+        strict_degraded_after: env_or("STRICT_DEGRADED_AFTER", 3)?,
+        metadata_ttl_secs: env_or("METADATA_TTL_SECS", 86_400)?,
+        netflow_by_block: env_flag("NETFLOW_BY_BLOCK"),
+        by_block_keep_blocks: env_parse("NETFLOW_BY_BLOCK_KEEP_BLOCKS")?,
+        native: env_or("NATIVE_FLOW", NativeMode::default())?,
+        backfill_chunk_blocks: env_or("BACKFILL_CHUNK_BLOCKS", 2000)?,
+        start_block: env_parse("START_BLOCK")?,
+        start_timestamp: env_parse("START_TIMESTAMP")?,
+        ws_max_retries: env_parse("WS_MAX_RETRIES")?,
+        expected_chain_id: env_parse("EXPECTED_CHAIN_ID")?,
+        mode: env_or("INDEXER_MODE", IndexerMode::default())?,
+        poll_interval_secs: env_or("POLL_INTERVAL_SECS", 5)?,
+        count_zero_transfers: env_flag("COUNT_ZERO_TRANSFERS"),
+        rpc_retries: env_or("RPC_RETRIES", 3)?,
+        netflow_flush_every: env_or("NETFLOW_FLUSH_EVERY", 100)?,
+        netflow_flush_ms: env_or("NETFLOW_FLUSH_MS", 250)?,
+        dry_run: args.dry_run,
+    };
+
+    match args.command {
+        Some(Command::ReplayLogs { file, db }) => {
+            if std::path::Path::new(&db).exists() {
+                bail!("replay DB {db} already exists; pass a fresh --db path");
+            }
+            let db = init_db(&db, &tokens, &pool_cfg).await?;
+            seed_exchanges(&db, exchanges_file.as_deref()).await?;
+            let ix = Indexer::new(db, cfg, Alerts::new(alert_cfg));
+            refresh_exchange_cache(&ix).await?;
+            let n = replay_logs(&ix, &file).await?;
+            tracing::info!("Replayed {} logs from {}", n, file.display());
+            return Ok(());
+        }
+        Some(Command::BackfillTimestamps) => {
+            let rpc_url = rpc_urls()?.swap_remove(0);
+            let db = init_db(&db_path, &tokens, &pool_cfg).await?;
+            let provider = transport::connect(&rpc_url).await?;
+            let n = blocks::backfill_timestamps(&db, &provider).await?;
+            let left = blocks::count_missing_timestamps(&db).await?;
+            tracing::info!("Backfilled {} block timestamps; {} still missing", n, left);
+            return Ok(());
+        }
+        Some(Command::RefreshMetadata) => {
+            let rpc_url = rpc_urls()?.swap_remove(0);
+            let db = init_db(&db_path, &tokens, &pool_cfg).await?;
+            let provider = transport::connect(&rpc_url).await?;
+            let supplies: Vec<_> = tokens.iter().map(|&t| (t, metadata::TOTAL_SUPPLY)).collect();
+            metadata::refresh_all(&db, &provider, &supplies).await?;
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if args.recompute {
+        let db = init_db(&db_path, &tokens, &pool_cfg).await?;
+        seed_exchanges(&db, exchanges_file.as_deref()).await?;
+        if cfg.exclude_contract_counterparties {
+            tracing::warn!("--recompute has no RPC: contract counterparties will not be excluded");
+        }
+        let ix = Indexer::new(db, cfg, Alerts::new(alert_cfg));
+        let report = recompute_netflow_single_tx(&ix).await?;
+        for (contract, before) in &report.before {
+            tracing::info!("{}: before {:?}", contract, before);
+            tracing::info!("{}: after  {:?}", contract, report.after.get(contract));
+        }
+        tracing::info!("Recomputed from {} transfers (last block {:?})", report.transfers, report.last_block);
+        return Ok(());
     }
 
-    let ix = Indexer {
-        db: db.clone(),
-        cfg: IndexerCfg { rpc_url, token, confirmations },
+    if let Some(before) = args.prune_before_block {
+        let db = init_db(&db_path, &tokens, &pool_cfg).await?;
+        seed_exchanges(&db, exchanges_file.as_deref()).await?;
+        let ix = Indexer::new(db, cfg, Alerts::new(alert_cfg));
+        refresh_exchange_cache(&ix).await?;
+        let report = prune_before_block(&ix, before).await?;
+        tracing::info!("Deleted {} transfers below block {}; netflow_state is unchanged", report.deleted, report.before);
+        return Ok(());
+    }
+
+    let retention_blocks: Option<u64> = env_parse("RETENTION_BLOCKS")?;
+    if let Some(keep) = retention_blocks.filter(|&keep| keep < MAX_REORG_DEPTH) {
+        bail!("RETENTION_BLOCKS={keep} is below the {MAX_REORG_DEPTH}-block reorg window");
+    }
+    let rpc_urls = rpc_urls()?;
+    let db = init_db(&db_path, &tokens, &pool_cfg).await?;
+    if !args.dry_run {
+        seed_exchanges(&db, exchanges_file.as_deref()).await?;
+        jobs::fail_interrupted(&db).await?;
+    }
+
+    cfg.rpc_urls = rpc_urls;
+    let alerts = Alerts::new(alert_cfg);
+    alerts.seed_exchange_totals(&db, &format!("{:#x}", tokens[0])).await?;
+    let ix = Indexer::new(db.clone(), cfg, alerts);
+    // A depth set through the admin API outlives the environment's
+    if let Some(n) = load_confirmations(&db).await?.filter(|n| *n != confirmations) {
+        tracing::warn!("Using CONFIRMATIONS={} from PATCH /config/confirmations instead of {} from the environment", n, confirmations);
+        ix.set_confirmations(n);
+    }
+    let n = refresh_exchange_cache(&ix).await?;
+    tracing::info!("Loaded {} exchange addresses", n);
+    if args.dry_run {
+        // The seed list is tried on top of the stored set without being stored
+        let mut exchanges = ix.exchanges.write().expect("exchange cache poisoned");
+        for (addr, label) in seed_addresses(exchanges_file.as_deref())? {
+            match addr.parse::<Address>() {
+                Ok(a) => { exchanges.insert(a, label); }
+                Err(_) => tracing::warn!("dry-run: ignoring malformed exchange address {addr:?}"),
+            }
+        }
+        let mut neutral = ix.neutral.write().expect("neutral cache poisoned");
+        for (addr, _) in seed_neutral() {
+            match addr.parse::<Address>() {
+                Ok(a) => { neutral.insert(a); }
+                Err(_) => tracing::warn!("dry-run: ignoring malformed neutral address {addr:?}"),
+            }
+        }
+        tracing::warn!(
+            "DRY RUN: nothing is written to {}; classifying against {} exchange addresses, totals in /netflow under dry_run",
+            db_path, exchanges.len()
+        );
+    }
+    // Databases from before netflow_by_exchange / address_balances existed: fill them
+    // from stored transfers
+    let unsplit: bool = sqlx::query_scalar(r#"
+        SELECT (NOT EXISTS(SELECT 1 FROM netflow_by_exchange) OR NOT EXISTS(SELECT 1 FROM address_balances))
+            AND EXISTS(SELECT 1 FROM exchange_coverage);
+    "#)
+        .fetch_one(&db).await?;
+    if unsplit && !args.dry_run {
+        tracing::info!("per-exchange tables are empty: rebuilding them with a background recompute");
+        start_recompute_job(&ix).await?;
+    }
+    let mut session = HashMap::new();
+    for &t in &tokens {
+        session.insert(t, SessionBaseline::load(&db, t).await?);
+    }
+    // On-chain symbol()/decimals(); DISPLAY_SYMBOL_* overrides still win for the label
+    let overrides = symbol_overrides();
+    let token_meta = token_meta::load_all(&db, &ix.cfg.rpc_urls[0], &tokens).await?
+        .into_iter()
+        .map(|(t, mut meta)| {
+            meta.symbol = resolve_symbol(&overrides, t, Some(&meta.symbol));
+            (t, meta)
+        })
+        .collect();
+    let api_state = ApiState {
+        db,
+        status: ix.status.clone(),
+        tracked_tokens: tokens.len(),
+        started_at: Instant::now(),
+        display,
+        token_meta,
+        rate_limits,
+        field_case: env_or("API_FIELD_CASE", FieldCase::default())?,
+        recent: ix.recent.clone(),
+        sampling,
+        reserve_divergence_wei: reserves.divergence_wei.clone(),
+        api_key: env::var("API_KEY").ok().filter(|s| !s.is_empty()),
+        indexer: ix.clone(),
+        min_exchanges: env_or("MIN_EXCHANGES", 1)?,
+        session,
+        max_age: MaxNetflowAge {
+            blocks: env_parse("MAX_NETFLOW_AGE_BLOCKS")?,
+            secs: env_parse("MAX_NETFLOW_AGE_SECS")?,
+        },
+        cors_origins: env_parse("API_CORS_ORIGINS")?,
+        ws_slots: Arc::new(Semaphore::new(env_or("WS_MAX_SUBSCRIBERS", 100)?)),
     };
 
+    let feed = exchange_feed::ExchangeFeedCfg {
+        url: env::var("EXCHANGE_LIST_URL").ok().filter(|s| !s.is_empty()),
+        interval_secs: env_or("EXCHANGE_LIST_REFRESH_SECS", 3600)?,
+        prune: env_flag("EXCHANGE_LIST_PRUNE"),
+    };
+    if !args.dry_run {
+        tokio::spawn(exchange_feed::run(ix.clone(), feed));
+        if let Some(keep) = retention_blocks {
+            tokio::spawn(run_retention(ix.clone(), keep));
+        }
+    }
+    tokio::spawn(report_throughput(ix.status.clone(), env_or("THROUGHPUT_LOG_SECS", 60)?));
+
+    if let Some(dir) = env::var("PARQUET_DIR").ok().filter(|s| !s.is_empty()) {
+        #[cfg(feature = "parquet-sink")]
+        tokio::spawn(parquet_sink::run(ix.db.clone(), parquet_sink::ParquetCfg {
+            dir: PathBuf::from(dir),
+            flush_secs: env_or("PARQUET_FLUSH_SECS", 60)?,
+        }));
+        #[cfg(not(feature = "parquet-sink"))]
+        tracing::warn!("PARQUET_DIR={dir} ignored: built without the parquet-sink feature");
+    }
+
+    // Ctrl-C stops both tasks; so does either one ending, so a fatal indexer error
+    // doesn't leave the API serving a frozen checkpoint
+    let shutdown = CancellationToken::new();
+    {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                tracing::info!("Ctrl-C received: shutting down");
+            }
+            shutdown.cancel();
+        });
+    }
+
     // Run both indexer and API
-    let indexer_task = tokio::spawn(async move { run_indexer(ix).await });
-    let api_task = tokio::spawn(async move { api::serve(db).await });
+    let db = ix.db.clone();
+    let indexer_task = {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let _stop = shutdown.clone().drop_guard();
+            run_indexer(ix, shutdown).await
+        })
+    };
+    let api_task = {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let _stop = shutdown.clone().drop_guard();
+            api::serve(api_state, shutdown).await
+        })
+    };
 
     // If either fails, bubble up
     let (r1, r2) = try_join!(indexer_task, api_task)?;
     r1?; r2?;
+    match resume_block(&db).await? {
+        Some(n) => tracing::info!("Shutdown complete at block {}", n),
+        None => tracing::info!("Shutdown complete (nothing indexed yet)"),
+    }
     Ok(())
 }
@@ -0,0 +1,143 @@
+
+use anyhow::Result;
+use ethers::providers::{Middleware, Provider};
+use ethers::types::Address;
+use sqlx::SqlitePool;
+use std::future::Future;
+use tracing::{info, warn};
+use crate::reserves::total_supply;
+use crate::transport::Transport;
+
+/// `"true"`/`"false"`: whether the address has deployed code
+pub const HAS_CODE: &str = "has_code";
+/// Decimal string of the token's `totalSupply()`
+pub const TOTAL_SUPPLY: &str = "total_supply";
+
+/// Cached `key` for `addr` if fetched less than `ttl_secs` ago.
+pub async fn get(db: &SqlitePool, addr: Address, key: &str, ttl_secs: u64) -> Result<Option<String>> {
+    Ok(sqlx::query_scalar::<_, String>(r#"
+        SELECT value FROM metadata
+        WHERE address = ? AND key = ? AND fetched_at > strftime('%s','now') - ?;
+    "#)
+        .bind(format!("{:#x}", addr))
+        .bind(key)
+        .bind(ttl_secs as i64)
+        .fetch_optional(db).await?)
+}
+
+pub async fn put(db: &SqlitePool, addr: Address, key: &str, value: &str) -> Result<()> {
+    sqlx::query(r#"
+        INSERT INTO metadata (address, key, value, fetched_at) VALUES (?, ?, ?, strftime('%s','now'))
+        ON CONFLICT(address, key) DO UPDATE SET value = excluded.value, fetched_at = excluded.fetched_at;
+    "#)
+        .bind(format!("{:#x}", addr))
+        .bind(key)
+        .bind(value)
+        .execute(db).await?;
+    Ok(())
+}
+
+/// Read-through lookup: the cached value while fresh, else `fetch` (an RPC call) and
+/// store the result. Fetch errors are returned and never cached.
+pub async fn get_or_fetch<F, Fut>(db: &SqlitePool, addr: Address, key: &str, ttl_secs: u64, fetch: F) -> Result<String>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<String>>,
+{
+    if let Some(hit) = get(db, addr, key, ttl_secs).await? {
+        return Ok(hit);
+    }
+    let value = fetch().await?;
+    put(db, addr, key, &value).await?;
+    Ok(value)
+}
+
+/// Every cached `(address, key)`, for a forced refresh.
+pub async fn entries(db: &SqlitePool) -> Result<Vec<(Address, String)>> {
+    let rows = sqlx::query_as::<_, (String, String)>("SELECT address, key FROM metadata ORDER BY address, key;")
+        .fetch_all(db).await?;
+    rows.into_iter()
+        .map(|(a, k)| -> Result<_> { Ok((a.parse::<Address>()?, k)) })
+        .collect()
+}
+
+/// Refetch `key` for `addr` over RPC regardless of age.
+async fn fetch(provider: &Provider<Transport>, addr: Address, key: &str) -> Result<Option<String>> {
+    Ok(match key {
+        HAS_CODE => Some((!provider.get_code(addr, None).await?.as_ref().is_empty()).to_string()),
+        TOTAL_SUPPLY => Some(total_supply(provider, addr).await?.to_string()),
+        _ => None,
+    })
+}
+
+/// `refresh-metadata`: refetch every cached entry plus the `extra` ones. Failures are
+/// logged and keep the old value; returns how many entries were refreshed.
+pub async fn refresh_all(db: &SqlitePool, provider: &Provider<Transport>, extra: &[(Address, &str)]) -> Result<usize> {
+    let mut todo = entries(db).await?;
+    for (addr, key) in extra {
+        if !todo.iter().any(|(a, k)| a == addr && k == key) {
+            todo.push((*addr, key.to_string()));
+        }
+    }
+    let mut refreshed = 0;
+    for (addr, key) in &todo {
+        match fetch(provider, *addr, key).await {
+            Ok(Some(value)) => {
+                put(db, *addr, key, &value).await?;
+                refreshed += 1;
+            }
+            Ok(None) => warn!("unknown metadata key {key:?} for {:#x}; left as is", addr),
+            Err(e) => warn!("refresh of {key} for {:#x} failed: {e:#}", addr),
+        }
+    }
+    info!("Refreshed {}/{} metadata entries", refreshed, todo.len());
+    Ok(refreshed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{init_db, PoolCfg};
+    use crate::indexer::tests::{token, USER};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn test_db() -> SqlitePool {
+        let pool = PoolCfg { max_connections: 1, ..PoolCfg::default() };
+        init_db("sqlite::memory:", &[token()], &pool).await.expect("in-memory database")
+    }
+
+    #[tokio::test]
+    async fn cached_values_are_refetched_once_past_the_ttl() {
+        let db = test_db().await;
+        let addr: Address = USER.parse().unwrap();
+        let calls = AtomicUsize::new(0);
+        let lookup = |value: &'static str| {
+            let calls = &calls;
+            let db = &db;
+            async move {
+                get_or_fetch(db, addr, HAS_CODE, 60, || async move {
+                    calls.fetch_add(1, Ordering::Relaxed);
+                    Ok(value.to_string())
+                }).await
+            }
+        };
+
+        assert_eq!(lookup("false").await.expect("miss"), "false");
+        assert_eq!(lookup("true").await.expect("hit"), "false", "fresh entries are served from the cache");
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        // Past the TTL the entry is refetched and its age starts over
+        sqlx::query("UPDATE metadata SET fetched_at = fetched_at - 61;").execute(&db).await.expect("age entry");
+        assert_eq!(get(&db, addr, HAS_CODE, 60).await.expect("get"), None);
+        assert_eq!(lookup("true").await.expect("expired"), "true");
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        assert_eq!(lookup("false").await.expect("hit"), "true");
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        // A failed refetch is returned and leaves the stale value in place
+        sqlx::query("UPDATE metadata SET fetched_at = fetched_at - 61;").execute(&db).await.expect("age entry");
+        let failed = get_or_fetch(&db, addr, HAS_CODE, 60, || async { Err(anyhow::anyhow!("node down")) }).await;
+        assert!(failed.is_err());
+        assert_eq!(get(&db, addr, HAS_CODE, u64::from(u32::MAX)).await.expect("get"), Some("true".into()));
+    }
+}
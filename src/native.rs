@@ -0,0 +1,199 @@
+
+use anyhow::{Context, Result};
+use ethers::providers::{Middleware, Provider, StreamExt};
+use ethers::types::{Action, Address, BlockNumber, CallType, H256, U256};
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use tracing::{error, info, warn};
+use crate::indexer::{tx_succeeded, Indexer};
+use crate::transport::Transport;
+use crate::units::{parse_amount, u256_to_integer};
+
+/// How native POL movements into/out of exchange wallets are found. Kept apart from
+/// the ERC-20 netflow in `native_netflow_state`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NativeMode {
+    #[default]
+    Off,
+    /// Top-level transaction `value`s (any RPC node; misses contract-internal sends)
+    Tx,
+    /// `trace_block` call traces, including internal sends (needs a node exposing the trace API)
+    Trace,
+}
+
+impl NativeMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Tx => "tx",
+            Self::Trace => "trace",
+        }
+    }
+}
+
+impl std::str::FromStr for NativeMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" | "" => Ok(Self::Off),
+            "tx" => Ok(Self::Tx),
+            "trace" => Ok(Self::Trace),
+            other => anyhow::bail!("unknown NATIVE_FLOW {other:?} (off|tx|trace)"),
+        }
+    }
+}
+
+/// One value movement that touched an exchange wallet
+struct NativeFlow {
+    inflow: bool,
+    value: U256,
+}
+
+fn exchange_set(ix: &Indexer) -> HashSet<Address> {
+    ix.exchanges.read().expect("exchange cache poisoned").keys().copied().collect()
+}
+
+/// Classify a `from -> to` send; wallet-to-wallet moves between exchanges don't count.
+fn classify(exchanges: &HashSet<Address>, from: Address, to: Address, value: U256) -> Option<NativeFlow> {
+    if value.is_zero() {
+        return None;
+    }
+    match (exchanges.contains(&from), exchanges.contains(&to)) {
+        (false, true) => Some(NativeFlow { inflow: true, value }),
+        (true, false) => Some(NativeFlow { inflow: false, value }),
+        _ => None,
+    }
+}
+
+async fn flows_from_txs(ix: &Indexer, provider: &Provider<Transport>, exchanges: &HashSet<Address>, n: u64) -> Result<Vec<NativeFlow>> {
+    let block = provider.get_block_with_txs(n).await
+        .with_context(|| format!("failed to fetch block {n}"))?
+        .with_context(|| format!("block {n} not returned by the node"))?;
+    let mut flows = Vec::new();
+    for tx in block.transactions {
+        let Some(to) = tx.to else { continue };
+        let Some(flow) = classify(exchanges, tx.from, to, tx.value) else { continue };
+        // A reverted tx moves no value; only the few exchange txs pay for a receipt
+        if tx_succeeded(ix, provider, tx.hash).await? {
+            flows.push(flow);
+        }
+    }
+    Ok(flows)
+}
+
+async fn flows_from_traces(provider: &Provider<Transport>, exchanges: &HashSet<Address>, n: u64) -> Result<Vec<NativeFlow>> {
+    let traces = provider.trace_block(BlockNumber::Number(n.into())).await
+        .with_context(|| format!("trace_block({n}) failed; NATIVE_FLOW=trace needs a node with the trace API"))?;
+    // Calls under a reverted frame are rolled back with it
+    let mut failed: Vec<(Option<H256>, Vec<usize>)> = Vec::new();
+    let mut flows = Vec::new();
+    for t in traces {
+        let reverted = t.error.is_some() || failed.iter().any(|(tx, path)| {
+            *tx == t.transaction_hash && t.trace_address.starts_with(path)
+        });
+        if reverted {
+            failed.push((t.transaction_hash, t.trace_address));
+            continue;
+        }
+        let flow = match t.action {
+            Action::Call(c) if matches!(c.call_type, CallType::Call | CallType::None) => {
+                classify(exchanges, c.from, c.to, c.value)
+            }
+            Action::Suicide(s) => classify(exchanges, s.address, s.refund_address, s.balance),
+            _ => None,
+        };
+        flows.extend(flow);
+    }
+    Ok(flows)
+}
+
+/// Add one block's flows to `native_netflow_state` and advance its cursor.
+async fn apply_block(db: &SqlitePool, n: u64, flows: &[NativeFlow]) -> Result<()> {
+    let mut tx = db.begin().await?;
+    let (in_wei, out_wei) = sqlx::query_as::<_, (String, String)>(
+        "SELECT cumulative_in_wei, cumulative_out_wei FROM native_netflow_state WHERE id=1;")
+        .fetch_one(&mut *tx).await?;
+    let mut in_int = parse_amount(&in_wei)?;
+    let mut out_int = parse_amount(&out_wei)?;
+    for f in flows {
+        if f.inflow {
+            in_int += u256_to_integer(f.value);
+        } else {
+            out_int += u256_to_integer(f.value);
+        }
+    }
+    sqlx::query(r#"
+        UPDATE native_netflow_state
+        SET cumulative_in_wei = ?, cumulative_out_wei = ?, last_block = ?,
+            seq = seq + CASE WHEN ? > 0 THEN 1 ELSE 0 END
+        WHERE id=1;
+    "#)
+        .bind(in_int.to_string())
+        .bind(out_int.to_string())
+        .bind(n as i64)
+        .bind(flows.len() as i64)
+        .execute(&mut *tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn last_block(db: &SqlitePool) -> Result<Option<u64>> {
+    Ok(sqlx::query_scalar::<_, Option<i64>>("SELECT last_block FROM native_netflow_state WHERE id=1;")
+        .fetch_one(db).await?
+        .map(|b| b as u64))
+}
+
+/// Follow new heads and scan every block once it has `confirmations`, starting at the
+/// stored cursor (or the current confirmed head on first run, like the ERC-20 path).
+/// A failed block is retried on the next head.
+pub async fn run(ix: Indexer, provider: Provider<Transport>) {
+    let mode = ix.cfg.native;
+    if mode == NativeMode::Off {
+        return;
+    }
+    let mut heads = match provider.subscribe_blocks().await {
+        Ok(s) => s,
+        Err(e) => {
+            error!("native flow tracking disabled: cannot subscribe to new heads: {e:#}");
+            return;
+        }
+    };
+    info!("Tracking native exchange flow from {} data", mode.as_str());
+    let mut next = match last_block(&ix.db).await {
+        Ok(b) => b.map(|b| b + 1),
+        Err(e) => {
+            error!("native flow tracking disabled: {e:#}");
+            return;
+        }
+    };
+    while let Some(head) = heads.next().await {
+        let Some(head) = head.number.map(|n| n.as_u64()) else { continue };
+        let Some(confirmed) = head.checked_sub(ix.confirmations()) else { continue };
+        let from = *next.get_or_insert(confirmed);
+        for n in from..=confirmed {
+            let scanned = async {
+                let exchanges = exchange_set(&ix);
+                let flows = match mode {
+                    NativeMode::Trace => flows_from_traces(&provider, &exchanges, n).await?,
+                    _ => flows_from_txs(&ix, &provider, &exchanges, n).await?,
+                };
+                apply_block(&ix.db, n, &flows).await?;
+                anyhow::Ok(flows.len())
+            }.await;
+            match scanned {
+                Ok(k) => {
+                    if k > 0 {
+                        info!("Native flow: {} exchange transfers in block {}", k, n);
+                    }
+                    next = Some(n + 1);
+                }
+                Err(e) => {
+                    warn!("native flow: block {n} failed, retrying on the next head: {e:#}");
+                    break;
+                }
+            }
+        }
+    }
+    warn!("native flow: new-heads subscription ended");
+}
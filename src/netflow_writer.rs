@@ -0,0 +1,451 @@
+
+use ethers::types::{Address, H256};
+use rug::Integer;
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{error, warn};
+use crate::alerts::Alerts;
+use crate::error::{IndexerError, Result};
+use crate::indexer::{
+    apply_balances, apply_block_flow, apply_exchange_flow, parse_total, record_coverage, to_lower_hex, Flow,
+    NetflowUpdate, COUNTER_COLUMNS,
+};
+
+/// Transfers queued ahead of the writer before `send` waits for it
+const QUEUE_CAPACITY: usize = 10_000;
+
+/// Ticks a failing batch is retried for before it is dropped
+const WRITE_ATTEMPTS: u32 = 3;
+
+/// One new `erc20_transfers` row, with what it adds to the counters if it is counted.
+pub struct TransferWrite {
+    pub tx_hash: H256,
+    pub log_index: i64,
+    pub block: i64,
+    pub contract: Address,
+    pub from: Address,
+    pub to: Address,
+    pub amount: Integer,
+    pub block_ts: Option<i64>,
+    /// `(topics, data)` for `erc20_transfer_raw`, with `STORE_RAW_LOGS`
+    pub raw: Option<(String, String)>,
+    /// `None` for transfers stored without touching any counter
+    pub counted: Option<Counted>,
+}
+
+/// How a transfer touching an exchange was classified.
+pub struct Counted {
+    pub flow: Flow,
+    pub from_ex: Option<String>,
+    pub to_ex: Option<String>,
+    /// Also add it to `netflow_by_block`
+    pub by_block: bool,
+}
+
+enum Msg {
+    Transfer(Box<TransferWrite>),
+    /// Write whatever is batched, then answer with how that went
+    Flush(oneshot::Sender<std::result::Result<(), String>>),
+}
+
+/// Handle to the task that owns every live transfer write. Transfers are committed
+/// together every `every` transfers or `interval`, whichever comes first, each batch in
+/// one transaction holding the new `erc20_transfers` rows and everything they add to
+/// `netflow_state` and the other counters, so the stored totals always equal the sum
+/// over the stored transfers.
+#[derive(Clone)]
+pub struct NetflowWriter {
+    tx: mpsc::Sender<Msg>,
+    db: SqlitePool,
+    state: Arc<Mutex<State>>,
+}
+
+/// What the handles and the writer task share.
+#[derive(Default)]
+struct State {
+    /// `(tx hash, log index)` sent but not committed yet
+    pending: HashSet<(H256, i64)>,
+    /// Set from a batch's first failed write until it is written or a `flush` reports
+    /// it; `send` refuses transfers meanwhile
+    failed: Option<String>,
+    /// Batches given up on, whose transfers are in no table
+    dropped: u64,
+}
+
+/// What the writer needs to announce a batch once it is committed.
+pub struct Announce {
+    pub primary: Address,
+    pub alerts: Arc<Alerts>,
+    pub updates: broadcast::Sender<NetflowUpdate>,
+}
+
+/// What a committed batch changed, for `announce_batch`.
+#[derive(Default)]
+struct Committed {
+    /// `(token, in, out, last_block)` for the tokens that moved
+    totals: Vec<(Address, Integer, Integer, i64)>,
+    /// `(token, exchange, in, out, block)` per counted flow, in batch order
+    exchange_flows: Vec<(Address, String, Integer, Integer, i64)>,
+}
+
+/// One token's share of a batch.
+#[derive(Default)]
+struct Batched {
+    sums: [Integer; 6],
+    last_block: i64,
+    deltas: i64,
+    /// Any in/out movement, i.e. something `/ws/netflow` and alerts care about
+    moved: bool,
+}
+
+impl NetflowWriter {
+    /// Start the writer task (needs a Tokio runtime). It runs until every handle is dropped.
+    /// `keep_blocks` is the `netflow_by_block` retention (`BY_BLOCK_KEEP_BLOCKS`).
+    pub fn spawn(db: SqlitePool, every: usize, interval: Duration, keep_blocks: Option<u64>, announce: Announce) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let state = Arc::new(Mutex::new(State::default()));
+        let writer = Writer { db: db.clone(), state: state.clone(), keep_blocks, announce };
+        tokio::spawn(run(writer, rx, every.max(1), interval.max(Duration::from_millis(1))));
+        Self { tx, db, state }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("netflow writer state poisoned")
+    }
+
+    /// Whether the log is neither stored nor waiting in a batch. Logs are processed one
+    /// at a time, so nothing can slip in before the caller `send`s it; the writer's
+    /// `INSERT OR IGNORE` drops a duplicate anyway.
+    pub async fn is_new(&self, tx_hash: H256, log_index: i64) -> Result<bool> {
+        let key = (tx_hash, log_index);
+        if self.state().pending.contains(&key) {
+            return Ok(false);
+        }
+        // Keys leave `pending` only once committed or dropped, so a miss there is settled here
+        let stored = sqlx::query_scalar::<_, i64>(
+            "SELECT EXISTS(SELECT 1 FROM erc20_transfers WHERE tx_hash = ? AND log_index = ?);")
+            .bind(format!("{:#x}", tx_hash))
+            .bind(log_index)
+            .fetch_one(&self.db).await? != 0;
+        Ok(!stored)
+    }
+
+    /// Nothing sent is still waiting to be committed, and no write failure is unreported.
+    pub fn is_idle(&self) -> bool {
+        let state = self.state();
+        state.pending.is_empty() && state.failed.is_none()
+    }
+
+    /// Whether the writer has a failed write nobody has `flush`ed yet.
+    pub fn is_failing(&self) -> bool {
+        self.state().failed.is_some()
+    }
+
+    /// Batches dropped so far after failing `WRITE_ATTEMPTS` times.
+    pub fn dropped_batches(&self) -> u64 {
+        self.state().dropped
+    }
+
+    /// Queue a transfer; waits only while the queue is full. Err while a write is
+    /// failing: the caller's session should `flush` and restart from the database.
+    pub async fn send(&self, transfer: TransferWrite) -> Result<()> {
+        let key = (transfer.tx_hash, transfer.log_index);
+        {
+            let mut state = self.state();
+            if let Some(e) = &state.failed {
+                return Err(IndexerError::Other(anyhow::anyhow!("netflow writer: {e}")));
+            }
+            state.pending.insert(key);
+        }
+        self.tx.send(Msg::Transfer(Box::new(transfer))).await
+            .map_err(|_| IndexerError::Other(anyhow::anyhow!("netflow writer stopped")))
+    }
+
+    /// Wait until everything sent so far has been written. Err if that write failed, or
+    /// if a batch was dropped since the last flush: those transfers are then in no table
+    /// and the caller has to resume from what is stored. Either way the writer accepts
+    /// transfers again afterwards.
+    pub async fn flush(&self) -> Result<()> {
+        let stopped = || IndexerError::Other(anyhow::anyhow!("netflow writer stopped"));
+        let (done, wait) = oneshot::channel();
+        self.tx.send(Msg::Flush(done)).await.map_err(|_| stopped())?;
+        wait.await.map_err(|_| stopped())?
+            .map_err(|e| IndexerError::Other(anyhow::anyhow!("netflow writer: {e}")))
+    }
+}
+
+struct Writer {
+    db: SqlitePool,
+    state: Arc<Mutex<State>>,
+    keep_blocks: Option<u64>,
+    announce: Announce,
+}
+
+impl Writer {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("netflow writer state poisoned")
+    }
+
+    /// Give up on the batch: forget its keys so re-delivered logs count as new, and
+    /// move `synced_block` below it so the next session's backfill fetches it again.
+    async fn drop_batch(&self, batch: &mut Vec<TransferWrite>) {
+        let Some(first) = batch.iter().map(|t| t.block).min() else { return };
+        error!("netflow writer: dropping {} transfers from block {first} on", batch.len());
+        {
+            let mut state = self.state();
+            for t in batch.drain(..) {
+                state.pending.remove(&(t.tx_hash, t.log_index));
+            }
+            state.dropped += 1;
+        }
+        let lowered = sqlx::query("UPDATE indexer_status SET synced_block = MIN(synced_block, ?);")
+            .bind((first - 1).max(0))
+            .execute(&self.db).await;
+        if let Err(e) = lowered {
+            warn!("netflow writer: lowering synced_block below block {first} failed: {e:#}");
+        }
+    }
+}
+
+async fn run(w: Writer, mut rx: mpsc::Receiver<Msg>, every: usize, interval: Duration) {
+    let mut batch: Vec<TransferWrite> = Vec::new();
+    let mut attempts = 0;
+    let mut tick = tokio::time::interval(interval);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        let mut waiter = None;
+        let due = tokio::select! {
+            msg = rx.recv() => match msg {
+                // Sent before the failure showed; not added to a batch known to fail
+                Some(Msg::Transfer(t)) if attempts > 0 || w.state().failed.is_some() => {
+                    w.state().pending.remove(&(t.tx_hash, t.log_index));
+                    false
+                }
+                Some(Msg::Transfer(t)) => {
+                    batch.push(*t);
+                    batch.len() >= every
+                }
+                Some(Msg::Flush(done)) => {
+                    waiter = Some(done);
+                    true
+                }
+                None => true,
+            },
+            _ = tick.tick() => !batch.is_empty(),
+        };
+        if due && !batch.is_empty() {
+            match write(&w.db, w.keep_blocks, &batch).await {
+                Ok(committed) => {
+                    announce_batch(&w.announce, committed);
+                    let mut state = w.state();
+                    for t in batch.drain(..) {
+                        state.pending.remove(&(t.tx_hash, t.log_index));
+                    }
+                    state.failed = None;
+                    attempts = 0;
+                }
+                Err(e) => {
+                    attempts += 1;
+                    error!(
+                        "netflow writer: writing {} batched transfers failed (attempt {attempts} of {WRITE_ATTEMPTS}): {e:#}",
+                        batch.len(),
+                    );
+                    w.state().failed = Some(format!("writing {} batched transfers failed: {e:#}", batch.len()));
+                    // A flush is the caller waiting on the outcome: no point keeping it
+                    if attempts >= WRITE_ATTEMPTS || waiter.is_some() || rx.is_closed() {
+                        w.drop_batch(&mut batch).await;
+                        attempts = 0;
+                    }
+                }
+            }
+        }
+        if let Some(done) = waiter {
+            let _ = done.send(w.state().failed.take().map_or(Ok(()), Err));
+        }
+        if rx.is_closed() && rx.is_empty() && batch.is_empty() {
+            return;
+        }
+    }
+}
+
+/// Store the batch's transfers and add the counted ones to the counters, all in one
+/// transaction. A row already in `erc20_transfers` adds nothing. `netflow_state` gets
+/// per-token sums (`seq` goes up by one per counted transfer, as before batching).
+/// Sums are done with `rug`; column names come from `COUNTER_COLUMNS`.
+async fn write(db: &SqlitePool, keep_blocks: Option<u64>, batch: &[TransferWrite]) -> Result<Committed> {
+    let mut tx = db.begin().await?;
+    let mut per_token: BTreeMap<Address, Batched> = BTreeMap::new();
+    let mut committed = Committed::default();
+    for t in batch {
+        let tx_hash = format!("{:#x}", t.tx_hash);
+        let contract = format!("{:#x}", t.contract);
+        let amount = t.amount.to_string();
+        let inserted = sqlx::query(r#"
+            INSERT OR IGNORE INTO erc20_transfers
+                (tx_hash, log_index, block_number, contract, "from", "to", amount_wei, block_ts)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?);
+        "#)
+            .bind(&tx_hash)
+            .bind(t.log_index)
+            .bind(t.block)
+            .bind(&contract)
+            .bind(to_lower_hex(t.from))
+            .bind(to_lower_hex(t.to))
+            .bind(&amount)
+            .bind(t.block_ts)
+            .execute(&mut *tx).await?
+            .rows_affected() > 0;
+        if !inserted {
+            continue;
+        }
+        if let Some((topics, data)) = &t.raw {
+            sqlx::query(r#"
+                INSERT OR IGNORE INTO erc20_transfer_raw (tx_hash, log_index, topics, data_hex)
+                VALUES (?, ?, ?, ?);
+            "#)
+                .bind(&tx_hash)
+                .bind(t.log_index)
+                .bind(topics)
+                .bind(data)
+                .execute(&mut *tx).await?;
+        }
+        let Some(c) = &t.counted else { continue };
+        if c.flow.columns().next().is_some() {
+            let b = per_token.entry(t.contract).or_default();
+            for (sum, on) in b.sums.iter_mut().zip(c.flow.flags()) {
+                if on {
+                    *sum += &t.amount;
+                }
+            }
+            b.last_block = b.last_block.max(t.block);
+            b.deltas += 1;
+            b.moved |= c.flow.inflow || c.flow.outflow;
+        }
+        let (from_ex, to_ex) = (c.from_ex.as_deref(), c.to_ex.as_deref());
+        if let (true, Some(label)) = (c.flow.inflow, to_ex) {
+            committed.exchange_flows.push((t.contract, label.to_owned(), t.amount.clone(), Integer::new(), t.block));
+        }
+        if let (true, Some(label)) = (c.flow.outflow, from_ex) {
+            committed.exchange_flows.push((t.contract, label.to_owned(), Integer::new(), t.amount.clone(), t.block));
+        }
+        apply_exchange_flow(&mut tx, &contract, &c.flow, from_ex, to_ex, &amount, t.block).await?;
+        apply_balances(&mut tx, &contract, from_ex.and(Some(&t.from)), to_ex.and(Some(&t.to)), &amount, t.block).await?;
+        record_coverage(&mut tx, &t.from, &t.to, t.block).await?;
+        if c.by_block && (c.flow.inflow || c.flow.outflow) {
+            apply_block_flow(&mut tx, &contract, &c.flow, &amount, t.block, keep_blocks).await?;
+        }
+    }
+
+    let columns = COUNTER_COLUMNS.join(", ");
+    let sets = COUNTER_COLUMNS.iter().map(|c| format!("{c} = ?")).collect::<Vec<_>>().join(", ");
+    let update_sql = format!("UPDATE netflow_state SET {sets} WHERE contract = ?;");
+    for (contract, b) in &per_token {
+        let contract_s = format!("{:#x}", contract);
+        // Write first so the transaction holds the write lock before reading the counters
+        sqlx::query(r#"
+            UPDATE netflow_state
+            SET last_block = MAX(COALESCE(last_block, 0), ?),
+                seq = seq + ?
+            WHERE contract = ?;
+        "#)
+            .bind(b.last_block)
+            .bind(b.deltas)
+            .bind(&contract_s)
+            .execute(&mut *tx).await?;
+        let current = sqlx::query_as::<_, (String, String, String, String, String, String)>(
+            &format!("SELECT {columns} FROM netflow_state WHERE contract = ?;"))
+            .bind(&contract_s)
+            .fetch_one(&mut *tx).await?;
+        let (c0, c1, c2, c3, c4, c5) = current;
+        let mut sums: Vec<Integer> = Vec::with_capacity(6);
+        for ((stored, column), add) in [c0, c1, c2, c3, c4, c5].iter().zip(COUNTER_COLUMNS).zip(&b.sums) {
+            sums.push(parse_total(stored, column)? + add);
+        }
+        let mut update = sqlx::query(&update_sql);
+        for sum in &sums {
+            update = update.bind(sum.to_string());
+        }
+        update.bind(&contract_s).execute(&mut *tx).await?;
+        if b.moved {
+            committed.totals.push((*contract, sums[0].clone(), sums[1].clone(), b.last_block));
+        }
+    }
+    tx.commit().await?;
+    Ok(committed)
+}
+
+/// Fresh totals to `/ws/netflow` subscribers, and the committed flows to the netflow and
+/// per-exchange alerts for the primary token (their thresholds are in its units).
+fn announce_batch(announce: &Announce, committed: Committed) {
+    if announce.alerts.tracks_exchanges() {
+        for (contract, label, in_wei, out_wei, block) in &committed.exchange_flows {
+            if *contract == announce.primary {
+                announce.alerts.on_exchange_flow(label, in_wei, out_wei, *block);
+            }
+        }
+    }
+    for (contract, in_wei, out_wei, block) in committed.totals {
+        if contract == announce.primary {
+            announce.alerts.on_netflow(&in_wei, &out_wei, block);
+        }
+        // Err only means nobody is listening
+        let _ = announce.updates.send(NetflowUpdate { contract, in_wei, out_wei, block });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::tests::{exchange_state, state, stored_transfers, test_cfg, test_indexer, token, BINANCE, USER};
+
+    fn deposit(amount: u64, block: i64) -> TransferWrite {
+        TransferWrite {
+            tx_hash: H256::from_low_u64_be(block as u64),
+            log_index: 0,
+            block,
+            contract: token(),
+            from: USER.parse().expect("address"),
+            to: BINANCE.parse().expect("address"),
+            amount: Integer::from(amount),
+            block_ts: None,
+            raw: None,
+            counted: Some(Counted {
+                flow: Flow { inflow: true, ..Flow::default() },
+                from_ex: None,
+                to_ex: Some("binance".into()),
+                by_block: false,
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failed_counter_update_keeps_the_transfer_rows_out_too() {
+        let ix = test_indexer(test_cfg(), &[(BINANCE, "binance")]).await;
+        ix.netflow.send(deposit(100, 1)).await.expect("send");
+        ix.netflow.flush().await.expect("flush");
+
+        // Fails the batch after its inserts, the per-exchange update and the seq bump
+        sqlx::query(r#"
+            CREATE TRIGGER fail_counters BEFORE UPDATE OF cumulative_in_wei ON netflow_state
+            BEGIN SELECT RAISE(ABORT, 'injected failure'); END;
+        "#).execute(&ix.db).await.expect("trigger");
+        ix.netflow.send(deposit(50, 2)).await.expect("send");
+        let err = ix.netflow.flush().await.expect_err("the write fails");
+        assert!(format!("{err:#}").contains("injected failure"), "{err:#}");
+        assert_eq!(ix.netflow.dropped_batches(), 1);
+        assert_eq!(stored_transfers(&ix).await, vec![(1, 0, "100".into())]);
+        assert_eq!(state(&ix).await, ("100".into(), "0".into(), Some(1), 1));
+        assert_eq!(exchange_state(&ix, "binance").await, Some(("100".into(), "0".into())));
+
+        // With the cause gone the same transfer is written, and counted once
+        sqlx::query("DROP TRIGGER fail_counters;").execute(&ix.db).await.expect("drop trigger");
+        ix.netflow.send(deposit(50, 2)).await.expect("send");
+        ix.netflow.flush().await.expect("flush");
+        assert_eq!(stored_transfers(&ix).await.len(), 2);
+        assert_eq!(state(&ix).await, ("150".into(), "0".into(), Some(2), 2));
+        assert_eq!(exchange_state(&ix, "binance").await, Some(("150".into(), "0".into())));
+    }
+}
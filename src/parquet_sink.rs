@@ -0,0 +1,190 @@
+
+use anyhow::{Context, Result};
+use arrow_array::{ArrayRef, Int64Array, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+
+/// `export_cursor` row for this sink
+const CURSOR_NAME: &str = "parquet_transfers";
+/// Most rows written per file
+const MAX_ROWS_PER_FILE: i64 = 50_000;
+
+#[derive(Clone, Debug)]
+pub struct ParquetCfg {
+    pub dir: PathBuf,
+    pub flush_secs: u64,
+}
+
+/// (rowid, tx_hash, log_index, block_number, contract, from, to, amount_wei, block_ts, day)
+type ExportRow = (i64, String, i64, i64, String, String, String, String, Option<i64>, String);
+
+/// Highest rowid already in a finished file. Files are named `transfers-<first>-<last>.parquet`
+/// and only appear via rename, so they are the source of truth after a crash. Stray
+/// temp files are removed on the way.
+fn last_rowid_on_disk(dir: &Path) -> Result<i64> {
+    let mut max = 0;
+    if !dir.exists() {
+        return Ok(0);
+    }
+    for day in std::fs::read_dir(dir)? {
+        let day = day?.path();
+        if !day.is_dir() {
+            continue;
+        }
+        for f in std::fs::read_dir(&day)? {
+            let f = f?;
+            let name = f.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".parquet.tmp") {
+                // Left by a crash before the rename; its rows are exported again
+                std::fs::remove_file(f.path())?;
+                continue;
+            }
+            let last = name.strip_prefix("transfers-")
+                .and_then(|s| s.strip_suffix(".parquet"))
+                .and_then(|s| s.rsplit('-').next())
+                .and_then(|s| s.parse::<i64>().ok());
+            if let Some(last) = last {
+                max = max.max(last);
+            }
+        }
+    }
+    Ok(max)
+}
+
+async fn load_cursor(db: &SqlitePool) -> Result<i64> {
+    Ok(sqlx::query_scalar::<_, i64>("SELECT last_rowid FROM export_cursor WHERE name = ?;")
+        .bind(CURSOR_NAME)
+        .fetch_optional(db).await?
+        .unwrap_or(0))
+}
+
+async fn store_cursor(db: &SqlitePool, last_rowid: i64) -> Result<()> {
+    sqlx::query(r#"
+        INSERT INTO export_cursor(name, last_rowid) VALUES (?, ?)
+        ON CONFLICT(name) DO UPDATE SET last_rowid = MAX(last_rowid, excluded.last_rowid);
+    "#)
+        .bind(CURSOR_NAME)
+        .bind(last_rowid)
+        .execute(db).await?;
+    Ok(())
+}
+
+/// Rows after `cursor`, cut at the first change of day so each file is one partition.
+/// The day is the block's UTC date when its timestamp is known, else the export date.
+async fn next_batch(db: &SqlitePool, cursor: i64) -> Result<Vec<ExportRow>> {
+    let mut rows = sqlx::query_as::<_, ExportRow>(r#"
+        SELECT t.rowid, t.tx_hash, t.log_index, t.block_number, t.contract, t."from", t."to", t.amount_wei,
+               b.ts, date(COALESCE(b.ts, strftime('%s','now')), 'unixepoch')
+        FROM erc20_transfers t
+        LEFT JOIN blocks b ON b.number = t.block_number
+        WHERE t.rowid > ? ORDER BY t.rowid LIMIT ?;
+    "#)
+        .bind(cursor)
+        .bind(MAX_ROWS_PER_FILE)
+        .fetch_all(db).await?;
+    if let Some(day) = rows.first().map(|r| r.9.clone()) {
+        let cut = rows.iter().position(|r| r.9 != day).unwrap_or(rows.len());
+        rows.truncate(cut);
+    }
+    Ok(rows)
+}
+
+fn write_file(path: &Path, rows: &[ExportRow]) -> Result<()> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("tx_hash", DataType::Utf8, false),
+        Field::new("log_index", DataType::Int64, false),
+        Field::new("block_number", DataType::Int64, false),
+        Field::new("contract", DataType::Utf8, false),
+        Field::new("from", DataType::Utf8, false),
+        Field::new("to", DataType::Utf8, false),
+        // uint256 needs up to 78 digits, more than Decimal256 holds
+        Field::new("amount_wei", DataType::Utf8, false),
+        Field::new("block_ts", DataType::Int64, true),
+    ]));
+    let strings = |f: fn(&ExportRow) -> &str| -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(rows.iter().map(f)))
+    };
+    let batch = RecordBatch::try_new(schema.clone(), vec![
+        strings(|r| r.1.as_str()),
+        Arc::new(Int64Array::from_iter_values(rows.iter().map(|r| r.2))),
+        Arc::new(Int64Array::from_iter_values(rows.iter().map(|r| r.3))),
+        strings(|r| r.4.as_str()),
+        strings(|r| r.5.as_str()),
+        strings(|r| r.6.as_str()),
+        strings(|r| r.7.as_str()),
+        Arc::new(Int64Array::from(rows.iter().map(|r| r.8).collect::<Vec<_>>())),
+    ])?;
+
+    let tmp = path.with_extension("parquet.tmp");
+    let file = std::fs::File::create(&tmp)
+        .with_context(|| format!("failed to create {}", tmp.display()))?;
+    let props = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    let mut writer = ArrowWriter::try_new(file, schema, Some(props))?;
+    writer.write(&batch)?;
+    writer.into_inner()?.sync_all()?;
+    // The rename is the commit point: a file either exists complete or not at all
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Export one batch; returns the number of rows written.
+async fn flush(db: &SqlitePool, cfg: &ParquetCfg, cursor: &mut i64) -> Result<usize> {
+    let rows = next_batch(db, *cursor).await?;
+    let (Some(first), Some(last)) = (rows.first(), rows.last()) else {
+        return Ok(0);
+    };
+    let (first_rowid, last_rowid) = (first.0, last.0);
+    let day_dir = cfg.dir.join(format!("date={}", first.9));
+    let path = day_dir.join(format!("transfers-{first_rowid:012}-{last_rowid:012}.parquet"));
+    let n = rows.len();
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        std::fs::create_dir_all(&day_dir)?;
+        write_file(&path, &rows)
+    }).await??;
+    store_cursor(db, last_rowid).await?;
+    *cursor = last_rowid;
+    Ok(n)
+}
+
+/// Periodically append new `erc20_transfers` rows to `<dir>/date=YYYY-MM-DD/*.parquet`.
+/// Resumes after the newer of the stored cursor and the newest finished file, so a
+/// crash between writing a file and storing the cursor never exports rows twice.
+pub async fn run(db: SqlitePool, cfg: ParquetCfg) {
+    let on_disk = match last_rowid_on_disk(&cfg.dir) {
+        Ok(n) => n,
+        Err(e) => {
+            error!("parquet sink disabled: cannot read {}: {e:#}", cfg.dir.display());
+            return;
+        }
+    };
+    let mut cursor = match load_cursor(&db).await {
+        Ok(c) => c.max(on_disk),
+        Err(e) => {
+            error!("parquet sink disabled: {e:#}");
+            return;
+        }
+    };
+    info!("Parquet sink exporting to {} after rowid {}", cfg.dir.display(), cursor);
+    let mut tick = tokio::time::interval(Duration::from_secs(cfg.flush_secs.max(1)));
+    loop {
+        tick.tick().await;
+        // Drain the backlog in file-sized batches before waiting again
+        loop {
+            match flush(&db, &cfg, &mut cursor).await {
+                Ok(0) => break,
+                Ok(n) => info!("Exported {} transfers to parquet (through rowid {})", n, cursor),
+                Err(e) => {
+                    error!("parquet export failed: {e:#}");
+                    break;
+                }
+            }
+        }
+    }
+}
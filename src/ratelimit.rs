@@ -0,0 +1,72 @@
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::error::ApiError;
+
+const WINDOW: Duration = Duration::from_secs(60);
+/// Forget idle clients once the table grows past this many entries
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RateLimitCfg {
+    /// Requests per minute for expensive routes; `None` disables limiting
+    pub expensive_per_min: Option<u32>,
+    /// Requests per minute for cheap routes (`/netflow`, `/summary`, ...); `None` = exempt
+    pub cheap_per_min: Option<u32>,
+    /// Share one budget across all clients instead of one per IP
+    pub global: bool,
+}
+
+/// Fixed one-minute window counter, keyed per client IP (or one shared key).
+pub struct RateLimiter {
+    limit: u32,
+    global: bool,
+    windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, global: bool) -> Arc<Self> {
+        Arc::new(Self { limit, global, windows: Mutex::new(HashMap::new()) })
+    }
+
+    /// Count a request; on rejection returns the seconds until the window resets.
+    fn check(&self, ip: IpAddr) -> Result<(), u64> {
+        let key = if self.global { IpAddr::V4(Ipv4Addr::UNSPECIFIED) } else { ip };
+        let now = Instant::now();
+        let mut windows = self.windows.lock().expect("rate limiter poisoned");
+        if windows.len() > MAX_TRACKED_CLIENTS {
+            windows.retain(|_, (start, _)| now.duration_since(*start) < WINDOW);
+        }
+        let (start, count) = windows.entry(key).or_insert((now, 0));
+        if now.duration_since(*start) >= WINDOW {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= self.limit {
+            let reset = WINDOW.saturating_sub(now.duration_since(*start));
+            return Err(reset.as_secs().max(1));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+/// Axum middleware: 429 (`rate_limited`) with `Retry-After` once the client's budget is spent.
+pub async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    match limiter.check(addr.ip()) {
+        Ok(()) => next.run(req).await,
+        Err(retry_after) => ApiError::RateLimited { retry_after }.into_response(),
+    }
+}
@@ -0,0 +1,83 @@
+
+use ethers::types::{Address, H256, U256};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::RwLock;
+use crate::units::to_checksum;
+
+/// Hard ceiling on the ring size (~120 bytes per row) regardless of config
+pub const MAX_RECENT_CAP: usize = 1_000_000;
+
+/// Column-oriented ring: one deque per field, fixed-size binary values.
+/// Rows share an index across columns; front is the oldest.
+#[derive(Default)]
+struct Columns {
+    tx_hash: VecDeque<H256>,
+    log_index: VecDeque<u64>,
+    block_number: VecDeque<u64>,
+    from: VecDeque<Address>,
+    to: VecDeque<Address>,
+    amount_wei: VecDeque<U256>,
+}
+
+/// Last N persisted transfers, kept in memory so hot dashboard reads never touch SQLite.
+pub struct RecentTransfers {
+    cap: usize,
+    cols: RwLock<Columns>,
+}
+
+/// Columnar JSON (newest first): each field is an array of equal length.
+#[derive(Serialize)]
+pub struct RecentColumns {
+    pub count: usize,
+    pub tx_hash: Vec<String>,
+    pub log_index: Vec<u64>,
+    pub block_number: Vec<u64>,
+    pub from: Vec<String>,
+    pub to: Vec<String>,
+    pub amount_wei: Vec<String>,
+}
+
+impl RecentTransfers {
+    pub fn new(cap: usize) -> Self {
+        Self { cap: cap.min(MAX_RECENT_CAP), cols: RwLock::new(Columns::default()) }
+    }
+
+    pub fn push(&self, tx_hash: H256, log_index: u64, block_number: u64, from: Address, to: Address, amount: U256) {
+        if self.cap == 0 {
+            return;
+        }
+        let mut c = self.cols.write().expect("recent ring poisoned");
+        if c.tx_hash.len() == self.cap {
+            c.tx_hash.pop_front();
+            c.log_index.pop_front();
+            c.block_number.pop_front();
+            c.from.pop_front();
+            c.to.pop_front();
+            c.amount_wei.pop_front();
+        }
+        c.tx_hash.push_back(tx_hash);
+        c.log_index.push_back(log_index);
+        c.block_number.push_back(block_number);
+        c.from.push_back(from);
+        c.to.push_back(to);
+        c.amount_wei.push_back(amount);
+    }
+
+    /// Up to `limit` newest rows. Formatting happens outside the write path.
+    pub fn snapshot(&self, limit: usize) -> RecentColumns {
+        let c = self.cols.read().expect("recent ring poisoned");
+        let n = limit.min(c.tx_hash.len());
+        let newest = |len: usize| (0..n).map(move |i| len - 1 - i);
+        let len = c.tx_hash.len();
+        RecentColumns {
+            count: n,
+            tx_hash: newest(len).map(|i| format!("{:#x}", c.tx_hash[i])).collect(),
+            log_index: newest(len).map(|i| c.log_index[i]).collect(),
+            block_number: newest(len).map(|i| c.block_number[i]).collect(),
+            from: newest(len).map(|i| to_checksum(&c.from[i])).collect(),
+            to: newest(len).map(|i| to_checksum(&c.to[i])).collect(),
+            amount_wei: newest(len).map(|i| c.amount_wei[i].to_string()).collect(),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+
+use anyhow::{Context, Result};
+use ethers::abi::AbiDecode;
+use ethers::providers::{Middleware, Provider};
+use ethers::types::{Address, BlockId, Bytes, TransactionRequest, U256};
+use rug::Integer;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use crate::indexer::Indexer;
+use crate::metadata;
+use crate::transport::Transport;
+use crate::units::u256_to_integer;
+
+/// `balanceOf(address)` selector
+const BALANCE_OF: [u8; 4] = [0x70, 0xa0, 0x82, 0x31];
+/// `totalSupply()` selector
+const TOTAL_SUPPLY: [u8; 4] = [0x18, 0x16, 0x0d, 0xdd];
+
+#[derive(Clone, Debug)]
+pub struct ReserveCfg {
+    /// Seconds between snapshots; 0 disables reserve tracking
+    pub interval_secs: u64,
+    /// Flag a snapshot when reserve change and netflow change disagree by more than this (wei)
+    pub divergence_wei: Integer,
+}
+
+/// ERC-20 `balanceOf(holder)` at `block`.
+pub async fn balance_of(provider: &Provider<Transport>, token: Address, holder: Address, block: u64) -> Result<U256> {
+    let mut data = BALANCE_OF.to_vec();
+    data.extend_from_slice(&[0u8; 12]);
+    data.extend_from_slice(holder.as_bytes());
+    let tx = TransactionRequest::new().to(token).data(Bytes::from(data));
+    let out = provider.call(&tx.into(), Some(BlockId::from(block))).await
+        .with_context(|| format!("balanceOf({:#x}) failed", holder))?;
+    Ok(U256::decode(out.as_ref())?)
+}
+
+/// ERC-20 `totalSupply()` at the latest block; errors if the token reverts or lacks it.
+pub async fn total_supply(provider: &Provider<Transport>, token: Address) -> Result<U256> {
+    let tx = TransactionRequest::new().to(token).data(Bytes::from(TOTAL_SUPPLY.to_vec()));
+    let out = provider.call(&tx.into(), None).await.context("totalSupply() failed")?;
+    Ok(U256::decode(out.as_ref())?)
+}
+
+/// Keep `ix.supply` (the primary token's) current every `interval_secs` (0 disables). A token that reverts
+/// leaves it unset, which omits `net_pct_of_supply` from the API.
+pub async fn run_supply_refresh(ix: Indexer, provider: Provider<Transport>, interval_secs: u64) {
+    if interval_secs == 0 {
+        return;
+    }
+    let mut tick = tokio::time::interval(Duration::from_secs(interval_secs));
+    let mut warned = false;
+    // A cached value younger than one interval stands in for the call (e.g. right after a restart)
+    let ttl = interval_secs.min(ix.cfg.metadata_ttl_secs);
+    let token = ix.cfg.primary_token();
+    loop {
+        tick.tick().await;
+        let fetched = metadata::get_or_fetch(&ix.db, token, metadata::TOTAL_SUPPLY, ttl, || async {
+            Ok(total_supply(&provider, token).await?.to_string())
+        }).await;
+        match fetched.and_then(|s| Ok(Integer::from_str_radix(&s, 10)?)) {
+            Ok(supply) if supply > 0 => {
+                *ix.supply.write().expect("supply lock poisoned") = Some(supply);
+                warned = false;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                if !warned {
+                    warn!("totalSupply unavailable, omitting net_pct_of_supply: {e:#}");
+                    warned = true;
+                }
+                *ix.supply.write().expect("supply lock poisoned") = None;
+            }
+        }
+    }
+}
+
+/// Periodically record total exchange-held balance of the primary token next to its
+/// cumulative netflow.
+/// Snapshots are taken at `head - CONFIRMATIONS` so they line up with what netflow
+/// has counted. Runs until the provider goes away; errors are logged and retried
+/// on the next tick.
+pub async fn run_snapshots(ix: Indexer, provider: Provider<Transport>) {
+    let cfg = ix.cfg.reserves.clone();
+    if cfg.interval_secs == 0 {
+        return;
+    }
+    let mut tick = tokio::time::interval(Duration::from_secs(cfg.interval_secs));
+    let mut prev: Option<(Integer, Integer)> = None;
+    loop {
+        tick.tick().await;
+        match snapshot(&ix, &provider).await {
+            Ok((reserve, net)) => {
+                if let Some((prev_reserve, prev_net)) = &prev {
+                    let divergence = divergence(&reserve, prev_reserve, &net, prev_net);
+                    if divergence > cfg.divergence_wei {
+                        warn!(
+                            "Reserve change diverges from netflow change by {} wei (data-quality check)",
+                            divergence
+                        );
+                    }
+                }
+                prev = Some((reserve, net));
+            }
+            Err(e) => error!("reserve snapshot failed: {e:#}"),
+        }
+    }
+}
+
+/// |Δreserve − Δnet| between two snapshots.
+fn divergence(reserve: &Integer, prev_reserve: &Integer, net: &Integer, prev_net: &Integer) -> Integer {
+    let d_reserve = Integer::from(reserve - prev_reserve);
+    let d_net = Integer::from(net - prev_net);
+    Integer::from(&d_reserve - &d_net).abs()
+}
+
+async fn snapshot(ix: &Indexer, provider: &Provider<Transport>) -> Result<(Integer, Integer)> {
+    let head = provider.get_block_number().await?.as_u64();
+    let block = head.saturating_sub(ix.confirmations());
+
+    let token = ix.cfg.primary_token();
+
+    let addrs = sqlx::query_scalar::<_, String>("SELECT address FROM exchange_addresses;")
+        .fetch_all(&ix.db).await?;
+    let mut reserve = Integer::new();
+    for a in &addrs {
+        let holder: Address = a.parse().with_context(|| format!("bad exchange address {a}"))?;
+        let bal = balance_of(provider, token, holder, block).await?;
+        reserve += u256_to_integer(bal);
+    }
+
+    let (in_wei, out_wei) = sqlx::query_as::<_, (String, String)>(
+        "SELECT cumulative_in_wei, cumulative_out_wei FROM netflow_state WHERE contract = ?;")
+        .bind(format!("{:#x}", token))
+        .fetch_one(&ix.db).await?;
+    let net = Integer::from_str_radix(&in_wei, 10)? - Integer::from_str_radix(&out_wei, 10)?;
+
+    sqlx::query(r#"
+        INSERT INTO reserve_snapshots (ts, block_number, addresses, reserve_wei, cumulative_net_wei)
+        VALUES (strftime('%s','now'), ?, ?, ?, ?);
+    "#)
+        .bind(block as i64)
+        .bind(addrs.len() as i64)
+        .bind(reserve.to_string())
+        .bind(net.to_string())
+        .execute(&ix.db).await?;
+    info!("Reserve snapshot at block {}: {} wei across {} addresses", block, reserve, addrs.len());
+    Ok((reserve, net))
+}
+
+#[derive(Serialize)]
+pub struct ReservePoint {
+    pub ts: i64,
+    pub block_number: i64,
+    pub addresses: i64,
+    pub reserve_wei: String,
+    pub cumulative_net_wei: String,
+    /// Changes since the previous (older) snapshot; absent for the oldest one returned
+    pub reserve_change_wei: Option<String>,
+    pub netflow_change_wei: Option<String>,
+    pub divergence_wei: Option<String>,
+    /// Reserve and netflow changes disagree by more than the configured tolerance
+    pub divergent: bool,
+}
+
+/// Newest-first reserve history with per-step reserve vs netflow changes.
+pub async fn history(db: &SqlitePool, limit: i64, tolerance: &Integer) -> Result<Vec<ReservePoint>> {
+    // One extra row so the oldest returned point still has a delta
+    let rows = sqlx::query_as::<_, (i64, i64, i64, String, String)>(r#"
+        SELECT ts, block_number, addresses, reserve_wei, cumulative_net_wei
+        FROM reserve_snapshots ORDER BY id DESC LIMIT ?;
+    "#)
+        .bind(limit + 1)
+        .fetch_all(db).await?;
+
+    let parsed = rows.into_iter()
+        .map(|(ts, bn, n, r, net)| -> Result<_> {
+            Ok((ts, bn, n, Integer::from_str_radix(&r, 10)?, Integer::from_str_radix(&net, 10)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut out = Vec::new();
+    for (i, (ts, block_number, addresses, reserve, net)) in parsed.iter().enumerate().take(limit as usize) {
+        let older = parsed.get(i + 1);
+        let (reserve_change, netflow_change, div) = match older {
+            Some((_, _, _, prev_reserve, prev_net)) => (
+                Some(Integer::from(reserve - prev_reserve)),
+                Some(Integer::from(net - prev_net)),
+                Some(divergence(reserve, prev_reserve, net, prev_net)),
+            ),
+            None => (None, None, None),
+        };
+        out.push(ReservePoint {
+            ts: *ts,
+            block_number: *block_number,
+            addresses: *addresses,
+            reserve_wei: reserve.to_string(),
+            cumulative_net_wei: net.to_string(),
+            divergent: div.as_ref().is_some_and(|d| d > tolerance),
+            reserve_change_wei: reserve_change.map(|x| x.to_string()),
+            netflow_change_wei: netflow_change.map(|x| x.to_string()),
+            divergence_wei: div.map(|x| x.to_string()),
+        });
+    }
+    Ok(out)
+}
@@ -0,0 +1,138 @@
+
+use anyhow::{Context, Result};
+use ethers::abi::{AbiDecode, ParamType, Token};
+use ethers::providers::{Middleware, Provider, RpcError};
+use ethers::types::{Address, Bytes, TransactionRequest, U256};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use tracing::{info, warn};
+use crate::transport::{self, Transport};
+
+/// `decimals()` selector
+const DECIMALS: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+/// `symbol()` selector
+const SYMBOL: [u8; 4] = [0x95, 0xd8, 0x9b, 0x41];
+
+/// Used when a token doesn't implement (or reverts on) the optional ERC-20 getters
+pub const FALLBACK_DECIMALS: u8 = 18;
+pub const FALLBACK_SYMBOL: &str = "UNKNOWN";
+
+/// A token's on-chain `symbol()` and `decimals()`, as stored in `token_meta`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenMeta {
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl Default for TokenMeta {
+    fn default() -> Self {
+        Self { symbol: FALLBACK_SYMBOL.into(), decimals: FALLBACK_DECIMALS }
+    }
+}
+
+async fn call(provider: &Provider<Transport>, token: Address, selector: [u8; 4]) -> Result<Bytes> {
+    let tx = TransactionRequest::new().to(token).data(Bytes::from(selector.to_vec()));
+    Ok(provider.call(&tx.into(), None).await?)
+}
+
+/// `symbol()` returns `string` per the standard, but some early tokens return `bytes32`.
+fn decode_symbol(out: &[u8]) -> Option<String> {
+    if let Ok(mut tokens) = ethers::abi::decode(&[ParamType::String], out) {
+        if let Some(Token::String(s)) = tokens.pop() {
+            return Some(s);
+        }
+    }
+    if out.len() == 32 {
+        let end = out.iter().position(|&b| b == 0).unwrap_or(32);
+        return String::from_utf8(out[..end].to_vec()).ok();
+    }
+    None
+}
+
+/// Ask the token itself. A revert or undecodable answer falls back per field; only
+/// transport errors are returned (so nothing wrong gets cached).
+async fn fetch(provider: &Provider<Transport>, token: Address) -> Result<TokenMeta> {
+    // Reverts come back as JSON-RPC errors; an empty result means no such function
+    let decimals = match call(provider, token, DECIMALS).await {
+        Ok(out) => U256::decode(out.as_ref()).ok()
+            .filter(|d| *d <= U256::from(u8::MAX))
+            .map(|d| d.as_u32() as u8),
+        Err(e) if is_revert(&e) => None,
+        Err(e) => return Err(e).context("decimals() call failed"),
+    };
+    let symbol = match call(provider, token, SYMBOL).await {
+        Ok(out) => decode_symbol(out.as_ref()).map(|s| s.trim().to_string()).filter(|s| !s.is_empty()),
+        Err(e) if is_revert(&e) => None,
+        Err(e) => return Err(e).context("symbol() call failed"),
+    };
+    if decimals.is_none() || symbol.is_none() {
+        warn!("Token {:#x} has no usable decimals()/symbol(); falling back to {}/{}", token, FALLBACK_DECIMALS, FALLBACK_SYMBOL);
+    }
+    Ok(TokenMeta {
+        symbol: symbol.unwrap_or_else(|| FALLBACK_SYMBOL.into()),
+        decimals: decimals.unwrap_or(FALLBACK_DECIMALS),
+    })
+}
+
+/// A JSON-RPC error reply (the node ran the call and it reverted), as opposed to a
+/// connection problem.
+fn is_revert(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<ethers::providers::ProviderError>()
+        .and_then(|pe| pe.as_error_response())
+        .is_some()
+}
+
+async fn cached(db: &SqlitePool) -> Result<HashMap<Address, TokenMeta>> {
+    let rows = sqlx::query_as::<_, (String, String, i64)>("SELECT contract, symbol, decimals FROM token_meta;")
+        .fetch_all(db).await?;
+    Ok(rows.into_iter()
+        .filter_map(|(c, symbol, decimals)| Some((c.parse().ok()?, TokenMeta { symbol, decimals: decimals as u8 })))
+        .collect())
+}
+
+/// Metadata for every token: from `token_meta` when already known, else fetched over
+/// RPC and stored (one connection, opened only if something is missing). A token that
+/// can't be reached gets the fallback for this run without caching it.
+pub async fn load_all(db: &SqlitePool, rpc_url: &str, tokens: &[Address]) -> Result<HashMap<Address, TokenMeta>> {
+    let mut out = cached(db).await?;
+    out.retain(|t, _| tokens.contains(t));
+    let missing: Vec<Address> = tokens.iter().copied().filter(|t| !out.contains_key(t)).collect();
+    if missing.is_empty() {
+        return Ok(out);
+    }
+
+    let provider = match transport::connect(rpc_url).await {
+        Ok(p) => Some(p),
+        Err(e) => {
+            warn!("cannot fetch token metadata, using {}/{} for now: {e:#}", FALLBACK_DECIMALS, FALLBACK_SYMBOL);
+            None
+        }
+    };
+    for token in missing {
+        let fetched = match &provider {
+            Some(p) => fetch(p, token).await,
+            None => Err(anyhow::anyhow!("no RPC connection")),
+        };
+        match fetched {
+            Ok(meta) => {
+                sqlx::query(r#"
+                    INSERT INTO token_meta (contract, symbol, decimals) VALUES (?, ?, ?)
+                    ON CONFLICT(contract) DO UPDATE SET symbol = excluded.symbol, decimals = excluded.decimals;
+                "#)
+                    .bind(format!("{:#x}", token))
+                    .bind(&meta.symbol)
+                    .bind(meta.decimals as i64)
+                    .execute(db).await?;
+                info!("Token {:#x}: symbol {:?}, {} decimals", token, meta.symbol, meta.decimals);
+                out.insert(token, meta);
+            }
+            Err(e) => {
+                if provider.is_some() {
+                    warn!("token metadata for {:#x} unavailable, using the fallback for now: {e:#}", token);
+                }
+                out.insert(token, TokenMeta::default());
+            }
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,113 @@
+
+use async_trait::async_trait;
+use ethers::providers::{
+    Http, HttpClientError, JsonRpcClient, JsonRpcError, Provider, ProviderError, PubsubClient, RpcError, Ws,
+    WsClientError,
+};
+use ethers::types::U256;
+use serde::{de::DeserializeOwned, Serialize};
+use std::str::FromStr;
+use crate::env_template::redact_url;
+
+/// RPC connection picked by the URL scheme: `ws(s)://` supports subscriptions,
+/// `http(s)://` (what many managed providers hand out) only plain requests.
+#[derive(Clone, Debug)]
+pub enum Transport {
+    Ws(Ws),
+    Http(Http),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransportError {
+    #[error(transparent)]
+    Ws(#[from] WsClientError),
+    #[error(transparent)]
+    Http(#[from] HttpClientError),
+    #[error("invalid RPC URL {0}")]
+    Url(String),
+    /// A pub/sub call over HTTP
+    #[error("{0} needs a WebSocket (ws:// or wss://) RPC endpoint")]
+    Unsupported(&'static str),
+}
+
+impl RpcError for TransportError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            Self::Ws(e) => e.as_error_response(),
+            Self::Http(e) => e.as_error_response(),
+            Self::Url(_) | Self::Unsupported(_) => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            Self::Ws(e) => e.as_serde_error(),
+            Self::Http(e) => e.as_serde_error(),
+            Self::Url(_) | Self::Unsupported(_) => None,
+        }
+    }
+}
+
+impl From<TransportError> for ProviderError {
+    fn from(e: TransportError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(e))
+    }
+}
+
+impl Transport {
+    /// Open a connection to `url`: HTTP(S) for `http://`/`https://`, WebSocket otherwise.
+    pub async fn connect(url: &str) -> Result<Self, TransportError> {
+        let scheme = url.split_once("://").map(|(s, _)| s.to_ascii_lowercase());
+        match scheme.as_deref() {
+            Some("http" | "https") => Http::from_str(url)
+                .map(Self::Http)
+                .map_err(|e| TransportError::Url(format!("{}: {e}", redact_url(url)))),
+            Some("ws" | "wss") => Ok(Self::Ws(Ws::connect(url).await?)),
+            _ => Err(TransportError::Url(format!("{} (expected ws://, wss://, http:// or https://)", redact_url(url)))),
+        }
+    }
+
+    /// `false` over HTTP, where logs and heads have to be polled.
+    pub fn can_subscribe(&self) -> bool {
+        matches!(self, Self::Ws(_))
+    }
+}
+
+/// `Provider::new(Transport::connect(url))`
+pub async fn connect(url: &str) -> Result<Provider<Transport>, TransportError> {
+    Ok(Provider::new(Transport::connect(url).await?))
+}
+
+#[async_trait]
+impl JsonRpcClient for Transport {
+    type Error = TransportError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, TransportError>
+    where
+        T: std::fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        match self {
+            Self::Ws(ws) => Ok(JsonRpcClient::request(ws, method, params).await?),
+            Self::Http(http) => Ok(JsonRpcClient::request(http, method, params).await?),
+        }
+    }
+}
+
+impl PubsubClient for Transport {
+    type NotificationStream = <Ws as PubsubClient>::NotificationStream;
+
+    fn subscribe<T: Into<U256>>(&self, id: T) -> Result<Self::NotificationStream, TransportError> {
+        match self {
+            Self::Ws(ws) => Ok(ws.subscribe(id)?),
+            Self::Http(_) => Err(TransportError::Unsupported("eth_subscribe")),
+        }
+    }
+
+    fn unsubscribe<T: Into<U256>>(&self, id: T) -> Result<(), TransportError> {
+        match self {
+            Self::Ws(ws) => Ok(ws.unsubscribe(id)?),
+            Self::Http(_) => Err(TransportError::Unsupported("eth_unsubscribe")),
+        }
+    }
+}
@@ -0,0 +1,340 @@
+
+use ethers::types::{Address, U256};
+use rug::integer::Order;
+use rug::ops::Pow;
+use rug::Integer;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+const SYMBOL_OVERRIDE_PREFIX: &str = "DISPLAY_SYMBOL_";
+
+/// EIP-55 mixed-case form of `addr`: a hex letter is uppercased when the matching
+/// nibble of keccak256(lowercase hex) is 8 or more. For display only; storage and
+/// matching stay lowercase.
+pub fn to_checksum(addr: &Address) -> String {
+    let lower = hex::encode(addr.as_bytes());
+    let hash = ethers::utils::keccak256(lower.as_bytes());
+    let mut out = String::with_capacity(42);
+    out.push_str("0x");
+    for (i, c) in lower.chars().enumerate() {
+        let nibble = if i % 2 == 0 { hash[i / 2] >> 4 } else { hash[i / 2] & 0x0f };
+        out.push(if nibble >= 8 { c.to_ascii_uppercase() } else { c });
+    }
+    out
+}
+
+/// `to_checksum` of a stored (lowercase) address string; anything unparsable is
+/// returned unchanged.
+pub fn checksum_str(stored: &str) -> String {
+    stored.parse::<Address>().map(|a| to_checksum(&a)).unwrap_or_else(|_| stored.to_string())
+}
+
+/// Exact conversion of an on-chain amount; never fails or wraps.
+pub fn u256_to_integer(x: U256) -> Integer {
+    let mut bytes = [0u8; 32];
+    x.to_big_endian(&mut bytes);
+    Integer::from_digits(&bytes, Order::MsfBe)
+}
+
+/// Parse a stored per-transfer amount, rejecting anything a uint256 can't hold
+/// (negative or above 2^256-1), which can only come from a corrupt row.
+pub fn parse_amount(s: &str) -> anyhow::Result<Integer> {
+    let x = Integer::from_str_radix(s, 10)
+        .map_err(|e| anyhow::anyhow!("amount {s:?} is not an integer: {e}"))?;
+    let max = (Integer::from(1) << 256u32) - 1u32;
+    if x < 0 || x > max {
+        anyhow::bail!("amount {s} is outside the uint256 range");
+    }
+    Ok(x)
+}
+
+/// Parse a non-negative decimal amount in token units ("1500", "0.25") into base
+/// units, rejecting more fractional digits than `decimals`.
+pub fn parse_units(s: &str, decimals: u32) -> anyhow::Result<Integer> {
+    let s = s.trim();
+    let (whole, frac) = s.split_once('.').unwrap_or((s, ""));
+    let digits = |p: &str| p.bytes().all(|b| b.is_ascii_digit());
+    if (whole.is_empty() && frac.is_empty()) || !digits(whole) || !digits(frac) {
+        anyhow::bail!("invalid amount {s:?}");
+    }
+    if frac.len() > decimals as usize {
+        anyhow::bail!("amount {s:?} has more than {decimals} fractional digits");
+    }
+    let padded = format!("{whole}{frac:0<width$}", width = decimals as usize);
+    Ok(Integer::from_str_radix(&padded, 10).unwrap_or_default())
+}
+
+/// How the fractional part is reduced when a display precision cap applies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Drop extra digits (round toward zero)
+    #[default]
+    Truncate,
+    /// Round half away from zero
+    HalfUp,
+    /// Round half to even (banker's rounding)
+    HalfEven,
+}
+
+impl FromStr for Rounding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "truncate" => Ok(Rounding::Truncate),
+            "half_up" => Ok(Rounding::HalfUp),
+            "half_even" => Ok(Rounding::HalfEven),
+            other => anyhow::bail!("unknown rounding mode {other:?} (truncate|half_up|half_even)"),
+        }
+    }
+}
+
+/// Display settings applied to every formatted amount.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DisplayCfg {
+    /// Max fractional digits shown; `None` shows full token precision
+    pub precision: Option<u32>,
+    pub rounding: Rounding,
+}
+
+/// Round `x` (in base units) so that only `drop` trailing digits are removed.
+/// Returns the reduced integer, i.e. `x / 10^drop` rounded per `mode`.
+fn round_digits(x: &Integer, drop: u32, mode: Rounding) -> Integer {
+    if drop == 0 {
+        return x.clone();
+    }
+    let div = Integer::from(10).pow(drop);
+    let (mut q, r) = x.clone().div_rem(div.clone());
+    let twice = Integer::from(r.abs_ref()) * 2u32;
+    let away = match mode {
+        Rounding::Truncate => false,
+        Rounding::HalfUp => twice >= div,
+        Rounding::HalfEven => twice > div || (twice == div && q.is_odd()),
+    };
+    if away {
+        if *x < 0 {
+            q -= 1;
+        } else {
+            q += 1;
+        }
+    }
+    q
+}
+
+/// Format base units as a decimal string with `decimals` token decimals,
+/// trimming trailing zeros and honoring the display precision cap.
+/// Negative values are formatted from their magnitude with a leading `-`
+/// (`div_rem` truncates toward zero, so the remainder would carry the sign too).
+pub fn format_units(x: &Integer, decimals: u32, display: &DisplayCfg) -> String {
+    let (x, decimals) = match display.precision {
+        Some(p) if p < decimals => (round_digits(x, decimals - p, display.rounding), p),
+        _ => (x.clone(), decimals),
+    };
+    let sign = if x < 0 { "-" } else { "" };
+    let scale = Integer::from(10).pow(decimals);
+    let (q, r) = x.abs().div_rem(scale);
+    if r == 0 {
+        format!("{}{}", sign, q)
+    } else {
+        let mut frac = r.to_string_radix(10);
+        // pad leading zeros in fractional part
+        if frac.len() < decimals as usize {
+            let pad = (decimals as usize) - frac.len();
+            frac = "0".repeat(pad) + &frac;
+        }
+        // trim trailing zeros
+        while frac.ends_with('0') { frac.pop(); }
+        format!("{}{}.{}", sign, q, frac)
+    }
+}
+
+/// Format base units with exactly `places` fractional digits, zero-padded and never
+/// trimmed; extra digits are reduced per `rounding`.
+pub fn format_fixed(x: &Integer, decimals: u32, places: u32, rounding: Rounding) -> String {
+    let (x, decimals) = if places < decimals {
+        (round_digits(x, decimals - places, rounding), places)
+    } else {
+        (x.clone(), decimals)
+    };
+    let sign = if x < 0 { "-" } else { "" };
+    let (q, r) = x.abs().div_rem(Integer::from(10).pow(decimals));
+    if places == 0 {
+        return format!("{}{}", sign, q);
+    }
+    let mut frac = if decimals == 0 {
+        String::new()
+    } else {
+        format!("{:0>width$}", r.to_string_radix(10), width = decimals as usize)
+    };
+    frac.push_str(&"0".repeat((places - decimals) as usize));
+    format!("{}{}.{}", sign, q, frac)
+}
+
+/// Operator-chosen labels from `DISPLAY_SYMBOL_<address>=LABEL` env vars
+/// (address in any case, with or without `0x`).
+pub fn symbol_overrides() -> HashMap<Address, String> {
+    parse_symbol_overrides(std::env::vars())
+}
+
+fn parse_symbol_overrides(vars: impl IntoIterator<Item = (String, String)>) -> HashMap<Address, String> {
+    vars.into_iter()
+        .filter_map(|(k, v)| {
+            let addr = k.strip_prefix(SYMBOL_OVERRIDE_PREFIX)?;
+            let addr = addr.strip_prefix("0x").or_else(|| addr.strip_prefix("0X")).unwrap_or(addr);
+            let addr = Address::from_str(addr).ok()?;
+            Some((addr, v.trim().to_string())).filter(|(_, v)| !v.is_empty())
+        })
+        .collect()
+}
+
+/// Symbol shown for `token`: the operator override wins, then the on-chain
+/// symbol, then the address itself.
+pub fn resolve_symbol(overrides: &HashMap<Address, String>, token: Address, onchain: Option<&str>) -> String {
+    overrides.get(&token).cloned()
+        .or_else(|| onchain.filter(|s| !s.trim().is_empty()).map(str::to_string))
+        .unwrap_or_else(|| to_checksum(&token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(x: i64, decimals: u32, precision: Option<u32>, rounding: Rounding) -> String {
+        format_units(&Integer::from(x), decimals, &DisplayCfg { precision, rounding })
+    }
+
+    #[test]
+    fn format_units_at_full_precision() {
+        let full = |x: i64, decimals| fmt(x, decimals, None, Rounding::Truncate);
+        assert_eq!(full(0, 18), "0");
+        assert_eq!(full(1_500_000, 6), "1.5");
+        assert_eq!(full(-1_250_000, 6), "-1.25");
+        // Fewer digits than decimals: the fraction is zero-padded on the left
+        assert_eq!(full(5, 6), "0.000005");
+        assert_eq!(full(-5, 6), "-0.000005");
+        assert_eq!(full(42, 0), "42");
+        assert_eq!(full(-42, 0), "-42");
+        assert_eq!(
+            format_units(&u256_to_integer(U256::MAX), 18, &DisplayCfg::default()),
+            "115792089237316195423570985008687907853269984665640564039457.584007913129639935",
+        );
+    }
+
+    #[test]
+    fn symbol_overrides_take_precedence_over_the_onchain_symbol() {
+        let pol: Address = "0x455e53cbb86018ac2b8092fdcd39d8444affc3f6".parse().unwrap();
+        let matic: Address = "0x7d1afa7b718fb893db30a3abc0cfc608aacfebb0".parse().unwrap();
+        let native: Address = "0x0000000000000000000000000000000000001010".parse().unwrap();
+        let overrides = parse_symbol_overrides([
+            ("DISPLAY_SYMBOL_0x455e53cbb86018ac2b8092fdcd39d8444affc3f6", "POL"),
+            // No prefix, checksummed case, padded value
+            ("DISPLAY_SYMBOL_7D1aFA7b718fb893dB30A3aBc0Cfc608AaCfeBB0", " MATIC "),
+            ("DISPLAY_SYMBOL_0X0000000000000000000000000000000000001010", ""),
+            ("DISPLAY_SYMBOL_not-an-address", "X"),
+            ("POL_TOKEN_ADDRESS", "0x455e53cbb86018ac2b8092fdcd39d8444affc3f6"),
+        ].map(|(k, v)| (k.to_string(), v.to_string())));
+        assert_eq!(overrides, HashMap::from([(pol, "POL".to_string()), (matic, "MATIC".to_string())]));
+
+        assert_eq!(resolve_symbol(&overrides, pol, Some("WPOL")), "POL");
+        assert_eq!(resolve_symbol(&overrides, native, Some("POL")), "POL");
+        assert_eq!(resolve_symbol(&overrides, native, Some("  ")), "0x0000000000000000000000000000000000001010");
+        assert_eq!(resolve_symbol(&overrides, native, None), "0x0000000000000000000000000000000000001010");
+    }
+
+    #[test]
+    fn precision_cap_carries_into_the_whole_part() {
+        assert_eq!(fmt(1_999_999, 6, Some(2), Rounding::Truncate), "1.99");
+        assert_eq!(fmt(1_999_999, 6, Some(2), Rounding::HalfUp), "2");
+        assert_eq!(fmt(-1_999_999, 6, Some(2), Rounding::HalfEven), "-2");
+        assert_eq!(fmt(999_500, 6, Some(3), Rounding::HalfUp), "1");
+        // Rounded away entirely, with no "-0"
+        assert_eq!(fmt(4, 6, Some(2), Rounding::HalfUp), "0");
+        assert_eq!(fmt(-4, 6, Some(2), Rounding::HalfUp), "0");
+        // A cap at or above the token's decimals changes nothing
+        assert_eq!(fmt(1_234_567, 6, Some(6), Rounding::HalfUp), "1.234567");
+        assert_eq!(fmt(1_234_567, 6, Some(9), Rounding::HalfUp), "1.234567");
+    }
+
+    #[test]
+    fn round_digits_rounds_the_magnitude() {
+        let round = |x: i64, drop, mode| round_digits(&Integer::from(x), drop, mode);
+        assert_eq!(round(12_345, 0, Rounding::HalfUp), 12_345);
+        assert_eq!(round(12_345, 2, Rounding::Truncate), 123);
+        assert_eq!(round(-12_345, 2, Rounding::Truncate), -123);
+        assert_eq!(round(12_355, 2, Rounding::HalfUp), 124);
+        assert_eq!(round(-12_355, 2, Rounding::HalfUp), -124);
+        assert_eq!(round(9_999, 2, Rounding::HalfUp), 100);
+        assert_eq!(round(-9_999, 2, Rounding::HalfEven), -100);
+        assert_eq!(round(49, 2, Rounding::HalfUp), 0);
+    }
+
+    #[test]
+    fn rounding_modes_at_the_half() {
+        use Rounding::*;
+        for (x, mode, want) in [
+            (2_500_000, Truncate, "2"), (2_500_000, HalfUp, "3"), (2_500_000, HalfEven, "2"),
+            (3_500_000, Truncate, "3"), (3_500_000, HalfUp, "4"), (3_500_000, HalfEven, "4"),
+            (-2_500_000, Truncate, "-2"), (-2_500_000, HalfUp, "-3"), (-2_500_000, HalfEven, "-2"),
+            (-3_500_000, Truncate, "-3"), (-3_500_000, HalfUp, "-4"), (-3_500_000, HalfEven, "-4"),
+            // Off the half both modes agree
+            (2_500_001, HalfEven, "3"), (2_499_999, HalfUp, "2"),
+        ] {
+            assert_eq!(fmt(x, 6, Some(0), mode), want, "{x} with {mode:?}");
+        }
+        assert_eq!(fmt(125_000, 6, Some(2), HalfEven), "0.12");
+        assert_eq!(fmt(135_000, 6, Some(2), HalfEven), "0.14");
+        assert_eq!(fmt(125_000, 6, Some(2), HalfUp), "0.13");
+        assert_eq!(format_fixed(&Integer::from(125_000), 6, 2, HalfEven), "0.12");
+        assert_eq!(format_fixed(&Integer::from(-125_000), 6, 2, HalfUp), "-0.13");
+    }
+
+    #[test]
+    fn rounding_parses_case_insensitively() {
+        assert_eq!("HALF_EVEN".parse::<Rounding>().unwrap(), Rounding::HalfEven);
+        assert_eq!(" half_up ".parse::<Rounding>().unwrap(), Rounding::HalfUp);
+        assert_eq!("Truncate".parse::<Rounding>().unwrap(), Rounding::Truncate);
+        assert!("bankers".parse::<Rounding>().is_err());
+    }
+
+    #[test]
+    fn parse_units_scales_to_base_units() {
+        assert_eq!(parse_units("1500", 18).unwrap().to_string(), "1500000000000000000000");
+        assert_eq!(parse_units("0.25", 6).unwrap(), 250_000);
+        assert_eq!(parse_units(" .5 ", 6).unwrap(), 500_000);
+        assert_eq!(parse_units("7.", 6).unwrap(), 7_000_000);
+        assert_eq!(parse_units("0", 6).unwrap(), 0);
+        assert_eq!(parse_units("0.000001", 6).unwrap(), 1);
+        assert_eq!(parse_units("42", 0).unwrap(), 42);
+        for s in ["1.5", "0.000001", "1500"] {
+            assert_eq!(format_units(&parse_units(s, 6).unwrap(), 6, &DisplayCfg::default()), s);
+        }
+    }
+
+    #[test]
+    fn parse_units_rejects_malformed_amounts() {
+        for s in ["", ".", "-1", "1e18", "1.2.3", "0x10", "1,5", "one"] {
+            assert!(parse_units(s, 6).is_err(), "{s:?} parsed");
+        }
+        assert!(parse_units("0.0000001", 6).is_err());
+        assert!(parse_units("1.5", 0).is_err());
+    }
+
+    #[test]
+    fn to_checksum_matches_the_eip55_vectors() {
+        for expected in [
+            "0x52908400098527886E0F7030069857D2E4169EE7",
+            "0x8617E340B3D01FA5F11F306F4090FD50E238070D",
+            "0xde709f2102306220921060314715629080e2fb77",
+            "0x27b1fdb04752bbc536007a920d24acb045561c26",
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            let addr: Address = expected.parse().expect("vector address");
+            assert_eq!(to_checksum(&addr), expected);
+            assert_eq!(checksum_str(&expected.to_lowercase()), expected);
+        }
+        assert_eq!(checksum_str("not-an-address"), "not-an-address");
+    }
+}